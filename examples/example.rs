@@ -6,5 +6,4 @@
 /// 5. remote AmsAdr definieren (externer server)
 /// 6. Sende AdsSyncAddDeviceNotificationReqEx zum port des remote ads servers
 ///
-
 fn main() {}
@@ -0,0 +1,37 @@
+use ads::proto::ams_address::AmsNetId;
+use ads::server::ads_server::AdsServer;
+use ads::server::simulator::VirtualPlc;
+use std::env;
+use std::thread::sleep;
+use std::time::Duration;
+
+//Serve the symbols of a declaration file like a PLC on port 851.
+//cargo run --example simulator -- examples/symbols.st
+fn main() {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "examples/symbols.st".to_string());
+    let plc = match VirtualPlc::from_file(&path) {
+        Ok(plc) => plc,
+        Err(e) => {
+            println!("Failed to load {}. {}", path, e);
+            return;
+        }
+    };
+
+    let server = AdsServer::new(AmsNetId::new(127, 0, 0, 1, 1, 1));
+    server.add_device(851, plc.clone());
+    let handle = server.start().expect("Failed to start server");
+    println!("Virtual PLC listening on {}", handle.local_addr());
+
+    //Count MAIN.counter up like a running PLC program
+    let mut counter: i16 = 0;
+    loop {
+        sleep(Duration::from_millis(100));
+        counter = counter.wrapping_add(1);
+        if let Err(e) = plc.set_value("MAIN.counter", &counter.to_le_bytes()) {
+            println!("Failed to write MAIN.counter. {}", e);
+            return;
+        }
+    }
+}
@@ -1,25 +1,72 @@
-#![allow(warnings)]
+use ads::error::AdsError;
+use ads::proto::ams_address::{AmsAddress, AmsNetId};
+use ads::server::ads_server::AdsServer;
+use ads::server::device::{AdsDevice, DeviceInfo, DeviceResult};
 
-use std::io::{Read, Write};
-use std::net::TcpListener;
-use std::thread;
+///Index group of the memory area
+const MEMORY: u32 = 0x4020;
 
-// TODO trait or struct for the server demo?
-// TODO blocking or sync --> how does the official impl solve this issue?
-// server encapsulates a tcp stream to listen and write to
+///Device with a memory area which can be read and written by offset
+struct MemoryDevice {
+    memory: Vec<u8>,
+}
+
+impl AdsDevice for MemoryDevice {
+    fn read_device_info(&mut self) -> DeviceResult<DeviceInfo> {
+        Ok(DeviceInfo::new(1, 0, 1, "Test server"))
+    }
+
+    fn read(
+        &mut self,
+        _source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        length: u32,
+    ) -> DeviceResult<Vec<u8>> {
+        if index_group != MEMORY {
+            return Err(AdsError::AdsErrDeviceInvalidGrp);
+        }
+        let start = index_offset as usize;
+        match self.memory.get(start..start + length as usize) {
+            Some(data) => Ok(data.to_vec()),
+            None => Err(AdsError::AdsErrDeviceInvalidSize),
+        }
+    }
+
+    fn write(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        data: &[u8],
+    ) -> DeviceResult<()> {
+        if index_group != MEMORY {
+            return Err(AdsError::AdsErrDeviceInvalidGrp);
+        }
+        let start = index_offset as usize;
+        match self.memory.get_mut(start..start + data.len()) {
+            Some(memory) => {
+                memory.copy_from_slice(data);
+                println!("{:?} wrote {:?} at {}", source, data, index_offset);
+                Ok(())
+            }
+            None => Err(AdsError::AdsErrDeviceInvalidSize),
+        }
+    }
+}
 
 fn main() {
-    //    let endpoint = format!("127.0.0.1:{}", router::PORT_BASE);
-    //    let listener = TcpListener::bind(endpoint.as_str()).unwrap();
-    //    println!("listening started, ready to accept");
-    //
-    //    for stream in listener.incoming() {
-    //        thread::spawn(|| {
-    //            let mut stream = stream.unwrap();
-    //            stream.write(b"Hello World\r\n").unwrap();
-    //            let mut content = String::new();
-    //            stream.read_to_string(&mut content);
-    //            println!("{}", content);
-    //        });
-    //    }
+    let server = AdsServer::new(AmsNetId::new(127, 0, 0, 1, 1, 1));
+    server.add_device(
+        851,
+        MemoryDevice {
+            memory: vec![0; 1024],
+        },
+    );
+
+    let handle = server.start().expect("Failed to start server");
+    println!("ADS server listening on {}", handle.local_addr());
+    loop {
+        std::thread::park();
+    }
 }
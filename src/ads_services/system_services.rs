@@ -4,6 +4,27 @@ pub struct AdsServiceInterface {
     pub index_offset_end: u32,
 }
 
+///Read the state of a device.
+///Index offset 0 -> ADS state (u16), index offset 2 -> device state (u16)
+pub const ADSIGRP_DEVICE_DATA: AdsServiceInterface = AdsServiceInterface {
+    index_group: 0x0000F100,
+    index_offset_start: 0x00000000,
+    index_offset_end: 0x00000002,
+};
+
+///Index offset of the ADS state in ADSIGRP_DEVICE_DATA
+pub const ADSIOFFS_DEVDATA_ADSSTATE: u32 = 0x00000000;
+///Index offset of the device state in ADSIGRP_DEVICE_DATA
+pub const ADSIOFFS_DEVDATA_DEVSTATE: u32 = 0x00000002;
+
+///Symbol version of the device (u8). Changes after an online change or download.
+///Index offset allways 0
+pub const ADSIGRP_SYM_VERSION: AdsServiceInterface = AdsServiceInterface {
+    index_group: 0x0000F008,
+    index_offset_start: 0x00000000,
+    index_offset_end: 0x00000000,
+};
+
 ///Reqeust a handle for for spezific var.
 ///Index offset allways 0
 pub const GET_SYMHANDLE_BY_NAME: AdsServiceInterface = AdsServiceInterface {
@@ -12,6 +33,14 @@ pub const GET_SYMHANDLE_BY_NAME: AdsServiceInterface = AdsServiceInterface {
     index_offset_end: 0x00000000,
 };
 
+///Release a handle requested with GET_SYMHANDLE_BY_NAME. The handle is written as u32.
+///Index offset allways 0
+pub const RELEASE_SYMHANDLE: AdsServiceInterface = AdsServiceInterface {
+    index_group: 0x0000F006,
+    index_offset_start: 0x00000000,
+    index_offset_end: 0x00000000,
+};
+
 ///Read or write to the the var behind the handle requested with GET_SYMHANDLE_BY_NAME
 ///Index offset is symhandle
 pub const READ_WRITE_SYMVAL_BY_HANDLE: AdsServiceInterface = AdsServiceInterface {
@@ -1,35 +1,50 @@
-use anyhow::anyhow;
 use byteorder::{LittleEndian, ReadBytesExt};
+use bytes::Bytes;
 use std::collections::hash_map;
 use std::collections::HashMap;
-use std::io::{BufReader, Read, Write};
-use std::net::TcpStream;
+use std::io::{self, BufReader, Read, Write};
 use std::net::{Ipv4Addr, SocketAddr};
 use std::result;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::ads_services::system_services::*;
+use crate::client::builder::{ConnectionBuilder, ConnectionConfig};
+use crate::client::connection_state::{ConnectionState, ConnectionStateTracker};
+use crate::client::correlation::PendingRequest;
+use crate::client::dispatch::{decode_response, Dispatcher};
+use crate::client::handle::ClientHandle;
+use crate::client::notification::{
+    queue, NotificationResult, OverflowPolicy, QueueConfig, QueueSender, StateSubscription,
+    Subscription,
+};
 use crate::client::plc_types::Var;
-use crate::client::read::AdsReader;
-use crate::error::AdsError;
+use crate::client::read::{check_link, AdsReader, TcpFrame};
+use crate::client::retry::RetryPolicy;
+use crate::client::transport::{Connector, Transport, WriteHalf};
+use crate::error::{AdsError, ClientError, ConnectionError, ProtocolError};
 use crate::proto::ads_state::*;
 use crate::proto::ads_transition_mode::AdsTransMode;
 use crate::proto::ams_address::{AmsAddress, AmsNetId};
 use crate::proto::ams_header::*;
 use crate::proto::command_id::CommandID;
+use crate::proto::frame_view::{AmsFrameView, SumupReadResponseView};
 use crate::proto::proto_traits::*;
 use crate::proto::request::*;
 use crate::proto::response::*;
+use crate::proto::router_command::RouterFrame;
 use crate::proto::state_flags::*;
 use crate::proto::sumup::sumup_request::{
     SumupReadRequest, SumupReadWriteRequest, SumupWriteRequest,
 };
 use crate::proto::sumup::sumup_response::{SumupReadResponse, SumupWriteResponse};
+use crate::router::connection::PortWriter;
+use crate::router::port::AdsPort;
 
 use std::convert::TryInto;
 
@@ -42,22 +57,97 @@ pub const ADS_SECURE_TCP_SERVER_PORT: u16 = 8016;
 //Tcp Header size without response data
 pub const AMS_HEADER_SIZE: usize = 38;
 
-pub type ClientResult<T> = result::Result<T, anyhow::Error>;
+pub type ClientResult<T> = result::Result<T, ClientError>;
 type SymHandle = u32;
+///Senders of the requests waiting for a response. Key is the invoke id.
+pub(crate) type ResponseChannels = Arc<Mutex<HashMap<u32, Sender<ClientResult<AmsFrameView>>>>>;
+pub(crate) type PendingRequests = Arc<Mutex<HashMap<u32, PendingRequest>>>;
+pub(crate) type ProtocolErrorListeners = Arc<Mutex<Vec<Sender<ProtocolError>>>>;
+pub(crate) type SharedLink = Arc<Mutex<Option<Link>>>;
+
+///Write side of an established connection.
+///Shared with all handles so frames are written one after the other.
+#[derive(Debug)]
+pub(crate) struct Link {
+    stream: WriteHalf,
+    ///Local AMS address of this connection
+    pub(crate) source: AmsAddress,
+    ///Reused for all requests so sending does not allocate
+    encoder: FrameEncoder,
+}
+
+impl Link {
+    pub(crate) fn new(stream: WriteHalf, source: AmsAddress) -> Self {
+        Link {
+            stream,
+            source,
+            encoder: FrameEncoder::new(),
+        }
+    }
+
+    pub(crate) fn write(&mut self, buffer: &[u8]) -> ClientResult<()> {
+        self.stream.write_all(buffer).map_err(check_link)
+    }
+
+    ///Close the transport. Unblocks the reader thread.
+    pub(crate) fn shutdown(&self) {
+        self.stream.shutdown();
+    }
+
+    ///Encode a request and write it. Returns the number of bytes written.
+    pub(crate) fn send(
+        &mut self,
+        target: &AmsAddress,
+        source: &AmsAddress,
+        state_flags: StateFlags,
+        invoke_id: u32,
+        request: &Request,
+    ) -> ClientResult<usize> {
+        let frame = self
+            .encoder
+            .encode_request(target, source, state_flags, invoke_id, request)?;
+        self.stream.write_all(frame).map_err(check_link)?;
+        Ok(frame.len())
+    }
+}
+
+///Parameters of a device notification on a variable.
+///Used to register the notification again after an online change.
+#[derive(Debug, Clone)]
+struct NotificationRegistration {
+    var: Var,
+    trans_mode: AdsTransMode,
+    max_delay: u32,
+    cycle_time: u32,
+    handle: u32,
+}
+
+///Symbol version of the target device. Changes with every online change or download.
+#[derive(Debug)]
+struct SymbolVersion {
+    subscription: Subscription,
+    value: Option<u8>,
+}
 
 #[derive(Debug)]
 pub struct Connection {
-    route: Ipv4Addr,
+    config: ConnectionConfig,
     ams_targed_address: AmsAddress,
     ams_source_address: AmsAddress,
-    stream: Option<TcpStream>,
+    connector: Option<Connector>,
+    ///Port of an in-process AmsRouter the connection sends from
+    router_port: Option<AdsPort>,
     sym_handle: HashMap<String, SymHandle>,
     read_thread: Option<JoinHandle<ClientResult<()>>>,
-    notification_channels: Arc<Mutex<HashMap<u32, Sender<Result<Response, AdsError>>>>>,
-    device_notification_stream_channels:
-        Arc<Mutex<HashMap<u32, Sender<Result<AdsNotificationStream, AdsError>>>>>,
     pub tx_thread_cancel: Option<Sender<bool>>,
-    notification_handles: HashMap<String, u32>,
+    notifications: HashMap<String, NotificationRegistration>,
+    symbol_version: Option<SymbolVersion>,
+    state: ConnectionStateTracker,
+    ///Requests waiting for a response and notification queues. Shared with the reader thread.
+    dispatcher: Dispatcher,
+    retry_policy: Option<RetryPolicy>,
+    link: SharedLink,
+    handle: ClientHandle,
 }
 
 impl Connection {
@@ -66,18 +156,48 @@ impl Connection {
             Some(r) => r,
             None => Ipv4Addr::new(127, 0, 0, 1),
         };
+        let config = ConnectionConfig {
+            host: ip.to_string(),
+            ..ConnectionConfig::default()
+        };
+        Connection::with_config(ams_targed_address, config)
+    }
+
+    ///Configure TCP endpoint, transport, source address and socket options
+    pub fn builder(ams_targed_address: AmsAddress) -> ConnectionBuilder {
+        ConnectionBuilder::new(ams_targed_address)
+    }
+
+    pub fn with_config(ams_targed_address: AmsAddress, config: ConnectionConfig) -> Self {
+        let state = ConnectionStateTracker::new(ConnectionState::Disconnected);
+        let source = AmsAddress::new(AmsNetId::from([0, 0, 0, 0, 0, 0]), 0);
+        let dispatcher = Dispatcher::new(source.clone(), state.clone());
+        let link: SharedLink = Arc::new(Mutex::new(None));
+        let handle = ClientHandle::new(
+            ams_targed_address.clone(),
+            Arc::clone(&link),
+            Arc::clone(&dispatcher.responses),
+            Arc::clone(&dispatcher.pending_requests),
+            state.clone(),
+        )
+        .with_timeout(config.response_timeout);
 
         Connection {
-            route: ip,
+            config,
             ams_targed_address,
-            ams_source_address: AmsAddress::new(AmsNetId::from([0, 0, 0, 0, 0, 0]), 0),
-            stream: None,
+            ams_source_address: source,
+            connector: None,
+            router_port: None,
             sym_handle: HashMap::new(),
             read_thread: None,
-            notification_channels: Arc::new(Mutex::new(HashMap::new())),
-            device_notification_stream_channels: Arc::new(Mutex::new(HashMap::new())),
             tx_thread_cancel: None,
-            notification_handles: HashMap::new(),
+            notifications: HashMap::new(),
+            symbol_version: None,
+            state,
+            dispatcher,
+            retry_policy: None,
+            link,
+            handle,
         }
     }
 
@@ -86,158 +206,394 @@ impl Connection {
             return Ok(());
         }
 
-        let socket_addr = SocketAddr::from((self.route, ADS_TCP_SERVER_PORT));
-        let stream = TcpStream::connect(socket_addr)?;
-        stream.set_read_timeout(Some(Duration::from_millis(1000)));
-        stream.set_write_timeout(Some(Duration::from_millis(1000)));
-        self.ams_source_address
-            .update_from_socket_addr(stream.local_addr()?.to_string().as_str())?;
-        self.stream = Some(stream);
-        self.run_reader_thread()?;
+        self.stop_reader_thread();
+        self.state.set(ConnectionState::Connecting);
+        self.establish()
+    }
+
+    ///Re-establish a lost connection.
+    ///Notifications have to be added again after a successful reconnect.
+    pub fn reconnect(&mut self) -> ClientResult<()> {
+        if self.is_connected() {
+            return Ok(());
+        }
+
+        self.stop_reader_thread();
+        if !self.state.set(ConnectionState::Reconnecting) {
+            self.state.set(ConnectionState::Connecting);
+        }
+        self.notifications.clear();
+        self.symbol_version = None;
+        self.establish()
+    }
+
+    ///Close the connection and stop the reader thread.
+    ///Pending requests and notifications fail with AdsError::ErrPortNotConnected.
+    pub fn close(&mut self) {
+        if self.config.local_router && self.is_connected() {
+            self.port_close();
+        }
+        self.state.set(ConnectionState::Closed);
+        self.stop_reader_thread();
+        self.dispatcher.fail_pending();
+        self.notifications.clear();
+        self.symbol_version = None;
+    }
+
+    ///Get a cloneable handle which can be used from other threads at the same time.
+    ///Requests of the handle are sent over this connection.
+    pub fn handle(&self) -> ClientHandle {
+        self.handle.clone()
+    }
+
+    ///Get a handle for another AMS port of the target device.
+    ///e.g. connection.port(500) for the NC while the connection targets the PLC on 851.
+    pub fn port(&self, port: u16) -> ClientHandle {
+        self.handle.port(port)
+    }
+
+    ///Current state of the connection
+    pub fn state(&self) -> ConnectionState {
+        self.state.get()
+    }
+
+    ///Get a channel which receives every state change of the connection
+    pub fn state_events(&self) -> Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    ///Validate every received frame against the request it answers.
+    ///Frames with a wrong command id, address or state flag are dropped and reported
+    ///as ProtocolError to the listeners of protocol_errors.
+    pub fn set_strict_responses(&self, strict: bool) {
+        self.dispatcher.strict.store(strict, Ordering::Relaxed);
+    }
+
+    pub fn strict_responses(&self) -> bool {
+        self.dispatcher.strict.load(Ordering::Relaxed)
+    }
+
+    ///Get a channel which receives frames dropped in strict mode
+    pub fn protocol_errors(&self) -> Receiver<ProtocolError> {
+        let (tx, rx) = channel::<ProtocolError>();
+        match self.dispatcher.listeners.lock() {
+            Ok(mut c) => c.push(tx),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        rx
+    }
+
+    ///Retry idempotent requests (read and write) which failed with a transient error.
+    ///None disables retries.
+    pub fn set_retry_policy(&mut self, retry_policy: Option<RetryPolicy>) {
+        self.retry_policy = retry_policy;
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    ///Open the transport with the connector instead of a TCP connection to the configured host.
+    ///Takes effect on the next connect or reconnect.
+    pub fn set_connector(&mut self, connector: Option<Connector>) {
+        self.connector = connector;
+    }
+
+    ///Send from a port of an in-process AmsRouter instead of an own transport.
+    ///Takes effect on the next connect or reconnect.
+    pub(crate) fn set_router_port(&mut self, port: Option<AdsPort>) {
+        self.router_port = port;
+    }
+
+    ///Run op and repeat it according to the retry policy
+    fn with_retry<T, F>(&mut self, mut op: F) -> ClientResult<T>
+    where
+        F: FnMut(&mut Connection) -> ClientResult<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op(self) {
+                Err(e) => match &self.retry_policy {
+                    Some(policy) if policy.should_retry(&e, attempt) => {
+                        thread::sleep(policy.backoff(attempt));
+                        attempt += 1;
+                    }
+                    _ => return Err(e),
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn establish(&mut self) -> ClientResult<()> {
+        match self.open_stream() {
+            Ok(()) => {
+                self.state.set(ConnectionState::Connected);
+                Ok(())
+            }
+            Err(e) => {
+                self.stop_reader_thread();
+                self.state.set(ConnectionState::Disconnected);
+                Err(e)
+            }
+        }
+    }
+
+    fn open_stream(&mut self) -> ClientResult<()> {
+        if self.router_port.is_some() {
+            return self.open_router_port();
+        }
+        if self.config.local_router && self.config.source.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The source address is assigned by the local router. Don't set both",
+            )
+            .into());
+        }
+        let stream: Box<dyn Transport> = match &self.connector {
+            Some(connector) => connector.open(),
+            None => self
+                .config
+                .open()
+                .map(|s| Box::new(s) as Box<dyn Transport>),
+        }
+        .map_err(check_link)?;
+        let local_addr = stream.local_addr();
+        let dispatcher = self.dispatcher.clone();
+        stream.on_give_up(Box::new(move |invoke_id| dispatcher.expire(invoke_id)));
+        let (read, write) = stream.split()?;
+        let mut reader = AdsReader::from_read(read);
+        let mut link = Link::new(write, self.ams_source_address.clone());
+        let source = match &self.config.source {
+            Some(source) => Ok(source.clone()),
+            None if self.config.local_router => {
+                Connection::port_connect(&mut link, &mut reader, self.config.response_timeout)
+            }
+            None => match local_addr {
+                Some(addr) => AmsAddress::from_socket_addr(&addr).map_err(ClientError::from),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Transport has no socket address. Set the source address explicitly",
+                )
+                .into()),
+            },
+        };
+        let source = match source {
+            Ok(source) => source,
+            Err(e) => {
+                link.shutdown();
+                return Err(e);
+            }
+        };
+        self.ams_source_address = source.clone();
+        self.dispatcher.source = source.clone();
+        link.source = source;
+        match self.link.lock() {
+            Ok(mut l) => *l = Some(link),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        self.run_reader_thread(reader)?;
         Ok(())
     }
 
+    ///Send over the shared connection of the router to the remote router of the target.
+    ///The reader thread of the router connection dispatches the frames of the port.
+    fn open_router_port(&mut self) -> ClientResult<()> {
+        let (router, port) = match &self.router_port {
+            Some(p) => (p.router().clone(), p.port()),
+            None => return Err(AdsError::ErrPortNotConnected.into()),
+        };
+        let connection = router.connection(&self.ams_targed_address.ams_net_id)?;
+        let source = AmsAddress::new(router.local_net_id(), port);
+        self.ams_source_address = source.clone();
+        self.dispatcher.source = source.clone();
+        connection.attach(self.dispatcher.clone())?;
+        let link = Link::new(Box::new(PortWriter::new(connection, port)), source);
+        match self.link.lock() {
+            Ok(mut l) => *l = Some(link),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        Ok(())
+    }
+
+    ///Register a port at the local AMS router.
+    ///Returns the AMS address (router NetId and assigned port) to use as source.
+    ///Fails with ClientError::Timeout if the router doesn't answer within timeout.
+    fn port_connect(
+        link: &mut Link,
+        reader: &mut AdsReader,
+        timeout: Duration,
+    ) -> ClientResult<AmsAddress> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = Vec::new();
+        RouterFrame::PortConnectRequest { port: 0 }.write_to(&mut buffer)?;
+        link.write(&buffer)?;
+        loop {
+            match reader.read_frame() {
+                Ok(TcpFrame::Router(RouterFrame::PortConnectResponse(address))) => {
+                    return Ok(address)
+                }
+                //Router state changes may be sent at any time
+                Ok(TcpFrame::Router(RouterFrame::RouterNotification(_))) => (),
+                Ok(frame) => {
+                    return Err(ProtocolError::UnexpectedFrame {
+                        expected: "port connect response",
+                        received: format!("{:?}", frame),
+                    }
+                    .into())
+                }
+                //Read timeout of the socket
+                Err(ClientError::Timeout) if Instant::now() < deadline => (),
+                Err(e) => return Err(e),
+            }
+            if Instant::now() >= deadline {
+                return Err(ClientError::Timeout);
+            }
+        }
+    }
+
+    ///Request the NetId of the local AMS router (GetLocalNetId).
+    ///Only available if the connection was opened with ConnectionConfig::local_router.
+    pub fn get_local_net_id(&mut self) -> ClientResult<AmsNetId> {
+        if !self.config.local_router {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not connected through a local router",
+            )
+            .into());
+        }
+
+        let (tx, rx) = channel::<RouterFrame>();
+        match self.dispatcher.router_responses.lock() {
+            Ok(mut c) => *c = Some(tx),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let mut buffer = Vec::new();
+        RouterFrame::GetLocalNetIdRequest.write_to(&mut buffer)?;
+        let result = self.stream_write(&buffer).and_then(|_| loop {
+            match rx.recv_timeout(self.config.response_timeout) {
+                Ok(RouterFrame::GetLocalNetIdResponse(net_id)) => return Ok(net_id),
+                Ok(frame) => log::debug!("Unexpected router frame {:?}", frame),
+                Err(RecvTimeoutError::Timeout) => return Err(ClientError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(ConnectionError::ResponseChannelClosed.into())
+                }
+            }
+        });
+        match self.dispatcher.router_responses.lock() {
+            Ok(mut c) => *c = None,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        result
+    }
+
+    ///Release the port at the local AMS router
+    fn port_close(&mut self) {
+        let mut buffer = Vec::new();
+        let frame = RouterFrame::PortClose {
+            port: self.ams_source_address.port,
+        };
+        if frame.write_to(&mut buffer).is_ok() {
+            if let Err(e) = self.stream_write(&buffer) {
+                log::warn!("Failed to release router port. {}", e);
+            }
+        }
+    }
+
     pub fn connect_secure(&mut self) -> ClientResult<()> {
         unimplemented!()
     }
 
     pub fn is_connected(&self) -> bool {
-        self.stream.is_some()
+        self.state.get() == ConnectionState::Connected
     }
 
     pub fn request(&mut self, request: Request, invoke_id: u32) -> ClientResult<usize> {
-        let mut buffer = Vec::new();
-        self.create_payload(request, StateFlags::req_default(), invoke_id, &mut buffer)?;
-        self.stream_write(&mut buffer)
+        let target = self.ams_targed_address.clone();
+        self.request_to(&target, request, invoke_id)
     }
 
-    fn create_payload(
+    ///Send a request to another target (port) over this connection
+    pub fn request_to(
         &mut self,
+        target: &AmsAddress,
         request: Request,
-        state_flag: StateFlags,
         invoke_id: u32,
-        mut buffer: &mut Vec<u8>,
-    ) -> ClientResult<()> {
-        let ams_header = AmsHeader::new(
-            self.ams_targed_address.clone(),
-            self.ams_source_address.clone(),
-            state_flag,
-            invoke_id,
-            request,
-        );
-        let ams_tcp_header = AmsTcpHeader::from(ams_header);
-        ams_tcp_header.write_to(&mut buffer)?;
-        Ok(())
+    ) -> ClientResult<usize> {
+        match self.dispatcher.pending_requests.lock() {
+            Ok(mut c) => c.insert(
+                invoke_id,
+                PendingRequest::new(
+                    request.command_id(),
+                    target.clone(),
+                    self.ams_source_address.clone(),
+                ),
+            ),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        self.send_frame(target, &request, StateFlags::req_default(), invoke_id)
     }
 
-    fn stream_write(&mut self, buffer: &mut [u8]) -> ClientResult<usize> {
-        if let Some(s) = &mut self.stream {
-            return Ok(s.write(buffer)?);
-        }
-        Err(anyhow!(AdsError::ErrPortNotConnected))
+    ///Encode the request into the reusable buffer of the link and write it
+    fn send_frame(
+        &self,
+        target: &AmsAddress,
+        request: &Request,
+        state_flags: StateFlags,
+        invoke_id: u32,
+    ) -> ClientResult<usize> {
+        let source = &self.ams_source_address;
+        self.with_link(|link| link.send(target, source, state_flags, invoke_id, request))
     }
 
-    //test
-    fn run_reader_thread(&mut self) -> ClientResult<()> {
-        let (tx, rx) = channel::<bool>();
-        self.tx_thread_cancel = Some(tx);
-        let rx_thread_cancel = rx;
-        let mut reader: AdsReader;
+    fn stream_write(&self, buffer: &[u8]) -> ClientResult<usize> {
+        self.with_link(|link| link.write(buffer).map(|_| buffer.len()))
+    }
+
+    fn with_link<T, F>(&self, f: F) -> ClientResult<T>
+    where
+        F: FnOnce(&mut Link) -> ClientResult<T>,
+    {
+        if !self.is_connected() {
+            return Err(AdsError::ErrPortNotConnected.into());
+        }
 
-        if let Some(s) = &mut self.stream {
-            let mut stream = s.try_clone()?;
-            reader = AdsReader::new(stream);
-        } else {
-            panic!("No tcp stream available");
+        let result = match self.link.lock() {
+            Ok(mut l) => match l.as_mut() {
+                Some(link) => f(link),
+                None => Err(AdsError::ErrPortNotConnected.into()),
+            },
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        if let Err(ClientError::Disconnected(_)) = result {
+            self.state.set(ConnectionState::Disconnected);
         }
+        result
+    }
 
-        let notificatino_channels = Arc::clone(&self.notification_channels);
-        let notification_stream_channels = Arc::clone(&self.device_notification_stream_channels);
+    fn stop_reader_thread(&mut self) {
+        if let Some(tx) = self.tx_thread_cancel.take() {
+            tx.send(true);
+        }
+        let link = match self.link.lock() {
+            Ok(mut l) => l.take(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        //Unblocks the reader thread which is waiting for data
+        if let Some(link) = link {
+            link.shutdown();
+        }
+        if let Some(t) = self.read_thread.take() {
+            t.join();
+        }
+    }
 
+    fn run_reader_thread(&mut self, reader: AdsReader) -> ClientResult<()> {
+        let (tx, rx) = channel::<bool>();
+        self.tx_thread_cancel = Some(tx);
+        let dispatcher = self.dispatcher.clone();
         self.read_thread = Some(thread::spawn(move || {
-            let mut cancel: bool = false;
-            let mut buf: Vec<u8> = vec![0; AMS_HEADER_SIZE];
-            let mut tcp_ams_header: AmsTcpHeader;
-            while (!cancel) {
-                tcp_ams_header = match reader.read_response() {
-                    Ok(a) => a,
-                    Err(e) => {
-                        let mut channels;
-                        match notification_stream_channels.lock() {
-                            Ok(c) => channels = c,
-                            Err(_) => panic!("Failed to get lock!"),
-                        };
-
-                        for (handle, sender) in channels.iter() {
-                            sender.send(Err(AdsError::AdsErrClientW32Error));
-                        }
-
-                        if let Ok(c) = rx_thread_cancel.try_recv() {
-                            cancel = c;
-                        }
-                        continue;
-                    }
-                };
-                match tcp_ams_header.command_id() {
-                    CommandID::DeviceNotification => {
-                        let mut channels = match notification_stream_channels.lock() {
-                            Ok(c) => c,
-                            Err(_) => panic!("Failed to get lock!"),
-                        };
-
-                        let stream: AdsNotificationStream =
-                            tcp_ams_header.response()?.try_into()?;
-                        let mut handle = 0;
-                        for header in stream.ads_stamp_headers {
-                            for sample in header.notification_samples {
-                                handle = sample.notification_handle;
-                            }
-                        }
-
-                        if let Some(sender) = channels.get(&handle) {
-                            if tcp_ams_header.ads_error() == &AdsError::ErrNoError {
-                                let response: AdsNotificationStream =
-                                    tcp_ams_header.response()?.try_into()?;
-                                sender.send(Ok(response));
-                            } else {
-                                sender.send(Err(tcp_ams_header.ads_error().clone()));
-                            }
-                        } else {
-                            println!(
-                                "No sender for invoke id {:?} found ....{:?}",
-                                &tcp_ams_header.invoke_id(),
-                                &tcp_ams_header.command_id()
-                            )
-                        }
-                    }
-                    _ => {
-                        let mut channels = match notificatino_channels.lock() {
-                            Ok(c) => c,
-                            Err(_) => panic!("Failed to get lock!"),
-                        };
-
-                        if let Some(sender) = channels.get(&tcp_ams_header.invoke_id()) {
-                            if tcp_ams_header.ads_error() == &AdsError::ErrNoError {
-                                let response = tcp_ams_header.response()?;
-                                sender.send(Ok(response));
-                            } else {
-                                sender.send(Err(tcp_ams_header.ads_error().clone()));
-                            }
-                        } else {
-                            println!(
-                                "No sender for invoke id {:?} found ....{:?}...",
-                                &tcp_ams_header.invoke_id(),
-                                &tcp_ams_header.command_id()
-                            )
-                        }
-                    }
-                }
-
-                if let Ok(c) = rx_thread_cancel.try_recv() {
-                    cancel = c;
-                }
-            }
-            println!("Thread is canceled");
+            dispatcher.run(reader, rx);
             Ok(())
         }));
         Ok(())
@@ -246,58 +602,86 @@ impl Connection {
     fn create_response_channel(
         &mut self,
         invoke_id: u32,
-    ) -> ClientResult<Receiver<Result<Response, AdsError>>> {
-        let mut channels = match self.notification_channels.lock() {
+    ) -> ClientResult<Receiver<ClientResult<AmsFrameView>>> {
+        if !self.is_connected() {
+            return Err(AdsError::ErrPortNotConnected.into());
+        }
+
+        let mut channels = match self.dispatcher.responses.lock() {
             Ok(c) => c,
             Err(_) => panic!("Failed to get lock!"),
         };
 
-        let (tx, rx) = channel::<Result<Response, AdsError>>();
+        let (tx, rx) = channel::<ClientResult<AmsFrameView>>();
         channels.insert(invoke_id, tx);
         Ok(rx)
     }
 
-    fn read_device_notification_response(
-        &mut self,
-        handle: u32,
-    ) -> ClientResult<Receiver<Result<AdsNotificationStream, AdsError>>> {
-        let mut channels = match self.device_notification_stream_channels.lock() {
-            Ok(c) => c,
-            Err(_) => panic!("Failed to get lock!"),
-        };
+    ///Block until the response to invoke_id arrives and decode it
+    fn wait_for_response(
+        &self,
+        rx: &Receiver<ClientResult<AmsFrameView>>,
+        invoke_id: u32,
+    ) -> ClientResult<Response> {
+        decode_response(&self.wait_for_frame(rx, invoke_id)?)
+    }
 
-        let (tx, rx) = channel::<Result<AdsNotificationStream, AdsError>>();
-        channels.insert(handle, tx);
-        Ok(rx)
+    ///Block until the response frame to invoke_id arrives.
+    ///Fails with ClientError::Timeout after the response timeout of the config.
+    fn wait_for_frame(
+        &self,
+        rx: &Receiver<ClientResult<AmsFrameView>>,
+        invoke_id: u32,
+    ) -> ClientResult<AmsFrameView> {
+        match rx.recv_timeout(self.config.response_timeout) {
+            Ok(response) => response,
+            Err(RecvTimeoutError::Timeout) => {
+                //Nobody waits for a late response
+                match self.dispatcher.responses.lock() {
+                    Ok(mut c) => c.remove(&invoke_id),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+                match self.dispatcher.pending_requests.lock() {
+                    Ok(mut c) => c.remove(&invoke_id),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+                Err(ClientError::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(ConnectionError::ResponseChannelClosed.into())
+            }
+        }
     }
 
     ///Request handle for a variable
     pub fn get_symhandle(&mut self, var: &Var, invoke_id: u32) -> ClientResult<u32> {
-        if self.sym_handle.contains_key(&var.name) {
-            if let Some(handle) = self.sym_handle.get(&var.name) {
-                return Ok(*handle);
-            }
+        if let Some(handle) = self.sym_handle.get(&var.name) {
+            return Ok(*handle);
         }
 
+        self.subscribe_symbol_version(invoke_id);
+        self.resolve_handle(&var.name, invoke_id)
+    }
+
+    fn resolve_handle(&mut self, name: &str, invoke_id: u32) -> ClientResult<u32> {
         let request = Request::ReadWrite(ReadWriteRequest::new(
             GET_SYMHANDLE_BY_NAME.index_group,
             GET_SYMHANDLE_BY_NAME.index_offset_start,
             4, //allways u32 for get_symhandle
-            var.name.as_bytes().to_vec(),
+            name.as_bytes().to_vec(),
         ));
+        let rx = self.create_response_channel(invoke_id)?;
         self.request(request, invoke_id)?;
-        let mut response: ReadWriteResponse = self
-            .create_response_channel(invoke_id)?
-            .recv()??
-            .try_into()?; //blocking call to the channel rx
+        let response: ReadWriteResponse = self.wait_for_response(&rx, invoke_id)?.try_into()?;
         Connection::check_ads_error(&response.result)?;
-        let handle = response.data.as_slice().read_u32::<LittleEndian>()?;
-        self.sym_handle.insert(var.name.to_string(), handle);
+        let handle = Connection::decode_handle(&response.data)?;
+        self.sym_handle.insert(name.to_string(), handle);
         Ok(handle)
     }
 
     ///Request handles for multiple variables.
     pub fn sumup_get_symhandle(&mut self, var_list: &[Var], invoke_id: u32) -> ClientResult<bool> {
+        self.subscribe_symbol_version(invoke_id);
         //Check for already available handles
         let mut request_handle_list: Vec<ReadWriteRequest> = Vec::new();
         let remaining_var_list = self.check_available_handles(var_list, &mut request_handle_list);
@@ -311,16 +695,15 @@ impl Connection {
             (request_count * 12),
             data_buf,
         ));
+        let rx = self.create_response_channel(invoke_id)?;
         self.request(request, invoke_id)?;
-        let mut read_write_response: ReadWriteResponse = self
-            .create_response_channel(invoke_id)?
-            .recv()??
-            .try_into()?; //blocking call to the channel rx
+        let mut read_write_response: ReadWriteResponse =
+            self.wait_for_response(&rx, invoke_id)?.try_into()?;
         Connection::check_ads_error(&read_write_response.result)?;
         let sumup_response: SumupReadResponse =
             SumupReadResponse::read_from(&mut read_write_response.data.as_slice())?;
 
-        self.collect_handles(&remaining_var_list, &sumup_response);
+        self.collect_handles(&remaining_var_list, &sumup_response)?;
         Ok(true)
     }
 
@@ -350,46 +733,54 @@ impl Connection {
         var_list: &[Var],
         sumup_response: &SumupReadResponse,
     ) -> ClientResult<()> {
-        for (n, var) in var_list.iter().enumerate() {
-            Connection::check_ads_error(&sumup_response.read_responses[n].result)?;
-            self.sym_handle.insert(
-                var.name.clone(),
-                sumup_response.read_responses[n]
-                    .data
-                    .as_slice()
-                    .read_u32::<LittleEndian>()?,
-            );
+        if sumup_response.read_responses.len() != var_list.len() {
+            return Err(ClientError::Decode(format!(
+                "Expected {} handles, got {}",
+                var_list.len(),
+                sumup_response.read_responses.len()
+            )));
+        }
+        for (var, response) in var_list.iter().zip(&sumup_response.read_responses) {
+            Connection::check_ads_error(&response.result)?;
+            self.sym_handle
+                .insert(var.name.clone(), Connection::decode_handle(&response.data)?);
         }
         Ok(())
     }
 
+    ///Symbol handles are allways u32
+    fn decode_handle(data: &[u8]) -> ClientResult<u32> {
+        let len = data.len();
+        let mut data = data;
+        data.read_u32::<LittleEndian>().map_err(|_| {
+            ClientError::Decode(format!("Expected a 4 byte handle, got {} bytes", len))
+        })
+    }
+
+    ///Read a variable. Handles are refreshed and the read is retried once
+    ///if the symbol version of the device changed.
     pub fn read_by_name(&mut self, var: &Var, invoke_id: u32) -> ClientResult<Vec<u8>> {
-        if !self.sym_handle.contains_key(&var.name) {
-            return Err(anyhow!("Symhandle for {:?} missing", var.name));
-        }
+        self.with_retry(|c| {
+            c.retry_on_symbol_version(invoke_id, |c| c.read_by_handle(var, invoke_id))
+        })
+    }
 
-        if let Some(handle) = self.sym_handle.get(&var.name) {
-            let request = Request::Read(ReadRequest::new(
-                READ_WRITE_SYMVAL_BY_HANDLE.index_group,
-                *handle,
-                var.plc_type.size() as u32,
-            ));
-            self.request(request, invoke_id)?;
-            let mut response = self.create_response_channel(invoke_id)?.recv()?;
-            let response: ReadResponse = response?.try_into()?;
-
-            match Connection::check_ads_error(&response.result) {
-                Ok(()) => Ok(response.data),
-                Err(e) => {
-                    if e == AdsError::AdsErrDeviceSymbolVersionInvalid {
-                        self.sym_handle.clear();
-                    }
-                    Err(anyhow!(e))
-                }
-            }
-        } else {
-            Err(anyhow!("No symHandle"))
-        }
+    fn read_by_handle(&mut self, var: &Var, invoke_id: u32) -> ClientResult<Vec<u8>> {
+        let handle = match self.sym_handle.get(&var.name) {
+            Some(handle) => *handle,
+            None => return Err(ClientError::MissingHandle(var.name.clone())),
+        };
+
+        let request = Request::Read(ReadRequest::new(
+            READ_WRITE_SYMVAL_BY_HANDLE.index_group,
+            handle,
+            var.plc_type.size() as u32,
+        ));
+        let rx = self.create_response_channel(invoke_id)?;
+        self.request(request, invoke_id)?;
+        let response: ReadResponse = self.wait_for_response(&rx, invoke_id)?.try_into()?;
+        Connection::check_ads_error(&response.result)?;
+        Ok(response.data)
     }
 
     pub fn sumup_read_by_name(
@@ -397,19 +788,44 @@ impl Connection {
         var_list: &[Var],
         invoke_id: u32,
     ) -> ClientResult<HashMap<String, Vec<u8>>> {
+        Ok(self
+            .sumup_read_by_name_bytes(var_list, invoke_id)?
+            .into_iter()
+            .map(|(name, data)| (name, data.to_vec()))
+            .collect())
+    }
+
+    ///Read several variables with one request like sumup_read_by_name.
+    ///The values reference the received frame instead of being copied.
+    pub fn sumup_read_by_name_bytes(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, Bytes>> {
+        self.with_retry(|c| {
+            c.retry_on_symbol_version(invoke_id, |c| c.sumup_read_by_handle(var_list, invoke_id))
+        })
+    }
+
+    fn sumup_read_by_handle(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, Bytes>> {
         self.handles_available(var_list)?; // Fails if a handles is missing.
-        let mut result: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut result: HashMap<String, Bytes> = HashMap::new();
         let request = self.create_read_request(self.create_read_request_list(var_list)?)?;
-        let response = self.create_response_channel(invoke_id)?;
-        self.request(request, invoke_id);
-        let response = response.recv()??;
-        let response: ReadWriteResponse = response.try_into()?;
+        let rx = self.create_response_channel(invoke_id)?;
+        self.request(request, invoke_id)?;
+        let frame = self.wait_for_frame(&rx, invoke_id)?;
+        let response = frame
+            .read_response()
+            .map_err(|e| ClientError::Decode(e.to_string()))?;
         Connection::check_ads_error(&response.result)?;
-        let mut read_values = SumupReadResponse::read_from(&mut response.data.as_slice())?;
+        let read_values = SumupReadResponseView::parse(&response.data, var_list.len())?;
 
-        for (n, var) in var_list.iter().enumerate() {
-            result.insert(var.name.clone(), read_values.read_responses[n].data.clone());
-            //ToDo find a way without clone for data.
+        for (var, read_response) in var_list.iter().zip(read_values.read_responses) {
+            result.insert(var.name.clone(), read_response.data);
         }
         Ok(result)
     }
@@ -418,7 +834,7 @@ impl Connection {
         //check if handles available
         for var in var_list {
             if !self.sym_handle.contains_key(&var.name) {
-                return Err(anyhow!("Symhandle for {:?} missing", var.name));
+                return Err(ClientError::MissingHandle(var.name.clone()));
             }
         }
         Ok(())
@@ -434,15 +850,15 @@ impl Connection {
                     var.plc_type.size() as u32,
                 ));
             } else {
-                return Err(anyhow!("Symhandle for {:?} missing", var.name));
+                return Err(ClientError::MissingHandle(var.name.clone()));
             }
         }
         Ok(result)
     }
 
     fn create_read_request(&self, requests: Vec<ReadRequest>) -> ClientResult<Request> {
-        let mut buf: Vec<u8> = Vec::new();
         let sumup = SumupReadRequest::new(requests);
+        let mut buf: Vec<u8> = Vec::with_capacity(sumup.encoded_len());
         sumup.write_to(&mut buf)?;
         let read_request = Request::ReadWrite(ReadWriteRequest::new(
             ADSIGRP_SUMUP_READEX.index_group,
@@ -454,51 +870,77 @@ impl Connection {
     }
 
     pub fn read_device_info(&mut self, invoke_id: u32) -> ClientResult<ReadDeviceInfoResponse> {
-        let rx = self.create_response_channel(invoke_id)?;
-        self.request(
-            Request::ReadDeviceInfo(ReadDeviceInfoRequest::new()),
-            invoke_id,
-        );
-        let response: ReadDeviceInfoResponse = rx.recv()??.try_into()?;
-        Connection::check_ads_error(&response.result)?;
-        Ok(response)
+        self.with_retry(|c| {
+            let rx = c.create_response_channel(invoke_id)?;
+            c.request(
+                Request::ReadDeviceInfo(ReadDeviceInfoRequest::new()),
+                invoke_id,
+            )?;
+            let response: ReadDeviceInfoResponse =
+                c.wait_for_response(&rx, invoke_id)?.try_into()?;
+            Connection::check_ads_error(&response.result)?;
+            Ok(response)
+        })
     }
 
     pub fn read_state(&mut self, invoke_id: u32) -> ClientResult<ReadStateResponse> {
+        self.with_retry(|c| {
+            let rx = c.create_response_channel(invoke_id)?;
+            c.request(Request::ReadState(ReadStateRequest::new()), invoke_id)?;
+            let response: ReadStateResponse = c.wait_for_response(&rx, invoke_id)?.try_into()?;
+            Connection::check_ads_error(&response.result)?;
+            Ok(response)
+        })
+    }
+
+    ///Write a variable. Handles are refreshed and the write is retried once
+    ///if the symbol version of the device changed.
+    pub fn write_by_name(&mut self, var: &Var, invoke_id: u32, data: Vec<u8>) -> ClientResult<()> {
+        self.with_retry(|c| {
+            c.retry_on_symbol_version(invoke_id, |c| c.write_by_handle(var, invoke_id, &data))
+        })
+    }
+
+    fn write_by_handle(&mut self, var: &Var, invoke_id: u32, data: &[u8]) -> ClientResult<()> {
+        let handle = match self.sym_handle.get(&var.name) {
+            Some(handle) => *handle,
+            None => return Err(ClientError::MissingHandle(var.name.clone())),
+        };
+
+        let request = Request::Write(WriteRequest::new(
+            READ_WRITE_SYMVAL_BY_HANDLE.index_group,
+            handle,
+            data.to_vec(),
+        ));
         let rx = self.create_response_channel(invoke_id)?;
-        self.request(Request::ReadState(ReadStateRequest::new()), invoke_id);
-        let response: ReadStateResponse = rx.recv()??.try_into()?;
+        self.request(request, invoke_id)?;
+        let response: WriteResponse = self.wait_for_response(&rx, invoke_id)?.try_into()?;
         Connection::check_ads_error(&response.result)?;
-        Ok(response)
+        Ok(())
     }
 
-    pub fn write_by_name(&mut self, var: &Var, invoke_id: u32, data: Vec<u8>) -> ClientResult<()> {
-        if !self.sym_handle.contains_key(&var.name) {
-            return Err(anyhow!("Symhandle for {:?} missing", var.name));
-        }
+    ///Write a variable without waiting for a response (fire and forget).
+    ///The device does not answer, so errors of the write are not reported.
+    pub fn write_by_name_no_return(
+        &mut self,
+        var: &Var,
+        invoke_id: u32,
+        data: Vec<u8>,
+    ) -> ClientResult<()> {
+        let handle = match self.sym_handle.get(&var.name) {
+            Some(handle) => *handle,
+            None => return Err(ClientError::MissingHandle(var.name.clone())),
+        };
 
-        if let Some(handle) = self.sym_handle.get(&var.name) {
-            let request = Request::Write(WriteRequest::new(
-                READ_WRITE_SYMVAL_BY_HANDLE.index_group,
-                *handle,
-                data,
-            ));
-            self.request(request, invoke_id)?;
-            let response = self.create_response_channel(invoke_id)?.recv()?;
-            let response: WriteResponse = response?.try_into()?;
-            //Delete handles if AdsError::AdsErrDeviceSymbolVersionInvalid in response data
-            match Connection::check_ads_error(&response.result) {
-                Ok(()) => Ok(()),
-                Err(e) => {
-                    if e == AdsError::AdsErrDeviceSymbolVersionInvalid {
-                        self.sym_handle.clear();
-                    }
-                    Err(anyhow!(e))
-                }
-            }
-        } else {
-            return Err(anyhow!("Symhandle for {:?} missing", var.name));
-        }
+        let request = Request::Write(WriteRequest::new(
+            READ_WRITE_SYMVAL_BY_HANDLE.index_group,
+            handle,
+            data,
+        ));
+        let mut state_flags = StateFlags::req_default();
+        state_flags.set_no_return(true);
+        self.send_frame(&self.ams_targed_address, &request, state_flags, invoke_id)?;
+        Ok(())
     }
 
     ///write multiple values at once
@@ -506,23 +948,29 @@ impl Connection {
         &mut self,
         var_list: &[Var],
         invoke_id: u32,
+    ) -> ClientResult<HashMap<String, AdsError>> {
+        self.with_retry(|c| {
+            c.retry_on_symbol_version(invoke_id, |c| c.sumup_write_by_handle(var_list, invoke_id))
+        })
+    }
+
+    fn sumup_write_by_handle(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
     ) -> ClientResult<HashMap<String, AdsError>> {
         let mut result: HashMap<String, AdsError> = HashMap::new();
         self.handles_available(var_list)?;
         let request = self.create_write_request(self.create_write_request_list(var_list)?)?;
-        let response = self.create_response_channel(invoke_id)?;
-        self.request(request, invoke_id);
-        let response = response.recv()??;
+        let rx = self.create_response_channel(invoke_id)?;
+        self.request(request, invoke_id)?;
+        let response = self.wait_for_response(&rx, invoke_id)?;
         let response: ReadWriteResponse = response.try_into()?;
         Connection::check_ads_error(&response.result)?;
-        let mut read_values = SumupWriteResponse::read_from(&mut response.data.as_slice())?;
+        let write_values = SumupWriteResponse::read_from(&mut response.data.as_slice())?;
 
-        for (n, var) in var_list.iter().enumerate() {
-            result.insert(
-                var.name.clone(),
-                read_values.write_responses[n].result.clone(),
-            );
-            //ToDo find a way without clone for data.
+        for (var, write_response) in var_list.iter().zip(write_values.write_responses) {
+            result.insert(var.name.clone(), write_response.result);
         }
         Ok(result)
     }
@@ -537,15 +985,15 @@ impl Connection {
                     var.data.clone(),
                 ));
             } else {
-                return Err(anyhow!("Symhandle for {:?} missing", var.name));
+                return Err(ClientError::MissingHandle(var.name.clone()));
             }
         }
         Ok(result)
     }
 
     fn create_write_request(&self, requests: Vec<WriteRequest>) -> ClientResult<Request> {
-        let mut buf: Vec<u8> = Vec::new();
         let sumup = SumupWriteRequest::new(requests);
+        let mut buf: Vec<u8> = Vec::with_capacity(sumup.encoded_len());
         sumup.write_to(&mut buf)?;
         let read_request = Request::ReadWrite(ReadWriteRequest::new(
             ADSIGRP_SUMUP_WRITE.index_group,
@@ -562,6 +1010,8 @@ impl Connection {
         device_state: u16,
         invoke_id: u32,
     ) -> ClientResult<()> {
+        //The channel has to exist before the response can arrive
+        let rx = self.create_response_channel(invoke_id)?;
         self.request(
             Request::WriteControl(WriteControlRequest::new(
                 new_ads_state,
@@ -570,13 +1020,13 @@ impl Connection {
                 Vec::with_capacity(0),
             )),
             invoke_id,
-        );
-        let mut response = self.create_response_channel(invoke_id)?.recv()?;
-        let response: WriteControlResponse = response?.try_into()?;
+        )?;
+        let response: WriteControlResponse = self.wait_for_response(&rx, invoke_id)?.try_into()?;
         Connection::check_ads_error(&response.result)?;
         Ok(())
     }
 
+    ///Add a device notification with the default queue configuration (see `QueueConfig::default`)
     pub fn add_device_notification(
         &mut self,
         var: &Var,
@@ -584,51 +1034,310 @@ impl Connection {
         max_delay: u32,
         cycle_time: u32,
         invoke_id: u32,
-    ) -> ClientResult<Receiver<Result<AdsNotificationStream, AdsError>>> {
-        let mut handle_val = self.get_symhandle(var, invoke_id)?;
-        self.request(
-            Request::AddDeviceNotification(AddDeviceNotificationRequest::new(
+    ) -> ClientResult<Subscription> {
+        self.add_device_notification_with_queue(
+            var,
+            trans_mode,
+            max_delay,
+            cycle_time,
+            QueueConfig::default(),
+            invoke_id,
+        )
+    }
+
+    ///Add a device notification. Samples are delivered through a bounded queue
+    ///which handles a slow consumer according to the overflow policy of queue_config.
+    pub fn add_device_notification_with_queue(
+        &mut self,
+        var: &Var,
+        trans_mode: AdsTransMode,
+        max_delay: u32,
+        cycle_time: u32,
+        queue_config: QueueConfig,
+        invoke_id: u32,
+    ) -> ClientResult<Subscription> {
+        let (tx, rx) = queue::<NotificationResult>(queue_config);
+        self.add_notification(var, trans_mode, max_delay, cycle_time, tx, invoke_id)?;
+        Ok(rx)
+    }
+
+    pub fn delete_device_notification(&mut self, var: &Var, invoke_id: u32) -> ClientResult<()> {
+        let handle = match self.notifications.get(&var.name) {
+            Some(registration) => registration.handle,
+            None => return Err(ClientError::MissingHandle(var.name.clone())),
+        };
+        self.release_device_notification(handle, invoke_id)?;
+        self.notifications.remove(&var.name);
+        Ok(())
+    }
+
+    fn add_notification(
+        &mut self,
+        var: &Var,
+        trans_mode: AdsTransMode,
+        max_delay: u32,
+        cycle_time: u32,
+        sender: QueueSender<NotificationResult>,
+        invoke_id: u32,
+    ) -> ClientResult<()> {
+        let handle_val = match self.get_symhandle(var, invoke_id) {
+            Ok(handle) => handle,
+            Err(e) => {
+                Connection::forward_ads_error(&sender, &e);
+                return Err(e);
+            }
+        };
+        let handle = self.register_device_notification(
+            AddDeviceNotificationRequest::new(
                 READ_WRITE_SYMVAL_BY_HANDLE.index_group,
                 handle_val,
                 var.plc_type.size() as u32,
                 trans_mode,
                 max_delay,
                 cycle_time,
-            )),
+            ),
+            sender,
             invoke_id,
+        )?;
+        self.notifications.insert(
+            var.name.clone(),
+            NotificationRegistration {
+                var: var.clone(),
+                trans_mode,
+                max_delay,
+                cycle_time,
+                handle,
+            },
         );
+        Ok(())
+    }
 
-        let mut response_rx = self.create_response_channel(invoke_id)?;
-        let response: AddDeviceNotificationResponse = response_rx.recv()??.try_into()?;
-        Connection::check_ads_error(&response.result)?;
-        self.notification_handles
-            .insert(var.name.clone(), response.notification_handle);
-        let rx = self.read_device_notification_response(response.notification_handle)?;
-        Ok(rx)
+    ///Subscribe to the ADS state of the target device (e.g. Run -> Stop).
+    ///The current state is delivered right after subscribing.
+    pub fn subscribe_ads_state(&mut self, invoke_id: u32) -> ClientResult<StateSubscription> {
+        let (tx, rx) = queue::<NotificationResult>(QueueConfig::default());
+        let handle = self.register_device_notification(
+            AddDeviceNotificationRequest::new(
+                ADSIGRP_DEVICE_DATA.index_group,
+                ADSIOFFS_DEVDATA_ADSSTATE,
+                4, //u16 ADS state followed by u16 device state
+                AdsTransMode::OnChange,
+                0,
+                0,
+            ),
+            tx,
+            invoke_id,
+        )?;
+        Ok(StateSubscription::new(handle, rx))
     }
 
-    pub fn delete_device_notification(&mut self, var: &Var, invoke_id: u32) -> ClientResult<()> {
+    pub fn unsubscribe_ads_state(
+        &mut self,
+        subscription: StateSubscription,
+        invoke_id: u32,
+    ) -> ClientResult<()> {
+        self.release_device_notification(subscription.handle(), invoke_id)
+    }
+
+    ///Blocks until the target device reports ads_state.
+    ///Fails with AdsError::AdsErrClientSyncTimeout if the timeout elapsed.
+    pub fn wait_for_state(
+        &mut self,
+        ads_state: AdsState,
+        timeout: Duration,
+        invoke_id: u32,
+    ) -> ClientResult<()> {
+        let subscription = self.subscribe_ads_state(invoke_id)?;
+        let result = subscription.wait_for(ads_state, timeout);
+        self.unsubscribe_ads_state(subscription, invoke_id)?;
+        Ok(result?)
+    }
+
+    ///Send an AddDeviceNotification request. Samples of the notification are sent to sender.
+    ///Returns the notification handle.
+    fn register_device_notification(
+        &mut self,
+        request: AddDeviceNotificationRequest,
+        sender: QueueSender<NotificationResult>,
+        invoke_id: u32,
+    ) -> ClientResult<u32> {
         let response_rx = self.create_response_channel(invoke_id)?;
-        let mut handle = 0;
-        if let Some(handle_val) = self.notification_handles.get(&var.name) {
-            handle = *handle_val;
-            self.request(
-                Request::DeleteDeviceNotification(DeleteDeviceNotificationRequest::new(handle)),
+        match self.dispatcher.pending_notifications.lock() {
+            Ok(mut c) => c.insert(invoke_id, sender),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        let response = self
+            .request(Request::AddDeviceNotification(request), invoke_id)
+            .and_then(|_| -> ClientResult<AddDeviceNotificationResponse> {
+                Ok(self
+                    .wait_for_response(&response_rx, invoke_id)?
+                    .try_into()?)
+            });
+        //Still pending if the request failed
+        let sender = match self.dispatcher.pending_notifications.lock() {
+            Ok(mut c) => c.remove(&invoke_id),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        let result = response.and_then(|r| {
+            Connection::check_ads_error(&r.result)?;
+            Ok(r.notification_handle)
+        });
+        if let (Err(e), Some(sender)) = (&result, sender) {
+            Connection::forward_ads_error(&sender, e);
+        }
+        result
+    }
+
+    ///Let the consumer of a notification know why it ends
+    fn forward_ads_error(sender: &QueueSender<NotificationResult>, error: &ClientError) {
+        if let Some(ads_error) = error.ads_error() {
+            sender.send(Err(ads_error.clone()));
+        }
+    }
+
+    ///Subscribe to the symbol version of the target device.
+    ///Tried again with the next handle request if the device refuses it
+    ///(devices without symbols don't support it).
+    fn subscribe_symbol_version(&mut self, invoke_id: u32) {
+        if self.symbol_version.is_some() {
+            return;
+        }
+
+        //Only the latest version is of interest
+        let (tx, rx) = queue::<NotificationResult>(QueueConfig::new(1, OverflowPolicy::Coalesce));
+        let request = AddDeviceNotificationRequest::new(
+            ADSIGRP_SYM_VERSION.index_group,
+            ADSIGRP_SYM_VERSION.index_offset_start,
+            1, //u8 symbol version
+            AdsTransMode::OnChange,
+            0,
+            0,
+        );
+        if self
+            .register_device_notification(request, tx, invoke_id)
+            .is_ok()
+        {
+            self.symbol_version = Some(SymbolVersion {
+                subscription: rx,
+                value: None,
+            });
+        }
+    }
+
+    ///Read pending symbol version notifications. Returns true if the version changed.
+    fn poll_symbol_version(&mut self) -> bool {
+        let symbol_version = match &mut self.symbol_version {
+            Some(v) => v,
+            None => return false,
+        };
+        let mut changed = false;
+        while let Ok(Ok(stream)) = symbol_version.subscription.try_recv() {
+            for (_, sample) in stream.samples() {
+                if let Some(version) = sample.data.first() {
+                    if symbol_version.value.is_some_and(|v| v != *version) {
+                        changed = true;
+                    }
+                    symbol_version.value = Some(*version);
+                }
+            }
+        }
+        changed
+    }
+
+    ///Run op. If the symbol version of the device changed (online change or download)
+    ///the handles are refreshed and op is retried once.
+    fn retry_on_symbol_version<T, F>(&mut self, invoke_id: u32, mut op: F) -> ClientResult<T>
+    where
+        F: FnMut(&mut Connection) -> ClientResult<T>,
+    {
+        if self.poll_symbol_version() {
+            self.refresh_handles(invoke_id)?;
+        }
+
+        match op(self) {
+            Err(e) if Connection::is_symbol_version_invalid(&e) => {
+                self.refresh_handles(invoke_id)?;
+                op(self)
+            }
+            result => result,
+        }
+    }
+
+    fn is_symbol_version_invalid(error: &ClientError) -> bool {
+        error.ads_error() == Some(&AdsError::AdsErrDeviceSymbolVersionInvalid)
+    }
+
+    ///Resolve all symbol handles again and register the notifications on the new handles.
+    ///Symbols which no longer exist on the device are dropped.
+    fn refresh_handles(&mut self, invoke_id: u32) -> ClientResult<()> {
+        self.poll_symbol_version();
+
+        let names: Vec<String> = self.sym_handle.drain().map(|(name, _)| name).collect();
+        for name in names {
+            Connection::ignore_ads_error(self.resolve_handle(&name, invoke_id))?;
+        }
+
+        let names: Vec<String> = self.notifications.keys().cloned().collect();
+        for name in names {
+            Connection::ignore_ads_error(self.reregister_notification(&name, invoke_id))?;
+        }
+        Ok(())
+    }
+
+    ///Register a notification again on the current handle of its variable.
+    ///The consumer keeps receiving samples through its subscription.
+    fn reregister_notification(&mut self, name: &str, invoke_id: u32) -> ClientResult<()> {
+        let registration = match self.notifications.remove(name) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let sender = match self.dispatcher.notifications.lock() {
+            Ok(mut c) => c.remove(&(self.ams_targed_address.clone(), registration.handle)),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        //Old handle is invalid in most cases. Release it in case the device kept it.
+        self.release_device_notification(registration.handle, invoke_id)
+            .ok();
+
+        match sender {
+            Some(sender) if sender.is_connected() => self.add_notification(
+                &registration.var,
+                registration.trans_mode,
+                registration.max_delay,
+                registration.cycle_time,
+                sender,
                 invoke_id,
-            );
-        } else {
-            anyhow!("No handle for var {:?}", var);
+            ),
+            _ => Ok(()),
         }
+    }
 
-        let response: DeleteDeviceNotificationResponse = response_rx.recv()??.try_into()?;
+    fn ignore_ads_error<T>(result: ClientResult<T>) -> ClientResult<()> {
+        match result {
+            Err(e) if e.ads_error().is_none() => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    ///Send a DeleteDeviceNotification request and close the queue of the notification
+    fn release_device_notification(&mut self, handle: u32, invoke_id: u32) -> ClientResult<()> {
+        let response_rx = self.create_response_channel(invoke_id)?;
+        self.request(
+            Request::DeleteDeviceNotification(DeleteDeviceNotificationRequest::new(handle)),
+            invoke_id,
+        )?;
+        let response: DeleteDeviceNotificationResponse = self
+            .wait_for_response(&response_rx, invoke_id)?
+            .try_into()?;
         Connection::check_ads_error(&response.result)?;
 
-        let mut channels = match self.device_notification_stream_channels.lock() {
+        let mut channels = match self.dispatcher.notifications.lock() {
             Ok(c) => c,
             Err(_) => panic!("Failed to get lock!"),
         };
-        channels.remove(&handle);
-
+        channels.remove(&(self.ams_targed_address.clone(), handle));
         Ok(())
     }
 
@@ -639,3 +1348,376 @@ impl Connection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Connection, ADS_TCP_SERVER_PORT};
+    use crate::ads_services::system_services::{
+        ADSIGRP_SYM_VERSION, GET_SYMHANDLE_BY_NAME, READ_WRITE_SYMVAL_BY_HANDLE,
+    };
+    use crate::client::plc_types::{PlcTypes, Var};
+    use crate::error::AdsError;
+    use crate::error::{ClientError, ProtocolError};
+    use crate::proto::ads_transition_mode::AdsTransMode;
+    use crate::proto::ams_address::{AmsAddress, AmsNetId};
+    use crate::proto::command_id::CommandID;
+    use crate::proto::proto_traits::WriteTo;
+    use crate::proto::router_command::RouterFrame;
+    use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    ///Request received by the fake device: command id, index group, index offset and payload
+    type Received = (u16, u32, u32, Vec<u8>);
+
+    ///Device which answers with raw AMS/TCP frames on port 48898 of a loopback address
+    struct FakeDevice {
+        received: Arc<Mutex<Vec<Received>>>,
+        stream: Arc<Mutex<Option<TcpStream>>>,
+        //Target and source address of the last request
+        addresses: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl FakeDevice {
+        ///Answer every request with the response data returned by respond
+        fn start<F>(ip: Ipv4Addr, mut respond: F) -> FakeDevice
+        where
+            F: FnMut(&Received, usize) -> Vec<u8> + Send + 'static,
+        {
+            let listener = TcpListener::bind((ip, ADS_TCP_SERVER_PORT)).unwrap();
+            let device = FakeDevice {
+                received: Arc::new(Mutex::new(Vec::new())),
+                stream: Arc::new(Mutex::new(None)),
+                addresses: Arc::new(Mutex::new(vec![0; 16])),
+            };
+            let received = Arc::clone(&device.received);
+            let writer = Arc::clone(&device.stream);
+            let addresses = Arc::clone(&device.addresses);
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                *writer.lock().unwrap() = Some(stream.try_clone().unwrap());
+                let mut tcp_header = [0; 6];
+                while stream.read_exact(&mut tcp_header).is_ok() {
+                    let length = (&tcp_header[2..]).read_u32::<LittleEndian>().unwrap();
+                    let mut frame = vec![0; length as usize];
+                    stream.read_exact(&mut frame).unwrap();
+                    let command_id = (&frame[16..18]).read_u16::<LittleEndian>().unwrap();
+                    let invoke_id = (&frame[28..32]).read_u32::<LittleEndian>().unwrap();
+                    let data = frame[32..].to_vec();
+                    let (index_group, index_offset) = if data.len() >= 8 {
+                        (
+                            (&data[0..4]).read_u32::<LittleEndian>().unwrap(),
+                            (&data[4..8]).read_u32::<LittleEndian>().unwrap(),
+                        )
+                    } else {
+                        (0, 0)
+                    };
+                    let request = (command_id, index_group, index_offset, data);
+                    let count = {
+                        let mut received = received.lock().unwrap();
+                        let count = received.iter().filter(|r| **r == request).count();
+                        received.push(request.clone());
+                        count
+                    };
+                    *addresses.lock().unwrap() = frame[..16].to_vec();
+                    let response = respond(&request, count);
+                    let mut writer = writer.lock().unwrap();
+                    write_frame(
+                        writer.as_mut().unwrap(),
+                        &frame[..16],
+                        command_id,
+                        5,
+                        invoke_id,
+                        &response,
+                    );
+                }
+            });
+            device
+        }
+
+        ///Send a device notification with one sample
+        fn notify(&self, handle: u32, data: &[u8]) {
+            let mut stream_data = Vec::new();
+            stream_data
+                .write_u32::<LittleEndian>(24 + data.len() as u32)
+                .unwrap();
+            stream_data.write_u32::<LittleEndian>(1).unwrap();
+            stream_data.write_u64::<LittleEndian>(1).unwrap();
+            stream_data.write_u32::<LittleEndian>(1).unwrap();
+            stream_data.write_u32::<LittleEndian>(handle).unwrap();
+            stream_data
+                .write_u32::<LittleEndian>(data.len() as u32)
+                .unwrap();
+            stream_data.extend_from_slice(data);
+
+            let addresses = self.addresses.lock().unwrap().clone();
+            let mut writer = self.stream.lock().unwrap();
+            write_frame(writer.as_mut().unwrap(), &addresses, 8, 4, 0, &stream_data);
+        }
+
+        ///Number of received requests with command id, index group and index offset
+        fn count(&self, command_id: CommandID, index_group: u32, index_offset: u32) -> usize {
+            self.received
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.0 == command_id as u16 && r.1 == index_group && r.2 == index_offset)
+                .count()
+        }
+    }
+
+    ///Write a frame to the client. Target and source of the request are swapped.
+    fn write_frame(
+        stream: &mut TcpStream,
+        addresses: &[u8],
+        command_id: u16,
+        state_flags: u16,
+        invoke_id: u32,
+        data: &[u8],
+    ) {
+        let mut frame = vec![0, 0];
+        frame
+            .write_u32::<LittleEndian>(32 + data.len() as u32)
+            .unwrap();
+        frame.extend_from_slice(&addresses[8..16]);
+        frame.extend_from_slice(&addresses[..8]);
+        frame.write_u16::<LittleEndian>(command_id).unwrap();
+        frame.write_u16::<LittleEndian>(state_flags).unwrap();
+        frame.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        frame.write_u32::<LittleEndian>(0).unwrap();
+        frame.write_u32::<LittleEndian>(invoke_id).unwrap();
+        frame.extend_from_slice(data);
+        stream.write_all(&frame).unwrap();
+    }
+
+    ///Response data: ADS result followed by the values
+    fn response(result: u32, values: &[u32]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(result).unwrap();
+        for value in values {
+            data.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        data
+    }
+
+    fn read_response(result: u32, data: &[u8]) -> Vec<u8> {
+        let mut response = response(result, &[data.len() as u32]);
+        response.extend_from_slice(data);
+        response
+    }
+
+    fn is_symbol_version(request: &Received) -> bool {
+        request.1 == ADSIGRP_SYM_VERSION.index_group
+            && request.2 == ADSIGRP_SYM_VERSION.index_offset_start
+    }
+
+    ///Name in a GET_SYMHANDLE_BY_NAME request
+    fn handle_name(request: &Received) -> &[u8] {
+        &request.3[16..]
+    }
+
+    fn connect(ip: Ipv4Addr) -> Connection {
+        let mut connection = Connection::new(
+            Some(ip),
+            AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851),
+        );
+        connection.connect().unwrap();
+        connection
+    }
+
+    ///Connect through a local router which is served by router
+    fn port_connect<F>(router: F) -> Result<Connection, ClientError>
+    where
+        F: FnOnce(TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || router(listener.accept().unwrap().0));
+        Connection::builder(AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851))
+            .port(port)
+            .local_router(true)
+            .read_timeout(Some(Duration::from_millis(50)))
+            .response_timeout(Duration::from_millis(300))
+            .connect()
+    }
+
+    #[test]
+    fn port_connect_timeout_test() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let error = port_connect(move |stream| {
+            //Keep the connection open without answering
+            rx.recv().ok();
+            drop(stream);
+        })
+        .unwrap_err();
+        assert!(matches!(error, ClientError::Timeout));
+        tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn port_connect_unexpected_frame_test() {
+        let error = port_connect(|mut stream| {
+            let mut buffer = Vec::new();
+            RouterFrame::GetLocalNetIdResponse(AmsNetId::new(10, 0, 0, 1, 1, 1))
+                .write_to(&mut buffer)
+                .unwrap();
+            stream.write_all(&buffer).unwrap();
+            stream.read_to_end(&mut Vec::new()).ok();
+        })
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            ClientError::Protocol(ProtocolError::UnexpectedFrame { .. })
+        ));
+    }
+
+    #[test]
+    fn symbol_version_subscription_test() {
+        let ip = Ipv4Addr::new(127, 0, 29, 1);
+        let device = FakeDevice::start(ip, |request, _| match CommandID::from(request.0) {
+            CommandID::AddDeviceNotification => response(0, &[9]),
+            CommandID::ReadWrite if handle_name(request).starts_with(b"MAIN.counter") => {
+                read_response(0, &7u32.to_le_bytes())
+            }
+            CommandID::ReadWrite => read_response(0, &8u32.to_le_bytes()),
+            _ => read_response(0, &[3, 0]),
+        });
+        let mut connection = connect(ip);
+        let var = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        connection.get_symhandle(&var, 1).unwrap();
+        connection
+            .get_symhandle(&Var::new("GVL.flag".to_string(), PlcTypes::Bool, None), 2)
+            .unwrap();
+        //Subscribed once for all handles
+        let subscriptions = device.count(
+            CommandID::AddDeviceNotification,
+            ADSIGRP_SYM_VERSION.index_group,
+            ADSIGRP_SYM_VERSION.index_offset_start,
+        );
+        assert_eq!(subscriptions, 1);
+        let handle_requests = || {
+            device.count(
+                CommandID::ReadWrite,
+                GET_SYMHANDLE_BY_NAME.index_group,
+                GET_SYMHANDLE_BY_NAME.index_offset_start,
+            )
+        };
+        let reads = || device.count(CommandID::Read, READ_WRITE_SYMVAL_BY_HANDLE.index_group, 7);
+
+        //The first version is the initial value
+        device.notify(9, &[1]);
+        thread::sleep(Duration::from_millis(100));
+        connection.read_by_name(&var, 3).unwrap();
+        assert_eq!(handle_requests(), 2);
+
+        //A new version resolves all handles before the read. No read fails.
+        device.notify(9, &[2]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(connection.read_by_name(&var, 4).unwrap(), vec![3, 0]);
+        assert_eq!(handle_requests(), 4);
+        assert_eq!(reads(), 2);
+    }
+
+    #[test]
+    fn symbol_version_retry_test() {
+        let ip = Ipv4Addr::new(127, 0, 29, 2);
+        let device = FakeDevice::start(ip, |request, _| match CommandID::from(request.0) {
+            CommandID::AddDeviceNotification => {
+                response(AdsError::AdsErrDeviceSrvNotSupp.as_u32(), &[0])
+            }
+            CommandID::ReadWrite => read_response(0, &7u32.to_le_bytes()),
+            //Still invalid after the handles were resolved again
+            _ => read_response(AdsError::AdsErrDeviceSymbolVersionInvalid.as_u32(), &[]),
+        });
+        let mut connection = connect(ip);
+        let var = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        connection.get_symhandle(&var, 1).unwrap();
+
+        //Retried exactly once
+        assert!(connection.read_by_name(&var, 2).is_err());
+        let handle_requests = device.count(
+            CommandID::ReadWrite,
+            GET_SYMHANDLE_BY_NAME.index_group,
+            GET_SYMHANDLE_BY_NAME.index_offset_start,
+        );
+        assert_eq!(handle_requests, 2);
+        let reads = device.count(CommandID::Read, READ_WRITE_SYMVAL_BY_HANDLE.index_group, 7);
+        assert_eq!(reads, 2);
+    }
+
+    #[test]
+    fn symbol_version_resubscribe_test() {
+        let ip = Ipv4Addr::new(127, 0, 29, 4);
+        let device = FakeDevice::start(ip, |request, count| match CommandID::from(request.0) {
+            //Refused once
+            CommandID::AddDeviceNotification if count == 0 => {
+                response(AdsError::AdsErrDeviceSrvNotSupp.as_u32(), &[0])
+            }
+            CommandID::AddDeviceNotification => response(0, &[9]),
+            _ => read_response(0, &7u32.to_le_bytes()),
+        });
+        let mut connection = connect(ip);
+        let subscriptions = || {
+            device.count(
+                CommandID::AddDeviceNotification,
+                ADSIGRP_SYM_VERSION.index_group,
+                ADSIGRP_SYM_VERSION.index_offset_start,
+            )
+        };
+        for (n, name) in ["MAIN.a", "MAIN.b", "MAIN.c"].iter().enumerate() {
+            let var = Var::new(name.to_string(), PlcTypes::Int, None);
+            connection.get_symhandle(&var, n as u32).unwrap();
+        }
+        //Subscribed again after the failure and kept afterwards
+        assert_eq!(subscriptions(), 2);
+    }
+
+    #[test]
+    fn notification_reregister_test() {
+        let ip = Ipv4Addr::new(127, 0, 29, 3);
+        let device = FakeDevice::start(ip, |request, count| match CommandID::from(request.0) {
+            CommandID::AddDeviceNotification if is_symbol_version(request) => {
+                response(AdsError::AdsErrDeviceSrvNotSupp.as_u32(), &[0])
+            }
+            CommandID::AddDeviceNotification => response(0, &[request.2 + 13]),
+            CommandID::DeleteDeviceNotification => response(0, &[]),
+            //The handle changes with the online change
+            CommandID::ReadWrite => read_response(0, &(7 + count as u32).to_le_bytes()),
+            _ if request.2 == 7 => {
+                read_response(AdsError::AdsErrDeviceSymbolVersionInvalid.as_u32(), &[])
+            }
+            _ => read_response(0, &[3, 0]),
+        });
+        let mut connection = connect(ip);
+        let var = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        let subscription = connection
+            .add_device_notification(&var, AdsTransMode::OnChange, 0, 100_000, 1)
+            .unwrap();
+
+        assert_eq!(connection.read_by_name(&var, 2).unwrap(), vec![3, 0]);
+        //Registered again on the new handle and the old notification is released
+        assert_eq!(
+            device.count(
+                CommandID::AddDeviceNotification,
+                READ_WRITE_SYMVAL_BY_HANDLE.index_group,
+                8
+            ),
+            1
+        );
+        assert_eq!(device.count(CommandID::DeleteDeviceNotification, 0, 0), 1);
+
+        //The queue receives the samples of the new notification only
+        device.notify(21, &[2, 0]);
+        assert!(subscription
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap()
+            .is_ok());
+        device.notify(20, &[1, 0]);
+        assert!(subscription
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+
+use crate::client::ads_client::{Connection, ADS_TCP_SERVER_PORT, ADS_UDP_SERVER_PORT};
+use crate::client::handle::DEFAULT_REQUEST_TIMEOUT;
+use crate::client::read::ClientResult;
+use crate::client::transport::{Connector, Transport};
+use crate::client::udp::{UdpConfig, UdpTransport};
+use crate::mqtt::transport::{MqttConfig, MqttTransport, MQTT_PORT};
+use crate::proto::ams_address::AmsAddress;
+
+///Default read and write timeout of the TCP socket
+pub const DEFAULT_SOCKET_TIMEOUT: Duration = Duration::from_millis(1000);
+
+///TCP endpoint and socket options of a Connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionConfig {
+    ///Host name, IPv4 or IPv6 address of the remote router
+    pub host: String,
+    pub port: u16,
+    ///Local AMS address. Derived from the local IPv4 address (ip.1.1) if None.
+    pub source: Option<AmsAddress>,
+    ///None blocks until the operating system gives up
+    pub connect_timeout: Option<Duration>,
+    ///Interval the reader thread checks for cancellation. None blocks.
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    ///Disable the Nagle algorithm
+    pub nodelay: bool,
+    ///Idle time before TCP keepalive probes are sent. None disables keepalive.
+    pub keepalive: Option<Duration>,
+    ///Connect through a local AMS router. The source address is assigned by the router.
+    pub local_router: bool,
+    ///Time to wait for the response to a request before it fails with ClientError::Timeout
+    pub response_timeout: Duration,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            host: "127.0.0.1".to_string(),
+            port: ADS_TCP_SERVER_PORT,
+            source: None,
+            connect_timeout: None,
+            read_timeout: Some(DEFAULT_SOCKET_TIMEOUT),
+            write_timeout: Some(DEFAULT_SOCKET_TIMEOUT),
+            nodelay: false,
+            keepalive: None,
+            local_router: false,
+            response_timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    ///Connect to the first reachable address the host resolves to
+    pub(crate) fn open(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for socket_addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match self.open_addr(&socket_addr) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No address found for {:?}", self.host),
+            )
+        }))
+    }
+
+    fn open_addr(&self, socket_addr: &SocketAddr) -> io::Result<TcpStream> {
+        let socket = Socket::new(
+            Domain::for_address(*socket_addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        match self.connect_timeout {
+            Some(timeout) => socket.connect_timeout(&(*socket_addr).into(), timeout)?,
+            None => socket.connect(&(*socket_addr).into())?,
+        }
+
+        let stream: TcpStream = socket.into();
+        stream.set_nodelay(self.nodelay)?;
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+        Ok(stream)
+    }
+}
+
+///Create a Connection with a custom TCP endpoint and socket options.
+///Unset options keep the defaults of Connection::new.
+#[derive(Debug, Clone)]
+pub struct ConnectionBuilder {
+    ams_targed_address: AmsAddress,
+    config: ConnectionConfig,
+    connector: Option<Connector>,
+    udp: Option<UdpConfig>,
+    mqtt: Option<MqttConfig>,
+}
+
+impl ConnectionBuilder {
+    pub fn new(ams_targed_address: AmsAddress) -> Self {
+        ConnectionBuilder {
+            ams_targed_address,
+            config: ConnectionConfig::default(),
+            connector: None,
+            udp: None,
+            mqtt: None,
+        }
+    }
+
+    ///Host name, IPv4 or IPv6 address of the remote router
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.config.host = host.into();
+        self
+    }
+
+    ///TCP port of the remote router. Defaults to 48898.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    ///Local AMS address. Needs a matching route on the remote router.
+    ///Can't be combined with local_router.
+    pub fn source(mut self, source: AmsAddress) -> Self {
+        self.config.source = Some(source);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.config.nodelay = nodelay;
+        self
+    }
+
+    pub fn keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.config.keepalive = idle;
+        self
+    }
+
+    ///Time to wait for a response. Defaults to 5 seconds.
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.config.response_timeout = timeout;
+        self
+    }
+
+    ///Register a port at a local AMS router on connect (PortConnect) instead of
+    ///using a source address. The port is released on close.
+    ///Connecting fails with InvalidInput if a source address is set as well.
+    pub fn local_router(mut self, local_router: bool) -> Self {
+        self.config.local_router = local_router;
+        self
+    }
+
+    ///Open the transport with this function on connect and reconnect instead of a TCP connection.
+    ///Host, port and socket options are ignored.
+    ///Without a socket address on the transport the source address has to be set.
+    pub fn transport<F>(mut self, open: F) -> Self
+    where
+        F: Fn() -> io::Result<Box<dyn Transport>> + Send + Sync + 'static,
+    {
+        self.connector = Some(Connector::new(open));
+        self
+    }
+
+    ///Send ADS commands as UDP datagrams to host and port instead of using a TCP connection.
+    ///Sets the port to 48899. Call port afterwards to use another one.
+    pub fn udp(mut self, config: UdpConfig) -> Self {
+        self.config.port = ADS_UDP_SERVER_PORT;
+        self.udp = Some(config);
+        self
+    }
+
+    ///Tunnel ADS over the MQTT broker at host and port instead of connecting to the device.
+    ///The source address is taken from the MQTT config.
+    ///Sets the port to 1883. Call port afterwards to use another one.
+    pub fn mqtt(mut self, config: MqttConfig) -> Self {
+        self.config.port = MQTT_PORT;
+        self.config.source = Some(config.source.clone());
+        self.mqtt = Some(config);
+        self
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    ///Create the connection without connecting
+    pub fn build(self) -> Connection {
+        let addr = (self.config.host.clone(), self.config.port);
+        let connector = match (self.connector, self.udp, self.mqtt) {
+            (None, Some(udp), _) => Some(Connector::new(move || {
+                Ok(Box::new(UdpTransport::connect(addr.clone(), udp.clone())?)
+                    as Box<dyn Transport>)
+            })),
+            (None, None, Some(mqtt)) => Some(Connector::new(move || {
+                Ok(
+                    Box::new(MqttTransport::connect(addr.clone(), mqtt.clone())?)
+                        as Box<dyn Transport>,
+                )
+            })),
+            (connector, _, _) => connector,
+        };
+        let mut connection = Connection::with_config(self.ams_targed_address, self.config);
+        connection.set_connector(connector);
+        connection
+    }
+
+    ///Create the connection and connect
+    pub fn connect(self) -> ClientResult<Connection> {
+        let mut connection = self.build();
+        connection.connect()?;
+        Ok(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ams_address::AmsNetId;
+    use std::net::TcpListener;
+
+    fn target() -> AmsAddress {
+        AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851)
+    }
+
+    #[test]
+    fn builder_test() {
+        let source = AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 40000);
+        let builder = ConnectionBuilder::new(target())
+            .host("plc.local")
+            .port(10000)
+            .source(source.clone())
+            .connect_timeout(Duration::from_secs(2))
+            .read_timeout(None)
+            .write_timeout(Some(Duration::from_millis(200)))
+            .nodelay(true)
+            .keepalive(Some(Duration::from_secs(30)))
+            .response_timeout(Duration::from_millis(500))
+            .local_router(true);
+
+        let config = builder.config();
+        assert_eq!(config.host, "plc.local");
+        assert_eq!(config.port, 10000);
+        assert_eq!(config.source, Some(source));
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(config.read_timeout, None);
+        assert_eq!(config.write_timeout, Some(Duration::from_millis(200)));
+        assert!(config.nodelay);
+        assert_eq!(config.keepalive, Some(Duration::from_secs(30)));
+        assert!(config.local_router);
+        assert_eq!(config.response_timeout, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn builder_default_test() {
+        let config = ConnectionBuilder::new(target()).config().clone();
+        assert_eq!(config, ConnectionConfig::default());
+        assert_eq!(config.port, ADS_TCP_SERVER_PORT);
+        assert_eq!(config.read_timeout, Some(DEFAULT_SOCKET_TIMEOUT));
+    }
+
+    #[test]
+    fn config_open_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ConnectionConfig {
+            host: "localhost".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            connect_timeout: Some(Duration::from_secs(1)),
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(10)),
+            ..ConnectionConfig::default()
+        };
+
+        let stream = config.open().unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(stream.read_timeout().unwrap(), Some(DEFAULT_SOCKET_TIMEOUT));
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+///State of the TCP link of a connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    ///TCP connection is being established
+    Connecting,
+    ///TCP connection is established and the reader thread is running
+    Connected,
+    ///The link was lost or never established
+    Disconnected,
+    ///A lost link is being re-established
+    Reconnecting,
+    ///Connection was closed on purpose
+    Closed,
+}
+
+impl ConnectionState {
+    ///Check if the state machine allows to go from self to next
+    pub fn can_transition(self, next: ConnectionState) -> bool {
+        use ConnectionState::*;
+        match (self, next) {
+            (_, Closed) => self != Closed,
+            (Disconnected, Connecting) | (Closed, Connecting) => true,
+            (Disconnected, Reconnecting) => true,
+            (Connecting, Connected) | (Reconnecting, Connected) => true,
+            (Connecting, Disconnected) | (Reconnecting, Disconnected) => true,
+            (Connected, Disconnected) => true,
+            _ => false,
+        }
+    }
+}
+
+struct Inner {
+    state: ConnectionState,
+    listeners: Vec<Sender<ConnectionState>>,
+}
+
+///Shared connection state. Every state change is sent to all registered listeners.
+#[derive(Clone)]
+pub struct ConnectionStateTracker {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ConnectionStateTracker {
+    pub fn new(state: ConnectionState) -> Self {
+        ConnectionStateTracker {
+            inner: Arc::new(Mutex::new(Inner {
+                state,
+                listeners: Vec::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("Failed to get lock!")
+    }
+
+    ///Current state
+    pub fn get(&self) -> ConnectionState {
+        self.lock().state
+    }
+
+    ///Go to the next state and notify listeners.
+    ///Returns false and keeps the current state if the transition is not allowed.
+    pub fn set(&self, next: ConnectionState) -> bool {
+        let mut inner = self.lock();
+        if !inner.state.can_transition(next) {
+            return false;
+        }
+        inner.state = next;
+        //Listeners with a dropped receiver are removed
+        inner.listeners.retain(|tx| tx.send(next).is_ok());
+        true
+    }
+
+    ///Register a listener for state changes
+    pub fn subscribe(&self) -> Receiver<ConnectionState> {
+        let (tx, rx) = channel::<ConnectionState>();
+        self.lock().listeners.push(tx);
+        rx
+    }
+}
+
+impl std::fmt::Debug for ConnectionStateTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionStateTracker")
+            .field("state", &self.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConnectionState::*;
+
+    #[test]
+    fn can_transition_test() {
+        assert!(Disconnected.can_transition(Connecting));
+        assert!(Connecting.can_transition(Connected));
+        assert!(Connected.can_transition(Disconnected));
+        assert!(Disconnected.can_transition(Reconnecting));
+        assert!(Reconnecting.can_transition(Connected));
+        assert!(Connected.can_transition(Closed));
+        assert!(Closed.can_transition(Connecting));
+
+        assert!(!Closed.can_transition(Closed));
+        assert!(!Closed.can_transition(Disconnected));
+        assert!(!Connected.can_transition(Connecting));
+        assert!(!Connected.can_transition(Reconnecting));
+        assert!(!Disconnected.can_transition(Connected));
+    }
+
+    #[test]
+    fn tracker_events_test() {
+        let tracker = ConnectionStateTracker::new(Disconnected);
+        let rx = tracker.subscribe();
+
+        assert!(tracker.set(Connecting));
+        assert!(tracker.set(Connected));
+        assert!(!tracker.set(Reconnecting));
+        assert!(tracker.set(Closed));
+
+        assert_eq!(tracker.get(), Closed);
+        let events: Vec<ConnectionState> = rx.try_iter().collect();
+        assert_eq!(events, vec![Connecting, Connected, Closed]);
+    }
+
+    #[test]
+    fn tracker_dropped_listener_test() {
+        let tracker = ConnectionStateTracker::new(Disconnected);
+        drop(tracker.subscribe());
+        assert!(tracker.set(Connecting));
+        assert_eq!(tracker.lock().listeners.len(), 0);
+    }
+}
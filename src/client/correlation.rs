@@ -0,0 +1,169 @@
+use crate::error::ProtocolError;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::command_id::CommandID;
+use crate::proto::frame_view::AmsFrameView;
+
+///Request which waits for its response. Used to validate the response in strict mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingRequest {
+    pub command_id: CommandID,
+    ///Address the request was sent to
+    pub target: AmsAddress,
+    ///Address the request was sent from
+    pub source: AmsAddress,
+}
+
+impl PendingRequest {
+    pub fn new(command_id: CommandID, target: AmsAddress, source: AmsAddress) -> Self {
+        PendingRequest {
+            command_id,
+            target,
+            source,
+        }
+    }
+
+    ///Check if the frame is the response to this request.
+    ///Target and source address are swapped in the response.
+    pub fn validate(&self, frame: &AmsFrameView) -> Result<(), ProtocolError> {
+        let invoke_id = frame.invoke_id();
+        if !frame.state_flags().is_response() {
+            return Err(ProtocolError::NotAResponse { invoke_id });
+        }
+
+        if frame.command_id() != self.command_id {
+            return Err(ProtocolError::CommandMismatch {
+                invoke_id,
+                expected: self.command_id,
+                received: frame.command_id(),
+            });
+        }
+
+        if frame.source_address() != &self.target {
+            return Err(ProtocolError::SourceMismatch {
+                invoke_id,
+                expected: self.target.clone(),
+                received: frame.source_address().clone(),
+            });
+        }
+
+        if frame.target_address() != &self.source {
+            return Err(ProtocolError::TargetMismatch {
+                invoke_id,
+                expected: self.source.clone(),
+                received: frame.target_address().clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+///Check a frame which is not an answer to a request (device notification)
+pub fn validate_unsolicited(
+    source: &AmsAddress,
+    frame: &AmsFrameView,
+) -> Result<(), ProtocolError> {
+    if frame.target_address() != source {
+        return Err(ProtocolError::TargetMismatch {
+            invoke_id: frame.invoke_id(),
+            expected: source.clone(),
+            received: frame.target_address().clone(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ams_address::AmsNetId;
+    use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+    use crate::proto::proto_traits::WriteTo;
+    use crate::proto::request::{ReadRequest, Request};
+    use crate::proto::state_flags::StateFlags;
+
+    fn device() -> AmsAddress {
+        AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851)
+    }
+
+    fn client() -> AmsAddress {
+        AmsAddress::new(AmsNetId::new(192, 168, 1, 2, 1, 1), 30000)
+    }
+
+    fn frame(target: AmsAddress, source: AmsAddress, state_flags: StateFlags) -> AmsFrameView {
+        let ams_header = AmsHeader::new(
+            target,
+            source,
+            state_flags,
+            7,
+            Request::Read(ReadRequest::new(259, 259, 4)),
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        AmsTcpHeader::from(ams_header).write_to(&mut buf).unwrap();
+        AmsFrameView::parse(buf.into()).unwrap()
+    }
+
+    #[test]
+    fn validate_response_test() {
+        let pending = PendingRequest::new(CommandID::Read, device(), client());
+        let response = frame(client(), device(), StateFlags::resp_default());
+        assert_eq!(pending.validate(&response), Ok(()));
+    }
+
+    #[test]
+    fn validate_not_a_response_test() {
+        let pending = PendingRequest::new(CommandID::Read, device(), client());
+        let request = frame(client(), device(), StateFlags::req_default());
+        assert_eq!(
+            pending.validate(&request),
+            Err(ProtocolError::NotAResponse { invoke_id: 7 })
+        );
+    }
+
+    #[test]
+    fn validate_command_mismatch_test() {
+        let pending = PendingRequest::new(CommandID::Write, device(), client());
+        let response = frame(client(), device(), StateFlags::resp_default());
+        assert_eq!(
+            pending.validate(&response),
+            Err(ProtocolError::CommandMismatch {
+                invoke_id: 7,
+                expected: CommandID::Write,
+                received: CommandID::Read
+            })
+        );
+    }
+
+    #[test]
+    fn validate_address_mismatch_test() {
+        let pending = PendingRequest::new(CommandID::Read, device(), client());
+        let other = AmsAddress::new(AmsNetId::new(192, 168, 1, 3, 1, 1), 851);
+
+        let response = frame(client(), other.clone(), StateFlags::resp_default());
+        assert_eq!(
+            pending.validate(&response),
+            Err(ProtocolError::SourceMismatch {
+                invoke_id: 7,
+                expected: device(),
+                received: other.clone()
+            })
+        );
+
+        let response = frame(other.clone(), device(), StateFlags::resp_default());
+        assert_eq!(
+            pending.validate(&response),
+            Err(ProtocolError::TargetMismatch {
+                invoke_id: 7,
+                expected: client(),
+                received: other
+            })
+        );
+    }
+
+    #[test]
+    fn validate_unsolicited_test() {
+        let notification = frame(client(), device(), StateFlags::req_default());
+        assert_eq!(validate_unsolicited(&client(), &notification), Ok(()));
+        assert!(validate_unsolicited(&device(), &notification).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::client::ads_client::{PendingRequests, ProtocolErrorListeners, ResponseChannels};
+use crate::client::connection_state::{ConnectionState, ConnectionStateTracker};
+use crate::client::correlation::validate_unsolicited;
+use crate::client::notification::{NotificationResult, QueueSender};
+use crate::client::read::{AdsReader, ClientResult, TcpFrameView};
+use crate::error::{AdsError, ClientError, ProtocolError};
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::command_id::CommandID;
+use crate::proto::frame_view::AmsFrameView;
+use crate::proto::response::Response;
+use crate::proto::router_command::RouterFrame;
+
+///Remote address and handle of a device notification
+pub(crate) type NotificationKey = (AmsAddress, u32);
+pub(crate) type NotificationChannels =
+    Arc<Mutex<HashMap<NotificationKey, QueueSender<NotificationResult>>>>;
+///Queues of notifications which are requested but not yet confirmed. Key is the invoke id.
+pub(crate) type PendingNotifications = Arc<Mutex<HashMap<u32, QueueSender<NotificationResult>>>>;
+
+///Decode the data of a response frame.
+///Fails with ClientError::Decode if the data doesn't match the command.
+pub(crate) fn decode_response(frame: &AmsFrameView) -> ClientResult<Response> {
+    frame.response().map_err(|e| {
+        log::warn!("Failed to decode response {:?}. {}", frame.invoke_id(), e);
+        ClientError::Decode(e.to_string())
+    })
+}
+
+///Hands received frames of one local AMS address to the requests waiting for them
+///and to the notification queues. Used by the reader thread of a Connection and
+///by the reader thread of a router connection, which has one dispatcher per local port.
+#[derive(Debug, Clone)]
+pub(crate) struct Dispatcher {
+    pub(crate) responses: ResponseChannels,
+    pub(crate) pending_requests: PendingRequests,
+    pub(crate) notifications: NotificationChannels,
+    pub(crate) pending_notifications: PendingNotifications,
+    pub(crate) state: ConnectionStateTracker,
+    pub(crate) strict: Arc<AtomicBool>,
+    pub(crate) listeners: ProtocolErrorListeners,
+    ///Local address the frames are sent to
+    pub(crate) source: AmsAddress,
+    ///Receives the frames of the local router while a router request waits
+    pub(crate) router_responses: Arc<Mutex<Option<Sender<RouterFrame>>>>,
+}
+
+impl Dispatcher {
+    pub(crate) fn new(source: AmsAddress, state: ConnectionStateTracker) -> Self {
+        Dispatcher {
+            responses: Arc::new(Mutex::new(HashMap::new())),
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            notifications: Arc::new(Mutex::new(HashMap::new())),
+            pending_notifications: Arc::new(Mutex::new(HashMap::new())),
+            state,
+            strict: Arc::new(AtomicBool::new(false)),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+            source,
+            router_responses: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    ///Read frames until the link is lost or the thread is cancelled.
+    ///A lost link sets the state to Disconnected and fails everything pending.
+    pub(crate) fn run(&self, mut reader: AdsReader, rx_thread_cancel: Receiver<bool>) {
+        let mut cancel: bool = false;
+        while !cancel {
+            match reader.read_frame_view() {
+                Ok(TcpFrameView::Ams(frame)) => self.dispatch(frame),
+                Ok(TcpFrameView::Router(frame)) => self.dispatch_router(frame),
+                Err(ClientError::Timeout) => (),
+                Err(ClientError::Disconnected(e)) => {
+                    log::warn!("Connection lost. {}", e);
+                    break;
+                }
+                Err(e) => {
+                    log::warn!("Invalid frame received. {}", e);
+                    self.fail_notifications(AdsError::AdsErrClientW32Error);
+                }
+            }
+
+            if let Ok(c) = rx_thread_cancel.try_recv() {
+                cancel = c;
+            }
+        }
+
+        if !cancel {
+            //Link is gone. Nothing will be answered anymore.
+            self.disconnect();
+        }
+        log::debug!("Thread is canceled");
+    }
+
+    ///Hand a received frame to the request or notifications it belongs to
+    pub(crate) fn dispatch(&self, frame: AmsFrameView) {
+        if !self.check_frame(&frame) {
+            return;
+        }
+        if frame.command_id() == CommandID::DeviceNotification {
+            self.dispatch_notification(&frame);
+        } else {
+            self.dispatch_response(frame);
+        }
+    }
+
+    ///Send a frame of the local router to the request waiting for it
+    fn dispatch_router(&self, frame: RouterFrame) {
+        match self.router_responses.lock() {
+            Ok(c) => match c.as_ref() {
+                Some(sender) => {
+                    sender.send(frame);
+                }
+                None => log::debug!("Router frame received {:?}", frame),
+            },
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+
+    ///Fail the request waiting for the invoke id with ClientError::Timeout.
+    ///Called by transports which give up on a request, e.g. UDP after the last retry.
+    pub(crate) fn expire(&self, invoke_id: u32) {
+        match self.pending_requests.lock() {
+            Ok(mut c) => c.remove(&invoke_id),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let sender = match self.responses.lock() {
+            Ok(mut c) => c.remove(&invoke_id),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        if let Some(sender) = sender {
+            sender.send(Err(ClientError::Timeout));
+        }
+    }
+
+    ///Set the state to Disconnected and fail everything pending
+    pub(crate) fn disconnect(&self) {
+        self.state.set(ConnectionState::Disconnected);
+        self.fail_pending();
+    }
+
+    ///Answer all waiting requests and notification subscriptions with AdsError::ErrPortNotConnected.
+    ///The notification queues are closed afterwards.
+    pub(crate) fn fail_pending(&self) {
+        let senders: Vec<_> = match self.responses.lock() {
+            Ok(mut c) => c.drain().map(|(_, sender)| sender).collect(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        for sender in senders {
+            sender.send(Err(AdsError::ErrPortNotConnected.into()));
+        }
+        match self.pending_requests.lock() {
+            Ok(mut c) => c.clear(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        let senders: Vec<QueueSender<NotificationResult>> = match self.notifications.lock() {
+            Ok(mut c) => c.drain().map(|(_, sender)| sender).collect(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        for sender in senders {
+            sender.send(Err(AdsError::ErrPortNotConnected));
+        }
+        match self.pending_notifications.lock() {
+            Ok(mut c) => c.clear(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+
+    ///Send an error to all notification queues. The queues stay registered.
+    fn fail_notifications(&self, error: AdsError) {
+        //Clone the senders so a blocking queue does not hold the lock
+        let senders: Vec<QueueSender<NotificationResult>> = match self.notifications.lock() {
+            Ok(c) => c.values().cloned().collect(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        for sender in senders {
+            sender.send(Err(error.clone()));
+        }
+    }
+
+    ///Match a received frame with its pending request.
+    ///Returns false if the frame is invalid in strict mode. Invalid frames are only reported to the listeners.
+    pub(crate) fn check_frame(&self, frame: &AmsFrameView) -> bool {
+        let strict = self.strict.load(Ordering::Relaxed);
+        let result = if frame.command_id() == CommandID::DeviceNotification {
+            validate_unsolicited(&self.source, frame)
+        } else {
+            let mut pending = match self.pending_requests.lock() {
+                Ok(c) => c,
+                Err(_) => panic!("Failed to get lock!"),
+            };
+            match pending.remove(&frame.invoke_id()) {
+                Some(request) => {
+                    let result = request.validate(frame);
+                    if result.is_err() && strict {
+                        //Misrouted frame. The request keeps waiting for its real response.
+                        pending.insert(frame.invoke_id(), request);
+                    }
+                    result
+                }
+                None => Err(ProtocolError::UnexpectedInvokeId {
+                    invoke_id: frame.invoke_id(),
+                }),
+            }
+        };
+
+        if !strict {
+            return true;
+        }
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("Dropped frame. {}", e);
+                match self.listeners.lock() {
+                    Ok(mut c) => c.retain(|tx| tx.send(e.clone()).is_ok()),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+                false
+            }
+        }
+    }
+
+    ///Send the samples of a device notification to the queues of their handles.
+    ///The samples reference the received frame. Invalid notifications are logged and dropped.
+    fn dispatch_notification(&self, frame: &AmsFrameView) {
+        let stream = match frame.notification() {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Invalid notification. {}", e);
+                return;
+            }
+        };
+
+        //A frame can carry samples of several notifications.
+        //Clone the senders so a blocking queue does not hold the lock
+        let remote = frame.source_address().clone();
+        let senders: Vec<(u32, QueueSender<NotificationResult>)> = match self.notifications.lock() {
+            Ok(c) => stream
+                .handles()
+                .into_iter()
+                .filter_map(|h| c.get(&(remote.clone(), h)).map(|s| (h, s.clone())))
+                .collect(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        //Each queue only gets the samples of its own handle
+        for (handle, sender) in &senders {
+            if frame.ads_error() == &AdsError::ErrNoError {
+                sender.send(Ok(stream.for_handle(*handle)));
+            } else {
+                sender.send(Err(frame.ads_error().clone()));
+            }
+        }
+        if senders.is_empty() {
+            log::debug!(
+                "No notification of {:?} found for {:?}",
+                remote,
+                stream.handles()
+            )
+        }
+    }
+
+    ///Send a response to the request waiting for its invoke id.
+    ///The receiver decodes the data, see decode_response.
+    fn dispatch_response(&self, frame: AmsFrameView) {
+        let sender = match self.responses.lock() {
+            Ok(mut c) => c.remove(&frame.invoke_id()),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let sender = match sender {
+            Some(s) => s,
+            None => {
+                log::debug!(
+                    "No sender for invoke id {:?} found ....{:?}...",
+                    &frame.invoke_id(),
+                    &frame.command_id()
+                );
+                return;
+            }
+        };
+
+        if frame.ads_error() != &AdsError::ErrNoError {
+            sender.send(Err(frame.ads_error().clone().into()));
+            return;
+        }
+        if frame.command_id() == CommandID::AddDeviceNotification {
+            if let Ok(response) = frame.response() {
+                let remote = frame.source_address().clone();
+                self.register_notification(frame.invoke_id(), remote, &response);
+            }
+        }
+        sender.send(Ok(frame));
+    }
+
+    ///Move the queue of a confirmed notification request to the notification channels.
+    ///Done by the reader thread so no sample sent right after the confirmation gets lost.
+    fn register_notification(&self, invoke_id: u32, remote: AmsAddress, response: &Response) {
+        let sender = match self.pending_notifications.lock() {
+            Ok(mut c) => c.remove(&invoke_id),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        if let (Some(sender), Response::AddDeviceNotification(r)) = (sender, response) {
+            if r.result == AdsError::ErrNoError {
+                match self.notifications.lock() {
+                    Ok(mut c) => c.insert((remote, r.notification_handle), sender),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+            }
+        }
+    }
+}
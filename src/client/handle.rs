@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::ads_services::system_services::*;
+use crate::client::ads_client::{PendingRequests, ResponseChannels, SharedLink};
+use crate::client::connection_state::{ConnectionState, ConnectionStateTracker};
+use crate::client::correlation::PendingRequest;
+use crate::client::dispatch::decode_response;
+use crate::client::plc_types::Var;
+use crate::client::read::ClientResult;
+use crate::error::{AdsError, ClientError, ConnectionError};
+use crate::proto::ads_state::AdsState;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::frame_view::AmsFrameView;
+use crate::proto::request::*;
+use crate::proto::response::*;
+use crate::proto::state_flags::StateFlags;
+
+///Invoke ids of handles start here so they don't collide with the ids passed to Connection
+pub const HANDLE_INVOKE_ID_START: u32 = 0x8000_0000;
+///Default time to wait for a response
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+///Symbol handles by target address and variable name
+type SymHandleCache = Arc<Mutex<HashMap<(AmsAddress, String), u32>>>;
+
+///Cloneable handle of a Connection. Can be sent to other threads.
+///Requests of all clones are sent over the TCP connection of the Connection
+///without waiting for outstanding responses. The reader thread of the Connection
+///assigns the responses by invoke id, which the handle allocates itself.
+///Each handle sends to one target address. Use port or with_target for a view
+///on another port or device behind the same router.
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    target: AmsAddress,
+    ///Overrides the source address of the link (virtual port of a local router)
+    source: Option<AmsAddress>,
+    link: SharedLink,
+    responses: ResponseChannels,
+    pending_requests: PendingRequests,
+    state: ConnectionStateTracker,
+    invoke_ids: Arc<AtomicU32>,
+    sym_handles: SymHandleCache,
+    timeout: Duration,
+}
+
+impl ClientHandle {
+    pub(crate) fn new(
+        target: AmsAddress,
+        link: SharedLink,
+        responses: ResponseChannels,
+        pending_requests: PendingRequests,
+        state: ConnectionStateTracker,
+    ) -> Self {
+        ClientHandle {
+            target,
+            source: None,
+            link,
+            responses,
+            pending_requests,
+            state,
+            invoke_ids: Arc::new(AtomicU32::new(0)),
+            sym_handles: Arc::new(Mutex::new(HashMap::new())),
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+
+    ///Time to wait for a response before a request fails with ClientError::Timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    ///Target address of the requests
+    pub fn target(&self) -> &AmsAddress {
+        &self.target
+    }
+
+    ///View on another AMS port of the same device (e.g. 851 PLC, 500 NC, 10000 system service).
+    ///The view shares the TCP connection with this handle.
+    pub fn port(&self, port: u16) -> ClientHandle {
+        let mut target = self.target.clone();
+        target.port = port;
+        self.with_target(target)
+    }
+
+    ///View on another target address. The view shares the TCP connection with this handle.
+    pub fn with_target(&self, target: AmsAddress) -> ClientHandle {
+        ClientHandle {
+            target,
+            ..self.clone()
+        }
+    }
+
+    ///View which sends from another source address. Used by the ports of a local router.
+    pub(crate) fn with_source(&self, source: AmsAddress) -> ClientHandle {
+        ClientHandle {
+            source: Some(source),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.get() == ConnectionState::Connected
+    }
+
+    pub(crate) fn next_invoke_id(&self) -> u32 {
+        HANDLE_INVOKE_ID_START
+            | (self.invoke_ids.fetch_add(1, Ordering::Relaxed) & !HANDLE_INVOKE_ID_START)
+    }
+
+    ///Send a request and wait for its response.
+    ///Other threads can send requests while this one waits.
+    pub fn request(&self, request: Request) -> ClientResult<Response> {
+        self.request_with_id(request, self.next_invoke_id())
+    }
+
+    ///Send a request with an invoke id taken from next_invoke_id and wait for its response
+    pub(crate) fn request_with_id(
+        &self,
+        request: Request,
+        invoke_id: u32,
+    ) -> ClientResult<Response> {
+        if !self.is_connected() {
+            return Err(AdsError::ErrPortNotConnected.into());
+        }
+
+        let (tx, rx) = channel::<ClientResult<AmsFrameView>>();
+        match self.responses.lock() {
+            Ok(mut c) => c.insert(invoke_id, tx),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        let result = self
+            .send(request, invoke_id, StateFlags::req_default())
+            .and_then(|_| self.wait_for_response(&rx));
+
+        if result.is_err() {
+            //Nobody waits for a late response
+            match self.responses.lock() {
+                Ok(mut c) => c.remove(&invoke_id),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+            match self.pending_requests.lock() {
+                Ok(mut c) => c.remove(&invoke_id),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+        result
+    }
+
+    fn wait_for_response(
+        &self,
+        rx: &Receiver<ClientResult<AmsFrameView>>,
+    ) -> ClientResult<Response> {
+        match rx.recv_timeout(self.timeout) {
+            Ok(frame) => decode_response(&frame?),
+            Err(RecvTimeoutError::Timeout) => Err(ClientError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(ConnectionError::ResponseChannelClosed.into())
+            }
+        }
+    }
+
+    ///Requests with the no return flag are not registered for a response
+    fn send(&self, request: Request, invoke_id: u32, state_flags: StateFlags) -> ClientResult<()> {
+        let mut link = match self.link.lock() {
+            Ok(l) => l,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let link = match link.as_mut() {
+            Some(l) => l,
+            None => return Err(AdsError::ErrPortNotConnected.into()),
+        };
+
+        let source = match &self.source {
+            Some(source) => source.clone(),
+            None => link.source.clone(),
+        };
+        if !state_flags.is_no_return() {
+            match self.pending_requests.lock() {
+                Ok(mut c) => c.insert(
+                    invoke_id,
+                    PendingRequest::new(request.command_id(), self.target.clone(), source.clone()),
+                ),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+
+        let result = link.send(&self.target, &source, state_flags, invoke_id, &request);
+        if let Err(ClientError::Disconnected(_)) = result {
+            self.state.set(ConnectionState::Disconnected);
+        }
+        result.map(|_| ())
+    }
+
+    pub fn read(&self, index_group: u32, index_offset: u32, length: u32) -> ClientResult<Vec<u8>> {
+        let request = Request::Read(ReadRequest::new(index_group, index_offset, length));
+        let response: ReadResponse = self.request(request)?.try_into()?;
+        check_ads_error(&response.result)?;
+        Ok(response.data)
+    }
+
+    pub fn write(&self, index_group: u32, index_offset: u32, data: Vec<u8>) -> ClientResult<()> {
+        let request = Request::Write(WriteRequest::new(index_group, index_offset, data));
+        let response: WriteResponse = self.request(request)?.try_into()?;
+        check_ads_error(&response.result)
+    }
+
+    ///Write without waiting for a response (fire and forget).
+    ///The device does not answer, so errors of the write are not reported.
+    pub fn write_no_return(
+        &self,
+        index_group: u32,
+        index_offset: u32,
+        data: Vec<u8>,
+    ) -> ClientResult<()> {
+        if !self.is_connected() {
+            return Err(AdsError::ErrPortNotConnected.into());
+        }
+        let request = Request::Write(WriteRequest::new(index_group, index_offset, data));
+        let mut state_flags = StateFlags::req_default();
+        state_flags.set_no_return(true);
+        self.send(request, self.next_invoke_id(), state_flags)
+    }
+
+    pub fn read_write(
+        &self,
+        index_group: u32,
+        index_offset: u32,
+        read_length: u32,
+        data: Vec<u8>,
+    ) -> ClientResult<Vec<u8>> {
+        let request = Request::ReadWrite(ReadWriteRequest::new(
+            index_group,
+            index_offset,
+            read_length,
+            data,
+        ));
+        let response: ReadWriteResponse = self.request(request)?.try_into()?;
+        check_ads_error(&response.result)?;
+        Ok(response.data)
+    }
+
+    pub fn read_device_info(&self) -> ClientResult<ReadDeviceInfoResponse> {
+        let request = Request::ReadDeviceInfo(ReadDeviceInfoRequest::new());
+        let response: ReadDeviceInfoResponse = self.request(request)?.try_into()?;
+        check_ads_error(&response.result)?;
+        Ok(response)
+    }
+
+    pub fn read_state(&self) -> ClientResult<ReadStateResponse> {
+        let request = Request::ReadState(ReadStateRequest::new());
+        let response: ReadStateResponse = self.request(request)?.try_into()?;
+        check_ads_error(&response.result)?;
+        Ok(response)
+    }
+
+    pub fn write_control(&self, ads_state: AdsState, device_state: u16) -> ClientResult<()> {
+        let request = Request::WriteControl(WriteControlRequest::new(
+            ads_state,
+            device_state,
+            0,
+            Vec::new(),
+        ));
+        let response: WriteControlResponse = self.request(request)?.try_into()?;
+        check_ads_error(&response.result)
+    }
+
+    ///Request a handle for a variable. Handles are cached and shared by all clones.
+    pub fn get_symhandle(&self, var: &Var) -> ClientResult<u32> {
+        if let Some(handle) = self.cached_handle(&var.name) {
+            return Ok(handle);
+        }
+
+        let data = self.read_write(
+            GET_SYMHANDLE_BY_NAME.index_group,
+            GET_SYMHANDLE_BY_NAME.index_offset_start,
+            4, //allways u32 for get_symhandle
+            var.name.as_bytes().to_vec(),
+        )?;
+        if data.len() < 4 {
+            return Err(ClientError::Decode(format!(
+                "Expected a 4 byte handle, got {} bytes",
+                data.len()
+            )));
+        }
+        let handle = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        match self.sym_handles.lock() {
+            Ok(mut c) => c.insert((self.target.clone(), var.name.clone()), handle),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        Ok(handle)
+    }
+
+    fn cached_handle(&self, name: &str) -> Option<u32> {
+        match self.sym_handles.lock() {
+            Ok(c) => c.get(&(self.target.clone(), name.to_string())).copied(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Read a variable by its handle. The handle is requested if it is not cached.
+    ///A handle invalidated by an online change is requested again once.
+    pub fn read_by_name(&self, var: &Var) -> ClientResult<Vec<u8>> {
+        self.retry_on_symbol_version(var, |handle| {
+            self.read(
+                READ_WRITE_SYMVAL_BY_HANDLE.index_group,
+                handle,
+                var.plc_type.size() as u32,
+            )
+        })
+    }
+
+    ///Write a variable by its handle. The handle is requested if it is not cached.
+    ///A handle invalidated by an online change is requested again once.
+    pub fn write_by_name(&self, var: &Var, data: Vec<u8>) -> ClientResult<()> {
+        self.retry_on_symbol_version(var, |handle| {
+            self.write(
+                READ_WRITE_SYMVAL_BY_HANDLE.index_group,
+                handle,
+                data.clone(),
+            )
+        })
+    }
+
+    ///Write a variable by its handle without waiting for a response.
+    ///The handle is requested if it is not cached.
+    pub fn write_by_name_no_return(&self, var: &Var, data: Vec<u8>) -> ClientResult<()> {
+        let handle = self.get_symhandle(var)?;
+        self.write_no_return(READ_WRITE_SYMVAL_BY_HANDLE.index_group, handle, data)
+    }
+
+    fn retry_on_symbol_version<T, F>(&self, var: &Var, op: F) -> ClientResult<T>
+    where
+        F: Fn(u32) -> ClientResult<T>,
+    {
+        match op(self.get_symhandle(var)?) {
+            Err(e) if e.ads_error() == Some(&AdsError::AdsErrDeviceSymbolVersionInvalid) => {
+                //Only handles of this target are invalid
+                match self.sym_handles.lock() {
+                    Ok(mut c) => c.retain(|(target, _), _| target != &self.target),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+                op(self.get_symhandle(var)?)
+            }
+            result => result,
+        }
+    }
+}
+
+fn check_ads_error(ads_error: &AdsError) -> ClientResult<()> {
+    if ads_error != &AdsError::ErrNoError {
+        return Err(ads_error.clone().into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ams_address::AmsNetId;
+
+    fn handle() -> ClientHandle {
+        ClientHandle::new(
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851),
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(HashMap::new())),
+            ConnectionStateTracker::new(ConnectionState::Disconnected),
+        )
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn handle_is_send_sync_test() {
+        assert_send_sync::<ClientHandle>();
+    }
+
+    #[test]
+    fn invoke_id_test() {
+        let handle = handle();
+        let clone = handle.clone();
+        assert_eq!(handle.next_invoke_id(), HANDLE_INVOKE_ID_START);
+        assert_eq!(clone.next_invoke_id(), HANDLE_INVOKE_ID_START + 1);
+
+        handle
+            .invoke_ids
+            .store(!HANDLE_INVOKE_ID_START, Ordering::Relaxed);
+        assert_eq!(handle.next_invoke_id(), u32::MAX);
+        assert_eq!(handle.next_invoke_id(), HANDLE_INVOKE_ID_START);
+    }
+
+    #[test]
+    fn port_view_test() {
+        let handle = handle();
+        let nc = handle.port(500);
+        assert_eq!(nc.target().port, 500);
+        assert_eq!(nc.target().ams_net_id, handle.target().ams_net_id);
+        assert_eq!(handle.target().port, 851);
+
+        //Views share invoke ids and the symbol handle cache
+        assert_eq!(handle.next_invoke_id(), HANDLE_INVOKE_ID_START);
+        assert_eq!(nc.next_invoke_id(), HANDLE_INVOKE_ID_START + 1);
+        match handle.sym_handles.lock() {
+            Ok(mut c) => c.insert((handle.target().clone(), "MAIN.x".to_string()), 7),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        assert_eq!(handle.cached_handle("MAIN.x"), Some(7));
+        assert_eq!(nc.cached_handle("MAIN.x"), None);
+    }
+
+    #[test]
+    fn request_not_connected_test() {
+        let error = handle().read_state().unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrPortNotConnected));
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::client::notification::{
+    queue, NotificationResult, QueueConfig, QueueSender, Subscription,
+};
+use crate::client::plc_types::Var;
+use crate::client::read::ClientResult;
+use crate::client::traits::AdsClient;
+use crate::error::{AdsError, ClientError};
+use crate::proto::ads_state::AdsState;
+use crate::proto::ads_transition_mode::AdsTransMode;
+use crate::proto::frame_view::{NotificationSampleView, NotificationStreamView, StampHeaderView};
+use crate::proto::response::{ReadDeviceInfoResponse, ReadStateResponse};
+use crate::server::notification::filetime_now;
+use bytes::Bytes;
+
+struct MemoryState {
+    values: HashMap<String, Vec<u8>>,
+    handles: HashMap<String, u32>,
+    next_handle: u32,
+    ///Notification handle and queue by variable name
+    notifications: HashMap<String, (u32, QueueSender<NotificationResult>)>,
+    ads_state: AdsState,
+    device_state: u16,
+}
+
+impl MemoryState {
+    fn handle(&self, name: &str) -> ClientResult<u32> {
+        match self.handles.get(name) {
+            Some(handle) => Ok(*handle),
+            None => Err(ClientError::MissingHandle(name.to_string())),
+        }
+    }
+
+    fn get_symhandle(&mut self, name: &str) -> ClientResult<u32> {
+        if let Some(handle) = self.handles.get(name) {
+            return Ok(*handle);
+        }
+        if !self.values.contains_key(name) {
+            return Err(AdsError::AdsErrDeviceSymbolNotFound.into());
+        }
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(name.to_string(), handle);
+        Ok(handle)
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>, AdsError> {
+        match self.values.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(AdsError::AdsErrDeviceSymbolNotFound),
+        }
+    }
+
+    ///Values keep their size
+    fn write(&mut self, name: &str, data: &[u8]) -> Result<(), AdsError> {
+        match self.values.get_mut(name) {
+            Some(value) if value.len() != data.len() => Err(AdsError::AdsErrDeviceInvalidSize),
+            Some(value) => {
+                let changed = value.as_slice() != data;
+                value.copy_from_slice(data);
+                if changed {
+                    self.notify(name);
+                }
+                Ok(())
+            }
+            None => Err(AdsError::AdsErrDeviceSymbolNotFound),
+        }
+    }
+
+    fn notify(&self, name: &str) {
+        if let (Some((handle, sender)), Some(value)) =
+            (self.notifications.get(name), self.values.get(name))
+        {
+            let sample = NotificationSampleView {
+                notification_handle: *handle,
+                data: Bytes::from(value.clone()),
+            };
+            sender.send(Ok(NotificationStreamView {
+                stamps: vec![StampHeaderView {
+                    time_stamp: filetime_now(),
+                    samples: vec![sample],
+                }],
+            }));
+        }
+    }
+}
+
+///AdsClient which keeps the variables in memory. Used to test application code without a device.
+///Variables have to be inserted before they can be accessed. Names are case sensitive.
+///Notifications deliver the current value and every change regardless of the transmission mode.
+///Clones share the variables, so a test can inspect and change them while the code under test
+///uses the client.
+#[derive(Clone)]
+pub struct MemoryClient {
+    inner: Arc<Mutex<MemoryState>>,
+}
+
+impl Default for MemoryClient {
+    fn default() -> Self {
+        MemoryClient::new()
+    }
+}
+
+impl MemoryClient {
+    pub fn new() -> Self {
+        MemoryClient {
+            inner: Arc::new(Mutex::new(MemoryState {
+                values: HashMap::new(),
+                handles: HashMap::new(),
+                next_handle: 1,
+                notifications: HashMap::new(),
+                ads_state: AdsState::AdsStateRun,
+                device_state: 0,
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        match self.inner.lock() {
+            Ok(s) => s,
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Add a variable or replace its value. Notifications of the variable are sent.
+    pub fn set_value(&self, name: &str, data: Vec<u8>) {
+        let mut state = self.state();
+        state.values.insert(name.to_string(), data);
+        state.notify(name);
+    }
+
+    ///Remove a variable. Further accesses fail with AdsErrDeviceSymbolNotFound.
+    pub fn remove(&self, name: &str) -> Option<Vec<u8>> {
+        self.state().values.remove(name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<Vec<u8>> {
+        self.state().values.get(name).cloned()
+    }
+
+    ///All variables with their value
+    pub fn values(&self) -> HashMap<String, Vec<u8>> {
+        self.state().values.clone()
+    }
+
+    pub fn ads_state(&self) -> AdsState {
+        self.state().ads_state
+    }
+}
+
+impl AdsClient for MemoryClient {
+    fn get_symhandle(&mut self, var: &Var, invoke_id: u32) -> ClientResult<u32> {
+        self.state().get_symhandle(&var.name)
+    }
+
+    ///Variables which do not exist get no handle
+    fn sumup_get_symhandle(&mut self, var_list: &[Var], invoke_id: u32) -> ClientResult<bool> {
+        let mut state = self.state();
+        for var in var_list {
+            state.get_symhandle(&var.name).ok();
+        }
+        Ok(true)
+    }
+
+    fn read_by_name(&mut self, var: &Var, invoke_id: u32) -> ClientResult<Vec<u8>> {
+        let state = self.state();
+        state.handle(&var.name)?;
+        Ok(state.read(&var.name)?)
+    }
+
+    ///A variable which can not be read has no data
+    fn sumup_read_by_name(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, Vec<u8>>> {
+        let state = self.state();
+        let mut result = HashMap::new();
+        for var in var_list {
+            state.handle(&var.name)?;
+        }
+        for var in var_list {
+            result.insert(var.name.clone(), state.read(&var.name).unwrap_or_default());
+        }
+        Ok(result)
+    }
+
+    fn write_by_name(&mut self, var: &Var, invoke_id: u32, data: Vec<u8>) -> ClientResult<()> {
+        let mut state = self.state();
+        state.handle(&var.name)?;
+        Ok(state.write(&var.name, &data)?)
+    }
+
+    fn sumup_write_by_name(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, AdsError>> {
+        let mut state = self.state();
+        let mut result = HashMap::new();
+        for var in var_list {
+            state.handle(&var.name)?;
+        }
+        for var in var_list {
+            let error = match state.write(&var.name, &var.data) {
+                Ok(()) => AdsError::ErrNoError,
+                Err(e) => e,
+            };
+            result.insert(var.name.clone(), error);
+        }
+        Ok(result)
+    }
+
+    fn read_device_info(&mut self, invoke_id: u32) -> ClientResult<ReadDeviceInfoResponse> {
+        let mut device_name = [0; 16];
+        device_name[..12].copy_from_slice(b"MemoryClient");
+        Ok(ReadDeviceInfoResponse::new(
+            AdsError::ErrNoError,
+            0,
+            1,
+            0,
+            device_name,
+        ))
+    }
+
+    fn read_state(&mut self, invoke_id: u32) -> ClientResult<ReadStateResponse> {
+        let state = self.state();
+        Ok(ReadStateResponse::new(
+            AdsError::ErrNoError,
+            state.ads_state,
+            state.device_state,
+        ))
+    }
+
+    fn write_control(
+        &mut self,
+        new_ads_state: AdsState,
+        device_state: u16,
+        invoke_id: u32,
+    ) -> ClientResult<()> {
+        let mut state = self.state();
+        state.ads_state = new_ads_state;
+        state.device_state = device_state;
+        Ok(())
+    }
+
+    ///The current value is delivered right after subscribing
+    fn add_device_notification(
+        &mut self,
+        var: &Var,
+        trans_mode: AdsTransMode,
+        max_delay: u32,
+        cycle_time: u32,
+        invoke_id: u32,
+    ) -> ClientResult<Subscription> {
+        let mut state = self.state();
+        let handle = state.get_symhandle(&var.name)?;
+        let (tx, rx) = queue::<NotificationResult>(QueueConfig::default());
+        state.notifications.insert(var.name.clone(), (handle, tx));
+        state.notify(&var.name);
+        Ok(rx)
+    }
+
+    fn delete_device_notification(&mut self, var: &Var, invoke_id: u32) -> ClientResult<()> {
+        match self.state().notifications.remove(&var.name) {
+            Some(_) => Ok(()),
+            None => Err(ClientError::MissingHandle(var.name.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::plc_types::PlcTypes;
+    use std::time::Duration;
+
+    fn counter() -> Var {
+        Var::new("MAIN.counter".to_string(), PlcTypes::Int, None)
+    }
+
+    ///Application code which only knows the trait
+    fn increment(client: &mut dyn AdsClient, var: &Var) -> ClientResult<i16> {
+        client.get_symhandle(var, 1)?;
+        let data = client.read_by_name(var, 2)?;
+        let value = i16::from_le_bytes([data[0], data[1]]) + 1;
+        client.write_by_name(var, 3, value.to_le_bytes().to_vec())?;
+        Ok(value)
+    }
+
+    #[test]
+    fn read_write_test() {
+        let client = MemoryClient::new();
+        client.set_value("MAIN.counter", 41i16.to_le_bytes().to_vec());
+
+        assert_eq!(increment(&mut client.clone(), &counter()).unwrap(), 42);
+        assert_eq!(client.value("MAIN.counter").unwrap(), 42i16.to_le_bytes());
+
+        let mut c = client.clone();
+        let error = c.write_by_name(&counter(), 4, vec![1]).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::AdsErrDeviceInvalidSize));
+        let missing = Var::new("MAIN.missing".to_string(), PlcTypes::Int, None);
+        assert!(matches!(
+            c.read_by_name(&missing, 5),
+            Err(ClientError::MissingHandle(_))
+        ));
+        let error = c.get_symhandle(&missing, 6).unwrap_err();
+        assert_eq!(
+            error.ads_error(),
+            Some(&AdsError::AdsErrDeviceSymbolNotFound)
+        );
+    }
+
+    #[test]
+    fn sumup_test() {
+        let mut client = MemoryClient::new();
+        client.set_value("a", vec![1]);
+        client.set_value("b", vec![2, 0]);
+        let vars = vec![
+            Var::new("a".to_string(), PlcTypes::Byte, Some(vec![3])),
+            Var::new("b".to_string(), PlcTypes::Int, Some(vec![4])),
+        ];
+        client.sumup_get_symhandle(&vars, 1).unwrap();
+
+        let values = client.sumup_read_by_name(&vars, 2).unwrap();
+        assert_eq!(values["a"], vec![1]);
+        assert_eq!(values["b"], vec![2, 0]);
+
+        let results = client.sumup_write_by_name(&vars, 3).unwrap();
+        assert_eq!(results["a"], AdsError::ErrNoError);
+        assert_eq!(results["b"], AdsError::AdsErrDeviceInvalidSize);
+        assert_eq!(client.value("a").unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn state_test() {
+        let mut client = MemoryClient::new();
+        client.write_control(AdsState::AdsStateStop, 1, 1).unwrap();
+        let state = client.read_state(2).unwrap();
+        assert_eq!(state.ads_state, AdsState::AdsStateStop);
+        assert_eq!(state.device_state, 1);
+        assert_eq!(
+            client
+                .read_device_info(3)
+                .unwrap()
+                .get_device_name()
+                .unwrap(),
+            "MemoryClient"
+        );
+    }
+
+    #[test]
+    fn notification_test() {
+        let mut client = MemoryClient::new();
+        client.set_value("MAIN.counter", vec![0, 0]);
+        let subscription = client
+            .add_device_notification(&counter(), AdsTransMode::OnChange, 0, 0, 1)
+            .unwrap();
+        let value = || {
+            let stream = subscription
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap()
+                .unwrap();
+            stream.stamps[0].samples[0].data.to_vec()
+        };
+        assert_eq!(value(), vec![0, 0]);
+
+        increment(&mut client.clone(), &counter()).unwrap();
+        assert_eq!(value(), vec![1, 0]);
+        client.set_value("MAIN.counter", vec![5, 0]);
+        assert_eq!(value(), vec![5, 0]);
+
+        client.delete_device_notification(&counter(), 4).unwrap();
+        client.set_value("MAIN.counter", vec![6, 0]);
+        assert!(subscription.try_recv().is_err());
+    }
+}
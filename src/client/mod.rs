@@ -1,3 +1,14 @@
 pub mod ads_client;
+pub mod builder;
+pub mod connection_state;
+pub mod correlation;
+pub(crate) mod dispatch;
+pub mod handle;
+pub mod memory_client;
+pub mod notification;
 pub mod plc_types;
 pub mod read;
+pub mod retry;
+pub mod traits;
+pub mod transport;
+pub mod udp;
@@ -0,0 +1,512 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::error::AdsError;
+use crate::proto::ads_state::AdsState;
+use crate::proto::frame_view::NotificationStreamView;
+use crate::proto::proto_traits::ReadFrom;
+
+///Default number of samples a subscription queue can hold
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+///Samples of a device notification. The data references the received frame.
+pub type NotificationResult = Result<NotificationStreamView, AdsError>;
+///ADS state and device state reported by a device
+pub type StateEvent = (AdsState, u16);
+
+///What happens with a new sample if the subscription queue is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    ///Discard the oldest queued sample to make room for the new one
+    DropOldest,
+    ///Discard the new sample and keep the queued ones
+    DropNewest,
+    ///Block the reader thread until the consumer made room.
+    ///All other responses on the connection are stalled meanwhile!
+    Block,
+    ///Keep only the latest sample. The capacity is ignored.
+    Coalesce,
+}
+
+///Capacity and overflow policy of a subscription queue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl QueueConfig {
+    pub fn new(capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        QueueConfig {
+            capacity,
+            overflow_policy,
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig::new(DEFAULT_QUEUE_CAPACITY, OverflowPolicy::DropOldest)
+    }
+}
+
+struct QueueState<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    config: QueueConfig,
+    state: Mutex<QueueState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    dropped: AtomicU64,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state.lock().expect("Failed to get lock!")
+    }
+}
+
+///Create a bounded queue. The sender is held by the reader thread, the subscription by the consumer.
+pub fn queue<T>(config: QueueConfig) -> (QueueSender<T>, Subscription<T>) {
+    let shared = Arc::new(Shared {
+        config,
+        state: Mutex::new(QueueState {
+            queue: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        dropped: AtomicU64::new(0),
+    });
+    (
+        QueueSender {
+            shared: Arc::clone(&shared),
+        },
+        Subscription { shared },
+    )
+}
+
+///Sending half of a subscription queue
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    ///Push a sample into the queue according to the overflow policy.
+    ///Returns false if the subscription was dropped.
+    pub fn send(&self, item: T) -> bool {
+        let shared = &self.shared;
+        let mut state = shared.lock();
+        if !state.receiver_alive {
+            return false;
+        }
+
+        let capacity = shared.config.capacity.max(1);
+        match shared.config.overflow_policy {
+            OverflowPolicy::DropOldest => {
+                while state.queue.len() >= capacity {
+                    state.queue.pop_front();
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            OverflowPolicy::DropNewest => {
+                if state.queue.len() >= capacity {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+            }
+            OverflowPolicy::Block => {
+                while state.queue.len() >= capacity && state.receiver_alive {
+                    state = shared.not_full.wait(state).expect("Failed to get lock!");
+                }
+                if !state.receiver_alive {
+                    return false;
+                }
+            }
+            OverflowPolicy::Coalesce => {
+                let replaced = state.queue.len() as u64;
+                state.queue.clear();
+                shared.dropped.fetch_add(replaced, Ordering::Relaxed);
+            }
+        }
+
+        state.queue.push_back(item);
+        shared.not_empty.notify_one();
+        true
+    }
+
+    ///Returns true if the consumer still holds the subscription
+    pub fn is_connected(&self) -> bool {
+        self.shared.lock().receiver_alive
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        QueueSender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+///Receiving half of a subscription queue.
+///The receive methods behave like the ones of `std::sync::mpsc::Receiver`.
+pub struct Subscription<T = NotificationResult> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Subscription<T> {
+    ///Blocks until a sample is available.
+    ///Fails if the queue is empty and the sender was dropped (e.g. notification deleted).
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.pop(&mut state) {
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self
+                .shared
+                .not_empty
+                .wait(state)
+                .expect("Failed to get lock!");
+        }
+    }
+
+    ///Returns a sample if available without blocking
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        if let Some(item) = self.pop(&mut state) {
+            return Ok(item);
+        }
+        if state.senders == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    ///Blocks until a sample is available or the timeout elapsed
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.pop(&mut state) {
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .shared
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .expect("Failed to get lock!")
+                .0;
+        }
+    }
+
+    ///Number of samples discarded because of the overflow policy
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    ///Number of samples waiting in the queue
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Queue configuration of this subscription
+    pub fn config(&self) -> QueueConfig {
+        self.shared.config
+    }
+
+    fn pop(&self, state: &mut QueueState<T>) -> Option<T> {
+        let item = state.queue.pop_front();
+        if item.is_some() {
+            self.shared.not_full.notify_one();
+        }
+        item
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.queue.clear();
+        self.shared.not_full.notify_all();
+    }
+}
+
+///Subscription to the ADS state of a device.
+///Every notification sample is decoded into the ADS state and the device state.
+pub struct StateSubscription {
+    handle: u32,
+    subscription: Subscription,
+    pending: RefCell<VecDeque<StateEvent>>,
+}
+
+impl StateSubscription {
+    pub fn new(handle: u32, subscription: Subscription) -> Self {
+        StateSubscription {
+            handle,
+            subscription,
+            pending: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    ///Notification handle of this subscription
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    ///Blocks until the next state change is available
+    pub fn recv(&self) -> Result<Result<StateEvent, AdsError>, RecvError> {
+        loop {
+            if let Some(event) = self.pending.borrow_mut().pop_front() {
+                return Ok(Ok(event));
+            }
+            if let Err(e) = self.decode(self.subscription.recv()?) {
+                return Ok(Err(e));
+            }
+        }
+    }
+
+    ///Blocks until the next state change is available or the timeout elapsed
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Result<StateEvent, AdsError>, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.pending.borrow_mut().pop_front() {
+                return Ok(Ok(event));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Err(e) = self.decode(self.subscription.recv_timeout(remaining)?) {
+                return Ok(Err(e));
+            }
+        }
+    }
+
+    ///Blocks until the device reports ads_state.
+    ///Fails with AdsError::AdsErrClientSyncTimeout if the timeout elapsed.
+    pub fn wait_for(&self, ads_state: AdsState, timeout: Duration) -> Result<(), AdsError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.recv_timeout(remaining) {
+                Ok(Ok((state, _))) if state == ads_state => return Ok(()),
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => return Err(e),
+                Err(RecvTimeoutError::Timeout) => return Err(AdsError::AdsErrClientSyncTimeout),
+                Err(RecvTimeoutError::Disconnected) => return Err(AdsError::ErrPortNotConnected),
+            }
+        }
+    }
+
+    ///Number of samples discarded because of the overflow policy
+    pub fn dropped(&self) -> u64 {
+        self.subscription.dropped()
+    }
+
+    fn decode(&self, notification: NotificationResult) -> Result<(), AdsError> {
+        let stream = notification?;
+        let mut pending = self.pending.borrow_mut();
+        for (_, sample) in stream.samples() {
+            let mut data = sample.data.as_ref();
+            let ads_state =
+                AdsState::read_from(&mut data).map_err(|_| AdsError::AdsErrDeviceInvalidSize)?;
+            //Device state is only available if the notification covers both values
+            let device_state = data.read_u16::<LittleEndian>().unwrap_or(0);
+            pending.push_back((ads_state, device_state));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for StateSubscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateSubscription")
+            .field("handle", &self.handle)
+            .field("subscription", &self.subscription)
+            .finish()
+    }
+}
+
+impl<T> std::fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("config", &self.shared.config)
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+impl<T> std::fmt::Debug for QueueSender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueueSender")
+            .field("config", &self.shared.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::response::{AdsNotificationSample, AdsNotificationStream, AdsStampHeader};
+    use std::thread;
+
+    fn state_stream(states: &[(u16, u16)]) -> NotificationStreamView {
+        let samples: Vec<AdsNotificationSample> = states
+            .iter()
+            .map(|(ads_state, device_state)| {
+                let mut data = ads_state.to_le_bytes().to_vec();
+                data.extend_from_slice(&device_state.to_le_bytes());
+                AdsNotificationSample::new(1, data)
+            })
+            .collect();
+        let header = AdsStampHeader::new(0, samples.len() as u32, samples);
+        let stream = AdsNotificationStream::new(header.stamp_len() as u32 + 4, 1, vec![header]);
+        NotificationStreamView::from(&stream)
+    }
+
+    #[test]
+    fn drop_oldest_test() {
+        let (tx, rx) = queue::<u32>(QueueConfig::new(2, OverflowPolicy::DropOldest));
+        for n in 0..5 {
+            assert!(tx.send(n));
+        }
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(rx.recv(), Ok(3));
+        assert_eq!(rx.recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn drop_newest_test() {
+        let (tx, rx) = queue::<u32>(QueueConfig::new(2, OverflowPolicy::DropNewest));
+        for n in 0..5 {
+            assert!(tx.send(n));
+        }
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn coalesce_test() {
+        let (tx, rx) = queue::<u32>(QueueConfig::new(10, OverflowPolicy::Coalesce));
+        for n in 0..5 {
+            assert!(tx.send(n));
+        }
+        assert_eq!(rx.dropped(), 4);
+        assert_eq!(rx.len(), 1);
+        assert_eq!(rx.recv(), Ok(4));
+    }
+
+    #[test]
+    fn block_test() {
+        let (tx, rx) = queue::<u32>(QueueConfig::new(1, OverflowPolicy::Block));
+        let sender = thread::spawn(move || {
+            for n in 0..3 {
+                assert!(tx.send(n));
+            }
+        });
+        for n in 0..3 {
+            assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(n));
+        }
+        sender.join().unwrap();
+        assert_eq!(rx.dropped(), 0);
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn block_released_on_drop_test() {
+        let (tx, rx) = queue::<u32>(QueueConfig::new(1, OverflowPolicy::Block));
+        assert!(tx.send(0));
+        let sender = thread::spawn(move || tx.send(1));
+        drop(rx);
+        assert!(!sender.join().unwrap());
+    }
+
+    #[test]
+    fn disconnected_test() {
+        let (tx, rx) = queue::<u32>(QueueConfig::default());
+        let tx2 = tx.clone();
+        tx.send(7);
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(7));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(tx2);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn state_subscription_recv_test() {
+        let (tx, rx) = queue::<NotificationResult>(QueueConfig::default());
+        let states = StateSubscription::new(1, rx);
+        tx.send(Ok(state_stream(&[(5, 0), (6, 1)])));
+        tx.send(Err(AdsError::AdsErrClientW32Error));
+
+        assert_eq!(states.recv(), Ok(Ok((AdsState::AdsStateRun, 0))));
+        assert_eq!(states.recv(), Ok(Ok((AdsState::AdsStateStop, 1))));
+        assert_eq!(states.recv(), Ok(Err(AdsError::AdsErrClientW32Error)));
+        drop(tx);
+        assert_eq!(states.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn state_subscription_wait_for_test() {
+        let (tx, rx) = queue::<NotificationResult>(QueueConfig::default());
+        let states = StateSubscription::new(1, rx);
+        tx.send(Ok(state_stream(&[(15, 0)])));
+        tx.send(Ok(state_stream(&[(5, 0)])));
+
+        assert_eq!(
+            states.wait_for(AdsState::AdsStateRun, Duration::from_millis(100)),
+            Ok(())
+        );
+        assert_eq!(
+            states.wait_for(AdsState::AdsStateRun, Duration::from_millis(10)),
+            Err(AdsError::AdsErrClientSyncTimeout)
+        );
+    }
+}
@@ -1,44 +1,548 @@
-use anyhow::anyhow;
-use std::io::{BufReader, Read, Write};
-use std::net::TcpStream;
+use std::io::{self, Read, Write};
 use std::result;
 
 use crate::ads_services::system_services::*;
 use crate::client::plc_types::Var;
-use crate::error::AdsError;
+use crate::client::transport::{ReadHalf, Transport};
+use crate::error::{AdsError, ClientError, ConnectionError, FrameError};
 use crate::proto::ads_state::*;
 use crate::proto::ams_address::{AmsAddress, AmsNetId};
 use crate::proto::ams_header::*;
 use crate::proto::command_id::CommandID;
+use crate::proto::frame_view::AmsFrameView;
 use crate::proto::proto_traits::*;
 use crate::proto::response::*;
+use crate::proto::router_command::{AmsTcpCommand, RouterFrame};
 use crate::proto::state_flags::*;
+use bytes::{Bytes, BytesMut};
 use std::convert::TryInto;
 
 pub const AMS_HEADER_SIZE: usize = 38;
+///Length of the AMS/TCP header (reserved + length)
+pub const AMS_TCP_HEADER_SIZE: usize = 6;
+///Default upper limit for the length of a single frame
+pub const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+///Bytes requested from the stream per read call
+const READ_CHUNK_SIZE: usize = 4096;
 
-pub type ClientResult<T> = result::Result<T, anyhow::Error>;
+pub type ClientResult<T> = result::Result<T, ClientError>;
 
 pub struct AdsReader {
-    pub stream: TcpStream,
+    pub stream: ReadHalf,
+    decoder: FrameDecoder,
 }
 
 impl AdsReader {
-    pub fn new(stream: TcpStream) -> Self {
-        AdsReader { stream }
+    pub fn new<T: Transport + 'static>(stream: T) -> Self {
+        AdsReader::from_transport(Box::new(stream))
     }
 
+    pub fn from_transport(stream: Box<dyn Transport>) -> Self {
+        AdsReader::from_read(Box::new(stream))
+    }
+
+    ///Read from the read half of a split transport
+    pub fn from_read(stream: ReadHalf) -> Self {
+        AdsReader {
+            stream,
+            decoder: FrameDecoder::new(),
+        }
+    }
+
+    ///Read the next frame with an AMS header from the stream. Router frames are skipped.
     pub fn read_response(&mut self) -> ClientResult<AmsTcpHeader> {
-        let mut buf = vec![0; AMS_HEADER_SIZE];
-        let mut reader = BufReader::new(&self.stream);
-        reader.read_exact(&mut buf)?;
-        let mut ams_tcp_header = AmsTcpHeader::read_from(&mut buf.as_slice())?;
-        if ams_tcp_header.response_data_len() > 0 {
-            let mut buf = vec![0; ams_tcp_header.response_data_len() as usize];
-            reader.read_exact(&mut buf)?;
-            ams_tcp_header.update_response_data(buf);
-            return Ok(ams_tcp_header);
-        }
-        Ok(ams_tcp_header)
+        loop {
+            match self.read_frame()? {
+                TcpFrame::Ams(frame) => return Ok(frame),
+                TcpFrame::Router(frame) => log::debug!("Skipped router frame {:?}", frame),
+            }
+        }
+    }
+
+    ///Read the next frame from the stream.
+    ///Bytes received past the current frame are kept for the next call.
+    ///A read timeout keeps a partially received frame.
+    ///Fails with a ConnectionError if the remote device closed or reset the connection
+    ///or sent an invalid frame.
+    pub fn read_frame(&mut self) -> ClientResult<TcpFrame> {
+        self.read_with(FrameDecoder::decode)
+    }
+
+    ///Read the next frame like read_frame. The data of ADS frames is not copied,
+    ///it references the receive buffer.
+    pub fn read_frame_view(&mut self) -> ClientResult<TcpFrameView> {
+        self.read_with(FrameDecoder::decode_view)
+    }
+
+    ///Read the next raw frame including the AMS/TCP header.
+    ///The frame is split off the receive buffer without copying, see `AmsFrameView`.
+    pub fn read_frame_bytes(&mut self) -> ClientResult<Bytes> {
+        self.read_with(FrameDecoder::decode_bytes)
+    }
+
+    ///Read from the stream until decode returns a frame
+    fn read_with<T, F>(&mut self, decode: F) -> ClientResult<T>
+    where
+        F: Fn(&mut FrameDecoder) -> Result<Option<T>, FrameError>,
+    {
+        let mut buf = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(frame) = decode(&mut self.decoder).map_err(ConnectionError::from)? {
+                return Ok(frame);
+            }
+
+            let n = self.stream.read(&mut buf).map_err(check_link)?;
+            if n == 0 {
+                self.decoder.finish().map_err(ConnectionError::from)?;
+                return Err(ConnectionError::ClosedByPeer.into());
+            }
+            self.decoder.extend(&buf[..n]);
+        }
+    }
+}
+
+///Frame received over AMS/TCP
+#[derive(Debug)]
+pub enum TcpFrame {
+    ///ADS frame with AMS header
+    Ams(AmsTcpHeader),
+    ///Frame of the local AMS router
+    Router(RouterFrame),
+}
+
+impl TcpFrame {
+    ///Returns the ADS frame. None for router frames.
+    pub fn into_ams(self) -> Option<AmsTcpHeader> {
+        match self {
+            TcpFrame::Ams(frame) => Some(frame),
+            TcpFrame::Router(_) => None,
+        }
+    }
+}
+
+///Frame received over AMS/TCP. The data of ADS frames references the receive buffer.
+#[derive(Debug)]
+pub enum TcpFrameView {
+    ///ADS frame with AMS header
+    Ams(AmsFrameView),
+    ///Frame of the local AMS router
+    Router(RouterFrame),
+}
+
+///Incremental decoder for AMS/TCP frames.
+///Received bytes are added with extend. Complete frames are taken with decode.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buf: BytesMut,
+    max_frame_size: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        FrameDecoder::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        FrameDecoder::with_max_frame_size(MAX_FRAME_SIZE)
+    }
+
+    ///Frames with more than max_frame_size bytes (AMS/TCP header included) are rejected
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        FrameDecoder {
+            buf: BytesMut::new(),
+            max_frame_size,
+        }
+    }
+
+    ///Add received bytes
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    ///Number of buffered bytes which are not decoded yet
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    ///Take the next complete frame from the buffer.
+    ///Returns None if more bytes are needed.
+    ///After an error the stream is out of sync and should be closed.
+    pub fn decode(&mut self) -> Result<Option<TcpFrame>, FrameError> {
+        let frame = match self.decode_bytes()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let frame = match self.command_of(&frame)? {
+            AmsTcpCommand::AmsCmd => {
+                AmsTcpHeader::read_from(&mut frame.as_ref()).map(TcpFrame::Ams)
+            }
+            _ => RouterFrame::read_from(&mut frame.as_ref()).map(TcpFrame::Router),
+        };
+        frame
+            .map(Some)
+            .map_err(|e| FrameError::Parse { kind: e.kind() })
+    }
+
+    ///Take the next complete frame from the buffer like decode.
+    ///The data of ADS frames is not copied.
+    pub fn decode_view(&mut self) -> Result<Option<TcpFrameView>, FrameError> {
+        let frame = match self.decode_bytes()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let frame = match self.command_of(&frame)? {
+            AmsTcpCommand::AmsCmd => AmsFrameView::parse(frame).map(TcpFrameView::Ams),
+            _ => RouterFrame::read_from(&mut frame.as_ref()).map(TcpFrameView::Router),
+        };
+        frame
+            .map(Some)
+            .map_err(|e| FrameError::Parse { kind: e.kind() })
+    }
+
+    ///Take the next complete frame including the AMS/TCP header without decoding it.
+    ///The frame is split off the buffer without copying.
+    ///Returns None if more bytes are needed.
+    pub fn decode_bytes(&mut self) -> Result<Option<Bytes>, FrameError> {
+        if self.buf.len() < AMS_TCP_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let command = self.command()?;
+        let frame_len = self.frame_len()?;
+        if command == AmsTcpCommand::AmsCmd && self.buf.len() >= AMS_HEADER_SIZE {
+            let ams_length = AMS_HEADER_SIZE - AMS_TCP_HEADER_SIZE + read_u32_at(&self.buf, 26);
+            let tcp_length = frame_len - AMS_TCP_HEADER_SIZE;
+            if ams_length != tcp_length {
+                return Err(FrameError::LengthMismatch {
+                    tcp_length,
+                    ams_length,
+                });
+            }
+        }
+
+        if self.buf.len() < frame_len {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buf.split_to(frame_len).freeze()))
+    }
+
+    ///Call at the end of the stream. Fails if a partial frame is left in the buffer.
+    pub fn finish(&mut self) -> Result<(), FrameError> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let expected = if self.buf.len() >= AMS_TCP_HEADER_SIZE {
+            self.frame_len()?
+        } else {
+            AMS_HEADER_SIZE
+        };
+        let received = self.buf.len();
+        self.buf.clear();
+        Err(FrameError::Truncated { expected, received })
+    }
+
+    ///Command of the frame at the start of the buffer
+    fn command(&self) -> Result<AmsTcpCommand, FrameError> {
+        self.command_of(&self.buf)
+    }
+
+    fn command_of(&self, frame: &[u8]) -> Result<AmsTcpCommand, FrameError> {
+        match AmsTcpCommand::from(u16::from_le_bytes([frame[0], frame[1]])) {
+            AmsTcpCommand::Unknown(command) => Err(FrameError::UnknownCommand { command }),
+            command => Ok(command),
+        }
+    }
+
+    ///Length of the frame at the start of the buffer including the AMS/TCP header
+    fn frame_len(&self) -> Result<usize, FrameError> {
+        let length = AMS_TCP_HEADER_SIZE + read_u32_at(&self.buf, 2);
+        //Router frames carry no AMS header
+        let min_length = match self.command()? {
+            AmsTcpCommand::AmsCmd => AMS_HEADER_SIZE,
+            _ => AMS_TCP_HEADER_SIZE,
+        };
+        if length < min_length {
+            return Err(FrameError::TooShort { length });
+        }
+        if length > self.max_frame_size {
+            return Err(FrameError::TooLarge {
+                length,
+                max: self.max_frame_size,
+            });
+        }
+        Ok(length)
+    }
+}
+
+fn read_u32_at(buf: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ]) as usize
+}
+
+///Returns true if the io error was caused by a read or write timeout
+pub fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+///Classify io errors of the stream.
+///Errors which indicate a lost link are converted into a ConnectionError.
+pub fn check_link(error: io::Error) -> ClientError {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => ConnectionError::ClosedByPeer.into(),
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::NotConnected => ConnectionError::Lost { kind: error.kind() }.into(),
+        _ if is_timeout(&error) => ClientError::Timeout,
+        _ => ClientError::Io(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::frame_view::AmsFrameView;
+    use crate::proto::request::{ReadRequest, Request};
+    use crate::proto::router_command::RouterState;
+    use std::str::FromStr;
+
+    fn frame(invoke_id: u32) -> Vec<u8> {
+        let ams_header = AmsHeader::new(
+            AmsAddress::new(AmsNetId::from_str("192.168.1.1.1.1").unwrap(), 851),
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000),
+            StateFlags::req_default(),
+            invoke_id,
+            Request::Read(ReadRequest::new(259, 259, 4)),
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        AmsTcpHeader::from(ams_header).write_to(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn decode_split_frame_test() {
+        let data = frame(1);
+        let mut decoder = FrameDecoder::new();
+        for byte in &data[..data.len() - 1] {
+            decoder.extend(&[*byte]);
+            assert!(decoder.decode().unwrap().is_none());
+        }
+        decoder.extend(&data[data.len() - 1..]);
+
+        let ams_tcp_header = decoder.decode().unwrap().unwrap().into_ams().unwrap();
+        assert_eq!(ams_tcp_header.invoke_id(), 1);
+        assert_eq!(ams_tcp_header.raw_response_data().len(), 12);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn decode_bytes_test() {
+        let mut data = frame(1);
+        data.append(&mut frame(2));
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&data);
+
+        let first = decoder.decode_bytes().unwrap().unwrap();
+        assert_eq!(&first[..], &frame(1)[..]);
+        let view = AmsFrameView::parse(first).unwrap();
+        assert_eq!(view.invoke_id(), 1);
+        assert_eq!(view.command_id(), CommandID::Read);
+        assert_eq!(view.data().len(), 12);
+
+        assert_eq!(
+            decoder
+                .decode()
+                .unwrap()
+                .unwrap()
+                .into_ams()
+                .unwrap()
+                .invoke_id(),
+            2
+        );
+        assert!(decoder.decode_bytes().unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_multiple_frames_test() {
+        let mut data = frame(1);
+        data.append(&mut frame(2));
+        let mut third = frame(3);
+        data.extend_from_slice(&third[..10]);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&data);
+        assert_eq!(
+            decoder
+                .decode()
+                .unwrap()
+                .unwrap()
+                .into_ams()
+                .unwrap()
+                .invoke_id(),
+            1
+        );
+        assert_eq!(
+            decoder
+                .decode()
+                .unwrap()
+                .unwrap()
+                .into_ams()
+                .unwrap()
+                .invoke_id(),
+            2
+        );
+        assert!(decoder.decode().unwrap().is_none());
+        assert_eq!(decoder.buffered(), 10);
+
+        decoder.extend(&third.split_off(10));
+        assert_eq!(
+            decoder
+                .decode()
+                .unwrap()
+                .unwrap()
+                .into_ams()
+                .unwrap()
+                .invoke_id(),
+            3
+        );
+    }
+
+    #[test]
+    fn decode_too_large_test() {
+        let mut decoder = FrameDecoder::with_max_frame_size(40);
+        decoder.extend(&frame(1)[..6]);
+        assert_eq!(
+            decoder.decode().unwrap_err(),
+            FrameError::TooLarge {
+                length: 50,
+                max: 40
+            }
+        );
+    }
+
+    #[test]
+    fn decode_too_short_test() {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&[0, 0, 4, 0, 0, 0]);
+        assert_eq!(
+            decoder.decode().unwrap_err(),
+            FrameError::TooShort { length: 10 }
+        );
+    }
+
+    #[test]
+    fn decode_length_mismatch_test() {
+        let mut data = frame(1);
+        //AMS header data length 12 -> 13
+        data[26] = 13;
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&data);
+        assert_eq!(
+            decoder.decode().unwrap_err(),
+            FrameError::LengthMismatch {
+                tcp_length: 44,
+                ams_length: 45
+            }
+        );
+    }
+
+    #[test]
+    fn decode_router_frame_test() {
+        let mut data = Vec::new();
+        RouterFrame::RouterNotification(RouterState::Start)
+            .write_to(&mut data)
+            .unwrap();
+        data.append(&mut frame(1));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&data[..8]);
+        assert!(decoder.decode().unwrap().is_none());
+        decoder.extend(&data[8..]);
+        assert!(matches!(
+            decoder.decode().unwrap(),
+            Some(TcpFrame::Router(RouterFrame::RouterNotification(
+                RouterState::Start
+            )))
+        ));
+        assert_eq!(
+            decoder
+                .decode()
+                .unwrap()
+                .unwrap()
+                .into_ams()
+                .unwrap()
+                .invoke_id(),
+            1
+        );
+    }
+
+    #[test]
+    fn decode_unknown_command_test() {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&[0x11, 0x47, 4, 0, 0, 0]);
+        assert_eq!(
+            decoder.decode().unwrap_err(),
+            FrameError::UnknownCommand { command: 0x4711 }
+        );
+    }
+
+    #[test]
+    fn check_link_test() {
+        let error = check_link(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert!(matches!(
+            error,
+            ClientError::Disconnected(ConnectionError::ClosedByPeer)
+        ));
+
+        let error = check_link(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(matches!(
+            error,
+            ClientError::Disconnected(ConnectionError::Lost { .. })
+        ));
+
+        let error = check_link(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(matches!(error, ClientError::Timeout));
+
+        let error = check_link(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(error, ClientError::Io(_)));
+    }
+
+    #[test]
+    fn finish_truncated_test() {
+        let data = frame(1);
+        let mut decoder = FrameDecoder::new();
+        assert!(decoder.finish().is_ok());
+
+        decoder.extend(&data[..20]);
+        assert!(decoder.decode().unwrap().is_none());
+        assert_eq!(
+            decoder.finish().unwrap_err(),
+            FrameError::Truncated {
+                expected: 50,
+                received: 20
+            }
+        );
+        assert_eq!(decoder.buffered(), 0);
+
+        decoder.extend(&data[..4]);
+        assert_eq!(
+            decoder.finish().unwrap_err(),
+            FrameError::Truncated {
+                expected: 38,
+                received: 4
+            }
+        );
     }
 }
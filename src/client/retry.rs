@@ -0,0 +1,99 @@
+use crate::error::ClientError;
+use std::time::Duration;
+
+///Retry failed idempotent requests with exponential backoff.
+///Only transient ADS errors are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    ///Number of retries after the first attempt
+    pub max_retries: u32,
+    ///Wait time before the first retry
+    pub initial_backoff: Duration,
+    ///Upper limit for the wait time between two attempts
+    pub max_backoff: Duration,
+    ///Factor the wait time grows with each retry
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3, Duration::from_millis(50))
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, initial_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_backoff,
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+        }
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    ///Wait time before retry number attempt (starts with 0)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(attempt);
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    ///Check if a request which failed attempt + 1 times should be sent again
+    pub fn should_retry(&self, error: &ClientError, attempt: u32) -> bool {
+        attempt < self.max_retries && is_retryable(error)
+    }
+}
+
+///Returns true if sending the same request again may succeed.
+///Timeouts are not retried. A partly written frame or a late response with the
+///same invoke id would mix up the stream.
+pub fn is_retryable(error: &ClientError) -> bool {
+    match error {
+        ClientError::Ads(e) => e.is_transient(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AdsError;
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(10))
+            .with_max_backoff(Duration::from_millis(50));
+        assert_eq!(policy.backoff(0), Duration::from_millis(10));
+        assert_eq!(policy.backoff(1), Duration::from_millis(20));
+        assert_eq!(policy.backoff(2), Duration::from_millis(40));
+        assert_eq!(policy.backoff(3), Duration::from_millis(50));
+        assert_eq!(policy.backoff(40), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn should_retry_test() {
+        let policy = RetryPolicy::new(2, Duration::from_millis(10));
+        let busy = ClientError::Ads(AdsError::AdsErrDeviceBusy);
+        assert!(policy.should_retry(&busy, 0));
+        assert!(policy.should_retry(&busy, 1));
+        assert!(!policy.should_retry(&busy, 2));
+
+        assert!(!policy.should_retry(&ClientError::Timeout, 0));
+        let not_found = ClientError::Ads(AdsError::AdsErrDeviceSymbolNotFound);
+        assert!(!policy.should_retry(&not_found, 0));
+        let missing = ClientError::MissingHandle("MAIN.x".to_string());
+        assert!(!policy.should_retry(&missing, 0));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::client::ads_client::Connection;
+use crate::client::notification::Subscription;
+use crate::client::plc_types::Var;
+use crate::client::read::ClientResult;
+use crate::error::AdsError;
+use crate::proto::ads_state::AdsState;
+use crate::proto::ads_transition_mode::AdsTransMode;
+use crate::proto::response::{ReadDeviceInfoResponse, ReadStateResponse};
+
+///Operations of an ADS client. Implemented by Connection and MemoryClient,
+///so application code can be tested without a device.
+///Variables are accessed by name. A handle has to be requested before a variable is read or written.
+pub trait AdsClient {
+    ///Request handle for a variable
+    fn get_symhandle(&mut self, var: &Var, invoke_id: u32) -> ClientResult<u32>;
+
+    ///Request handles for multiple variables
+    fn sumup_get_symhandle(&mut self, var_list: &[Var], invoke_id: u32) -> ClientResult<bool>;
+
+    fn read_by_name(&mut self, var: &Var, invoke_id: u32) -> ClientResult<Vec<u8>>;
+
+    fn sumup_read_by_name(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, Vec<u8>>>;
+
+    fn write_by_name(&mut self, var: &Var, invoke_id: u32, data: Vec<u8>) -> ClientResult<()>;
+
+    ///Write the data of every var. Returns the result of every write.
+    fn sumup_write_by_name(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, AdsError>>;
+
+    fn read_device_info(&mut self, invoke_id: u32) -> ClientResult<ReadDeviceInfoResponse>;
+
+    fn read_state(&mut self, invoke_id: u32) -> ClientResult<ReadStateResponse>;
+
+    fn write_control(
+        &mut self,
+        new_ads_state: AdsState,
+        device_state: u16,
+        invoke_id: u32,
+    ) -> ClientResult<()>;
+
+    fn add_device_notification(
+        &mut self,
+        var: &Var,
+        trans_mode: AdsTransMode,
+        max_delay: u32,
+        cycle_time: u32,
+        invoke_id: u32,
+    ) -> ClientResult<Subscription>;
+
+    fn delete_device_notification(&mut self, var: &Var, invoke_id: u32) -> ClientResult<()>;
+}
+
+impl AdsClient for Connection {
+    fn get_symhandle(&mut self, var: &Var, invoke_id: u32) -> ClientResult<u32> {
+        Connection::get_symhandle(self, var, invoke_id)
+    }
+
+    fn sumup_get_symhandle(&mut self, var_list: &[Var], invoke_id: u32) -> ClientResult<bool> {
+        Connection::sumup_get_symhandle(self, var_list, invoke_id)
+    }
+
+    fn read_by_name(&mut self, var: &Var, invoke_id: u32) -> ClientResult<Vec<u8>> {
+        Connection::read_by_name(self, var, invoke_id)
+    }
+
+    fn sumup_read_by_name(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, Vec<u8>>> {
+        Connection::sumup_read_by_name(self, var_list, invoke_id)
+    }
+
+    fn write_by_name(&mut self, var: &Var, invoke_id: u32, data: Vec<u8>) -> ClientResult<()> {
+        Connection::write_by_name(self, var, invoke_id, data)
+    }
+
+    fn sumup_write_by_name(
+        &mut self,
+        var_list: &[Var],
+        invoke_id: u32,
+    ) -> ClientResult<HashMap<String, AdsError>> {
+        Connection::sumup_write_by_name(self, var_list, invoke_id)
+    }
+
+    fn read_device_info(&mut self, invoke_id: u32) -> ClientResult<ReadDeviceInfoResponse> {
+        Connection::read_device_info(self, invoke_id)
+    }
+
+    fn read_state(&mut self, invoke_id: u32) -> ClientResult<ReadStateResponse> {
+        Connection::read_state(self, invoke_id)
+    }
+
+    fn write_control(
+        &mut self,
+        new_ads_state: AdsState,
+        device_state: u16,
+        invoke_id: u32,
+    ) -> ClientResult<()> {
+        Connection::write_control(self, new_ads_state, device_state, invoke_id)
+    }
+
+    fn add_device_notification(
+        &mut self,
+        var: &Var,
+        trans_mode: AdsTransMode,
+        max_delay: u32,
+        cycle_time: u32,
+        invoke_id: u32,
+    ) -> ClientResult<Subscription> {
+        Connection::add_device_notification(self, var, trans_mode, max_delay, cycle_time, invoke_id)
+    }
+
+    fn delete_device_notification(&mut self, var: &Var, invoke_id: u32) -> ClientResult<()> {
+        Connection::delete_device_notification(self, var, invoke_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::memory_client::MemoryClient;
+    use crate::client::plc_types::PlcTypes;
+    use crate::proto::ams_address::{AmsAddress, AmsNetId};
+    use crate::server::ads_server::AdsServer;
+    use crate::server::simulator::{SymbolTable, VirtualPlc};
+
+    ///Application code which only knows the trait
+    fn toggle(client: &mut dyn AdsClient) -> ClientResult<Vec<u8>> {
+        let flag = Var::new("GVL.flag".to_string(), PlcTypes::Bool, None);
+        client.get_symhandle(&flag, 1)?;
+        let value = client.read_by_name(&flag, 2)?;
+        client.write_by_name(&flag, 3, vec![(value[0] == 0) as u8])?;
+        client.read_by_name(&flag, 4)
+    }
+
+    #[test]
+    fn memory_client_test() {
+        let client = MemoryClient::new();
+        client.set_value("GVL.flag", vec![1]);
+        assert_eq!(toggle(&mut client.clone()).unwrap(), vec![0]);
+        assert_eq!(client.value("GVL.flag").unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn connection_test() {
+        let net_id = AmsNetId::new(127, 0, 0, 1, 1, 1);
+        let plc = VirtualPlc::new(SymbolTable::parse("GVL.flag : BOOL := TRUE;").unwrap());
+        let server = AdsServer::new(net_id.clone());
+        server.add_device(851, plc.clone());
+        let handle = server.start_on("127.0.0.1:0").unwrap();
+        let mut connection = Connection::builder(AmsAddress::new(net_id, 851))
+            .port(handle.local_addr().port())
+            .connect()
+            .unwrap();
+
+        assert_eq!(toggle(&mut connection).unwrap(), vec![0]);
+        assert_eq!(plc.value("GVL.flag").unwrap(), vec![0]);
+    }
+}
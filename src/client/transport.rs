@@ -0,0 +1,437 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+///Read half of a split transport. Read by the reader thread.
+pub type ReadHalf = Box<dyn Read + Send>;
+///Write half of a split transport. Frames are written on it.
+pub type WriteHalf = Box<dyn TransportWrite>;
+///Called with the invoke id of a request the transport gave up on
+pub type GiveUpHandler = Box<dyn Fn(u32) + Send>;
+
+///Byte stream an AMS/TCP connection runs over, e.g. TCP, TLS, a Unix domain socket
+///to a local router, an SSH channel or an in-memory pipe.
+///The stream is split into a read half for the reader thread and a write half for the frames sent.
+pub trait Transport: Read + Write + Send {
+    ///Split into a read and a write half which are used from different threads at the same time.
+    ///The default implementation clones the stream with try_clone. Streams which can't be cloned
+    ///(e.g. TLS or SSH) implement split instead.
+    fn split(self: Box<Self>) -> io::Result<(ReadHalf, WriteHalf)> {
+        Ok((Box::new(self.try_clone()?), Box::new(self.try_clone()?)))
+    }
+
+    ///Get another handle to the same stream. Reading and writing through
+    ///different handles from different threads has to be possible.
+    ///Not supported by default.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Transport can't be cloned",
+        ))
+    }
+
+    ///Close both directions. Unblocks reads on all handles of the stream.
+    fn shutdown(&self) -> io::Result<()>;
+
+    ///Local socket address. Used to derive the source AMS address if none is configured.
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    ///Set the handler for requests the transport gave up on. Only transports which
+    ///send requests again themselves (e.g. UDP) give up on requests. Ignored by default.
+    fn on_give_up(&self, handler: GiveUpHandler) {}
+}
+
+///Write half of a transport
+pub trait TransportWrite: Write + Send {
+    ///Close both directions. Unblocks a read on the read half.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl fmt::Debug for dyn TransportWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TransportWrite")
+    }
+}
+
+impl TransportWrite for TcpStream {
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl TransportWrite for Box<dyn Transport> {
+    fn shutdown(&self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+}
+
+impl fmt::Debug for dyn Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.local_addr() {
+            Some(addr) => write!(f, "Transport({})", addr),
+            None => write!(f, "Transport"),
+        }
+    }
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpStream::local_addr(self).ok()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(std::os::unix::net::UnixStream::try_clone(self)?))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl Transport for Box<dyn Transport> {
+    fn split(self: Box<Self>) -> io::Result<(ReadHalf, WriteHalf)> {
+        (*self).split()
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        (**self).try_clone()
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn on_give_up(&self, handler: GiveUpHandler) {
+        (**self).on_give_up(handler)
+    }
+}
+
+///Opens a new transport on connect and reconnect
+#[derive(Clone)]
+pub struct Connector {
+    open: Arc<dyn Fn() -> io::Result<Box<dyn Transport>> + Send + Sync>,
+}
+
+impl Connector {
+    pub fn new<F>(open: F) -> Self
+    where
+        F: Fn() -> io::Result<Box<dyn Transport>> + Send + Sync + 'static,
+    {
+        Connector {
+            open: Arc::new(open),
+        }
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn Transport>> {
+        (self.open)()
+    }
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Connector")
+    }
+}
+
+///Bytes of one direction of a pipe
+#[derive(Debug, Default)]
+struct Channel {
+    buffer: VecDeque<u8>,
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    channel: Mutex<Channel>,
+    readable: Condvar,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, Channel> {
+        match self.channel.lock() {
+            Ok(c) => c,
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+///One end of a pipe. Closes the pipe when the last handle is dropped.
+#[derive(Debug)]
+struct End {
+    read: Arc<Pipe>,
+    write: Arc<Pipe>,
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        self.read.close();
+        self.write.close();
+    }
+}
+
+///In-memory duplex stream. Bytes written on one end are read on the other end.
+///Reads block until data is available and return 0 once the pipe is closed.
+#[derive(Debug, Clone)]
+pub struct PipeStream {
+    end: Arc<End>,
+}
+
+///Create a connected pair of in-memory streams, e.g. to run a client against a server in tests
+pub fn pipe() -> (PipeStream, PipeStream) {
+    let a = Arc::new(Pipe::default());
+    let b = Arc::new(Pipe::default());
+    (
+        PipeStream {
+            end: Arc::new(End {
+                read: Arc::clone(&a),
+                write: Arc::clone(&b),
+            }),
+        },
+        PipeStream {
+            end: Arc::new(End { read: b, write: a }),
+        },
+    )
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pipe = &self.end.read;
+        let mut channel = pipe.lock();
+        while channel.buffer.is_empty() && !channel.closed {
+            channel = match pipe.readable.wait(channel) {
+                Ok(c) => c,
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+        let n = buf.len().min(channel.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(channel.buffer.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pipe = &self.end.write;
+        let mut channel = pipe.lock();
+        if channel.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        channel.buffer.extend(buf);
+        pipe.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for PipeStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.end.read.close();
+        self.end.write.close();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ads_client::Connection;
+    use crate::client::plc_types::{PlcTypes, Var};
+    use crate::error::ClientError;
+    use crate::proto::ams_address::{AmsAddress, AmsNetId};
+    use crate::server::ads_server::AdsServer;
+    use crate::server::simulator::{SymbolTable, VirtualPlc};
+    use std::thread;
+
+    #[test]
+    fn pipe_test() {
+        let (mut a, mut b) = pipe();
+        a.write_all(&[1, 2, 3]).unwrap();
+        let mut buf = [0; 2];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+
+        let mut reader = b.try_clone().unwrap();
+        let read_thread = thread::spawn(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            data
+        });
+        b.write_all(&[4]).unwrap();
+        a.write_all(&[5]).unwrap();
+        Transport::shutdown(&a).unwrap();
+        assert_eq!(read_thread.join().unwrap(), vec![3, 5]);
+        assert_eq!(a.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 4);
+        assert_eq!(b.write(&[6]).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn pipe_drop_test() {
+        let (a, mut b) = pipe();
+        let clone = a.try_clone().unwrap();
+        drop(a);
+        b.write_all(&[1]).unwrap();
+        drop(clone);
+        assert!(b.write_all(&[2]).is_err());
+        assert_eq!(b.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    ///Stream which can't be cloned, like a TLS session. Only supports split.
+    struct Unclonable(PipeStream);
+
+    impl Read for Unclonable {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Unclonable {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Transport for Unclonable {
+        fn split(self: Box<Self>) -> io::Result<(ReadHalf, WriteHalf)> {
+            let read = self.0.clone();
+            Ok((
+                Box::new(read),
+                Box::new(Box::new(self.0) as Box<dyn Transport>),
+            ))
+        }
+
+        fn shutdown(&self) -> io::Result<()> {
+            Transport::shutdown(&self.0)
+        }
+    }
+
+    #[test]
+    fn split_test() {
+        let (a, mut b) = pipe();
+        let (mut read, mut write) = (Box::new(a) as Box<dyn Transport>).split().unwrap();
+        write.write_all(&[1]).unwrap();
+        b.write_all(&[2]).unwrap();
+        let mut buf = [0; 1];
+        read.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [2]);
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1]);
+        write.shutdown().unwrap();
+        assert_eq!(read.read(&mut buf).unwrap(), 0);
+
+        let (a, _b) = pipe();
+        let error = Unclonable(a).try_clone().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn unclonable_connection_test() {
+        let net_id = AmsNetId::new(127, 0, 0, 1, 1, 1);
+        let plc = VirtualPlc::new(SymbolTable::parse("MAIN.counter : INT := 7;").unwrap());
+        let server = AdsServer::new(net_id.clone());
+        server.add_device(851, plc);
+        let mut connection = Connection::builder(AmsAddress::new(net_id, 851))
+            .source(AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000))
+            .transport(move || {
+                let (client, server_end) = pipe();
+                let server = server.clone();
+                thread::spawn(move || server.serve(server_end));
+                Ok(Box::new(Unclonable(client)) as Box<dyn Transport>)
+            })
+            .connect()
+            .unwrap();
+        let counter = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        connection.get_symhandle(&counter, 1).unwrap();
+        assert_eq!(connection.read_by_name(&counter, 2).unwrap(), vec![7, 0]);
+    }
+
+    #[test]
+    fn serve_unclonable_test() {
+        let net_id = AmsNetId::new(127, 0, 0, 1, 1, 1);
+        let plc = VirtualPlc::new(SymbolTable::parse("MAIN.counter : INT := 7;").unwrap());
+        let server = AdsServer::new(net_id.clone());
+        server.add_device(851, plc);
+        let mut connection = Connection::builder(AmsAddress::new(net_id, 851))
+            .source(AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000))
+            .transport(move || {
+                let (client, server_end) = pipe();
+                let server = server.clone();
+                //Served as boxed transport. Only split is supported.
+                let server_end = Box::new(Unclonable(server_end)) as Box<dyn Transport>;
+                thread::spawn(move || server.serve(server_end));
+                Ok(Box::new(client) as Box<dyn Transport>)
+            })
+            .connect()
+            .unwrap();
+        let counter = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        connection.get_symhandle(&counter, 1).unwrap();
+        assert_eq!(connection.read_by_name(&counter, 2).unwrap(), vec![7, 0]);
+    }
+
+    #[test]
+    fn connection_test() {
+        let net_id = AmsNetId::new(127, 0, 0, 1, 1, 1);
+        let plc = VirtualPlc::new(SymbolTable::parse("MAIN.counter : INT := 7;").unwrap());
+        let server = AdsServer::new(net_id.clone());
+        server.add_device(851, plc);
+        let builder = Connection::builder(AmsAddress::new(net_id, 851)).transport(move || {
+            let (client, server_end) = pipe();
+            let server = server.clone();
+            thread::spawn(move || server.serve(server_end));
+            Ok(Box::new(client) as Box<dyn Transport>)
+        });
+
+        let error = builder.clone().connect().unwrap_err();
+        assert!(matches!(error, ClientError::Io(_)));
+
+        let mut connection = builder
+            .source(AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000))
+            .connect()
+            .unwrap();
+        let counter = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        connection.get_symhandle(&counter, 1).unwrap();
+        assert_eq!(connection.read_by_name(&counter, 2).unwrap(), vec![7, 0]);
+
+        connection.close();
+        connection.reconnect().unwrap();
+        connection.get_symhandle(&counter, 3).unwrap();
+        assert_eq!(connection.read_by_name(&counter, 4).unwrap(), vec![7, 0]);
+    }
+}
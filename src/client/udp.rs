@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::client::read::{is_timeout, AMS_HEADER_SIZE, AMS_TCP_HEADER_SIZE};
+use crate::client::transport::{GiveUpHandler, Transport};
+use crate::proto::state_flags::{NetProto, StateFlags};
+
+///Largest payload of a UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65507;
+///Offset of the state flags in the AMS header
+const STATE_FLAGS_OFFSET: usize = 18;
+///Offset of the data length in the AMS header
+const LENGTH_OFFSET: usize = 20;
+///Offset of the invoke id in the AMS header
+const INVOKE_ID_OFFSET: usize = 28;
+
+///Retransmission of ADS requests over UDP
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpConfig {
+    ///Time to wait for a response before a request is sent again
+    pub retransmit_timeout: Duration,
+    ///Number of retransmissions before a request is given up.
+    ///The request then fails with ClientError::Timeout, even if the response timeout
+    ///of the connection is not over yet.
+    pub retries: u32,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            retransmit_timeout: Duration::from_millis(200),
+            retries: 3,
+        }
+    }
+}
+
+///Request which was not answered yet
+#[derive(Debug)]
+struct Unanswered {
+    datagram: Vec<u8>,
+    sent: Instant,
+    attempts: u32,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    config: UdpConfig,
+    unanswered: Mutex<HashMap<u32, Unanswered>>,
+    closed: AtomicBool,
+    give_up: Mutex<Option<GiveUpHandler>>,
+}
+
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("socket", &self.socket)
+            .field("config", &self.config)
+            .field("unanswered", &self.unanswered)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+///ADS over UDP. Every AMS frame is sent as one datagram without the AMS/TCP header
+///and with the UDP flag set in the state flags.
+///Requests which are not answered within the retransmit timeout are sent again with the
+///same invoke id. A device may execute a retransmitted write twice if the response was lost.
+///Requests with the no return flag are sent once.
+///Router commands (e.g. a local router port connect) are not supported.
+#[derive(Debug)]
+pub struct UdpTransport {
+    shared: Arc<Shared>,
+    ///Received frames with AMS/TCP header which were not read yet
+    incoming: Vec<u8>,
+    ///Receive buffer. Allocated on the first read.
+    datagram: Vec<u8>,
+}
+
+impl UdpTransport {
+    ///Bind a local port and send all frames to the first address the target resolves to
+    pub fn connect<A: ToSocketAddrs>(addr: A, config: UdpConfig) -> io::Result<Self> {
+        let addr = match addr.to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No address found for the UDP target",
+                ))
+            }
+        };
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        //Wake up regularly to send unanswered requests again
+        socket.set_read_timeout(Some(config.retransmit_timeout))?;
+        Ok(UdpTransport {
+            shared: Arc::new(Shared {
+                socket,
+                config,
+                unanswered: Mutex::new(HashMap::new()),
+                closed: AtomicBool::new(false),
+                give_up: Mutex::new(None),
+            }),
+            incoming: Vec::new(),
+            datagram: Vec::new(),
+        })
+    }
+
+    ///Number of requests waiting for a response
+    pub fn unanswered(&self) -> usize {
+        match self.shared.unanswered.lock() {
+            Ok(u) => u.len(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Queue a received datagram as AMS/TCP frame.
+    ///Datagrams which are not exactly one AMS frame are skipped.
+    fn received(shared: &Shared, incoming: &mut Vec<u8>, datagram: &[u8]) {
+        let header_size = AMS_HEADER_SIZE - AMS_TCP_HEADER_SIZE;
+        if datagram.len() < header_size {
+            log::warn!("Skipped UDP datagram with {} bytes", datagram.len());
+            return;
+        }
+        let length = read_u32(datagram, LENGTH_OFFSET) as usize;
+        if header_size + length != datagram.len() {
+            log::warn!(
+                "Skipped UDP datagram with {} bytes. The AMS header announces {} bytes of data.",
+                datagram.len(),
+                length
+            );
+            return;
+        }
+        let flags = StateFlags::from(read_u16(datagram, STATE_FLAGS_OFFSET));
+        if flags.is_response() {
+            match shared.unanswered.lock() {
+                Ok(mut u) => u.remove(&read_u32(datagram, INVOKE_ID_OFFSET)),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+        incoming.extend_from_slice(&[0, 0]);
+        incoming.extend_from_slice(&(datagram.len() as u32).to_le_bytes());
+        incoming.extend_from_slice(datagram);
+    }
+
+    ///Send requests again which were not answered within the retransmit timeout.
+    ///Requests without retries left are dropped and passed to the give up handler.
+    fn retransmit(&self) -> io::Result<()> {
+        let config = &self.shared.config;
+        let mut given_up = Vec::new();
+        {
+            let mut unanswered = match self.shared.unanswered.lock() {
+                Ok(u) => u,
+                Err(_) => panic!("Failed to get lock!"),
+            };
+            unanswered.retain(|invoke_id, request| {
+                let retry = request.sent.elapsed() < config.retransmit_timeout
+                    || request.attempts < config.retries;
+                if !retry {
+                    given_up.push(*invoke_id);
+                }
+                retry
+            });
+            for request in unanswered.values_mut() {
+                if request.sent.elapsed() >= config.retransmit_timeout {
+                    self.shared.socket.send(&request.datagram)?;
+                    request.sent = Instant::now();
+                    request.attempts += 1;
+                }
+            }
+        }
+
+        if !given_up.is_empty() {
+            match self.shared.give_up.lock() {
+                Ok(h) => match h.as_ref() {
+                    Some(handler) => given_up.into_iter().for_each(handler),
+                    None => log::warn!("Gave up requests {:?}", given_up),
+                },
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+        Ok(())
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+impl Read for UdpTransport {
+    ///Returns 0 once the transport is shut down
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.datagram.is_empty() {
+            self.datagram = vec![0; MAX_DATAGRAM_SIZE];
+        }
+        while self.incoming.is_empty() {
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            let result = self.shared.socket.recv(&mut self.datagram);
+            self.retransmit()?;
+            match result {
+                Ok(n) => {
+                    UdpTransport::received(&self.shared, &mut self.incoming, &self.datagram[..n])
+                }
+                Err(e) if is_timeout(&e) && self.shared.closed.load(Ordering::SeqCst) => {
+                    return Ok(0)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let n = buf.len().min(self.incoming.len());
+        buf[..n].copy_from_slice(&self.incoming[..n]);
+        self.incoming.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for UdpTransport {
+    ///buf has to be exactly one AMS/TCP frame
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        if buf.len() < AMS_HEADER_SIZE || buf[0..2] != [0, 0] {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Only ADS frames can be sent over UDP",
+            ));
+        }
+        if read_u32(buf, 2) as usize != buf.len() - AMS_TCP_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Buffer is not a single AMS/TCP frame",
+            ));
+        }
+
+        let mut datagram = buf[AMS_TCP_HEADER_SIZE..].to_vec();
+        let mut flags = StateFlags::from(read_u16(&datagram, STATE_FLAGS_OFFSET));
+        flags.set_net_proto(NetProto::Udp);
+        datagram[STATE_FLAGS_OFFSET..STATE_FLAGS_OFFSET + 2]
+            .copy_from_slice(&flags.value().to_le_bytes());
+        self.shared.socket.send(&datagram)?;
+        if !flags.is_response() && !flags.is_no_return() {
+            let invoke_id = read_u32(&datagram, INVOKE_ID_OFFSET);
+            let request = Unanswered {
+                datagram,
+                sent: Instant::now(),
+                attempts: 0,
+            };
+            match self.shared.unanswered.lock() {
+                Ok(mut u) => u.insert(invoke_id, request),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for UdpTransport {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(UdpTransport {
+            shared: Arc::clone(&self.shared),
+            incoming: Vec::new(),
+            datagram: Vec::new(),
+        }))
+    }
+
+    ///Reads return 0 after the next read timeout
+    fn shutdown(&self) -> io::Result<()> {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.shared.socket.local_addr().ok()
+    }
+
+    fn on_give_up(&self, handler: GiveUpHandler) {
+        match self.shared.give_up.lock() {
+            Ok(mut h) => *h = Some(handler),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ads_client::{Connection, ADS_UDP_SERVER_PORT};
+    use crate::client::builder::ConnectionBuilder;
+    use crate::error::{AdsError, ClientError};
+    use crate::proto::ads_state::AdsState;
+    use crate::proto::ams_address::{AmsAddress, AmsNetId};
+    use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+    use crate::proto::command_id::CommandID;
+    use crate::proto::proto_traits::{ReadFrom, WriteTo};
+    use crate::proto::response::{ReadStateResponse, Response};
+    use std::thread;
+
+    ///Decode a datagram like a frame received over TCP
+    fn decode(datagram: &[u8]) -> AmsTcpHeader {
+        let mut frame = vec![0, 0];
+        frame.extend_from_slice(&(datagram.len() as u32).to_le_bytes());
+        frame.extend_from_slice(datagram);
+        AmsTcpHeader::read_from(&mut frame.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn retransmit_test() {
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let device_addr = device.local_addr().unwrap();
+        let config = UdpConfig {
+            retransmit_timeout: Duration::from_millis(50),
+            retries: 3,
+        };
+        let mut connection =
+            ConnectionBuilder::new(AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851))
+                .udp(config)
+                .port(device_addr.port())
+                .connect()
+                .unwrap();
+
+        //The first request is lost, the retransmission is answered
+        let device_thread = thread::spawn(move || {
+            let mut datagram = vec![0; MAX_DATAGRAM_SIZE];
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let (n, client) = device.recv_from(&mut datagram).unwrap();
+                received.push(datagram[..n].to_vec());
+                if received.len() == 2 {
+                    let request = decode(&datagram[..n]);
+                    let response = AmsHeader::new_response(
+                        request.source_address().clone(),
+                        request.target_address().clone(),
+                        StateFlags::new(true, true, NetProto::Udp),
+                        request.invoke_id(),
+                        Response::ReadState(ReadStateResponse::new(
+                            AdsError::ErrNoError,
+                            AdsState::AdsStateRun,
+                            0,
+                        )),
+                    )
+                    .unwrap();
+                    let mut buffer = Vec::new();
+                    response.write_to(&mut buffer).unwrap();
+                    device.send_to(&buffer, client).unwrap();
+                }
+            }
+            received
+        });
+
+        let state = connection.read_state(7).unwrap();
+        assert_eq!(state.ads_state, AdsState::AdsStateRun);
+
+        let received = device_thread.join().unwrap();
+        assert_eq!(received[0], received[1]);
+        let request = decode(&received[0]);
+        assert!(!request.state_flags().is_tcp());
+        assert_eq!(request.command_id(), CommandID::ReadState);
+        assert_eq!(request.invoke_id(), 7);
+    }
+
+    #[test]
+    fn no_answer_test() {
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = UdpConfig {
+            retransmit_timeout: Duration::from_millis(20),
+            retries: 2,
+        };
+        let mut connection =
+            ConnectionBuilder::new(AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851))
+                .udp(config)
+                .port(device.local_addr().unwrap().port())
+                .response_timeout(Duration::from_secs(30))
+                .connect()
+                .unwrap();
+
+        //The request fails once the retries are used up, not after the response timeout
+        let start = Instant::now();
+        let error = connection.read_state(1).unwrap_err();
+        assert!(matches!(error, ClientError::Timeout));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        device
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut buf = [0; 64];
+        for _ in 0..3 {
+            device.recv(&mut buf).unwrap();
+        }
+    }
+
+    #[test]
+    fn length_mismatch_test() {
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut transport =
+            UdpTransport::connect(device.local_addr().unwrap(), UdpConfig::default()).unwrap();
+        let response = AmsHeader::new_response(
+            AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 2), 30000),
+            AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851),
+            StateFlags::new(true, true, NetProto::Udp),
+            1,
+            Response::ReadState(ReadStateResponse::new(
+                AdsError::ErrNoError,
+                AdsState::AdsStateRun,
+                0,
+            )),
+        )
+        .unwrap();
+        let mut datagram = Vec::new();
+        response.write_to(&mut datagram).unwrap();
+        let client = transport.local_addr().unwrap();
+
+        //Skipped. One byte more than the AMS header announces.
+        let mut invalid = datagram.clone();
+        invalid.push(0);
+        device.send_to(&invalid, client).unwrap();
+        device.send_to(&datagram, client).unwrap();
+
+        let mut frame = vec![0; AMS_TCP_HEADER_SIZE + datagram.len()];
+        transport.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame[AMS_TCP_HEADER_SIZE..], &datagram[..]);
+        assert_eq!(read_u32(&frame, 2) as usize, datagram.len());
+    }
+
+    #[test]
+    fn give_up_test() {
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = UdpConfig {
+            retransmit_timeout: Duration::from_millis(20),
+            retries: 2,
+        };
+        let mut transport = UdpTransport::connect(device.local_addr().unwrap(), config).unwrap();
+        let header = AmsHeader::new(
+            AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851),
+            AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 2), 30000),
+            StateFlags::req_default(),
+            1,
+            crate::proto::request::Request::ReadState(
+                crate::proto::request::ReadStateRequest::new(),
+            ),
+        )
+        .unwrap();
+        let mut frame = Vec::new();
+        AmsTcpHeader::from(header).write_to(&mut frame).unwrap();
+        transport.write_all(&frame).unwrap();
+        assert_eq!(transport.unanswered(), 1);
+        assert!(transport.write_all(&[1, 0, 2, 0, 0, 0, 0, 0]).is_err());
+
+        let mut buf = [0; 64];
+        while transport.unanswered() > 0 {
+            let error = transport.read(&mut buf).unwrap_err();
+            assert!(is_timeout(&error));
+        }
+        device
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        for _ in 0..3 {
+            device.recv(&mut buf).unwrap();
+        }
+
+        Transport::shutdown(&transport).unwrap();
+        assert_eq!(transport.read(&mut buf).unwrap(), 0);
+    }
+}
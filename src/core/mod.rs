@@ -1,8 +0,0 @@
-pub mod ads;
-pub mod connection;
-pub mod notify;
-pub mod port;
-pub mod requests;
-pub mod responses;
-pub mod router;
-pub mod serde;
@@ -1,3 +1,5 @@
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::command_id::CommandID;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq, Clone)]
@@ -16,6 +18,122 @@ pub enum AmsAddressError {
     SplitError { length: usize },
     #[error("Supplied address length {}! Expected a length of 6", length)]
     InvalidAddressLength { length: usize },
+    #[error(
+        "No AmsNetId for IPv6 address {}. Set the source address explicitly",
+        address
+    )]
+    Ipv6Address { address: std::net::Ipv6Addr },
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ConnectionError {
+    #[error("Connection closed by remote device")]
+    ClosedByPeer,
+    #[error("Connection lost ({:?})", kind)]
+    Lost { kind: std::io::ErrorKind },
+    #[error("Invalid AMS/TCP frame. {0}")]
+    Framing(#[from] FrameError),
+    #[error("Response channel closed before a response was received")]
+    ResponseChannelClosed,
+}
+
+///Error of a client request
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("I/O error. {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Request timed out")]
+    Timeout,
+    ///Error code returned by the ADS device or router
+    #[error(transparent)]
+    Ads(#[from] AdsError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error("Symhandle for {0:?} missing")]
+    MissingHandle(String),
+    #[error("Decode error. {0}")]
+    Decode(String),
+    #[error(transparent)]
+    Disconnected(#[from] ConnectionError),
+}
+
+impl ClientError {
+    ///Returns the ADS error code if the device answered with an error
+    pub fn ads_error(&self) -> Option<&AdsError> {
+        match self {
+            ClientError::Ads(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TryIntoError> for ClientError {
+    fn from(error: TryIntoError) -> Self {
+        ClientError::Decode(error.to_string())
+    }
+}
+
+impl From<AmsAddressError> for ClientError {
+    fn from(error: AmsAddressError) -> Self {
+        ClientError::Decode(error.to_string())
+    }
+}
+
+impl From<std::sync::mpsc::RecvError> for ClientError {
+    fn from(_: std::sync::mpsc::RecvError) -> Self {
+        ClientError::Disconnected(ConnectionError::ResponseChannelClosed)
+    }
+}
+
+///Received frame does not fit the request it answers
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ProtocolError {
+    #[error("Frame with invoke id {invoke_id} is not a response")]
+    NotAResponse { invoke_id: u32 },
+    #[error("No pending request for invoke id {invoke_id}")]
+    UnexpectedInvokeId { invoke_id: u32 },
+    #[error("Expected response to {expected:?}, received {received:?} (invoke id {invoke_id})")]
+    CommandMismatch {
+        invoke_id: u32,
+        expected: CommandID,
+        received: CommandID,
+    },
+    #[error("Frame from {received:?}, expected {expected:?} (invoke id {invoke_id})")]
+    SourceMismatch {
+        invoke_id: u32,
+        expected: AmsAddress,
+        received: AmsAddress,
+    },
+    #[error("Frame addressed to {received:?}, expected {expected:?} (invoke id {invoke_id})")]
+    TargetMismatch {
+        invoke_id: u32,
+        expected: AmsAddress,
+        received: AmsAddress,
+    },
+    #[error("Expected {expected}, received {received}")]
+    UnexpectedFrame {
+        expected: &'static str,
+        received: String,
+    },
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum FrameError {
+    #[error("Frame truncated. Expected {expected} bytes, received {received}")]
+    Truncated { expected: usize, received: usize },
+    #[error("Frame length {length} is below the AMS header length")]
+    TooShort { length: usize },
+    #[error("Frame length {length} exceeds the maximum of {max} bytes")]
+    TooLarge { length: usize, max: usize },
+    #[error("AMS/TCP length {tcp_length} does not match AMS header length {ams_length}")]
+    LengthMismatch {
+        tcp_length: usize,
+        ams_length: usize,
+    },
+    #[error("Failed to parse frame ({:?})", kind)]
+    Parse { kind: std::io::ErrorKind },
+    #[error("Unknown AMS/TCP command {command:#06x}")]
+    UnknownCommand { command: u16 },
 }
 
 #[derive(Error, Debug, PartialEq, Clone)]
@@ -454,7 +572,85 @@ impl From<u32> for AdsError {
     }
 }
 
+///Error code ranges of the ADS specification
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdsErrorCategory {
+    ///ErrNoError
+    None,
+    ///Global error codes (0x000 - 0x4FF)
+    Global,
+    ///Router error codes (0x500 - 0x6FF)
+    Router,
+    ///Device error codes returned by the target (0x700 - 0x73F)
+    Device,
+    ///Client error codes (0x740 - 0x7FF)
+    Client,
+    ///Real time error codes (0x1000 - 0x10FF)
+    RealTime,
+    ///Code outside the known ranges
+    Unknown,
+}
+
 impl AdsError {
+    ///Range of the error code
+    pub fn category(&self) -> AdsErrorCategory {
+        match self.as_u32() {
+            0 => AdsErrorCategory::None,
+            0x001..=0x4FF => AdsErrorCategory::Global,
+            0x500..=0x6FF => AdsErrorCategory::Router,
+            0x700..=0x73F => AdsErrorCategory::Device,
+            0x740..=0x7FF => AdsErrorCategory::Client,
+            0x1000..=0x10FF => AdsErrorCategory::RealTime,
+            _ => AdsErrorCategory::Unknown,
+        }
+    }
+
+    ///Returns true for temporary conditions (busy, full queues, timeouts).
+    ///The same request may succeed if it is sent again later.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            AdsError::ErrInsertMailBox
+                | AdsError::ErrAmsSyncTimeout
+                | AdsError::ErrTcpSend
+                | AdsError::RouterErrMailboxFull
+                | AdsError::RouterErrDebugBoxFull
+                | AdsError::RouterErrNoMoreQueues
+                | AdsError::RouterErrFragmentBoxFull
+                | AdsError::RouterErrFragmentTimeout
+                | AdsError::AdsErrDeviceNotReady
+                | AdsError::AdsErrDeviceBusy
+                | AdsError::AdsErrDeviceTimeout
+                | AdsError::AdsErrDevicePending
+                | AdsError::AdsErrClientSyncTimeout
+        )
+    }
+
+    ///Returns true if the error was raised by the AMS router
+    pub fn is_router(&self) -> bool {
+        self.category() == AdsErrorCategory::Router
+    }
+
+    ///Returns true if a missing or invalid license caused the error
+    pub fn is_license(&self) -> bool {
+        matches!(
+            self,
+            AdsError::AdsErrDeviceLicenseNotFound
+                | AdsError::AdsErrDeviceLicenseExpired
+                | AdsError::AdsErrDeviceLicenseExceeded
+                | AdsError::AdsErrDeviceLicenseInvalid
+                | AdsError::AdsErrDeviceLicenseSystemID
+                | AdsError::AdsErrDeviceLicenseNoTimeLimit
+                | AdsError::AdsErrDeviceLicenseFuturReissue
+                | AdsError::AdsErrDeivceLicenseTimeToLong
+                | AdsError::AdsErrDeviceLicenseDublicated
+                | AdsError::AdsErrDeviceLicenseOemNotFound
+                | AdsError::AdsErrDeviceLicenseRestricted
+                | AdsError::AdsErrDeviceLicenseDemoDenied
+                | AdsError::AdsErrDeviceLicensePlatform
+        )
+    }
+
     pub fn as_u32(&self) -> u32 {
         match self {
             //Global error codes
@@ -600,3 +796,49 @@ impl AdsError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ads_error_category_test() {
+        assert_eq!(AdsError::ErrNoError.category(), AdsErrorCategory::None);
+        assert_eq!(
+            AdsError::ErrAmsSyncTimeout.category(),
+            AdsErrorCategory::Global
+        );
+        assert_eq!(
+            AdsError::RouterErrMailboxFull.category(),
+            AdsErrorCategory::Router
+        );
+        assert_eq!(
+            AdsError::AdsErrDeviceBusy.category(),
+            AdsErrorCategory::Device
+        );
+        assert_eq!(
+            AdsError::AdsErrClientSyncTimeout.category(),
+            AdsErrorCategory::Client
+        );
+        assert_eq!(
+            AdsError::RtErrInternal.category(),
+            AdsErrorCategory::RealTime
+        );
+        assert_eq!(AdsError::from(0x2000).category(), AdsErrorCategory::Unknown);
+    }
+
+    #[test]
+    fn ads_error_classification_test() {
+        assert!(AdsError::AdsErrDeviceBusy.is_transient());
+        assert!(AdsError::ErrAmsSyncTimeout.is_transient());
+        assert!(AdsError::RouterErrMailboxFull.is_transient());
+        assert!(!AdsError::AdsErrDeviceSymbolNotFound.is_transient());
+        assert!(!AdsError::AdsErrDeviceAccessDenied.is_transient());
+
+        assert!(AdsError::RouterErrMailboxFull.is_router());
+        assert!(!AdsError::AdsErrDeviceBusy.is_router());
+
+        assert!(AdsError::AdsErrDeviceLicenseExpired.is_license());
+        assert!(!AdsError::AdsErrDeviceAccessDenied.is_license());
+    }
+}
@@ -4,4 +4,7 @@ extern crate log;
 pub mod ads_services;
 pub mod client;
 pub mod error;
+pub mod mqtt;
 pub mod proto;
+pub mod router;
+pub mod server;
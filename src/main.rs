@@ -105,14 +105,14 @@ fn main() {
 
     //Add device notification
     let var = Var::new("Main._dint".to_string(), PlcTypes::DInt, None);
-    let notification_rx;
-    match connection.add_device_notification(&var, AdsTransMode::OnChange, 10, 10, 2222) {
-        Ok(rx) => notification_rx = rx,
-        Err(e) => {
-            println!("failed to add device notification!\n{}", e);
-            return;
-        }
-    };
+    let notification_rx =
+        match connection.add_device_notification(&var, AdsTransMode::OnChange, 10, 10, 2222) {
+            Ok(rx) => rx,
+            Err(e) => {
+                println!("failed to add device notification!\n{}", e);
+                return;
+            }
+        };
     println!("added device notification");
     let mut counter = 0;
     while counter <= 1000 {
@@ -191,9 +191,8 @@ fn main() {
         Err(e) => println!("Error write control   {:?}", e),
     }
 
-    if let Some(sender) = connection.tx_thread_cancel {
-        println!("cancel reader thread -> {:?}", sender.send(true));
-    }
+    connection.close();
+    println!("connection state -> {:?}", connection.state());
 
     println!("Sleep 5 seconds");
     sleep(Duration::from_millis(5000))
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::mqtt::packet::{topic_matches, Packet, Will};
+use crate::proto::proto_traits::{ReadFrom, WriteTo};
+
+#[derive(Debug)]
+struct Session {
+    stream: TcpStream,
+    filters: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct BrokerState {
+    sessions: HashMap<u64, Session>,
+    retained: BTreeMap<String, Vec<u8>>,
+    published: Vec<(String, Vec<u8>)>,
+}
+
+impl BrokerState {
+    ///Store retained messages and forward to all subscribers
+    fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) {
+        if retain {
+            if payload.is_empty() {
+                self.retained.remove(topic);
+            } else {
+                self.retained.insert(topic.to_string(), payload.to_vec());
+            }
+        }
+        self.published.push((topic.to_string(), payload.to_vec()));
+        let packet = Packet::Publish {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            retain: false,
+        };
+        for session in self.sessions.values_mut() {
+            if session.filters.iter().any(|f| topic_matches(f, topic)) {
+                packet.write_to(&mut session.stream);
+            }
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<BrokerState>>;
+
+fn lock(state: &SharedState) -> MutexGuard<'_, BrokerState> {
+    match state.lock() {
+        Ok(s) => s,
+        Err(_) => panic!("Failed to get lock!"),
+    }
+}
+
+///Minimal MQTT 3.1.1 broker for tests. Supports QoS 0, retained messages,
+///wildcard subscriptions and last will messages. Authentication is not checked.
+#[derive(Debug)]
+pub struct MqttBroker {
+    local_addr: SocketAddr,
+    state: SharedState,
+    running: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl MqttBroker {
+    ///Listen on an ephemeral port of the loopback interface
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let local_addr = listener.local_addr()?;
+        let state: SharedState = Arc::new(Mutex::new(BrokerState::default()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let accept_state = Arc::clone(&state);
+        let accept_running = Arc::clone(&running);
+        let accept_thread = thread::spawn(move || {
+            let session_ids = AtomicU64::new(0);
+            for stream in listener.incoming() {
+                if !accept_running.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        log::warn!("Failed to accept MQTT client. {}", e);
+                        continue;
+                    }
+                };
+                let id = session_ids.fetch_add(1, Ordering::Relaxed);
+                let state = Arc::clone(&accept_state);
+                thread::spawn(move || {
+                    if let Err(e) = MqttBroker::serve(&state, id, stream) {
+                        log::debug!("MQTT session {} closed. {}", id, e);
+                    }
+                });
+            }
+        });
+
+        Ok(MqttBroker {
+            local_addr,
+            state,
+            running,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    ///Publish a message as if a client sent it, e.g. the retained info of a device
+    pub fn publish(&self, topic: &str, payload: &[u8], retain: bool) {
+        lock(&self.state).publish(topic, payload, retain);
+    }
+
+    pub fn retained(&self, topic: &str) -> Option<Vec<u8>> {
+        lock(&self.state).retained.get(topic).cloned()
+    }
+
+    ///Topic and payload of all published messages in order
+    pub fn published(&self) -> Vec<(String, Vec<u8>)> {
+        lock(&self.state).published.clone()
+    }
+
+    ///Number of connected clients
+    pub fn clients(&self) -> usize {
+        lock(&self.state).sessions.len()
+    }
+
+    ///Close all client connections without DISCONNECT. The last will of the clients is published.
+    pub fn drop_clients(&self) {
+        for session in lock(&self.state).sessions.values() {
+            session.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    ///Close the listener and all connections
+    pub fn shutdown(&mut self) {
+        if !self.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        //Wake up the listener which is blocked in accept
+        TcpStream::connect(self.local_addr);
+        if let Some(t) = self.accept_thread.take() {
+            t.join();
+        }
+        self.drop_clients();
+    }
+
+    fn serve(state: &SharedState, id: u64, mut stream: TcpStream) -> io::Result<()> {
+        let will = match Packet::read_from(&mut stream)? {
+            Packet::Connect(connect) => connect.will,
+            packet => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected CONNECT, got {:?}", packet),
+                ))
+            }
+        };
+        let session = Session {
+            stream: stream.try_clone()?,
+            filters: Vec::new(),
+        };
+        {
+            let mut state = lock(state);
+            Packet::ConnAck {
+                session_present: false,
+                return_code: 0,
+            }
+            .write_to(&mut stream)?;
+            state.sessions.insert(id, session);
+        }
+
+        let result = MqttBroker::serve_session(state, id, &mut stream);
+        let mut state = lock(state);
+        state.sessions.remove(&id);
+        match (&result, will) {
+            (Ok(true), _) | (_, None) => (),
+            (_, Some(will)) => state.publish(&will.topic, &will.payload, will.retain),
+        }
+        result.map(|_| ())
+    }
+
+    ///Returns true if the client disconnected with DISCONNECT
+    fn serve_session(state: &SharedState, id: u64, stream: &mut TcpStream) -> io::Result<bool> {
+        loop {
+            let packet = match Packet::read_from(stream) {
+                Ok(p) => p,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            let mut state = lock(state);
+            match packet {
+                Packet::Publish {
+                    topic,
+                    payload,
+                    retain,
+                } => state.publish(&topic, &payload, retain),
+                Packet::Subscribe { packet_id, filters } => {
+                    let reply = Packet::SubAck {
+                        packet_id,
+                        return_codes: vec![0; filters.len()],
+                    };
+                    let retained: Vec<Packet> = state
+                        .retained
+                        .iter()
+                        .filter(|(topic, _)| filters.iter().any(|f| topic_matches(f, topic)))
+                        .map(|(topic, payload)| Packet::Publish {
+                            topic: topic.clone(),
+                            payload: payload.clone(),
+                            retain: true,
+                        })
+                        .collect();
+                    if let Some(session) = state.sessions.get_mut(&id) {
+                        session.filters.extend(filters);
+                    }
+                    reply.write_to(&mut *stream)?;
+                    for packet in retained {
+                        packet.write_to(&mut *stream)?;
+                    }
+                }
+                Packet::Unsubscribe { packet_id, filters } => {
+                    if let Some(session) = state.sessions.get_mut(&id) {
+                        session.filters.retain(|f| !filters.contains(f));
+                    }
+                    Packet::UnsubAck { packet_id }.write_to(&mut *stream)?;
+                }
+                Packet::PingReq => Packet::PingResp.write_to(&mut *stream)?,
+                Packet::Disconnect => return Ok(true),
+                packet => log::debug!("Unexpected MQTT packet {:?}", packet),
+            }
+        }
+    }
+}
+
+impl Drop for MqttBroker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
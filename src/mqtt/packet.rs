@@ -0,0 +1,456 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+use crate::proto::proto_traits::{ReadFrom, WriteTo};
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+///Protocol level of MQTT 3.1.1
+const PROTOCOL_LEVEL: u8 = 4;
+///Largest value of the remaining length field
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+///Message the broker publishes if the client disconnects without DISCONNECT
+#[derive(Debug, Clone, PartialEq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connect {
+    pub client_id: String,
+    ///Seconds. 0 disables the keep alive.
+    pub keep_alive: u16,
+    pub clean_session: bool,
+    pub will: Option<Will>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+///MQTT 3.1.1 control packets with QoS 0 publishing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Connect(Connect),
+    ConnAck {
+        session_present: bool,
+        return_code: u8,
+    },
+    ///Packet id of QoS 1 and 2 publishes is dropped
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    Subscribe {
+        packet_id: u16,
+        filters: Vec<String>,
+    },
+    SubAck {
+        packet_id: u16,
+        return_codes: Vec<u8>,
+    },
+    Unsubscribe {
+        packet_id: u16,
+        filters: Vec<String>,
+    },
+    UnsubAck {
+        packet_id: u16,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Packet {
+    ///Decode the first packet in buf.
+    ///Returns the packet and its length or None if buf does not hold a complete packet.
+    pub fn decode(buf: &[u8]) -> io::Result<Option<(Packet, usize)>> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let mut remaining_length = 0;
+        let mut header_length = 1;
+        loop {
+            let byte = match buf.get(header_length) {
+                Some(b) => *b,
+                None => return Ok(None),
+            };
+            remaining_length += ((byte & 0x7F) as usize) << (7 * (header_length - 1));
+            header_length += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if header_length > 4 {
+                return Err(invalid_data("Remaining length exceeds 4 bytes"));
+            }
+        }
+        let length = header_length + remaining_length;
+        if buf.len() < length {
+            return Ok(None);
+        }
+        let packet = Packet::parse(buf[0], &buf[header_length..length])?;
+        Ok(Some((packet, length)))
+    }
+
+    fn parse(first: u8, mut body: &[u8]) -> io::Result<Packet> {
+        let body = &mut body;
+        let packet = match first >> 4 {
+            CONNECT => {
+                if read_string(body)? != "MQTT" || body.read_u8()? != PROTOCOL_LEVEL {
+                    return Err(invalid_data("Only MQTT 3.1.1 is supported"));
+                }
+                let flags = body.read_u8()?;
+                let keep_alive = body.read_u16::<BigEndian>()?;
+                let client_id = read_string(body)?;
+                let will = match flags & 0x04 {
+                    0 => None,
+                    _ => Some(Will {
+                        topic: read_string(body)?,
+                        payload: read_bytes(body)?,
+                        retain: flags & 0x20 != 0,
+                    }),
+                };
+                let username = match flags & 0x80 {
+                    0 => None,
+                    _ => Some(read_string(body)?),
+                };
+                let password = match flags & 0x40 {
+                    0 => None,
+                    _ => Some(String::from_utf8(read_bytes(body)?).map_err(invalid_data)?),
+                };
+                Packet::Connect(Connect {
+                    client_id,
+                    keep_alive,
+                    clean_session: flags & 0x02 != 0,
+                    will,
+                    username,
+                    password,
+                })
+            }
+            CONNACK => Packet::ConnAck {
+                session_present: body.read_u8()? & 0x01 != 0,
+                return_code: body.read_u8()?,
+            },
+            PUBLISH => {
+                let topic = read_string(body)?;
+                if (first >> 1) & 0x03 > 0 {
+                    body.read_u16::<BigEndian>()?;
+                }
+                Packet::Publish {
+                    topic,
+                    payload: body.to_vec(),
+                    retain: first & 0x01 != 0,
+                }
+            }
+            SUBSCRIBE => {
+                let packet_id = body.read_u16::<BigEndian>()?;
+                let mut filters = Vec::new();
+                while !body.is_empty() {
+                    filters.push(read_string(body)?);
+                    body.read_u8()?;
+                }
+                Packet::Subscribe { packet_id, filters }
+            }
+            SUBACK => Packet::SubAck {
+                packet_id: body.read_u16::<BigEndian>()?,
+                return_codes: body.to_vec(),
+            },
+            UNSUBSCRIBE => {
+                let packet_id = body.read_u16::<BigEndian>()?;
+                let mut filters = Vec::new();
+                while !body.is_empty() {
+                    filters.push(read_string(body)?);
+                }
+                Packet::Unsubscribe { packet_id, filters }
+            }
+            UNSUBACK => Packet::UnsubAck {
+                packet_id: body.read_u16::<BigEndian>()?,
+            },
+            PINGREQ => Packet::PingReq,
+            PINGRESP => Packet::PingResp,
+            DISCONNECT => Packet::Disconnect,
+            packet_type => {
+                return Err(invalid_data(format!(
+                    "Unsupported packet type {}",
+                    packet_type
+                )))
+            }
+        };
+        Ok(packet)
+    }
+
+    fn first_byte(&self) -> u8 {
+        match self {
+            Packet::Connect(_) => CONNECT << 4,
+            Packet::ConnAck { .. } => CONNACK << 4,
+            Packet::Publish { retain, .. } => PUBLISH << 4 | *retain as u8,
+            Packet::Subscribe { .. } => SUBSCRIBE << 4 | 0x02,
+            Packet::SubAck { .. } => SUBACK << 4,
+            Packet::Unsubscribe { .. } => UNSUBSCRIBE << 4 | 0x02,
+            Packet::UnsubAck { .. } => UNSUBACK << 4,
+            Packet::PingReq => PINGREQ << 4,
+            Packet::PingResp => PINGRESP << 4,
+            Packet::Disconnect => DISCONNECT << 4,
+        }
+    }
+
+    fn write_body(&self, body: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            Packet::Connect(connect) => {
+                write_string(body, "MQTT")?;
+                body.write_u8(PROTOCOL_LEVEL)?;
+                let mut flags = 0;
+                if connect.clean_session {
+                    flags |= 0x02;
+                }
+                if let Some(will) = &connect.will {
+                    flags |= 0x04;
+                    if will.retain {
+                        flags |= 0x20;
+                    }
+                }
+                if connect.password.is_some() {
+                    flags |= 0x40;
+                }
+                if connect.username.is_some() {
+                    flags |= 0x80;
+                }
+                body.write_u8(flags)?;
+                body.write_u16::<BigEndian>(connect.keep_alive)?;
+                write_string(body, &connect.client_id)?;
+                if let Some(will) = &connect.will {
+                    write_string(body, &will.topic)?;
+                    write_bytes(body, &will.payload)?;
+                }
+                if let Some(username) = &connect.username {
+                    write_string(body, username)?;
+                }
+                if let Some(password) = &connect.password {
+                    write_string(body, password)?;
+                }
+            }
+            Packet::ConnAck {
+                session_present,
+                return_code,
+            } => {
+                body.write_u8(*session_present as u8)?;
+                body.write_u8(*return_code)?;
+            }
+            Packet::Publish { topic, payload, .. } => {
+                write_string(body, topic)?;
+                body.write_all(payload)?;
+            }
+            Packet::Subscribe { packet_id, filters } => {
+                body.write_u16::<BigEndian>(*packet_id)?;
+                for filter in filters {
+                    write_string(body, filter)?;
+                    body.write_u8(0)?;
+                }
+            }
+            Packet::SubAck {
+                packet_id,
+                return_codes,
+            } => {
+                body.write_u16::<BigEndian>(*packet_id)?;
+                body.write_all(return_codes)?;
+            }
+            Packet::Unsubscribe { packet_id, filters } => {
+                body.write_u16::<BigEndian>(*packet_id)?;
+                for filter in filters {
+                    write_string(body, filter)?;
+                }
+            }
+            Packet::UnsubAck { packet_id } => body.write_u16::<BigEndian>(*packet_id)?,
+            Packet::PingReq | Packet::PingResp | Packet::Disconnect => (),
+        }
+        Ok(())
+    }
+}
+
+impl WriteTo for Packet {
+    fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        let mut body = Vec::new();
+        self.write_body(&mut body)?;
+        if body.len() > MAX_REMAINING_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MQTT packet too large",
+            ));
+        }
+        let mut buffer = vec![self.first_byte()];
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            buffer.push(byte);
+            if length == 0 {
+                break;
+            }
+        }
+        buffer.extend_from_slice(&body);
+        wtr.write_all(&buffer)
+    }
+}
+
+impl ReadFrom for Packet {
+    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+        let first = read.read_u8()?;
+        let mut remaining_length = 0;
+        for i in 0..4 {
+            let byte = read.read_u8()?;
+            remaining_length += ((byte & 0x7F) as usize) << (7 * i);
+            if byte & 0x80 == 0 {
+                let mut body = vec![0; remaining_length];
+                read.read_exact(&mut body)?;
+                return Packet::parse(first, &body);
+            }
+        }
+        Err(invalid_data("Remaining length exceeds 4 bytes"))
+    }
+}
+
+///Check if a topic matches a subscription filter with + and # wildcards
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => continue,
+            (Some(f), Some(t)) if f == t => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn read_bytes(body: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let length = body.read_u16::<BigEndian>()? as usize;
+    let mut bytes = vec![0; length];
+    body.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_string(body: &mut &[u8]) -> io::Result<String> {
+    String::from_utf8(read_bytes(body)?).map_err(invalid_data)
+}
+
+fn write_bytes(body: &mut Vec<u8>, bytes: &[u8]) -> io::Result<()> {
+    if bytes.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "MQTT string too long",
+        ));
+    }
+    body.write_u16::<BigEndian>(bytes.len() as u16)?;
+    body.write_all(bytes)
+}
+
+fn write_string(body: &mut Vec<u8>, value: &str) -> io::Result<()> {
+    write_bytes(body, value.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(packet: Packet) {
+        let mut buffer = Vec::new();
+        packet.write_to(&mut buffer).unwrap();
+        assert_eq!(Packet::read_from(&mut buffer.as_slice()).unwrap(), packet);
+        assert_eq!(
+            Packet::decode(&buffer).unwrap(),
+            Some((packet, buffer.len()))
+        );
+        assert_eq!(Packet::decode(&buffer[..buffer.len() - 1]).unwrap(), None);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        round_trip(Packet::Connect(Connect {
+            client_id: "ads".to_string(),
+            keep_alive: 60,
+            clean_session: true,
+            will: Some(Will {
+                topic: "VirtualAmsNetwork1/1.2.3.4.1.1/info".to_string(),
+                payload: b"offline".to_vec(),
+                retain: true,
+            }),
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+        }));
+        round_trip(Packet::ConnAck {
+            session_present: false,
+            return_code: 0,
+        });
+        round_trip(Packet::Publish {
+            topic: "a/b".to_string(),
+            payload: vec![7; 200],
+            retain: true,
+        });
+        round_trip(Packet::Subscribe {
+            packet_id: 1,
+            filters: vec!["a/#".to_string(), "b/+/c".to_string()],
+        });
+        round_trip(Packet::SubAck {
+            packet_id: 1,
+            return_codes: vec![0, 0],
+        });
+        round_trip(Packet::Unsubscribe {
+            packet_id: 2,
+            filters: vec!["a/#".to_string()],
+        });
+        round_trip(Packet::UnsubAck { packet_id: 2 });
+        round_trip(Packet::PingReq);
+    }
+
+    #[test]
+    fn encode_test() {
+        let mut buffer = Vec::new();
+        Packet::Publish {
+            topic: "a".to_string(),
+            payload: vec![1, 2],
+            retain: false,
+        }
+        .write_to(&mut buffer)
+        .unwrap();
+        assert_eq!(buffer, [0x30, 5, 0, 1, b'a', 1, 2]);
+
+        buffer.clear();
+        Packet::Disconnect.write_to(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xE0, 0]);
+    }
+
+    #[test]
+    fn topic_matches_test() {
+        assert!(topic_matches("a/b", "a/b"));
+        assert!(!topic_matches("a/b", "a/b/c"));
+        assert!(topic_matches("a/+/c", "a/b/c"));
+        assert!(!topic_matches("a/+", "a/b/c"));
+        assert!(topic_matches("a/#", "a"));
+        assert!(topic_matches("a/#", "a/b/c"));
+        assert!(topic_matches("#", "a/b"));
+        assert!(!topic_matches("a/b", "a"));
+    }
+}
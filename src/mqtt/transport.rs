@@ -0,0 +1,660 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::client::read::{is_timeout, AMS_HEADER_SIZE, AMS_TCP_HEADER_SIZE};
+use crate::client::transport::{ReadHalf, Transport, TransportWrite, WriteHalf};
+use crate::mqtt::packet::{Connect, Packet, Will};
+use crate::proto::ams_address::{AmsAddress, AmsNetId};
+use crate::proto::proto_traits::WriteTo;
+use crate::proto::state_flags::StateFlags;
+
+///Default TCP port of an MQTT broker
+pub const MQTT_PORT: u16 = 1883;
+///Default topic of the virtual AMS network
+pub const DEFAULT_TOPIC: &str = "VirtualAmsNetwork1";
+///Offset of the state flags in the AMS header
+const STATE_FLAGS_OFFSET: usize = 18;
+///Bytes requested from the stream per read call
+const READ_CHUNK_SIZE: usize = 4096;
+
+///Participant of the virtual AMS network and its broker connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    ///Topic of the virtual AMS network. Has to match the topic configured on the devices.
+    pub topic: String,
+    ///Local AMS address. Frames addressed to its NetId are received.
+    pub source: AmsAddress,
+    ///Name published in the info topic
+    pub name: String,
+    ///MQTT client id. Derived from the NetId if empty.
+    pub client_id: String,
+    pub keep_alive: Duration,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl MqttConfig {
+    pub fn new(source: AmsAddress) -> Self {
+        MqttConfig {
+            topic: DEFAULT_TOPIC.to_string(),
+            source,
+            name: "rust-ads".to_string(),
+            client_id: String::new(),
+            keep_alive: Duration::from_secs(60),
+            username: None,
+            password: None,
+        }
+    }
+
+    ///Requests to a device are published here
+    pub fn request_topic(&self, net_id: &AmsNetId) -> String {
+        format!("{}/{}/ams", self.topic, net_id)
+    }
+
+    ///Responses to a device are published here
+    pub fn response_topic(&self, net_id: &AmsNetId) -> String {
+        format!("{}/{}/ams/res", self.topic, net_id)
+    }
+
+    ///Retained online state of a device
+    pub fn info_topic(&self, net_id: &AmsNetId) -> String {
+        format!("{}/{}/info", self.topic, net_id)
+    }
+
+    fn connect_packet(&self, client_id: String, will: Option<Will>) -> Packet {
+        Packet::Connect(Connect {
+            client_id,
+            keep_alive: self.keep_alive.as_secs().min(u16::MAX as u64) as u16,
+            clean_session: true,
+            will,
+            username: self.username.clone(),
+            password: self.password.clone(),
+        })
+    }
+
+    fn info(&self, online: bool) -> Vec<u8> {
+        format!(
+            "<info><online name=\"{}\">{}</online></info>",
+            self.name, online
+        )
+        .into_bytes()
+    }
+}
+
+///Device announced in the info topic of a virtual AMS network
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttDevice {
+    pub net_id: AmsNetId,
+    pub name: Option<String>,
+    pub online: bool,
+}
+
+impl MqttDevice {
+    ///Parse the payload of an info topic, e.g. <info><online name="PLC1">true</online></info>
+    fn parse(net_id: AmsNetId, info: &str) -> Self {
+        let online = info.find("<online").and_then(|start| {
+            let element = &info[start..];
+            let end = element.find("</online>")?;
+            let content_start = element.find('>')? + 1;
+            let tag = &element[..content_start];
+            let name = tag.find("name=\"").and_then(|i| {
+                let value = &tag[i + 6..];
+                value.find('"').map(|end| value[..end].to_string())
+            });
+            Some((name, element[content_start..end].trim() == "true"))
+        });
+        let (name, online) = online.unwrap_or((None, false));
+        MqttDevice {
+            net_id,
+            name,
+            online,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    writer: Mutex<WriteHalf>,
+    config: MqttConfig,
+    last_sent: Mutex<Instant>,
+    closed: AtomicBool,
+}
+
+impl Shared {
+    fn send(&self, packet: &Packet) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        packet.write_to(&mut buffer)?;
+        match self.writer.lock() {
+            Ok(mut w) => w.write_all(&buffer)?,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        *lock(&self.last_sent) = Instant::now();
+        Ok(())
+    }
+
+    ///Publish one AMS/TCP frame to the topic of its target
+    fn publish(&self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        if buf.len() < AMS_HEADER_SIZE || buf[0..2] != [0, 0] {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Only ADS frames can be sent over MQTT",
+            ));
+        }
+        let frame = &buf[AMS_TCP_HEADER_SIZE..];
+        let mut target = [0; 6];
+        target.copy_from_slice(&frame[..6]);
+        let target = AmsNetId::from(target);
+        let flags = StateFlags::from(u16::from_le_bytes([
+            frame[STATE_FLAGS_OFFSET],
+            frame[STATE_FLAGS_OFFSET + 1],
+        ]));
+        let topic = match flags.is_response() {
+            true => self.config.response_topic(&target),
+            false => self.config.request_topic(&target),
+        };
+        self.send(&Packet::Publish {
+            topic,
+            payload: frame.to_vec(),
+            retain: false,
+        })?;
+        Ok(buf.len())
+    }
+
+    ///Publish the offline state and disconnect from the broker
+    fn shutdown(&self) -> io::Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let config = &self.config;
+        self.send(&Packet::Publish {
+            topic: config.info_topic(&config.source.ams_net_id),
+            payload: config.info(false),
+            retain: true,
+        })?;
+        self.send(&Packet::Disconnect)?;
+        lock(&self.writer).shutdown()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(m) => m,
+        Err(_) => panic!("Failed to get lock!"),
+    }
+}
+
+///ADS over MQTT. AMS frames are published to the request topic of the target NetId
+///or to its response topic if the frame is a response. Frames published to the topics
+///of the own NetId are received.
+///The own online state is published retained in the info topic. The broker publishes
+///the offline state as last will if the connection is lost.
+pub struct MqttTransport {
+    shared: Arc<Shared>,
+    reader: ReadHalf,
+    ///Received MQTT bytes which do not form a complete packet yet
+    buffer: Vec<u8>,
+    ///Received frames with AMS/TCP header which were not read yet
+    incoming: Vec<u8>,
+}
+
+impl fmt::Debug for MqttTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttTransport")
+            .field("shared", &self.shared)
+            .field("buffer", &self.buffer)
+            .field("incoming", &self.incoming)
+            .finish()
+    }
+}
+
+///Write half of a split MqttTransport
+#[derive(Debug)]
+struct MqttWriter {
+    shared: Arc<Shared>,
+}
+
+impl MqttTransport {
+    ///Connect to the broker over TCP
+    pub fn connect<A: ToSocketAddrs>(addr: A, config: MqttConfig) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        //Wake up regularly to keep the broker connection alive
+        stream.set_read_timeout(Some(ping_interval(&config)))?;
+        MqttTransport::new(Box::new(stream), config)
+    }
+
+    ///Run the MQTT session over an established stream, e.g. a TLS connection to the broker.
+    ///Keep alive pings are sent on read timeouts of the stream.
+    pub fn new(stream: Box<dyn Transport>, config: MqttConfig) -> io::Result<Self> {
+        let net_id = &config.source.ams_net_id;
+        let client_id = match config.client_id.as_str() {
+            "" => format!("ads-{}", net_id),
+            id => id.to_string(),
+        };
+        let will = Will {
+            topic: config.info_topic(net_id),
+            payload: config.info(false),
+            retain: true,
+        };
+        let (reader, writer) = stream.split()?;
+        let mut transport = MqttTransport {
+            reader,
+            shared: Arc::new(Shared {
+                writer: Mutex::new(writer),
+                config: config.clone(),
+                last_sent: Mutex::new(Instant::now()),
+                closed: AtomicBool::new(false),
+            }),
+            buffer: Vec::new(),
+            incoming: Vec::new(),
+        };
+        transport.handshake(config.connect_packet(client_id, Some(will)))?;
+        transport.shared.send(&Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![format!("{}/#", config.request_topic(net_id))],
+        })?;
+        transport.shared.send(&Packet::Publish {
+            topic: config.info_topic(net_id),
+            payload: config.info(true),
+            retain: true,
+        })?;
+        Ok(transport)
+    }
+
+    ///Collect the devices which announced themselves in the info topic of the network.
+    ///Waits for retained info messages until timeout.
+    pub fn discover<A: ToSocketAddrs>(
+        addr: A,
+        config: &MqttConfig,
+        timeout: Duration,
+    ) -> io::Result<Vec<MqttDevice>> {
+        let stream = TcpStream::connect(addr)?;
+        let deadline = Instant::now() + timeout;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut session = MqttTransport {
+            reader: Box::new(stream.try_clone()?),
+            shared: Arc::new(Shared {
+                writer: Mutex::new(Box::new(stream.try_clone()?)),
+                config: config.clone(),
+                last_sent: Mutex::new(Instant::now()),
+                closed: AtomicBool::new(false),
+            }),
+            buffer: Vec::new(),
+            incoming: Vec::new(),
+        };
+        let client_id = format!("ads-discovery-{}", config.source.ams_net_id);
+        session.handshake(config.connect_packet(client_id, None))?;
+        session.shared.send(&Packet::Subscribe {
+            packet_id: 1,
+            filters: vec![format!("{}/+/info", config.topic)],
+        })?;
+
+        let mut devices = BTreeMap::new();
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if remaining.as_millis() == 0 {
+                break;
+            }
+            stream.set_read_timeout(Some(remaining))?;
+            let packet = match session.next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => break,
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(e),
+            };
+            if let Packet::Publish { topic, payload, .. } = packet {
+                let net_id = topic
+                    .strip_prefix(&format!("{}/", config.topic))
+                    .and_then(|t| t.strip_suffix("/info"))
+                    .and_then(|n| AmsNetId::from_str(n).ok());
+                if let Some(net_id) = net_id {
+                    let info = String::from_utf8_lossy(&payload);
+                    devices.insert(net_id.net_id(), MqttDevice::parse(net_id, &info));
+                }
+            }
+        }
+        session.shared.send(&Packet::Disconnect)?;
+        Ok(devices.into_values().collect())
+    }
+
+    ///Send CONNECT and wait for the CONNACK of the broker
+    fn handshake(&mut self, connect: Packet) -> io::Result<()> {
+        self.shared.send(&connect)?;
+        loop {
+            match self.next_packet() {
+                Ok(Some(Packet::ConnAck { return_code: 0, .. })) => return Ok(()),
+                Ok(Some(Packet::ConnAck { return_code, .. })) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        format!("Broker refused connection (return code {})", return_code),
+                    ))
+                }
+                Ok(Some(packet)) => log::debug!("Unexpected MQTT packet {:?}", packet),
+                Ok(None) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    ///Read the next MQTT packet. Returns None if the broker closed the connection.
+    ///Received bytes are kept on a read timeout.
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some((packet, length)) = Packet::decode(&self.buffer)? {
+                self.buffer.drain(..length);
+                return Ok(Some(packet));
+            }
+            let n = self.reader.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn keep_alive(&self) -> io::Result<()> {
+        let config = &self.shared.config;
+        let idle = lock(&self.shared.last_sent).elapsed();
+        if !config.keep_alive.is_zero() && idle >= ping_interval(config) {
+            self.shared.send(&Packet::PingReq)?;
+        }
+        Ok(())
+    }
+}
+
+fn ping_interval(config: &MqttConfig) -> Duration {
+    match config.keep_alive.is_zero() {
+        true => Duration::from_secs(1),
+        false => config.keep_alive / 2,
+    }
+}
+
+impl Read for MqttTransport {
+    ///Returns 0 once the transport is shut down or the broker closed the connection
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.incoming.is_empty() {
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            let packet = self.next_packet();
+            //Only sent packets keep the connection alive. Incoming publishes don't.
+            self.keep_alive()?;
+            let packet = match packet {
+                Ok(Some(packet)) => packet,
+                Ok(None) => return Ok(0),
+                Err(e) => return Err(e),
+            };
+            match packet {
+                Packet::Publish { payload, .. }
+                    if payload.len() >= AMS_HEADER_SIZE - AMS_TCP_HEADER_SIZE =>
+                {
+                    self.incoming.extend_from_slice(&[0, 0]);
+                    self.incoming
+                        .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                    self.incoming.extend_from_slice(&payload);
+                }
+                Packet::Publish { topic, .. } => {
+                    log::warn!("Skipped MQTT message without AMS frame on {}", topic)
+                }
+                Packet::SubAck { .. } | Packet::PingResp => (),
+                packet => log::debug!("Unexpected MQTT packet {:?}", packet),
+            }
+        }
+        let n = buf.len().min(self.incoming.len());
+        buf[..n].copy_from_slice(&self.incoming[..n]);
+        self.incoming.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for MqttTransport {
+    ///buf has to be exactly one AMS/TCP frame
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.publish(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MqttTransport {
+    ///The transport itself is the read half. Frames are published through the shared broker connection.
+    fn split(self: Box<Self>) -> io::Result<(ReadHalf, WriteHalf)> {
+        let writer = MqttWriter {
+            shared: Arc::clone(&self.shared),
+        };
+        Ok((self, Box::new(writer)))
+    }
+
+    ///Publish the offline state and disconnect from the broker
+    fn shutdown(&self) -> io::Result<()> {
+        self.shared.shutdown()
+    }
+}
+
+impl Write for MqttWriter {
+    ///buf has to be exactly one AMS/TCP frame
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.publish(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TransportWrite for MqttWriter {
+    fn shutdown(&self) -> io::Result<()> {
+        self.shared.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ads_client::Connection;
+    use crate::client::plc_types::{PlcTypes, Var};
+    use crate::mqtt::broker::MqttBroker;
+    use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+    use crate::proto::proto_traits::ReadFrom;
+    use crate::server::ads_server::AdsServer;
+    use crate::server::simulator::{SymbolTable, VirtualPlc};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn plc_net_id() -> AmsNetId {
+        AmsNetId::new(5, 1, 2, 3, 1, 1)
+    }
+
+    ///The broker handles messages on its own threads
+    fn assert_retained(broker: &MqttBroker, topic: &str, expected: &[u8]) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while broker.retained(topic).as_deref() != Some(expected) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(broker.retained(topic).as_deref(), Some(expected));
+    }
+
+    ///Serve a device behind the broker. Frames published to its request topic are
+    ///dispatched and the responses published to the response topic of the sender.
+    fn serve_device(broker: &MqttBroker, server: AdsServer) {
+        let mut config = MqttConfig::new(AmsAddress::new(plc_net_id(), 851));
+        config.name = "PLC1".to_string();
+        let mut transport = MqttTransport::connect(broker.local_addr(), config).unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0; READ_CHUNK_SIZE];
+            loop {
+                let n = match transport.read(&mut buffer) {
+                    Ok(0) => return,
+                    Ok(n) => n,
+                    Err(e) if is_timeout(&e) => continue,
+                    Err(_) => return,
+                };
+                let frame = AmsTcpHeader::read_from(&mut &buffer[..n]).unwrap();
+                if let Some(response) = server.dispatch(&frame) {
+                    let mut out = Vec::new();
+                    AmsTcpHeader::from(response).write_to(&mut out).unwrap();
+                    transport.write_all(&out).unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn connection_test() {
+        let broker = MqttBroker::start().unwrap();
+        let plc = VirtualPlc::new(SymbolTable::parse("MAIN.counter : INT := 7;").unwrap());
+        let server = AdsServer::new(plc_net_id());
+        server.add_device(851, plc);
+        serve_device(&broker, server);
+
+        let source = AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000);
+        let mut connection = Connection::builder(AmsAddress::new(plc_net_id(), 851))
+            .host("127.0.0.1")
+            .mqtt(MqttConfig::new(source))
+            .port(broker.local_addr().port())
+            .connect()
+            .unwrap();
+        let counter = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        connection.get_symhandle(&counter, 1).unwrap();
+        assert_eq!(connection.read_by_name(&counter, 2).unwrap(), vec![7, 0]);
+
+        let published = broker.published();
+        assert!(published
+            .iter()
+            .any(|(topic, _)| topic == "VirtualAmsNetwork1/5.1.2.3.1.1/ams"));
+        assert!(published
+            .iter()
+            .any(|(topic, _)| topic == "VirtualAmsNetwork1/10.0.0.1.1.1/ams/res"));
+
+        connection.close();
+        assert_retained(
+            &broker,
+            "VirtualAmsNetwork1/10.0.0.1.1.1/info",
+            b"<info><online name=\"rust-ads\">false</online></info>",
+        );
+    }
+
+    #[test]
+    fn keep_alive_with_incoming_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = MqttConfig::new(AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000));
+        config.keep_alive = Duration::from_millis(200);
+        let topic = config.request_topic(&config.source.ams_net_id);
+
+        //Broker which publishes to the client every 10 ms and waits for a ping
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Packet::read_from(&mut stream).unwrap();
+            Packet::ConnAck {
+                session_present: false,
+                return_code: 0,
+            }
+            .write_to(&mut stream)
+            .unwrap();
+            let mut publisher = stream.try_clone().unwrap();
+            thread::spawn(move || {
+                for _ in 0..300 {
+                    let publish = Packet::Publish {
+                        topic: topic.clone(),
+                        payload: vec![1, 2, 3],
+                        retain: false,
+                    };
+                    if publish.write_to(&mut publisher).is_err() {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+            });
+            //Pings are due every 100 ms
+            let deadline = Instant::now() + Duration::from_secs(1);
+            stream
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let mut pinged = false;
+            while !pinged && Instant::now() < deadline {
+                match Packet::read_from(&mut stream) {
+                    Ok(packet) => pinged = packet == Packet::PingReq,
+                    Err(_) => break,
+                }
+            }
+            //Also stops the publisher
+            stream.shutdown(std::net::Shutdown::Both);
+            pinged
+        });
+
+        let mut transport = MqttTransport::connect(addr, config).unwrap();
+        let mut buffer = [0; READ_CHUNK_SIZE];
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !broker.is_finished() && Instant::now() < deadline {
+            if let Ok(0) = transport.read(&mut buffer) {
+                break;
+            }
+        }
+        drop(transport);
+        assert!(broker.join().unwrap());
+    }
+
+    #[test]
+    fn discover_test() {
+        let broker = MqttBroker::start().unwrap();
+        serve_device(&broker, AdsServer::new(plc_net_id()));
+        broker.publish(
+            "VirtualAmsNetwork1/7.7.7.7.1.1/info",
+            b"<info><online name=\"PLC2\" osVersion=\"7.1\">false</online></info>",
+            true,
+        );
+        broker.publish("OtherNetwork/8.8.8.8.1.1/info", b"<info/>", true);
+
+        let config = MqttConfig::new(AmsAddress::new(AmsNetId::new(10, 0, 0, 2, 1, 1), 0));
+        let devices =
+            MqttTransport::discover(broker.local_addr(), &config, Duration::from_millis(300))
+                .unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                MqttDevice {
+                    net_id: plc_net_id(),
+                    name: Some("PLC1".to_string()),
+                    online: true,
+                },
+                MqttDevice {
+                    net_id: AmsNetId::new(7, 7, 7, 7, 1, 1),
+                    name: Some("PLC2".to_string()),
+                    online: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn last_will_test() {
+        let broker = MqttBroker::start().unwrap();
+        let config = MqttConfig::new(AmsAddress::new(plc_net_id(), 851));
+        let _transport = MqttTransport::connect(broker.local_addr(), config).unwrap();
+        let info_topic = "VirtualAmsNetwork1/5.1.2.3.1.1/info";
+        assert_retained(
+            &broker,
+            info_topic,
+            b"<info><online name=\"rust-ads\">true</online></info>",
+        );
+
+        broker.drop_clients();
+        assert_retained(
+            &broker,
+            info_topic,
+            b"<info><online name=\"rust-ads\">false</online></info>",
+        );
+    }
+}
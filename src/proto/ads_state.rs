@@ -1,4 +1,4 @@
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Read, Write};
 
@@ -56,6 +56,12 @@ impl WriteTo for AdsState {
     }
 }
 
+impl EncodedLen for AdsState {
+    fn encoded_len(&self) -> usize {
+        2
+    }
+}
+
 impl ReadFrom for AdsState {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(AdsState::from(read.read_u16::<LittleEndian>()?))
@@ -1,4 +1,4 @@
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Read, Write};
 
@@ -36,6 +36,12 @@ impl WriteTo for AdsTransMode {
     }
 }
 
+impl EncodedLen for AdsTransMode {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+}
+
 impl ReadFrom for AdsTransMode {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(AdsTransMode::from(read.read_u32::<LittleEndian>()?))
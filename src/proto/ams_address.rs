@@ -1,10 +1,12 @@
 use crate::error::AmsAddressError;
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
 use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AmsAddress {
     pub ams_net_id: AmsNetId,
     pub port: u16,
@@ -21,6 +23,22 @@ impl AmsAddress {
         self.port = ams_address.port;
         Ok(())
     }
+
+    ///Derive the address from an IPv4 socket address (ip.1.1:port)
+    pub fn from_socket_addr(socket_addr: &SocketAddr) -> Result<Self, AmsAddressError> {
+        let ip = match socket_addr.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => ip,
+                None => return Err(AmsAddressError::Ipv6Address { address: ip }),
+            },
+        };
+        let [a, b, c, d] = ip.octets();
+        Ok(AmsAddress::new(
+            AmsNetId::new(a, b, c, d, 1, 1),
+            socket_addr.port(),
+        ))
+    }
 }
 
 impl WriteTo for AmsAddress {
@@ -31,6 +49,12 @@ impl WriteTo for AmsAddress {
     }
 }
 
+impl EncodedLen for AmsAddress {
+    fn encoded_len(&self) -> usize {
+        self.ams_net_id.encoded_len() + 2
+    }
+}
+
 impl ReadFrom for AmsAddress {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(AmsAddress {
@@ -51,17 +75,15 @@ impl FromStr for AmsAddress {
         }
 
         let ams_net_id = AmsNetId::from_str(split_socket[0])?;
-        let port;
-
-        match split_socket[1].parse::<u16>() {
-            Ok(p) => port = p,
+        let port = match split_socket[1].parse::<u16>() {
+            Ok(p) => p,
             Err(e) => return Err(AmsAddressError::ParseError { source: e }),
-        }
+        };
         Ok(AmsAddress::new(ams_net_id, port))
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AmsNetId {
     net_id: [u8; 6],
 }
@@ -73,6 +95,12 @@ impl WriteTo for AmsNetId {
     }
 }
 
+impl EncodedLen for AmsNetId {
+    fn encoded_len(&self) -> usize {
+        self.net_id.len()
+    }
+}
+
 impl ReadFrom for AmsNetId {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let mut buffer: [u8; 6] = [0; 6];
@@ -111,6 +139,14 @@ impl FromStr for AmsNetId {
     }
 }
 
+///Dotted notation, e.g. 192.168.0.1.1.1
+impl fmt::Display for AmsNetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.net_id;
+        write!(f, "{}.{}.{}.{}.{}.{}", a, b, c, d, e, g)
+    }
+}
+
 impl AmsNetId {
     #[allow(clippy::many_single_char_names)]
     pub fn new(a: u8, b: u8, c: u8, d: u8, e: u8, f: u8) -> AmsNetId {
@@ -154,6 +190,12 @@ mod tests {
         //assert_eq!(ams_parse_error, AmsAddressError::ParseError{kind: std::num::IntErrorKind::PosOverflow});
     }
 
+    #[test]
+    fn ams_net_id_display_test() {
+        let ams_net_id = AmsNetId::new(192, 168, 1, 1, 1, 1);
+        assert_eq!(ams_net_id.to_string(), "192.168.1.1.1.1");
+    }
+
     #[test]
     fn ams_net_id_write_to_test() {
         let ams_net_id = AmsNetId::from([192, 168, 1, 1, 1, 1]);
@@ -191,6 +233,26 @@ mod tests {
         assert_eq!(buffer, [192, 168, 1, 1, 1, 1, 48, 117]);
     }
 
+    #[test]
+    fn ams_address_from_socket_addr_test() {
+        let socket_addr = SocketAddr::from_str("192.168.1.2:30000").unwrap();
+        let ams_address = AmsAddress::from_socket_addr(&socket_addr).unwrap();
+        assert_eq!(ams_address.ams_net_id.net_id, [192, 168, 1, 2, 1, 1]);
+        assert_eq!(ams_address.port, 30000);
+
+        let socket_addr = SocketAddr::from_str("[::ffff:192.168.1.2]:30000").unwrap();
+        let ams_address = AmsAddress::from_socket_addr(&socket_addr).unwrap();
+        assert_eq!(ams_address.ams_net_id.net_id, [192, 168, 1, 2, 1, 1]);
+
+        let socket_addr = SocketAddr::from_str("[::1]:30000").unwrap();
+        assert_eq!(
+            AmsAddress::from_socket_addr(&socket_addr).unwrap_err(),
+            AmsAddressError::Ipv6Address {
+                address: "::1".parse().unwrap()
+            }
+        );
+    }
+
     #[test]
     fn ams_address_read_from_test() {
         let data: Vec<u8> = vec![192, 168, 1, 1, 1, 1, 48, 117];
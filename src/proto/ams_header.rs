@@ -1,9 +1,10 @@
 use crate::error::{AdsError, TryIntoError};
 use crate::proto::ams_address::AmsAddress;
 use crate::proto::command_id::CommandID;
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use crate::proto::request::*;
 use crate::proto::response::*;
+use crate::proto::router_command::AmsTcpCommand;
 use crate::proto::state_flags::StateFlags;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Read, Write};
@@ -11,6 +12,8 @@ use std::str::FromStr;
 
 ///Length of the fix part of the AMS Header in bytes
 const FIX_AMS_HEADER_LEN: u32 = 32;
+///Length of the AMS/TCP header (reserved + length) in bytes
+const AMS_TCP_HEADER_LEN: usize = 6;
 
 #[derive(Debug)]
 pub struct AmsTcpHeader {
@@ -28,6 +31,12 @@ impl WriteTo for AmsTcpHeader {
     }
 }
 
+impl EncodedLen for AmsTcpHeader {
+    fn encoded_len(&self) -> usize {
+        AMS_TCP_HEADER_LEN + self.ams_header.encoded_len()
+    }
+}
+
 impl ReadFrom for AmsTcpHeader {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let reserved = read.read_u16::<LittleEndian>()?.to_le_bytes();
@@ -40,6 +49,11 @@ impl ReadFrom for AmsTcpHeader {
 }
 
 impl AmsTcpHeader {
+    ///Returns the command from the reserved field. AmsCmd for frames with an AMS header.
+    pub fn command(&self) -> AmsTcpCommand {
+        AmsTcpCommand::from(u16::from_le_bytes(self.reserved))
+    }
+
     ///Returns the command id from the ams header
     pub fn command_id(&self) -> CommandID {
         self.ams_header.command_id
@@ -50,6 +64,36 @@ impl AmsTcpHeader {
         self.ams_header.response()
     }
 
+    ///Returns the Request data as a Request enum. Used by servers to parse incoming requests.
+    pub fn request(&self) -> io::Result<Request> {
+        self.ams_header.request()
+    }
+
+    ///Decode the data as Request or Response depending on the state flags
+    pub fn frame(&self) -> io::Result<AmsFrame> {
+        self.ams_header.frame()
+    }
+
+    ///Returns the AMS header
+    pub fn ams_header(&self) -> &AmsHeader {
+        &self.ams_header
+    }
+
+    ///Returns the AMS address of the receiver
+    pub fn target_address(&self) -> &AmsAddress {
+        &self.ams_header.ams_address_targed
+    }
+
+    ///Returns the AMS address of the sender
+    pub fn source_address(&self) -> &AmsAddress {
+        &self.ams_header.ams_address_source
+    }
+
+    ///Returns the state flags from the ams header
+    pub fn state_flags(&self) -> &StateFlags {
+        &self.ams_header.state_flags
+    }
+
     ///Returns the response data length in bytes
     pub fn response_data_len(&self) -> u32 {
         self.ams_header.length
@@ -96,6 +140,42 @@ impl From<AmsHeader> for AmsTcpHeader {
     }
 }
 
+///Data of an AMS frame decoded according to the state flags and the command id
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmsFrame {
+    Request(Request),
+    Response(Response),
+    ///Response without data which only carries an error in the AMS header (see ads_error),
+    ///e.g. from a router which can't reach the target port
+    ErrorResponse(CommandID),
+}
+
+impl AmsFrame {
+    pub fn command_id(&self) -> CommandID {
+        match self {
+            AmsFrame::Request(r) => r.command_id(),
+            AmsFrame::Response(r) => r.command_id(),
+            AmsFrame::ErrorResponse(command_id) => *command_id,
+        }
+    }
+
+    pub fn is_response(&self) -> bool {
+        !matches!(self, AmsFrame::Request(_))
+    }
+}
+
+impl From<Request> for AmsFrame {
+    fn from(request: Request) -> Self {
+        AmsFrame::Request(request)
+    }
+}
+
+impl From<Response> for AmsFrame {
+    fn from(response: Response) -> Self {
+        AmsFrame::Response(response)
+    }
+}
+
 #[derive(Debug)]
 pub struct AmsHeader {
     ams_address_targed: AmsAddress,
@@ -122,6 +202,12 @@ impl WriteTo for AmsHeader {
     }
 }
 
+impl EncodedLen for AmsHeader {
+    fn encoded_len(&self) -> usize {
+        self.header_len() as usize
+    }
+}
+
 impl ReadFrom for AmsHeader {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let ams_address_targed = AmsAddress::read_from(read)?;
@@ -132,7 +218,7 @@ impl ReadFrom for AmsHeader {
         let ams_ads_error = AdsError::from(read.read_u32::<LittleEndian>()?);
         let invoke_id = read.read_u32::<LittleEndian>()?;
         let mut data: Vec<u8> = vec![0; length as usize];
-        read.read_exact(&mut data); //Todo fails silently at the moment if not the complete AmsHeader was read from the stream (read to check data length)
+        read.read_exact(&mut data)?;
 
         Ok(AmsHeader {
             ams_address_targed,
@@ -154,11 +240,10 @@ impl AmsHeader {
         state_flags: StateFlags,
         invoke_id: u32,
         request: Request,
-    ) -> Self {
-        let mut data: Vec<u8> = Vec::new();
-        request.write_to(&mut data);
+    ) -> io::Result<Self> {
+        let data = encode_to_vec(&request)?;
 
-        AmsHeader {
+        Ok(AmsHeader {
             ams_address_targed,
             ams_address_source,
             command_id: request.command_id(),
@@ -167,45 +252,130 @@ impl AmsHeader {
             ams_ads_error: AdsError::ErrNoError,
             invoke_id,
             data,
+        })
+    }
+
+    ///Create the header of a response. Target is the source of the request and vice versa.
+    pub fn new_response(
+        ams_address_targed: AmsAddress,
+        ams_address_source: AmsAddress,
+        state_flags: StateFlags,
+        invoke_id: u32,
+        response: Response,
+    ) -> io::Result<Self> {
+        let data = encode_to_vec(&response)?;
+
+        Ok(AmsHeader {
+            ams_address_targed,
+            ams_address_source,
+            command_id: response.command_id(),
+            state_flags,
+            length: data.len() as u32,
+            ams_ads_error: AdsError::ErrNoError,
+            invoke_id,
+            data,
+        })
+    }
+
+    ///Create a response without data which reports an error in the AMS header
+    ///(e.g. ErrTargetPortNotFound if no device is registered at the target port)
+    pub fn new_error_response(
+        ams_address_targed: AmsAddress,
+        ams_address_source: AmsAddress,
+        command_id: CommandID,
+        invoke_id: u32,
+        ams_ads_error: AdsError,
+    ) -> Self {
+        AmsHeader {
+            ams_address_targed,
+            ams_address_source,
+            command_id,
+            state_flags: StateFlags::resp_default(),
+            length: 0,
+            ams_ads_error,
+            invoke_id,
+            data: Vec::new(),
+        }
+    }
+
+    ///Decode the data as Request or Response depending on the state flags.
+    ///Device notifications are sent as requests but decoded as Response::DeviceNotification
+    ///because the notification stream is modelled as response.
+    ///Responses which only carry an error in the AMS header are returned as AmsFrame::ErrorResponse.
+    ///The error is returned by ads_error.
+    pub fn frame(&self) -> io::Result<AmsFrame> {
+        if self.command_id == CommandID::DeviceNotification {
+            return Ok(AmsFrame::Response(self.response()?));
+        }
+        if !self.state_flags.is_response() {
+            return Ok(AmsFrame::Request(self.request()?));
+        }
+        if self.data.is_empty() && self.ams_ads_error != AdsError::ErrNoError {
+            return Ok(AmsFrame::ErrorResponse(self.command_id));
         }
+        Ok(AmsFrame::Response(self.response()?))
+    }
+
+    ///Returns the AMS address of the receiver
+    pub fn target_address(&self) -> &AmsAddress {
+        &self.ams_address_targed
+    }
+
+    ///Returns the AMS address of the sender
+    pub fn source_address(&self) -> &AmsAddress {
+        &self.ams_address_source
+    }
+
+    pub fn command_id(&self) -> CommandID {
+        self.command_id
+    }
+
+    pub fn state_flags(&self) -> &StateFlags {
+        &self.state_flags
+    }
+
+    pub fn invoke_id(&self) -> u32 {
+        self.invoke_id
+    }
+
+    ///Error code of the AMS header. Responses carry another result in the data.
+    pub fn ads_error(&self) -> &AdsError {
+        &self.ams_ads_error
     }
 
-    fn response(&mut self) -> io::Result<Response> {
+    ///Raw data section
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn request(&self) -> io::Result<Request> {
+        let mut data = self.data.as_slice();
         match self.command_id {
-            CommandID::Invalid => Err(io::Error::new(
-                io::ErrorKind::Other,
-                AdsError::AdsErrDeviceInvalidData,
-            )),
-            CommandID::ReadDeviceInfo => Ok(Response::ReadDeviceInfo(
-                ReadDeviceInfoResponse::read_from(&mut self.data.as_slice())?,
-            )),
-            CommandID::Read => Ok(Response::Read(ReadResponse::read_from(
-                &mut self.data.as_slice(),
-            )?)),
-            CommandID::Write => Ok(Response::Write(WriteResponse::read_from(
-                &mut self.data.as_slice(),
-            )?)),
-            CommandID::ReadState => Ok(Response::ReadState(ReadStateResponse::read_from(
-                &mut self.data.as_slice(),
+            CommandID::Invalid => Err(io::Error::other(AdsError::AdsErrDeviceInvalidData)),
+            CommandID::ReadDeviceInfo => Ok(Request::ReadDeviceInfo(ReadDeviceInfoRequest::new())),
+            CommandID::ReadState => Ok(Request::ReadState(ReadStateRequest::new())),
+            CommandID::Read => Ok(Request::Read(ReadRequest::read_from(&mut data)?)),
+            CommandID::Write => Ok(Request::Write(WriteRequest::read_from(&mut data)?)),
+            CommandID::WriteControl => Ok(Request::WriteControl(WriteControlRequest::read_from(
+                &mut data,
             )?)),
-            CommandID::WriteControl => Ok(Response::WriteControl(WriteControlResponse::read_from(
-                &mut self.data.as_slice(),
-            )?)),
-            CommandID::AddDeviceNotification => Ok(Response::AddDeviceNotification(
-                AddDeviceNotificationResponse::read_from(&mut self.data.as_slice())?,
-            )),
-            CommandID::DeleteDeviceNotification => Ok(Response::DeleteDeviceNotification(
-                DeleteDeviceNotificationResponse::read_from(&mut self.data.as_slice())?,
+            CommandID::AddDeviceNotification => Ok(Request::AddDeviceNotification(
+                AddDeviceNotificationRequest::read_from(&mut data)?,
             )),
-            CommandID::DeviceNotification => Ok(Response::DeviceNotification(
-                AdsNotificationStream::read_from(&mut self.data.as_slice())?,
+            CommandID::DeleteDeviceNotification => Ok(Request::DeleteDeviceNotification(
+                DeleteDeviceNotificationRequest::read_from(&mut data)?,
             )),
-            CommandID::ReadWrite => Ok(Response::ReadWrite(ReadWriteResponse::read_from(
-                &mut self.data.as_slice(),
-            )?)),
+            CommandID::DeviceNotification => {
+                Ok(Request::DeviceNotification(DeviceNotificationRequest::new()))
+            }
+            CommandID::ReadWrite => Ok(Request::ReadWrite(ReadWriteRequest::read_from(&mut data)?)),
         }
     }
 
+    fn response(&self) -> io::Result<Response> {
+        decode_response(self.command_id, &self.data)
+    }
+
     ///get the length in bytes of the whole ams_header.
     fn header_len(&self) -> u32 {
         self.data.len() as u32 + FIX_AMS_HEADER_LEN
@@ -222,6 +392,123 @@ impl AmsHeader {
     }
 }
 
+///Decode the data section of a frame as Response of the command
+pub(crate) fn decode_response(command_id: CommandID, mut data: &[u8]) -> io::Result<Response> {
+    match command_id {
+        CommandID::Invalid => Err(io::Error::other(AdsError::AdsErrDeviceInvalidData)),
+        CommandID::ReadDeviceInfo => Ok(Response::ReadDeviceInfo(
+            ReadDeviceInfoResponse::read_from(&mut data)?,
+        )),
+        CommandID::Read => Ok(Response::Read(ReadResponse::read_from(&mut data)?)),
+        CommandID::Write => Ok(Response::Write(WriteResponse::read_from(&mut data)?)),
+        CommandID::ReadState => Ok(Response::ReadState(ReadStateResponse::read_from(
+            &mut data,
+        )?)),
+        CommandID::WriteControl => Ok(Response::WriteControl(WriteControlResponse::read_from(
+            &mut data,
+        )?)),
+        CommandID::AddDeviceNotification => Ok(Response::AddDeviceNotification(
+            AddDeviceNotificationResponse::read_from(&mut data)?,
+        )),
+        CommandID::DeleteDeviceNotification => Ok(Response::DeleteDeviceNotification(
+            DeleteDeviceNotificationResponse::read_from(&mut data)?,
+        )),
+        CommandID::DeviceNotification => Ok(Response::DeviceNotification(
+            AdsNotificationStream::read_from(&mut data)?,
+        )),
+        CommandID::ReadWrite => Ok(Response::ReadWrite(ReadWriteResponse::read_from(
+            &mut data,
+        )?)),
+    }
+}
+
+///Encode into a vector with the exact capacity
+fn encode_to_vec<T: WriteTo + EncodedLen>(value: &T) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(value.encoded_len());
+    value.write_to(&mut data)?;
+    Ok(data)
+}
+
+///Encodes frames with AMS/TCP header into a reusable buffer.
+///Headers and data are written in a single pass without intermediate buffers.
+///The buffer keeps its capacity, so it only grows if a frame is larger than all frames before.
+#[derive(Debug, Default)]
+pub struct FrameEncoder {
+    buf: Vec<u8>,
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        FrameEncoder::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        FrameEncoder {
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    ///Current capacity of the buffer in bytes
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    ///Encode a request. The returned frame is valid until the next call.
+    pub fn encode_request(
+        &mut self,
+        target: &AmsAddress,
+        source: &AmsAddress,
+        state_flags: StateFlags,
+        invoke_id: u32,
+        request: &Request,
+    ) -> io::Result<&[u8]> {
+        let command_id = request.command_id();
+        self.encode(target, source, command_id, state_flags, invoke_id, request)
+    }
+
+    ///Encode a response. The returned frame is valid until the next call.
+    pub fn encode_response(
+        &mut self,
+        target: &AmsAddress,
+        source: &AmsAddress,
+        state_flags: StateFlags,
+        invoke_id: u32,
+        response: &Response,
+    ) -> io::Result<&[u8]> {
+        let command_id = response.command_id();
+        self.encode(target, source, command_id, state_flags, invoke_id, response)
+    }
+
+    fn encode<T: WriteTo + EncodedLen>(
+        &mut self,
+        target: &AmsAddress,
+        source: &AmsAddress,
+        command_id: CommandID,
+        state_flags: StateFlags,
+        invoke_id: u32,
+        data: &T,
+    ) -> io::Result<&[u8]> {
+        let data_len = data.encoded_len();
+        let ams_len = FIX_AMS_HEADER_LEN as usize + data_len;
+        self.buf.clear();
+        self.buf.reserve(AMS_TCP_HEADER_LEN + ams_len);
+
+        let wtr = &mut self.buf;
+        wtr.write_u16::<LittleEndian>(AmsTcpCommand::AmsCmd.as_u16())?;
+        wtr.write_u32::<LittleEndian>(ams_len as u32)?;
+        target.write_to(&mut *wtr)?;
+        source.write_to(&mut *wtr)?;
+        command_id.write_to(&mut *wtr)?;
+        state_flags.write_to(&mut *wtr)?;
+        wtr.write_u32::<LittleEndian>(data_len as u32)?;
+        wtr.write_u32::<LittleEndian>(AdsError::ErrNoError.as_u32())?;
+        wtr.write_u32::<LittleEndian>(invoke_id)?;
+        data.write_to(&mut *wtr)?;
+        debug_assert_eq!(self.buf.len(), AMS_TCP_HEADER_LEN + ams_len);
+        Ok(&self.buf)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,7 +525,8 @@ mod tests {
             StateFlags::resp_default(),
             111,
             Request::Read(ReadRequest::new(259, 259, 4)),
-        );
+        )
+        .unwrap();
 
         ams_header.write_to(&mut buffer);
 
@@ -303,6 +591,11 @@ mod tests {
         assert_eq!(ams_header.ams_ads_error, AdsError::ErrNoError);
         assert_eq!(ams_header.invoke_id, 111);
         assert_eq!(ams_header.data, [3, 1, 0, 0, 3, 1, 0, 0, 4, 0, 0, 0]);
+
+        //Data section shorter than the length field
+        data.truncate(40);
+        let error = AmsHeader::read_from(&mut data.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
     }
 
     #[test]
@@ -314,7 +607,8 @@ mod tests {
             StateFlags::req_default(),
             111,
             Request::Read(ReadRequest::new(259, 259, 4)),
-        );
+        )
+        .unwrap();
 
         assert_eq!(ams_header.header_len(), 44);
     }
@@ -331,7 +625,8 @@ mod tests {
             StateFlags::req_default(),
             111,
             Request::Read(ReadRequest::new(259, 259, 4)),
-        );
+        )
+        .unwrap();
 
         let ams_tcp_header = AmsTcpHeader::from(ams_header);
         ams_tcp_header.write_to(&mut buffer);
@@ -390,6 +685,7 @@ mod tests {
 
         let ams_tcp_header = AmsTcpHeader::read_from(&mut data.as_slice()).unwrap();
         assert_eq!(ams_tcp_header.reserved, [0, 0]);
+        assert_eq!(ams_tcp_header.command(), AmsTcpCommand::AmsCmd);
         assert_eq!(ams_tcp_header.length, 44);
         assert_eq!(
             ams_tcp_header
@@ -485,7 +781,7 @@ mod tests {
 
         let mut ams_tcp_header = AmsTcpHeader::read_from(&mut data.as_slice()).unwrap();
         let new_data: Vec<u8> = vec![3, 1, 0, 0, 3, 1, 0, 0, 16, 0, 0, 0];
-        let len = ams_tcp_header.update_response_data(new_data.clone());
+        ams_tcp_header.update_response_data(new_data.clone());
         assert_eq!(new_data, ams_tcp_header.raw_response_data());
     }
 
@@ -578,4 +874,235 @@ mod tests {
             ams_tcp_header.response_result()
         );
     }
+
+    #[test]
+    fn ams_tcp_header_request_test() {
+        let ams_header = AmsHeader::new(
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851),
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000),
+            StateFlags::req_default(),
+            7,
+            Request::Read(ReadRequest::new(259, 259, 4)),
+        )
+        .unwrap();
+        let mut buffer = Vec::new();
+        AmsTcpHeader::from(ams_header)
+            .write_to(&mut buffer)
+            .unwrap();
+
+        let ams_tcp_header = AmsTcpHeader::read_from(&mut buffer.as_slice()).unwrap();
+        assert_eq!(
+            ams_tcp_header.request().unwrap(),
+            Request::Read(ReadRequest::new(259, 259, 4))
+        );
+    }
+
+    #[test]
+    fn ams_header_new_response_test() {
+        let target = AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000);
+        let source = AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851);
+        let response = Response::Read(ReadResponse::new(AdsError::ErrNoError, vec![1, 2]));
+        let ams_header = AmsHeader::new_response(
+            target.clone(),
+            source.clone(),
+            StateFlags::resp_default(),
+            7,
+            response,
+        )
+        .unwrap();
+        let mut buffer = Vec::new();
+        AmsTcpHeader::from(ams_header)
+            .write_to(&mut buffer)
+            .unwrap();
+
+        let mut ams_tcp_header = AmsTcpHeader::read_from(&mut buffer.as_slice()).unwrap();
+        assert_eq!(ams_tcp_header.command_id(), CommandID::Read);
+        assert_eq!(ams_tcp_header.target_address(), &target);
+        assert_eq!(ams_tcp_header.source_address(), &source);
+        assert!(ams_tcp_header.state_flags().is_response());
+        assert_eq!(
+            ams_tcp_header.response().unwrap(),
+            Response::Read(ReadResponse::new(AdsError::ErrNoError, vec![1, 2]))
+        );
+
+        let ams_header = AmsHeader::new_error_response(
+            target,
+            source,
+            CommandID::Read,
+            8,
+            AdsError::ErrTargetPortNotFound,
+        );
+        assert_eq!(ams_header.data_len(), 0);
+        assert_eq!(ams_header.ams_ads_error, AdsError::ErrTargetPortNotFound);
+    }
+
+    fn decode(ams_header: AmsHeader) -> io::Result<AmsFrame> {
+        let mut buffer = Vec::new();
+        AmsTcpHeader::from(ams_header)
+            .write_to(&mut buffer)
+            .unwrap();
+        AmsTcpHeader::read_from(&mut buffer.as_slice())
+            .unwrap()
+            .frame()
+    }
+
+    #[test]
+    fn ams_frame_test() {
+        let device = AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851);
+        let client = AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000);
+
+        let request = Request::Write(WriteRequest::new(259, 259, vec![1, 2]));
+        let frame = decode(
+            AmsHeader::new(
+                device.clone(),
+                client.clone(),
+                StateFlags::req_default(),
+                1,
+                request.clone(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(frame, AmsFrame::Request(request));
+        assert!(!frame.is_response());
+
+        //Same command id, decoded by the state flags
+        let response = Response::Write(WriteResponse::new(AdsError::ErrNoError));
+        let frame = decode(
+            AmsHeader::new_response(
+                client.clone(),
+                device.clone(),
+                StateFlags::resp_default(),
+                1,
+                response.clone(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(frame, AmsFrame::Response(response));
+        assert_eq!(frame.command_id(), CommandID::Write);
+
+        let stream = AdsNotificationStream::from_stamp_headers(vec![AdsStampHeader::new(
+            1,
+            1,
+            vec![AdsNotificationSample::new(7, vec![1])],
+        )]);
+        let frame = decode(
+            AmsHeader::new_response(
+                client.clone(),
+                device.clone(),
+                StateFlags::req_default(),
+                0,
+                Response::DeviceNotification(stream.clone()),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            frame,
+            AmsFrame::Response(Response::DeviceNotification(stream))
+        );
+
+        let mut buffer = Vec::new();
+        AmsTcpHeader::from(AmsHeader::new_error_response(
+            client,
+            device,
+            CommandID::Read,
+            2,
+            AdsError::ErrTargetPortNotFound,
+        ))
+        .write_to(&mut buffer)
+        .unwrap();
+        let ams_tcp_header = AmsTcpHeader::read_from(&mut buffer.as_slice()).unwrap();
+        let frame = ams_tcp_header.frame().unwrap();
+        assert_eq!(frame, AmsFrame::ErrorResponse(CommandID::Read));
+        assert!(frame.is_response());
+        assert_eq!(frame.command_id(), CommandID::Read);
+        assert_eq!(ams_tcp_header.ads_error(), &AdsError::ErrTargetPortNotFound);
+    }
+
+    #[test]
+    fn ams_header_getter_test() {
+        let mut buffer = Vec::new();
+        AmsHeader::new(
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851),
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000),
+            StateFlags::req_default(),
+            9,
+            Request::ReadState(ReadStateRequest::new()),
+        )
+        .unwrap()
+        .write_to(&mut buffer)
+        .unwrap();
+
+        //Without AMS/TCP header, e.g. received over UDP
+        let ams_header = AmsHeader::read_from(&mut buffer.as_slice()).unwrap();
+        assert_eq!(ams_header.target_address().port, 851);
+        assert_eq!(ams_header.source_address().port, 30000);
+        assert_eq!(ams_header.command_id(), CommandID::ReadState);
+        assert!(!ams_header.state_flags().is_response());
+        assert_eq!(ams_header.invoke_id(), 9);
+        assert_eq!(ams_header.ads_error(), &AdsError::ErrNoError);
+        assert!(ams_header.data().is_empty());
+        assert_eq!(
+            ams_header.frame().unwrap(),
+            AmsFrame::Request(Request::ReadState(ReadStateRequest::new()))
+        );
+    }
+
+    #[test]
+    fn frame_encoder_test() {
+        let target = AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851);
+        let source = AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000);
+        let mut encoder = FrameEncoder::new();
+
+        let request = Request::Write(WriteRequest::new(259, 259, vec![1; 100]));
+        let ams_tcp_header = AmsTcpHeader::from(
+            AmsHeader::new(
+                target.clone(),
+                source.clone(),
+                StateFlags::req_default(),
+                1,
+                request.clone(),
+            )
+            .unwrap(),
+        );
+        let mut expected = Vec::new();
+        ams_tcp_header.write_to(&mut expected).unwrap();
+        assert_eq!(ams_tcp_header.encoded_len(), expected.len());
+        let frame = encoder
+            .encode_request(&target, &source, StateFlags::req_default(), 1, &request)
+            .unwrap();
+        assert_eq!(frame, expected.as_slice());
+
+        //Smaller frames reuse the buffer
+        let capacity = encoder.capacity();
+        let request = Request::Read(ReadRequest::new(259, 259, 4));
+        let frame = encoder
+            .encode_request(&target, &source, StateFlags::req_default(), 2, &request)
+            .unwrap();
+        let frame = AmsTcpHeader::read_from(&mut &frame[..]).unwrap();
+        assert_eq!(frame.invoke_id(), 2);
+        assert_eq!(frame.request().unwrap(), request);
+        assert_eq!(encoder.capacity(), capacity);
+
+        let response = Response::Read(ReadResponse::new(AdsError::ErrNoError, vec![1, 2]));
+        let mut expected = Vec::new();
+        AmsTcpHeader::from(
+            AmsHeader::new_response(
+                source.clone(),
+                target.clone(),
+                StateFlags::resp_default(),
+                2,
+                response.clone(),
+            )
+            .unwrap(),
+        )
+        .write_to(&mut expected)
+        .unwrap();
+        let frame = encoder
+            .encode_response(&source, &target, StateFlags::resp_default(), 2, &response)
+            .unwrap();
+        assert_eq!(frame, expected.as_slice());
+    }
 }
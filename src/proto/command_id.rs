@@ -1,4 +1,4 @@
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Read, Write};
@@ -25,6 +25,12 @@ impl WriteTo for CommandID {
     }
 }
 
+impl EncodedLen for CommandID {
+    fn encoded_len(&self) -> usize {
+        2
+    }
+}
+
 impl ReadFrom for CommandID {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(CommandID::from(read.read_u16::<LittleEndian>()?))
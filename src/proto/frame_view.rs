@@ -0,0 +1,529 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use bytes::Bytes;
+use std::io::{self, Read};
+
+use crate::error::AdsError;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::ams_header::decode_response;
+use crate::proto::command_id::CommandID;
+use crate::proto::proto_traits::ReadFrom;
+use crate::proto::response::{
+    AdsNotificationSample, AdsNotificationStream, AdsStampHeader, ReadResponse, Response,
+};
+use crate::proto::router_command::AmsTcpCommand;
+use crate::proto::state_flags::StateFlags;
+
+///Reads fields from a shared buffer. Taken slices reference the buffer instead of copying.
+#[derive(Debug)]
+struct Cursor<'a> {
+    buf: &'a Bytes,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a Bytes) -> Self {
+        Cursor { buf, pos: 0 }
+    }
+
+    ///Decode a field with a ReadFrom style function and advance by the consumed bytes
+    fn read<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut &[u8]) -> io::Result<T>,
+    {
+        let mut rest = &self.buf[self.pos..];
+        let len = rest.len();
+        let value = f(&mut rest)?;
+        self.pos += len - rest.len();
+        Ok(value)
+    }
+
+    fn read_from<T: ReadFrom>(&mut self) -> io::Result<T> {
+        self.read(|r| T::read_from(r))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        self.read(|r| r.read_u32::<LittleEndian>())
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        self.read(|r| r.read_u64::<LittleEndian>())
+    }
+
+    ///Number of bytes not read yet
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    ///Take the next len bytes as a slice of the shared buffer
+    fn take(&mut self, len: usize) -> io::Result<Bytes> {
+        if len > self.remaining() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Expected {} bytes, only {} left", len, self.remaining()),
+            ));
+        }
+        let slice = self.buf.slice(self.pos, self.pos + len);
+        self.pos += len;
+        Ok(slice)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+///Received AMS frame with decoded header fields.
+///The data section references the receive buffer, no bytes are copied.
+///Slices of up to 31 bytes are stored inline by `Bytes` instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmsFrameView {
+    target_address: AmsAddress,
+    source_address: AmsAddress,
+    command_id: CommandID,
+    state_flags: StateFlags,
+    ads_error: AdsError,
+    invoke_id: u32,
+    data: Bytes,
+}
+
+impl AmsFrameView {
+    ///Parse a complete AMS/TCP frame (AMS/TCP header, AMS header and data)
+    pub fn parse(frame: Bytes) -> io::Result<Self> {
+        let mut cursor = Cursor::new(&frame);
+        let command = AmsTcpCommand::from(cursor.read(|r| r.read_u16::<LittleEndian>())?);
+        if command != AmsTcpCommand::AmsCmd {
+            return Err(invalid_data(format!(
+                "Expected AMS command, got {:?}",
+                command
+            )));
+        }
+        let length = cursor.read_u32()? as usize;
+        let ams = cursor.take(length)?;
+        AmsFrameView::parse_ams(ams)
+    }
+
+    ///Parse an AMS header followed by its data, e.g. a frame received over UDP
+    pub fn parse_ams(frame: Bytes) -> io::Result<Self> {
+        let mut cursor = Cursor::new(&frame);
+        let target_address = cursor.read_from()?;
+        let source_address = cursor.read_from()?;
+        let command_id = cursor.read_from()?;
+        let state_flags = cursor.read_from()?;
+        let length = cursor.read_u32()? as usize;
+        let ads_error = AdsError::from(cursor.read_u32()?);
+        let invoke_id = cursor.read_u32()?;
+        let data = cursor.take(length)?;
+
+        Ok(AmsFrameView {
+            target_address,
+            source_address,
+            command_id,
+            state_flags,
+            ads_error,
+            invoke_id,
+            data,
+        })
+    }
+
+    pub fn target_address(&self) -> &AmsAddress {
+        &self.target_address
+    }
+
+    pub fn source_address(&self) -> &AmsAddress {
+        &self.source_address
+    }
+
+    pub fn command_id(&self) -> CommandID {
+        self.command_id
+    }
+
+    pub fn state_flags(&self) -> StateFlags {
+        self.state_flags
+    }
+
+    ///Error code of the AMS header
+    pub fn ads_error(&self) -> &AdsError {
+        &self.ads_error
+    }
+
+    pub fn invoke_id(&self) -> u32 {
+        self.invoke_id
+    }
+
+    ///Data section after the AMS header
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    ///Decode the data of a Read or ReadWrite response
+    pub fn read_response(&self) -> io::Result<ReadResponseView> {
+        let is_read = matches!(self.command_id, CommandID::Read | CommandID::ReadWrite);
+        if !is_read || !self.state_flags.is_response() {
+            return Err(invalid_data(format!(
+                "Expected Read or ReadWrite response, got {:?}",
+                self.command_id
+            )));
+        }
+        ReadResponseView::parse(&self.data)
+    }
+
+    ///Decode the data as Response of the command.
+    ///Copies the data into the response, use read_response or notification to avoid it.
+    pub fn response(&self) -> io::Result<Response> {
+        decode_response(self.command_id, &self.data)
+    }
+
+    ///Decode the data of a device notification
+    pub fn notification(&self) -> io::Result<NotificationStreamView> {
+        if self.command_id != CommandID::DeviceNotification {
+            return Err(invalid_data(format!(
+                "Expected DeviceNotification, got {:?}",
+                self.command_id
+            )));
+        }
+        NotificationStreamView::parse(&self.data)
+    }
+}
+
+///Read or ReadWrite response referencing the receive buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadResponseView {
+    pub result: AdsError,
+    pub data: Bytes,
+}
+
+impl ReadResponseView {
+    ///Parse result, length and data of a Read or ReadWrite response
+    pub fn parse(data: &Bytes) -> io::Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let result = AdsError::from(cursor.read_u32()?);
+        let length = cursor.read_u32()? as usize;
+        let data = cursor.take(length)?;
+        Ok(ReadResponseView { result, data })
+    }
+}
+
+impl From<&ReadResponseView> for ReadResponse {
+    fn from(view: &ReadResponseView) -> Self {
+        ReadResponse::new(view.result.clone(), view.data.to_vec())
+    }
+}
+
+///Sumup read response referencing the receive buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct SumupReadResponseView {
+    pub read_responses: Vec<ReadResponseView>,
+}
+
+impl SumupReadResponseView {
+    ///Parse the data of the ReadWrite response to a sumup read of count variables.
+    ///The results and lengths of all reads come first, followed by the data of all reads.
+    pub fn parse(data: &Bytes, count: usize) -> io::Result<Self> {
+        let mut cursor = Cursor::new(data);
+        //Result and length take 8 bytes per read
+        let mut access = Vec::with_capacity(count.min(cursor.remaining() / 8));
+        for _ in 0..count {
+            let result = AdsError::from(cursor.read_u32()?);
+            let length = cursor.read_u32()? as usize;
+            access.push((result, length));
+        }
+
+        let mut read_responses = Vec::with_capacity(access.len());
+        for (result, length) in access {
+            read_responses.push(ReadResponseView {
+                result,
+                data: cursor.take(length)?,
+            });
+        }
+        Ok(SumupReadResponseView { read_responses })
+    }
+}
+
+///Notification sample referencing the receive buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationSampleView {
+    pub notification_handle: u32,
+    pub data: Bytes,
+}
+
+///Samples with the same time stamp
+#[derive(Debug, Clone, PartialEq)]
+pub struct StampHeaderView {
+    pub time_stamp: u64,
+    pub samples: Vec<NotificationSampleView>,
+}
+
+///Device notification referencing the receive buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationStreamView {
+    pub stamps: Vec<StampHeaderView>,
+}
+
+impl NotificationStreamView {
+    ///Parse the data of a device notification
+    pub fn parse(data: &Bytes) -> io::Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let length = cursor.read_u32()? as usize;
+        //length includes the stamp count
+        let stamp_data = cursor.take(length)?;
+        let mut cursor = Cursor::new(&stamp_data);
+        let stamp_count = cursor.read_u32()? as usize;
+        //The counts are not trusted. A stamp takes at least 12 bytes, a sample 8 bytes.
+        let mut stamps = Vec::with_capacity(stamp_count.min(cursor.remaining() / 12));
+        for _ in 0..stamp_count {
+            let time_stamp = cursor.read_u64()?;
+            let sample_count = cursor.read_u32()? as usize;
+            let mut samples = Vec::with_capacity(sample_count.min(cursor.remaining() / 8));
+            for _ in 0..sample_count {
+                let notification_handle = cursor.read_u32()?;
+                let sample_size = cursor.read_u32()? as usize;
+                samples.push(NotificationSampleView {
+                    notification_handle,
+                    data: cursor.take(sample_size)?,
+                });
+            }
+            stamps.push(StampHeaderView {
+                time_stamp,
+                samples,
+            });
+        }
+        Ok(NotificationStreamView { stamps })
+    }
+
+    ///All samples with their time stamp in the order received
+    pub fn samples(&self) -> impl Iterator<Item = (u64, &NotificationSampleView)> {
+        self.stamps
+            .iter()
+            .flat_map(|stamp| stamp.samples.iter().map(move |s| (stamp.time_stamp, s)))
+    }
+
+    ///Notification handles of the samples without duplicates in the order received
+    pub fn handles(&self) -> Vec<u32> {
+        let mut handles: Vec<u32> = Vec::new();
+        for (_, sample) in self.samples() {
+            if !handles.contains(&sample.notification_handle) {
+                handles.push(sample.notification_handle);
+            }
+        }
+        handles
+    }
+
+    ///Stream with the samples of one notification handle.
+    ///Stamps without a sample of the handle are left out. The data is not copied.
+    pub fn for_handle(&self, handle: u32) -> NotificationStreamView {
+        let stamps = self
+            .stamps
+            .iter()
+            .filter_map(|stamp| {
+                let samples: Vec<NotificationSampleView> = stamp
+                    .samples
+                    .iter()
+                    .filter(|s| s.notification_handle == handle)
+                    .cloned()
+                    .collect();
+                if samples.is_empty() {
+                    return None;
+                }
+                Some(StampHeaderView {
+                    time_stamp: stamp.time_stamp,
+                    samples,
+                })
+            })
+            .collect();
+        NotificationStreamView { stamps }
+    }
+}
+
+impl From<&NotificationStreamView> for AdsNotificationStream {
+    fn from(view: &NotificationStreamView) -> Self {
+        let stamps = view
+            .stamps
+            .iter()
+            .map(|stamp| {
+                let samples: Vec<AdsNotificationSample> = stamp
+                    .samples
+                    .iter()
+                    .map(|s| AdsNotificationSample::new(s.notification_handle, s.data.to_vec()))
+                    .collect();
+                AdsStampHeader::new(stamp.time_stamp, samples.len() as u32, samples)
+            })
+            .collect();
+        AdsNotificationStream::from_stamp_headers(stamps)
+    }
+}
+
+impl From<&AdsNotificationStream> for NotificationStreamView {
+    fn from(stream: &AdsNotificationStream) -> Self {
+        let stamps = stream
+            .ads_stamp_headers
+            .iter()
+            .map(|header| StampHeaderView {
+                time_stamp: header.time_stamp,
+                samples: header
+                    .notification_samples
+                    .iter()
+                    .map(|s| NotificationSampleView {
+                        notification_handle: s.notification_handle,
+                        data: Bytes::from(s.data.as_slice()),
+                    })
+                    .collect(),
+            })
+            .collect();
+        NotificationStreamView { stamps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ams_address::AmsNetId;
+    use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+    use crate::proto::proto_traits::WriteTo;
+    use crate::proto::response::{ReadWriteResponse, Response};
+    use crate::proto::sumup::sumup_response::SumupReadResponse;
+
+    fn frame(state_flags: StateFlags, response: Response) -> Bytes {
+        let ams_header = AmsHeader::new_response(
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 2), 30000),
+            AmsAddress::new(AmsNetId::new(192, 168, 1, 1, 1, 1), 851),
+            state_flags,
+            7,
+            response,
+        )
+        .unwrap();
+        let mut buffer = Vec::new();
+        AmsTcpHeader::from(ams_header)
+            .write_to(&mut buffer)
+            .unwrap();
+        Bytes::from(buffer)
+    }
+
+    ///True if the slice lies within the buffer. Slices with less than 32 bytes are always copied.
+    fn is_borrowed(slice: &Bytes, buffer: &Bytes) -> bool {
+        let start = buffer.as_ptr() as usize;
+        let ptr = slice.as_ptr() as usize;
+        ptr >= start && ptr + slice.len() <= start + buffer.len()
+    }
+
+    #[test]
+    fn read_response_view_test() {
+        let buffer = frame(
+            StateFlags::resp_default(),
+            Response::Read(ReadResponse::new(AdsError::ErrNoError, vec![1; 64])),
+        );
+        let view = AmsFrameView::parse(buffer.clone()).unwrap();
+        assert_eq!(view.command_id(), CommandID::Read);
+        assert_eq!(view.invoke_id(), 7);
+        assert_eq!(view.source_address().port, 851);
+        assert_eq!(view.ads_error(), &AdsError::ErrNoError);
+        assert!(is_borrowed(view.data(), &buffer));
+
+        let response = view.read_response().unwrap();
+        assert_eq!(response.result, AdsError::ErrNoError);
+        assert_eq!(&response.data[..], &[1; 64][..]);
+        assert!(is_borrowed(&response.data, &buffer));
+        assert_eq!(
+            ReadResponse::from(&response),
+            ReadResponse::new(AdsError::ErrNoError, vec![1; 64])
+        );
+
+        assert!(view.notification().is_err());
+        assert!(AmsFrameView::parse(buffer.slice_to(buffer.len() - 1)).is_err());
+    }
+
+    #[test]
+    fn sumup_read_view_test() {
+        let mut sumup = Vec::new();
+        SumupReadResponse::new(vec![
+            ReadResponse::new(AdsError::ErrNoError, vec![1, 0]),
+            ReadResponse::new(AdsError::AdsErrDeviceSymbolNotFound, vec![]),
+            ReadResponse::new(AdsError::ErrNoError, vec![2; 40]),
+        ])
+        .write_to(&mut sumup)
+        .unwrap();
+        let buffer = frame(
+            StateFlags::resp_default(),
+            Response::ReadWrite(ReadWriteResponse::new(AdsError::ErrNoError, sumup)),
+        );
+
+        let view = AmsFrameView::parse(buffer.clone()).unwrap();
+        let response = view.read_response().unwrap();
+        let sumup = SumupReadResponseView::parse(&response.data, 3).unwrap();
+        let data: Vec<&[u8]> = sumup.read_responses.iter().map(|r| &r.data[..]).collect();
+        assert_eq!(data, vec![&[1, 0][..], &[][..], &[2; 40][..]]);
+        assert_eq!(
+            sumup.read_responses[1].result,
+            AdsError::AdsErrDeviceSymbolNotFound
+        );
+        assert!(is_borrowed(&sumup.read_responses[2].data, &buffer));
+
+        assert!(SumupReadResponseView::parse(&response.data, 4).is_err());
+    }
+
+    #[test]
+    fn notification_view_test() {
+        let stream = AdsNotificationStream::from_stamp_headers(vec![
+            AdsStampHeader::new(
+                100,
+                2,
+                vec![
+                    AdsNotificationSample::new(1, vec![1, 2]),
+                    AdsNotificationSample::new(2, vec![3]),
+                ],
+            ),
+            AdsStampHeader::new(200, 1, vec![AdsNotificationSample::new(1, vec![4; 32])]),
+        ]);
+        let buffer = frame(
+            StateFlags::req_default(),
+            Response::DeviceNotification(stream.clone()),
+        );
+
+        let view = AmsFrameView::parse(buffer.clone())
+            .unwrap()
+            .notification()
+            .unwrap();
+        let samples: Vec<(u64, u32, &[u8])> = view
+            .samples()
+            .map(|(t, s)| (t, s.notification_handle, &s.data[..]))
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                (100, 1, &[1, 2][..]),
+                (100, 2, &[3][..]),
+                (200, 1, &[4; 32][..])
+            ]
+        );
+        let (_, large) = view.samples().last().unwrap();
+        assert!(is_borrowed(&large.data, &buffer));
+        assert_eq!(AdsNotificationStream::from(&view), stream);
+        assert_eq!(NotificationStreamView::from(&stream), view);
+
+        assert_eq!(view.handles(), vec![1, 2]);
+        let first = view.for_handle(1);
+        assert_eq!(first.stamps.len(), 2);
+        assert_eq!(first.handles(), vec![1]);
+        let (_, large) = first.samples().last().unwrap();
+        assert!(is_borrowed(&large.data, &buffer));
+        let second = view.for_handle(2);
+        assert_eq!(second.stamps.len(), 1);
+        assert_eq!(second.stamps[0].time_stamp, 100);
+        assert!(view.for_handle(3).stamps.is_empty());
+    }
+
+    #[test]
+    fn notification_view_count_exceeds_data_test() {
+        //Stamp and sample counts far beyond the received bytes
+        let mut data = Vec::new();
+        data.extend_from_slice(&20u32.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        let error = NotificationStreamView::parse(&Bytes::from(data)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let error = SumupReadResponseView::parse(&Bytes::from(vec![0; 8]), usize::MAX).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
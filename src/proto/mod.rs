@@ -3,8 +3,10 @@ pub mod ads_transition_mode;
 pub mod ams_address;
 pub mod ams_header;
 pub mod command_id;
+pub mod frame_view;
 pub mod proto_traits;
 pub mod request;
 pub mod response;
+pub mod router_command;
 pub mod state_flags;
 pub mod sumup;
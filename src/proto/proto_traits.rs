@@ -8,6 +8,11 @@ pub trait WriteTo {
     fn write_to<W: Write>(&self, wtr: W) -> io::Result<()>;
 }
 
+///Exact number of bytes written by write_to. Used to size buffers before encoding.
+pub trait EncodedLen {
+    fn encoded_len(&self) -> usize;
+}
+
 pub trait SendRecieve {
     // TODO add router as param that implements read to write
     fn send_receive(&self) -> io::Result<()>;
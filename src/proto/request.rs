@@ -5,10 +5,10 @@ use crate::error::TryIntoError;
 use crate::proto::ads_state::AdsState;
 use crate::proto::ads_transition_mode::AdsTransMode;
 use crate::proto::command_id::CommandID;
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use std::convert::TryInto;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Request {
     Invalid(InvalidRequest),
     ReadDeviceInfo(ReadDeviceInfoRequest),
@@ -39,6 +39,23 @@ impl WriteTo for Request {
     }
 }
 
+impl EncodedLen for Request {
+    fn encoded_len(&self) -> usize {
+        match self {
+            Request::Invalid(_) => 0,
+            Request::ReadDeviceInfo(_) => 0,
+            Request::ReadState(_) => 0,
+            Request::Read(r) => r.encoded_len(),
+            Request::Write(r) => r.encoded_len(),
+            Request::ReadWrite(r) => r.encoded_len(),
+            Request::AddDeviceNotification(r) => r.encoded_len(),
+            Request::WriteControl(r) => r.encoded_len(),
+            Request::DeviceNotification(_) => 0,
+            Request::DeleteDeviceNotification(r) => r.encoded_len(),
+        }
+    }
+}
+
 impl Request {
     pub fn command_id(&self) -> CommandID {
         match self {
@@ -335,6 +352,12 @@ impl WriteTo for ReadRequest {
     }
 }
 
+impl EncodedLen for ReadRequest {
+    fn encoded_len(&self) -> usize {
+        12
+    }
+}
+
 impl ReadFrom for ReadRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(ReadRequest {
@@ -378,6 +401,13 @@ impl WriteTo for WriteRequest {
     }
 }
 
+impl EncodedLen for WriteRequest {
+    fn encoded_len(&self) -> usize {
+        //index group, index offset, length
+        12 + self.data.len()
+    }
+}
+
 impl ReadFrom for WriteRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let index_group = read.read_u32::<LittleEndian>()?;
@@ -428,6 +458,13 @@ impl WriteTo for WriteControlRequest {
     }
 }
 
+impl EncodedLen for WriteControlRequest {
+    fn encoded_len(&self) -> usize {
+        //ads state, device state, length
+        8 + self.data.len()
+    }
+}
+
 impl ReadFrom for WriteControlRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let ads_state = AdsState::read_from(read)?;
@@ -493,6 +530,13 @@ impl WriteTo for AddDeviceNotificationRequest {
     }
 }
 
+impl EncodedLen for AddDeviceNotificationRequest {
+    fn encoded_len(&self) -> usize {
+        //6 fields with 4 bytes and 16 reserved bytes
+        24 + self.reserved.len()
+    }
+}
+
 impl ReadFrom for AddDeviceNotificationRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(AddDeviceNotificationRequest {
@@ -522,6 +566,12 @@ impl WriteTo for DeleteDeviceNotificationRequest {
     }
 }
 
+impl EncodedLen for DeleteDeviceNotificationRequest {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+}
+
 impl ReadFrom for DeleteDeviceNotificationRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(DeleteDeviceNotificationRequest {
@@ -575,6 +625,13 @@ impl WriteTo for ReadWriteRequest {
     }
 }
 
+impl EncodedLen for ReadWriteRequest {
+    fn encoded_len(&self) -> usize {
+        //index group, index offset, read length, write length
+        16 + self.data.len()
+    }
+}
+
 impl ReadFrom for ReadWriteRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let index_group = read.read_u32::<LittleEndian>()?;
@@ -1011,4 +1068,35 @@ mod tests {
         assert_eq!(request.handle, compare.handle, "Wrong handle");
         assert_eq!(request.command_id, compare.command_id, "Wrong command id");
     }
+
+    #[test]
+    fn encoded_len_test() {
+        let requests = vec![
+            Request::ReadDeviceInfo(ReadDeviceInfoRequest::new()),
+            Request::ReadState(ReadStateRequest::new()),
+            Request::Read(ReadRequest::new(259, 259, 4)),
+            Request::Write(WriteRequest::new(259, 259, vec![1, 2, 3])),
+            Request::WriteControl(WriteControlRequest::new(
+                AdsState::AdsStateRun,
+                0,
+                2,
+                vec![1, 2],
+            )),
+            Request::AddDeviceNotification(AddDeviceNotificationRequest::new(
+                259,
+                259,
+                4,
+                AdsTransMode::OnChange,
+                1,
+                1,
+            )),
+            Request::DeleteDeviceNotification(DeleteDeviceNotificationRequest::new(7)),
+            Request::ReadWrite(ReadWriteRequest::new(259, 259, 4, vec![1, 2, 3, 4, 5])),
+        ];
+        for request in requests {
+            let mut buffer = Vec::new();
+            request.write_to(&mut buffer).unwrap();
+            assert_eq!(request.encoded_len(), buffer.len(), "{:?}", request);
+        }
+    }
 }
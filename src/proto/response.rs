@@ -1,13 +1,13 @@
 use crate::error::{AdsError, TryIntoError};
 use crate::proto::ads_state::AdsState;
 use crate::proto::command_id::CommandID;
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::convert::TryInto;
 use std::io::{self, Read, Write};
 use std::string::FromUtf8Error;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Response {
     ReadDeviceInfo(ReadDeviceInfoResponse),
     Read(ReadResponse),
@@ -36,6 +36,38 @@ impl WriteTo for Response {
     }
 }
 
+impl EncodedLen for Response {
+    fn encoded_len(&self) -> usize {
+        match self {
+            Response::ReadDeviceInfo(w) => w.encoded_len(),
+            Response::Read(w) => w.encoded_len(),
+            Response::Write(w) => w.encoded_len(),
+            Response::ReadState(w) => w.encoded_len(),
+            Response::WriteControl(w) => w.encoded_len(),
+            Response::AddDeviceNotification(w) => w.encoded_len(),
+            Response::DeleteDeviceNotification(w) => w.encoded_len(),
+            Response::DeviceNotification(w) => w.encoded_len(),
+            Response::ReadWrite(w) => w.encoded_len(),
+        }
+    }
+}
+
+impl Response {
+    pub fn command_id(&self) -> CommandID {
+        match self {
+            Response::ReadDeviceInfo(_) => CommandID::ReadDeviceInfo,
+            Response::Read(_) => CommandID::Read,
+            Response::Write(_) => CommandID::Write,
+            Response::ReadState(_) => CommandID::ReadState,
+            Response::WriteControl(_) => CommandID::WriteControl,
+            Response::AddDeviceNotification(_) => CommandID::AddDeviceNotification,
+            Response::DeleteDeviceNotification(_) => CommandID::DeleteDeviceNotification,
+            Response::DeviceNotification(_) => CommandID::DeviceNotification,
+            Response::ReadWrite(_) => CommandID::ReadWrite,
+        }
+    }
+}
+
 impl From<ReadDeviceInfoResponse> for Response {
     fn from(response: ReadDeviceInfoResponse) -> Self {
         Response::ReadDeviceInfo(response)
@@ -228,6 +260,13 @@ impl WriteTo for ReadDeviceInfoResponse {
     }
 }
 
+impl EncodedLen for ReadDeviceInfoResponse {
+    fn encoded_len(&self) -> usize {
+        //result, versions and device name
+        8 + self.device_name.len()
+    }
+}
+
 impl ReadDeviceInfoResponse {
     pub fn new(
         result: AdsError,
@@ -278,6 +317,12 @@ impl WriteTo for WriteResponse {
     }
 }
 
+impl EncodedLen for WriteResponse {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+}
+
 impl WriteResponse {
     pub fn new(result: AdsError) -> Self {
         WriteResponse { result }
@@ -311,6 +356,12 @@ impl WriteTo for ReadStateResponse {
     }
 }
 
+impl EncodedLen for ReadStateResponse {
+    fn encoded_len(&self) -> usize {
+        8
+    }
+}
+
 impl ReadStateResponse {
     pub fn new(result: AdsError, ads_state: AdsState, device_state: u16) -> Self {
         ReadStateResponse {
@@ -342,6 +393,12 @@ impl WriteTo for WriteControlResponse {
     }
 }
 
+impl EncodedLen for WriteControlResponse {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+}
+
 impl WriteControlResponse {
     pub fn new(result: AdsError) -> Self {
         WriteControlResponse { result }
@@ -372,6 +429,12 @@ impl WriteTo for AddDeviceNotificationResponse {
     }
 }
 
+impl EncodedLen for AddDeviceNotificationResponse {
+    fn encoded_len(&self) -> usize {
+        8
+    }
+}
+
 impl AddDeviceNotificationResponse {
     pub fn new(result: AdsError, notification_handle: u32) -> Self {
         AddDeviceNotificationResponse {
@@ -402,12 +465,32 @@ impl WriteTo for DeleteDeviceNotificationResponse {
     }
 }
 
+impl EncodedLen for DeleteDeviceNotificationResponse {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+}
+
 impl DeleteDeviceNotificationResponse {
     pub fn new(result: AdsError) -> Self {
         DeleteDeviceNotificationResponse { result }
     }
 }
 
+///Read len bytes. The buffer grows with the received bytes,
+///so a wrong length field can't allocate more than the frame holds.
+fn read_exact_bounded<R: Read>(read: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut data: Vec<u8> = Vec::new();
+    read.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Expected {} bytes, only {} left", len, data.len()),
+        ));
+    }
+    Ok(data)
+}
+
 //ADS Device Notification Response
 #[derive(Debug, PartialEq, Clone)]
 pub struct AdsNotificationSample {
@@ -441,14 +524,13 @@ impl ReadFrom for AdsStampHeader {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let time_stamp = read.read_u64::<LittleEndian>()?;
         let samples = read.read_u32::<LittleEndian>()?;
-        let mut notification_samples: Vec<AdsNotificationSample> =
-            Vec::with_capacity(samples as usize);
+        //Grows with the decoded samples. A wrong sample count can't allocate more than the frame holds.
+        let mut notification_samples: Vec<AdsNotificationSample> = Vec::new();
 
         for _ in 0..samples {
             let notification_handle = read.read_u32::<LittleEndian>()?;
             let sample_size = read.read_u32::<LittleEndian>()?;
-            let mut data = vec![0; sample_size as usize];
-            read.read_exact(&mut data)?;
+            let data = read_exact_bounded(read, sample_size as u64)?;
             notification_samples.push(AdsNotificationSample {
                 notification_handle,
                 sample_size,
@@ -478,6 +560,12 @@ impl WriteTo for AdsStampHeader {
     }
 }
 
+impl EncodedLen for AdsStampHeader {
+    fn encoded_len(&self) -> usize {
+        self.stamp_len()
+    }
+}
+
 impl AdsStampHeader {
     pub fn new(
         time_stamp: u64,
@@ -512,13 +600,14 @@ impl ReadFrom for AdsNotificationStream {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let length = read.read_u32::<LittleEndian>()?;
         let stamps = read.read_u32::<LittleEndian>()?;
-        let stamp_data_size = ((length - 4) / stamps) as u32; //-4 -> stamps is in length incl. but already read in previous line!
-        let mut ads_stamp_headers: Vec<AdsStampHeader> = Vec::with_capacity(stamps as usize);
-        let mut buffer: Vec<u8> = vec![0; ((stamp_data_size) as usize)];
+        //Stamps can differ in size. length includes stamps which is already read.
+        let buffer = read_exact_bounded(read, length.saturating_sub(4) as u64)?;
+        let mut stamp_data = buffer.as_slice();
+        //A stamp header has at least 12 bytes (time stamp, sample count)
+        let capacity = (stamps as usize).min(buffer.len() / 12);
+        let mut ads_stamp_headers: Vec<AdsStampHeader> = Vec::with_capacity(capacity);
         for _ in 0..stamps {
-            read.read_exact(&mut buffer)?;
-            let stamp = AdsStampHeader::read_from(&mut buffer.as_slice())?;
-            ads_stamp_headers.push(stamp);
+            ads_stamp_headers.push(AdsStampHeader::read_from(&mut stamp_data)?);
         }
 
         Ok(Self {
@@ -541,6 +630,12 @@ impl WriteTo for AdsNotificationStream {
     }
 }
 
+impl EncodedLen for AdsNotificationStream {
+    fn encoded_len(&self) -> usize {
+        self.stream_len()
+    }
+}
+
 impl AdsNotificationStream {
     pub fn new(length: u32, stamps: u32, ads_stamp_headers: Vec<AdsStampHeader>) -> Self {
         AdsNotificationStream {
@@ -550,6 +645,15 @@ impl AdsNotificationStream {
         }
     }
 
+    ///Create a stream and calculate length and stamps
+    pub fn from_stamp_headers(ads_stamp_headers: Vec<AdsStampHeader>) -> Self {
+        let mut stream =
+            AdsNotificationStream::new(0, ads_stamp_headers.len() as u32, ads_stamp_headers);
+        //length counts the bytes after the length field
+        stream.length = stream.stream_len() as u32 - 4;
+        stream
+    }
+
     pub fn stream_len(&self) -> usize {
         let mut len: usize = 0;
         for stamp in &self.ads_stamp_headers {
@@ -558,6 +662,41 @@ impl AdsNotificationStream {
         //plus fixed byte size (length, stamps)
         len + 8
     }
+
+    ///Notification handles of the samples in the order they appear
+    pub fn handles(&self) -> Vec<u32> {
+        let mut handles: Vec<u32> = Vec::new();
+        for header in &self.ads_stamp_headers {
+            for sample in &header.notification_samples {
+                if !handles.contains(&sample.notification_handle) {
+                    handles.push(sample.notification_handle);
+                }
+            }
+        }
+        handles
+    }
+
+    ///Stream with the samples of one notification handle.
+    ///Stamp headers without a sample of the handle are left out.
+    pub fn for_handle(&self, handle: u32) -> AdsNotificationStream {
+        let mut ads_stamp_headers: Vec<AdsStampHeader> = Vec::new();
+        for header in &self.ads_stamp_headers {
+            let samples: Vec<AdsNotificationSample> = header
+                .notification_samples
+                .iter()
+                .filter(|s| s.notification_handle == handle)
+                .cloned()
+                .collect();
+            if !samples.is_empty() {
+                ads_stamp_headers.push(AdsStampHeader::new(
+                    header.time_stamp,
+                    samples.len() as u32,
+                    samples,
+                ));
+            }
+        }
+        AdsNotificationStream::from_stamp_headers(ads_stamp_headers)
+    }
 }
 
 //Ads Read response
@@ -591,6 +730,13 @@ impl WriteTo for ReadResponse {
     }
 }
 
+impl EncodedLen for ReadResponse {
+    fn encoded_len(&self) -> usize {
+        //result, length
+        8 + self.data.len()
+    }
+}
+
 impl ReadResponse {
     pub fn new(result: AdsError, data: Vec<u8>) -> Self {
         ReadResponse {
@@ -632,6 +778,13 @@ impl WriteTo for ReadWriteResponse {
     }
 }
 
+impl EncodedLen for ReadWriteResponse {
+    fn encoded_len(&self) -> usize {
+        //result, length
+        8 + self.data.len()
+    }
+}
+
 impl ReadWriteResponse {
     pub fn new(result: AdsError, data: Vec<u8>) -> Self {
         ReadWriteResponse {
@@ -1142,6 +1295,84 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ads_notification_stream_unequal_stamps_test() {
+        let stamp_header1 = AdsStampHeader::new(
+            100,
+            2,
+            vec![
+                AdsNotificationSample::new(1, vec![1, 0]),
+                AdsNotificationSample::new(2, vec![2, 0, 0, 0]),
+            ],
+        );
+        let stamp_header2 =
+            AdsStampHeader::new(200, 1, vec![AdsNotificationSample::new(3, vec![3])]);
+        let stream = AdsNotificationStream::from_stamp_headers(vec![stamp_header1, stamp_header2]);
+        assert_eq!(stream.length, 59);
+        assert_eq!(stream.stamps, 2);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        stream.write_to(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), stream.stream_len());
+
+        let read = AdsNotificationStream::read_from(&mut buffer.as_slice()).unwrap();
+        assert_eq!(read, stream);
+    }
+
+    #[test]
+    fn ads_notification_stream_for_handle_test() {
+        let stream = AdsNotificationStream::from_stamp_headers(vec![
+            AdsStampHeader::new(
+                100,
+                2,
+                vec![
+                    AdsNotificationSample::new(1, vec![1]),
+                    AdsNotificationSample::new(2, vec![2]),
+                ],
+            ),
+            AdsStampHeader::new(200, 1, vec![AdsNotificationSample::new(2, vec![3])]),
+        ]);
+        assert_eq!(stream.handles(), vec![1, 2]);
+
+        let expected = AdsNotificationStream::from_stamp_headers(vec![AdsStampHeader::new(
+            100,
+            1,
+            vec![AdsNotificationSample::new(1, vec![1])],
+        )]);
+        assert_eq!(stream.for_handle(1), expected);
+        assert_eq!(stream.for_handle(2).stamps, 2);
+        assert_eq!(stream.for_handle(3).stamps, 0);
+    }
+
+    #[test]
+    fn ads_notification_stream_length_exceeds_frame_test() {
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes()); //length
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes()); //stamps
+        buffer.extend_from_slice(&[0; 12]);
+        let error = AdsNotificationStream::read_from(&mut buffer.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        //Stamp count larger than the stamp data
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.extend_from_slice(&16u32.to_le_bytes());
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes());
+        buffer.extend_from_slice(&[0; 12]);
+        assert!(AdsNotificationStream::read_from(&mut buffer.as_slice()).is_err());
+    }
+
+    #[test]
+    fn ads_stamp_header_sample_exceeds_frame_test() {
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.extend_from_slice(&100u64.to_le_bytes()); //time stamp
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes()); //samples
+        buffer.extend_from_slice(&1u32.to_le_bytes()); //handle
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes()); //sample size
+        buffer.extend_from_slice(&[1, 2]);
+        let error = AdsStampHeader::read_from(&mut buffer.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
     #[test]
     fn ads_notification_stream_write_to_test() {
         //4+4+4=12byte
@@ -1169,19 +1400,14 @@ mod tests {
         };
 
         //8+4+12+10=34byte
-        let mut notification_samples = Vec::new();
-        notification_samples.push(notification_sample1);
-        notification_samples.push(notification_sample2);
+        let notification_samples = vec![notification_sample1, notification_sample2];
         let stamp_header1 = AdsStampHeader::new(1234567890, 2, notification_samples);
 
         //8+4+16=28byte
-        let mut notification_samples = Vec::new();
-        notification_samples.push(notification_sample3);
+        let notification_samples = vec![notification_sample3];
         let stamp_header2 = AdsStampHeader::new(1234567890, 1, notification_samples);
 
-        let mut stamp_headers = Vec::new();
-        stamp_headers.push(stamp_header1);
-        stamp_headers.push(stamp_header2);
+        let stamp_headers = vec![stamp_header1, stamp_header2];
 
         let mut len: usize = 0;
         for header in &stamp_headers {
@@ -1243,4 +1469,41 @@ mod tests {
 
         assert_eq!(buffer, expected_data, "Data in buffer is not as expected");
     }
+
+    #[test]
+    fn encoded_len_test() {
+        let responses = vec![
+            Response::ReadDeviceInfo(ReadDeviceInfoResponse::new(
+                AdsError::ErrNoError,
+                1,
+                2,
+                33,
+                [1; 16],
+            )),
+            Response::Read(ReadResponse::new(AdsError::ErrNoError, vec![1, 2, 3])),
+            Response::Write(WriteResponse::new(AdsError::ErrNoError)),
+            Response::ReadState(ReadStateResponse::new(
+                AdsError::ErrNoError,
+                AdsState::AdsStateRun,
+                0,
+            )),
+            Response::WriteControl(WriteControlResponse::new(AdsError::ErrNoError)),
+            Response::AddDeviceNotification(AddDeviceNotificationResponse::new(
+                AdsError::ErrNoError,
+                7,
+            )),
+            Response::DeleteDeviceNotification(DeleteDeviceNotificationResponse::new(
+                AdsError::ErrNoError,
+            )),
+            Response::DeviceNotification(AdsNotificationStream::from_stamp_headers(vec![
+                AdsStampHeader::new(1, 1, vec![AdsNotificationSample::new(7, vec![1, 2])]),
+            ])),
+            Response::ReadWrite(ReadWriteResponse::new(AdsError::ErrNoError, vec![1; 9])),
+        ];
+        for response in responses {
+            let mut buffer = Vec::new();
+            response.write_to(&mut buffer).unwrap();
+            assert_eq!(response.encoded_len(), buffer.len(), "{:?}", response);
+        }
+    }
 }
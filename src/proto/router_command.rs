@@ -0,0 +1,290 @@
+use crate::proto::ams_address::{AmsAddress, AmsNetId};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+///Command in the reserved field of the AMS/TCP header.
+///Frames with a router command carry no AMS header. They are exchanged with a local AMS router.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AmsTcpCommand {
+    ///Frame carries an AMS header (ADS command)
+    AmsCmd,
+    ///Release a port registered at the router
+    PortClose,
+    ///Register a port at the router
+    PortConnect,
+    ///State change of the router
+    RouterNotification,
+    ///Request the NetId of the router
+    GetLocalNetId,
+    Unknown(u16),
+}
+
+impl From<u16> for AmsTcpCommand {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0000 => AmsTcpCommand::AmsCmd,
+            0x0001 => AmsTcpCommand::PortClose,
+            0x1000 => AmsTcpCommand::PortConnect,
+            0x1001 => AmsTcpCommand::RouterNotification,
+            0x1002 => AmsTcpCommand::GetLocalNetId,
+            value => AmsTcpCommand::Unknown(value),
+        }
+    }
+}
+
+impl AmsTcpCommand {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            AmsTcpCommand::AmsCmd => 0x0000,
+            AmsTcpCommand::PortClose => 0x0001,
+            AmsTcpCommand::PortConnect => 0x1000,
+            AmsTcpCommand::RouterNotification => 0x1001,
+            AmsTcpCommand::GetLocalNetId => 0x1002,
+            AmsTcpCommand::Unknown(value) => *value,
+        }
+    }
+}
+
+///State reported by a router notification
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RouterState {
+    Stop,
+    Start,
+    Removed,
+    Unknown(u32),
+}
+
+impl From<u32> for RouterState {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => RouterState::Stop,
+            1 => RouterState::Start,
+            2 => RouterState::Removed,
+            value => RouterState::Unknown(value),
+        }
+    }
+}
+
+impl RouterState {
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            RouterState::Stop => 0,
+            RouterState::Start => 1,
+            RouterState::Removed => 2,
+            RouterState::Unknown(value) => *value,
+        }
+    }
+}
+
+///Frame of the AMS router protocol (AMS/TCP header + payload, no AMS header)
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouterFrame {
+    ///Request a port. 0 lets the router choose a free port.
+    PortConnectRequest {
+        port: u16,
+    },
+    ///Address (router NetId and port) assigned by the router
+    PortConnectResponse(AmsAddress),
+    PortClose {
+        port: u16,
+    },
+    RouterNotification(RouterState),
+    GetLocalNetIdRequest,
+    GetLocalNetIdResponse(AmsNetId),
+}
+
+impl RouterFrame {
+    pub fn command(&self) -> AmsTcpCommand {
+        match self {
+            RouterFrame::PortConnectRequest { .. } | RouterFrame::PortConnectResponse(_) => {
+                AmsTcpCommand::PortConnect
+            }
+            RouterFrame::PortClose { .. } => AmsTcpCommand::PortClose,
+            RouterFrame::RouterNotification(_) => AmsTcpCommand::RouterNotification,
+            RouterFrame::GetLocalNetIdRequest | RouterFrame::GetLocalNetIdResponse(_) => {
+                AmsTcpCommand::GetLocalNetId
+            }
+        }
+    }
+
+    ///Length of the payload in bytes
+    pub fn payload_len(&self) -> u32 {
+        match self {
+            RouterFrame::PortConnectRequest { .. } => 2,
+            RouterFrame::PortConnectResponse(_) => 8,
+            RouterFrame::PortClose { .. } => 2,
+            RouterFrame::RouterNotification(_) => 4,
+            RouterFrame::GetLocalNetIdRequest => 4,
+            RouterFrame::GetLocalNetIdResponse(_) => 6,
+        }
+    }
+
+    ///Parse the payload of a frame. Requests and responses are told apart by the payload length.
+    pub fn parse(command: AmsTcpCommand, mut data: &[u8]) -> io::Result<Self> {
+        let frame = match (command, data.len()) {
+            (AmsTcpCommand::PortConnect, 2) => RouterFrame::PortConnectRequest {
+                port: data.read_u16::<LittleEndian>()?,
+            },
+            (AmsTcpCommand::PortConnect, 8) => {
+                RouterFrame::PortConnectResponse(AmsAddress::read_from(&mut data)?)
+            }
+            (AmsTcpCommand::PortClose, 2) => RouterFrame::PortClose {
+                port: data.read_u16::<LittleEndian>()?,
+            },
+            (AmsTcpCommand::RouterNotification, 4) => {
+                RouterFrame::RouterNotification(RouterState::from(data.read_u32::<LittleEndian>()?))
+            }
+            (AmsTcpCommand::GetLocalNetId, 4) => RouterFrame::GetLocalNetIdRequest,
+            (AmsTcpCommand::GetLocalNetId, 6) => {
+                RouterFrame::GetLocalNetIdResponse(AmsNetId::read_from(&mut data)?)
+            }
+            (command, len) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("No router frame {:?} with {} bytes payload", command, len),
+                ))
+            }
+        };
+        Ok(frame)
+    }
+}
+
+impl WriteTo for RouterFrame {
+    fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        wtr.write_u16::<LittleEndian>(self.command().as_u16())?;
+        wtr.write_u32::<LittleEndian>(self.payload_len())?;
+        match self {
+            RouterFrame::PortConnectRequest { port } | RouterFrame::PortClose { port } => {
+                wtr.write_u16::<LittleEndian>(*port)?
+            }
+            RouterFrame::PortConnectResponse(address) => address.write_to(&mut wtr)?,
+            RouterFrame::RouterNotification(state) => {
+                wtr.write_u32::<LittleEndian>(state.as_u32())?
+            }
+            RouterFrame::GetLocalNetIdRequest => wtr.write_u32::<LittleEndian>(0)?,
+            RouterFrame::GetLocalNetIdResponse(net_id) => net_id.write_to(&mut wtr)?,
+        }
+        Ok(())
+    }
+}
+
+impl EncodedLen for RouterFrame {
+    fn encoded_len(&self) -> usize {
+        //command, length
+        6 + self.payload_len() as usize
+    }
+}
+
+impl ReadFrom for RouterFrame {
+    fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
+        let command = AmsTcpCommand::from(read.read_u16::<LittleEndian>()?);
+        let length = read.read_u32::<LittleEndian>()?;
+        //Router frames are tiny. Anything else is garbage.
+        if length > 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Router frame with {} bytes payload", length),
+            ));
+        }
+        let mut data = vec![0; length as usize];
+        read.read_exact(&mut data)?;
+        RouterFrame::parse(command, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ams_tcp_command_test() {
+        assert_eq!(AmsTcpCommand::from(0), AmsTcpCommand::AmsCmd);
+        assert_eq!(AmsTcpCommand::from(0x1000), AmsTcpCommand::PortConnect);
+        assert_eq!(
+            AmsTcpCommand::from(0x1001),
+            AmsTcpCommand::RouterNotification
+        );
+        assert_eq!(AmsTcpCommand::from(0x1002), AmsTcpCommand::GetLocalNetId);
+        assert_eq!(AmsTcpCommand::from(0x4711), AmsTcpCommand::Unknown(0x4711));
+        assert_eq!(AmsTcpCommand::GetLocalNetId.as_u16(), 0x1002);
+    }
+
+    #[test]
+    fn port_connect_request_write_to_test() {
+        let mut buffer: Vec<u8> = Vec::new();
+        RouterFrame::PortConnectRequest { port: 0 }
+            .write_to(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [0, 16, 2, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn port_connect_response_read_from_test() {
+        let data: Vec<u8> = vec![0, 16, 8, 0, 0, 0, 192, 168, 1, 2, 1, 1, 57, 128];
+        let frame = RouterFrame::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            frame,
+            RouterFrame::PortConnectResponse(AmsAddress::new(
+                AmsNetId::new(192, 168, 1, 2, 1, 1),
+                32825
+            ))
+        );
+    }
+
+    #[test]
+    fn get_local_net_id_test() {
+        let mut buffer: Vec<u8> = Vec::new();
+        RouterFrame::GetLocalNetIdRequest
+            .write_to(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [2, 16, 4, 0, 0, 0, 0, 0, 0, 0]);
+
+        let data: Vec<u8> = vec![2, 16, 6, 0, 0, 0, 5, 1, 2, 3, 1, 1];
+        let frame = RouterFrame::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            frame,
+            RouterFrame::GetLocalNetIdResponse(AmsNetId::new(5, 1, 2, 3, 1, 1))
+        );
+    }
+
+    #[test]
+    fn router_notification_round_trip_test() {
+        let frame = RouterFrame::RouterNotification(RouterState::Stop);
+        let mut buffer: Vec<u8> = Vec::new();
+        frame.write_to(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(
+            RouterFrame::read_from(&mut buffer.as_slice()).unwrap(),
+            frame
+        );
+    }
+
+    #[test]
+    fn invalid_router_frame_test() {
+        let data: Vec<u8> = vec![0, 16, 3, 0, 0, 0, 1, 2, 3];
+        let error = RouterFrame::read_from(&mut data.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn router_frame_encoded_len_test() {
+        let frames = vec![
+            RouterFrame::PortConnectRequest { port: 0 },
+            RouterFrame::PortConnectResponse(AmsAddress::new(
+                AmsNetId::new(1, 2, 3, 4, 1, 1),
+                30000,
+            )),
+            RouterFrame::PortClose { port: 30000 },
+            RouterFrame::RouterNotification(RouterState::Start),
+            RouterFrame::GetLocalNetIdRequest,
+            RouterFrame::GetLocalNetIdResponse(AmsNetId::new(1, 2, 3, 4, 1, 1)),
+        ];
+        for frame in frames {
+            let mut buffer = Vec::new();
+            frame.write_to(&mut buffer).unwrap();
+            assert_eq!(frame.encoded_len(), buffer.len(), "{:?}", frame);
+        }
+    }
+}
@@ -1,34 +1,39 @@
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use bitfield::Bit;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Read, Write};
 
-#[derive(Debug)]
+const RESPONSE: usize = 0;
+const NO_RETURN: usize = 1;
+const ADS_COMMAND: usize = 2;
+const SYSTEM_COMMAND: usize = 3;
+const HIGH_PRIORITY: usize = 4;
+const TIMESTAMP_ADDED: usize = 5;
+const UDP: usize = 6;
+const INIT_COMMAND: usize = 7;
+const SECURE: usize = 11;
+const MQTT: usize = 12;
+const BROADCAST: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NetProto {
     Tcp,
     Udp,
 }
 
-#[derive(Debug)]
+///State flags of the AMS header
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct StateFlags {
     value: u16,
 }
 
 impl StateFlags {
     pub fn new(response: bool, ads_command: bool, net_proto: NetProto) -> Self {
-        let mut state_flags: u16 = 0;
-        state_flags.set_bit(0, response);
-        state_flags.set_bit(2, ads_command);
-
-        state_flags.set_bit(
-            6,
-            match net_proto {
-                NetProto::Tcp => false,
-                NetProto::Udp => true,
-            },
-        );
-
-        StateFlags { value: state_flags }
+        let mut state_flags = StateFlags { value: 0 };
+        state_flags.set_response(response);
+        state_flags.set_ads_command(ads_command);
+        state_flags.set_net_proto(net_proto);
+        state_flags
     }
 
     ///default for response (response=true, ads_command=true, net_proto=Tcp)
@@ -45,16 +50,108 @@ impl StateFlags {
         self.value
     }
 
+    pub fn net_proto(&self) -> NetProto {
+        match self.value.bit(UDP) {
+            true => NetProto::Udp,
+            false => NetProto::Tcp,
+        }
+    }
+
+    pub fn set_net_proto(&mut self, net_proto: NetProto) {
+        self.value.set_bit(UDP, net_proto == NetProto::Udp);
+    }
+
     pub fn is_tcp(&self) -> bool {
-        !self.value.bit(6)
+        !self.value.bit(UDP)
+    }
+
+    pub fn is_udp(&self) -> bool {
+        self.value.bit(UDP)
     }
 
     pub fn is_response(&self) -> bool {
-        self.value.bit(0)
+        self.value.bit(RESPONSE)
+    }
+
+    pub fn set_response(&mut self, response: bool) {
+        self.value.set_bit(RESPONSE, response);
+    }
+
+    ///The sender does not expect a response (fire and forget)
+    pub fn is_no_return(&self) -> bool {
+        self.value.bit(NO_RETURN)
+    }
+
+    pub fn set_no_return(&mut self, no_return: bool) {
+        self.value.set_bit(NO_RETURN, no_return);
     }
 
     pub fn is_ads_command(&self) -> bool {
-        self.value.bit(2)
+        self.value.bit(ADS_COMMAND)
+    }
+
+    pub fn set_ads_command(&mut self, ads_command: bool) {
+        self.value.set_bit(ADS_COMMAND, ads_command);
+    }
+
+    ///Command of the AMS system (e.g. the router) instead of an ADS device
+    pub fn is_system_command(&self) -> bool {
+        self.value.bit(SYSTEM_COMMAND)
+    }
+
+    pub fn set_system_command(&mut self, system_command: bool) {
+        self.value.set_bit(SYSTEM_COMMAND, system_command);
+    }
+
+    pub fn is_high_priority(&self) -> bool {
+        self.value.bit(HIGH_PRIORITY)
+    }
+
+    pub fn set_high_priority(&mut self, high_priority: bool) {
+        self.value.set_bit(HIGH_PRIORITY, high_priority);
+    }
+
+    ///An 8 byte timestamp follows the data
+    pub fn is_timestamp_added(&self) -> bool {
+        self.value.bit(TIMESTAMP_ADDED)
+    }
+
+    pub fn set_timestamp_added(&mut self, timestamp_added: bool) {
+        self.value.set_bit(TIMESTAMP_ADDED, timestamp_added);
+    }
+
+    pub fn is_init_command(&self) -> bool {
+        self.value.bit(INIT_COMMAND)
+    }
+
+    pub fn set_init_command(&mut self, init_command: bool) {
+        self.value.set_bit(INIT_COMMAND, init_command);
+    }
+
+    ///Frame was sent over a secured (TLS) ADS connection
+    pub fn is_secure(&self) -> bool {
+        self.value.bit(SECURE)
+    }
+
+    pub fn set_secure(&mut self, secure: bool) {
+        self.value.set_bit(SECURE, secure);
+    }
+
+    ///Frame was tunnelled over MQTT
+    pub fn is_mqtt(&self) -> bool {
+        self.value.bit(MQTT)
+    }
+
+    pub fn set_mqtt(&mut self, mqtt: bool) {
+        self.value.set_bit(MQTT, mqtt);
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.value.bit(BROADCAST)
+    }
+
+    pub fn set_broadcast(&mut self, broadcast: bool) {
+        self.value.set_bit(BROADCAST, broadcast);
     }
 }
 
@@ -72,6 +169,12 @@ impl WriteTo for StateFlags {
     }
 }
 
+impl EncodedLen for StateFlags {
+    fn encoded_len(&self) -> usize {
+        2
+    }
+}
+
 impl ReadFrom for StateFlags {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         Ok(StateFlags::from(read.read_u16::<LittleEndian>()?))
@@ -147,7 +250,7 @@ mod tests {
         let mut buffer: Vec<u8> = vec![5, 0, 1, 99, 4];
         let state_flags = StateFlags::read_from(&mut buffer.as_slice()).unwrap();
 
-        assert_eq!(state_flags.is_tcp(), true);
+        assert!(state_flags.is_tcp());
     }
 
     #[test]
@@ -155,7 +258,7 @@ mod tests {
         let mut buffer: Vec<u8> = vec![69, 0, 1, 99, 4];
         let state_flags = StateFlags::read_from(&mut buffer.as_slice()).unwrap();
 
-        assert_eq!(state_flags.is_tcp(), false);
+        assert!(!state_flags.is_tcp());
     }
 
     #[test]
@@ -163,7 +266,7 @@ mod tests {
         let mut buffer: Vec<u8> = vec![5, 0, 1, 99, 4];
         let state_flags = StateFlags::read_from(&mut buffer.as_slice()).unwrap();
 
-        assert_eq!(state_flags.is_response(), true);
+        assert!(state_flags.is_response());
     }
 
     #[test]
@@ -171,7 +274,40 @@ mod tests {
         let mut buffer: Vec<u8> = vec![4, 0, 1, 99, 4];
         let state_flags = StateFlags::read_from(&mut buffer.as_slice()).unwrap();
 
-        assert_eq!(state_flags.is_ads_command(), true);
-        assert_eq!(state_flags.is_response(), false);
+        assert!(state_flags.is_ads_command());
+        assert!(!state_flags.is_response());
+    }
+
+    #[test]
+    fn setter_test() {
+        let mut state_flags = StateFlags::req_default();
+        state_flags.set_no_return(true);
+        assert_eq!(state_flags.value(), 0x0006);
+        assert!(state_flags.is_no_return());
+
+        state_flags.set_system_command(true);
+        state_flags.set_high_priority(true);
+        state_flags.set_timestamp_added(true);
+        state_flags.set_init_command(true);
+        state_flags.set_broadcast(true);
+        assert_eq!(state_flags.value(), 0x80BE);
+
+        state_flags.set_net_proto(NetProto::Udp);
+        assert_eq!(state_flags.net_proto(), NetProto::Udp);
+        state_flags.set_secure(true);
+        state_flags.set_mqtt(true);
+        assert_eq!(state_flags.value(), 0x98FE);
+
+        state_flags.set_no_return(false);
+        state_flags.set_broadcast(false);
+        state_flags.set_ads_command(false);
+        assert_eq!(state_flags.value(), 0x18F8);
+        assert!(state_flags.is_system_command());
+        assert!(state_flags.is_high_priority());
+        assert!(state_flags.is_timestamp_added());
+        assert!(state_flags.is_init_command());
+        assert!(state_flags.is_secure());
+        assert!(state_flags.is_mqtt());
+        assert!(!state_flags.is_broadcast());
     }
 }
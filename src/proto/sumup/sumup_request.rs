@@ -5,7 +5,7 @@ use crate::error::TryIntoError;
 use crate::proto::ads_state::AdsState;
 use crate::proto::ads_transition_mode::AdsTransMode;
 use crate::proto::command_id::CommandID;
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use crate::proto::request::{ReadRequest, ReadWriteRequest, Request, WriteRequest};
 use std::convert::TryInto;
 
@@ -62,21 +62,29 @@ impl SumupReadWriteRequest {
 
 impl WriteTo for SumupReadWriteRequest {
     fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
-        let mut access_data: Vec<u8> = Vec::new();
-        let mut data: Vec<u8> = Vec::new();
+        //Access data of all requests first, followed by the data of all requests
+        for request in &self.read_write_requests {
+            wtr.write_u32::<LittleEndian>(request.index_group)?;
+            wtr.write_u32::<LittleEndian>(request.index_offset)?;
+            wtr.write_u32::<LittleEndian>(request.read_length)?;
+            wtr.write_u32::<LittleEndian>(request.write_length)?;
+        }
         for request in &self.read_write_requests {
-            access_data.write_u32::<LittleEndian>(request.index_group)?;
-            access_data.write_u32::<LittleEndian>(request.index_offset)?;
-            access_data.write_u32::<LittleEndian>(request.read_length)?;
-            access_data.write_u32::<LittleEndian>(request.write_length)?;
-            data.write_all(request.data.as_slice());
+            wtr.write_all(request.data.as_slice())?;
         }
-        access_data.append(&mut data);
-        wtr.write_all(&access_data);
         Ok(())
     }
 }
 
+impl EncodedLen for SumupReadWriteRequest {
+    fn encoded_len(&self) -> usize {
+        self.read_write_requests
+            .iter()
+            .map(|r| 16 + r.data.len())
+            .sum()
+    }
+}
+
 impl ReadFrom for SumupReadWriteRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let mut data_buf: Vec<u8> = Vec::new();
@@ -148,15 +156,19 @@ impl SumupReadRequest {
 
 impl WriteTo for SumupReadRequest {
     fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
-        let mut access_data: Vec<u8> = Vec::new();
         for request in &self.read_requests {
-            request.write_to(&mut access_data);
+            request.write_to(&mut wtr)?;
         }
-        wtr.write_all(&access_data);
         Ok(())
     }
 }
 
+impl EncodedLen for SumupReadRequest {
+    fn encoded_len(&self) -> usize {
+        self.read_requests.iter().map(|r| r.encoded_len()).sum()
+    }
+}
+
 impl ReadFrom for SumupReadRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let access_data_size: usize = 12; //Index group(4 byte) + index offset(4 byte) + read length(4 byte)
@@ -234,20 +246,25 @@ impl SumupWriteRequest {
 
 impl WriteTo for SumupWriteRequest {
     fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
-        let mut access_data: Vec<u8> = Vec::new();
-        let mut data: Vec<u8> = Vec::new();
+        //Access data of all requests first, followed by the data of all requests
+        for request in &self.write_requests {
+            wtr.write_u32::<LittleEndian>(request.index_group)?;
+            wtr.write_u32::<LittleEndian>(request.index_offset)?;
+            wtr.write_u32::<LittleEndian>(request.length)?;
+        }
         for request in &self.write_requests {
-            access_data.write_u32::<LittleEndian>(request.index_group)?;
-            access_data.write_u32::<LittleEndian>(request.index_offset)?;
-            access_data.write_u32::<LittleEndian>(request.length)?;
-            data.write_all(request.data.as_slice());
+            wtr.write_all(request.data.as_slice())?;
         }
-        access_data.append(&mut data);
-        wtr.write_all(&access_data);
         Ok(())
     }
 }
 
+impl EncodedLen for SumupWriteRequest {
+    fn encoded_len(&self) -> usize {
+        self.write_requests.iter().map(|r| 12 + r.data.len()).sum()
+    }
+}
+
 impl ReadFrom for SumupWriteRequest {
     fn read_from<R: Read>(read: &mut R) -> io::Result<Self> {
         let mut data_buf: Vec<u8> = Vec::new();
@@ -477,4 +494,31 @@ mod tests {
             "comparing sum_read_write_request failed"
         );
     }
+
+    #[test]
+    fn sumup_encoded_len_test() {
+        let mut buffer = Vec::new();
+        let read = SumupReadRequest::new(vec![
+            ReadRequest::new(259, 259, 4),
+            ReadRequest::new(259, 260, 2),
+        ]);
+        read.write_to(&mut buffer).unwrap();
+        assert_eq!(read.encoded_len(), buffer.len());
+
+        buffer.clear();
+        let write = SumupWriteRequest::new(vec![
+            WriteRequest::new(259, 259, vec![1, 2, 3]),
+            WriteRequest::new(259, 260, vec![4]),
+        ]);
+        write.write_to(&mut buffer).unwrap();
+        assert_eq!(write.encoded_len(), buffer.len());
+
+        buffer.clear();
+        let read_write = SumupReadWriteRequest::new(vec![
+            ReadWriteRequest::new(259, 259, 4, vec![1, 2]),
+            ReadWriteRequest::new(259, 260, 4, vec![]),
+        ]);
+        read_write.write_to(&mut buffer).unwrap();
+        assert_eq!(read_write.encoded_len(), buffer.len());
+    }
 }
@@ -1,7 +1,7 @@
 use crate::error::{AdsError, TryIntoError};
 use crate::proto::ads_state::AdsState;
 use crate::proto::command_id::CommandID;
-use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::proto_traits::{EncodedLen, ReadFrom, WriteTo};
 use crate::proto::response::{ReadResponse, ReadWriteResponse, Response, WriteResponse};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::convert::TryInto;
@@ -82,19 +82,24 @@ impl ReadFrom for SumupReadResponse {
 
 impl WriteTo for SumupReadResponse {
     fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
-        let mut access_data: Vec<u8> = Vec::new();
-        let mut data: Vec<u8> = Vec::new();
+        //Results and lengths of all responses first, followed by the data of all responses
+        for response in &self.read_responses {
+            wtr.write_u32::<LittleEndian>(response.result.as_u32())?;
+            wtr.write_u32::<LittleEndian>(response.length)?;
+        }
         for response in &self.read_responses {
-            access_data.write_u32::<LittleEndian>(response.result.as_u32())?;
-            access_data.write_u32::<LittleEndian>(response.length)?;
-            data.write_all(response.data.as_slice());
+            wtr.write_all(response.data.as_slice())?;
         }
-        access_data.append(&mut data);
-        wtr.write_all(&access_data);
         Ok(())
     }
 }
 
+impl EncodedLen for SumupReadResponse {
+    fn encoded_len(&self) -> usize {
+        self.read_responses.iter().map(|r| r.encoded_len()).sum()
+    }
+}
+
 ///Ads Sumup Write response
 ///Bundle multiple responses toghether. Add this data to the read write response or parse from.
 #[derive(Debug, Clone, PartialEq)]
@@ -124,12 +129,18 @@ impl ReadFrom for SumupWriteResponse {
 impl WriteTo for SumupWriteResponse {
     fn write_to<W: Write>(&self, mut wtr: W) -> io::Result<()> {
         for result in &self.write_responses {
-            result.write_to(&mut wtr);
+            result.write_to(&mut wtr)?;
         }
         Ok(())
     }
 }
 
+impl EncodedLen for SumupWriteResponse {
+    fn encoded_len(&self) -> usize {
+        self.write_responses.iter().map(|r| r.encoded_len()).sum()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,4 +287,23 @@ mod tests {
 
         assert_eq!(sum_write_response, compare);
     }
+
+    #[test]
+    fn sumup_encoded_len_test() {
+        let mut buffer = Vec::new();
+        let read = SumupReadResponse::new(vec![
+            ReadResponse::new(AdsError::ErrNoError, vec![1, 2]),
+            ReadResponse::new(AdsError::AdsErrDeviceSymbolNotFound, vec![]),
+        ]);
+        read.write_to(&mut buffer).unwrap();
+        assert_eq!(read.encoded_len(), buffer.len());
+
+        buffer.clear();
+        let write = SumupWriteResponse::new(vec![
+            WriteResponse::new(AdsError::ErrNoError),
+            WriteResponse::new(AdsError::ErrNoError),
+        ]);
+        write.write_to(&mut buffer).unwrap();
+        assert_eq!(write.encoded_len(), buffer.len());
+    }
 }
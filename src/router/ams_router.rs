@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use crate::client::ads_client::{Connection, ADS_TCP_SERVER_PORT};
+use crate::client::handle::ClientHandle;
+use crate::client::read::ClientResult;
+use crate::error::AdsError;
+use crate::proto::ams_address::{AmsAddress, AmsNetId};
+use crate::router::connection::RouterConnection;
+use crate::router::port::AdsPort;
+
+///First port handed out by open_port
+pub const PORT_BASE: u16 = 30000;
+///Number of ports which can be open at the same time
+pub const MAX_PORTS: usize = 128;
+
+///In-process AMS router.
+///Local clients open virtual ports. Requests to a remote NetId are sent over one TCP
+///connection per route target, which is shared by all ports. Notifications are
+///dispatched to the port which added them.
+///Clones share the same router.
+#[derive(Debug, Clone)]
+pub struct AmsRouter {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    local_net_id: Mutex<AmsNetId>,
+    ///Open flag for every port starting at PORT_BASE
+    ports: Mutex<Vec<bool>>,
+    routes: Mutex<HashMap<AmsNetId, SocketAddr>>,
+    connections: Mutex<HashMap<SocketAddr, Arc<RouterConnection>>>,
+}
+
+impl AmsRouter {
+    pub fn new(local_net_id: AmsNetId) -> Self {
+        AmsRouter {
+            inner: Arc::new(Inner {
+                local_net_id: Mutex::new(local_net_id),
+                ports: Mutex::new(Vec::new()),
+                routes: Mutex::new(HashMap::new()),
+                connections: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    ///NetId used as source address by all ports
+    pub fn local_net_id(&self) -> AmsNetId {
+        match self.inner.local_net_id.lock() {
+            Ok(c) => c.clone(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Change the local NetId. Applies to handles created afterwards.
+    pub fn set_local_net_id(&self, net_id: AmsNetId) {
+        match self.inner.local_net_id.lock() {
+            Ok(mut c) => *c = net_id,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+
+    ///Route the NetId to the ADS port (48898) of a remote router
+    pub fn add_route(&self, net_id: AmsNetId, ip: IpAddr) -> ClientResult<()> {
+        self.add_route_addr(net_id, SocketAddr::new(ip, ADS_TCP_SERVER_PORT))
+    }
+
+    ///Route the NetId to a remote router. Several NetIds can share one remote router.
+    ///Fails with RouterErrPortAlreadyInUse if the NetId is routed elsewhere.
+    pub fn add_route_addr(&self, net_id: AmsNetId, addr: SocketAddr) -> ClientResult<()> {
+        let mut routes = match self.inner.routes.lock() {
+            Ok(c) => c,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        match routes.get(&net_id) {
+            Some(existing) if existing != &addr => Err(AdsError::RouterErrPortAlreadyInUse.into()),
+            _ => {
+                routes.insert(net_id, addr);
+                Ok(())
+            }
+        }
+    }
+
+    ///Remove the route. The TCP connection is closed if no other route uses it.
+    pub fn delete_route(&self, net_id: &AmsNetId) -> ClientResult<()> {
+        let mut routes = match self.inner.routes.lock() {
+            Ok(c) => c,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let addr = match routes.remove(net_id) {
+            Some(addr) => addr,
+            None => return Err(AdsError::ErrTargetMachineNotFound.into()),
+        };
+        if routes.values().all(|a| a != &addr) {
+            match self.inner.connections.lock() {
+                Ok(mut c) => c.remove(&addr),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+        Ok(())
+    }
+
+    ///Address of the remote router for the NetId
+    pub fn route(&self, net_id: &AmsNetId) -> Option<SocketAddr> {
+        match self.inner.routes.lock() {
+            Ok(c) => c.get(net_id).copied(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Open a virtual port. Closed ports are reused.
+    ///Fails with RouterErrNoMoreQueues if MAX_PORTS ports are open.
+    pub fn open_port(&self) -> ClientResult<AdsPort> {
+        let mut ports = match self.inner.ports.lock() {
+            Ok(c) => c,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let index = match ports.iter().position(|open| !open) {
+            Some(index) => index,
+            None if ports.len() < MAX_PORTS => {
+                ports.push(false);
+                ports.len() - 1
+            }
+            None => return Err(AdsError::RouterErrNoMoreQueues.into()),
+        };
+        ports[index] = true;
+        Ok(AdsPort::new(self.clone(), PORT_BASE + index as u16))
+    }
+
+    pub fn is_port_open(&self, port: u16) -> bool {
+        let index = match port.checked_sub(PORT_BASE) {
+            Some(index) => index as usize,
+            None => return false,
+        };
+        match self.inner.ports.lock() {
+            Ok(c) => c.get(index).copied().unwrap_or(false),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Release the port and delete its notifications on all remote devices
+    pub(crate) fn close_port(&self, port: u16) {
+        let connections: Vec<Arc<RouterConnection>> = match self.inner.connections.lock() {
+            Ok(c) => c.values().cloned().collect(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let source = AmsAddress::new(self.local_net_id(), port);
+        for connection in connections {
+            connection.release_port(&source);
+        }
+
+        if let Some(index) = port.checked_sub(PORT_BASE) {
+            match self.inner.ports.lock() {
+                Ok(mut c) => {
+                    if let Some(open) = c.get_mut(index as usize) {
+                        *open = false;
+                    }
+                }
+                Err(_) => panic!("Failed to get lock!"),
+            };
+        }
+    }
+
+    ///Connection to the remote router of the target. Opened on first use and after a lost link.
+    ///Connecting is done without holding the lock so other routes are not blocked meanwhile.
+    pub(crate) fn connection(&self, target: &AmsNetId) -> ClientResult<Arc<RouterConnection>> {
+        let addr = match self.route(target) {
+            Some(addr) => addr,
+            None => return Err(AdsError::ErrTargetMachineNotFound.into()),
+        };
+        if let Some(connection) = self.connected(&addr) {
+            return Ok(connection);
+        }
+
+        let connection = Arc::new(RouterConnection::open(
+            addr,
+            AmsAddress::new(self.local_net_id(), 0),
+        )?);
+        let mut connections = match self.inner.connections.lock() {
+            Ok(c) => c,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        //Another thread connected meanwhile. Its connection may be in use already.
+        match connections.get(&addr) {
+            Some(existing) if existing.is_connected() => Ok(Arc::clone(existing)),
+            _ => {
+                connections.insert(addr, Arc::clone(&connection));
+                Ok(connection)
+            }
+        }
+    }
+
+    ///Established connection to the remote router
+    fn connected(&self, addr: &SocketAddr) -> Option<Arc<RouterConnection>> {
+        match self.inner.connections.lock() {
+            Ok(c) => c.get(addr).filter(|c| c.is_connected()).cloned(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Open a port and a Connection to the target on it. See AdsPort::connect.
+    pub fn connect(&self, target: AmsAddress) -> ClientResult<Connection> {
+        self.open_port()?.connect(target)
+    }
+
+    ///Handle which sends from the local port to the target
+    pub(crate) fn handle(&self, port: u16, target: AmsAddress) -> ClientResult<ClientHandle> {
+        if !self.is_port_open(port) {
+            return Err(AdsError::ErrPortNotConnected.into());
+        }
+        let connection = self.connection(&target.ams_net_id)?;
+        Ok(connection.handle(AmsAddress::new(self.local_net_id(), port), target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::notification::QueueConfig;
+    use crate::client::plc_types::{PlcTypes, Var};
+    use crate::error::ClientError;
+    use crate::proto::ads_transition_mode::AdsTransMode;
+    use crate::proto::request::AddDeviceNotificationRequest;
+    use crate::server::ads_server::AdsServer;
+    use crate::server::simulator::{SymbolTable, VirtualPlc};
+    use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn local() -> AmsNetId {
+        AmsNetId::new(10, 0, 0, 1, 1, 1)
+    }
+
+    fn device() -> AmsAddress {
+        AmsAddress::new(AmsNetId::new(10, 0, 0, 2, 1, 1), 851)
+    }
+
+    ///AMS header of an answer to a request header (addresses swapped)
+    fn answer(request: &[u8], command: u16, invoke_id: u32, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0, 0];
+        out.write_u32::<LittleEndian>(32 + data.len() as u32)
+            .unwrap();
+        out.extend_from_slice(&request[8..16]);
+        out.extend_from_slice(&request[0..8]);
+        out.write_u16::<LittleEndian>(command).unwrap();
+        out.write_u16::<LittleEndian>(if command == 8 { 4 } else { 5 })
+            .unwrap();
+        out.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        out.write_u32::<LittleEndian>(0).unwrap();
+        out.write_u32::<LittleEndian>(invoke_id).unwrap();
+        out.extend_from_slice(data);
+        out
+    }
+
+    ///Device which answers AddDeviceNotification with handle 1 and
+    ///sends a notification with the port number as value right after it.
+    fn fake_device(accepted: Arc<AtomicUsize>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || loop {
+                    let mut header = [0u8; 38];
+                    if stream.read_exact(&mut header).is_err() {
+                        return;
+                    }
+                    let length = (&header[26..30]).read_u32::<LittleEndian>().unwrap();
+                    let mut data = vec![0; length as usize];
+                    stream.read_exact(&mut data).unwrap();
+                    let ams = &header[6..];
+                    let command = (&ams[16..18]).read_u16::<LittleEndian>().unwrap();
+                    let invoke_id = (&ams[28..32]).read_u32::<LittleEndian>().unwrap();
+                    let port = (&ams[14..16]).read_u16::<LittleEndian>().unwrap();
+                    match command {
+                        6 => {
+                            let mut payload = vec![0; 4];
+                            payload.write_u32::<LittleEndian>(1).unwrap();
+                            stream
+                                .write_all(&answer(ams, 6, invoke_id, &payload))
+                                .unwrap();
+
+                            let mut sample = Vec::new();
+                            sample.write_u32::<LittleEndian>(26).unwrap();
+                            sample.write_u32::<LittleEndian>(1).unwrap();
+                            sample.write_u64::<LittleEndian>(0).unwrap();
+                            sample.write_u32::<LittleEndian>(1).unwrap();
+                            sample.write_u32::<LittleEndian>(1).unwrap();
+                            sample.write_u32::<LittleEndian>(2).unwrap();
+                            sample.write_u16::<LittleEndian>(port).unwrap();
+                            stream.write_all(&answer(ams, 8, 0, &sample)).unwrap();
+                        }
+                        _ => stream
+                            .write_all(&answer(ams, command, invoke_id, &[0; 8]))
+                            .unwrap(),
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn open_port_test() {
+        let router = AmsRouter::new(local());
+        let first = router.open_port().unwrap();
+        let second = router.open_port().unwrap();
+        assert_eq!(first.port(), PORT_BASE);
+        assert_eq!(second.port(), PORT_BASE + 1);
+        assert_eq!(first.local_address(), AmsAddress::new(local(), PORT_BASE));
+
+        drop(first);
+        assert!(!router.is_port_open(PORT_BASE));
+        assert_eq!(router.open_port().unwrap().port(), PORT_BASE);
+    }
+
+    #[test]
+    fn max_ports_test() {
+        let router = AmsRouter::new(local());
+        let ports: Vec<AdsPort> = (0..MAX_PORTS)
+            .map(|_| router.open_port().unwrap())
+            .collect();
+        let error = router.open_port().unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::RouterErrNoMoreQueues));
+        drop(ports);
+        assert!(router.open_port().is_ok());
+    }
+
+    #[test]
+    fn route_test() {
+        let router = AmsRouter::new(local());
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+        router.add_route(device().ams_net_id, ip).unwrap();
+        assert_eq!(
+            router.route(&device().ams_net_id),
+            Some(SocketAddr::new(ip, ADS_TCP_SERVER_PORT))
+        );
+        //Same route again is fine, another target is not
+        router.add_route(device().ams_net_id, ip).unwrap();
+        let error = router
+            .add_route(
+                device().ams_net_id,
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 3)),
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.ads_error(),
+            Some(&AdsError::RouterErrPortAlreadyInUse)
+        );
+
+        router.delete_route(&device().ams_net_id).unwrap();
+        assert_eq!(router.route(&device().ams_net_id), None);
+        let port = router.open_port().unwrap();
+        let error = port.handle(device()).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrTargetMachineNotFound));
+    }
+
+    #[test]
+    fn shared_connection_test() {
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let addr = fake_device(Arc::clone(&accepted));
+        let router = AmsRouter::new(local());
+        router.add_route_addr(device().ams_net_id, addr).unwrap();
+
+        let first = router.open_port().unwrap();
+        let second = router.open_port().unwrap();
+        first.handle(device()).unwrap().read_state().unwrap();
+        second.handle(device()).unwrap().read_state().unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        //Both ports get notification handle 1 from the device
+        let request =
+            || AddDeviceNotificationRequest::new(0x4020, 0, 2, AdsTransMode::OnChange, 0, 0);
+        let (handle, first_rx) = first
+            .add_device_notification(device(), request(), QueueConfig::default())
+            .unwrap();
+        assert_eq!(handle, 1);
+        let (_, second_rx) = second
+            .add_device_notification(device(), request(), QueueConfig::default())
+            .unwrap();
+
+        for (port, rx) in [(first.port(), first_rx), (second.port(), second_rx)].iter() {
+            let stream = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+            let sample = &stream.stamps[0].samples[0];
+            assert_eq!(sample.data, port.to_le_bytes().to_vec());
+        }
+    }
+
+    #[test]
+    fn connection_on_port_test() {
+        let plc = VirtualPlc::new(SymbolTable::parse("MAIN.counter : INT := 7;").unwrap());
+        let server = AdsServer::new(device().ams_net_id);
+        server.add_device(851, plc);
+        let server_handle = server.start_on("127.0.0.1:0").unwrap();
+        let router = AmsRouter::new(local());
+        router
+            .add_route_addr(device().ams_net_id, server_handle.local_addr())
+            .unwrap();
+
+        let counter = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        let mut first = router.connect(device()).unwrap();
+        let mut second = router.connect(device()).unwrap();
+        let port = router.open_port().unwrap();
+        //Same invoke id on both connections. Responses are dispatched by port.
+        first.get_symhandle(&counter, 1).unwrap();
+        second.get_symhandle(&counter, 1).unwrap();
+        assert_eq!(first.read_by_name(&counter, 1).unwrap(), vec![7, 0]);
+        assert_eq!(second.read_by_name(&counter, 1).unwrap(), vec![7, 0]);
+        port.handle(device()).unwrap().read_state().unwrap();
+        assert_eq!(server_handle.clients(), 1);
+
+        let timeout = Duration::from_secs(1);
+        let first_rx = first
+            .add_device_notification(&counter, AdsTransMode::OnChange, 0, 0, 2)
+            .unwrap();
+        let second_rx = second
+            .add_device_notification(&counter, AdsTransMode::OnChange, 0, 0, 2)
+            .unwrap();
+        for rx in [first_rx, second_rx].iter() {
+            let stream = rx.recv_timeout(timeout).unwrap().unwrap();
+            let sample = &stream.stamps[0].samples[0];
+            assert_eq!(sample.data, vec![7, 0]);
+        }
+
+        //The connection owns its port
+        drop(first);
+        assert!(!router.is_port_open(PORT_BASE));
+        assert_eq!(second.read_by_name(&counter, 3).unwrap(), vec![7, 0]);
+
+        //Handles of the port were used already
+        let error = port.connect(device()).unwrap_err();
+        assert_eq!(
+            error.ads_error(),
+            Some(&AdsError::RouterErrPortAlreadyInUse)
+        );
+    }
+
+    #[test]
+    fn closed_port_test() {
+        let router = AmsRouter::new(local());
+        let port = router.open_port().unwrap();
+        let number = port.port();
+        drop(port);
+        let error = router.handle(number, device()).unwrap_err();
+        assert!(matches!(
+            error,
+            ClientError::Ads(AdsError::ErrPortNotConnected)
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::client::ads_client::{Link, SharedLink};
+use crate::client::builder::ConnectionConfig;
+use crate::client::connection_state::{ConnectionState, ConnectionStateTracker};
+use crate::client::dispatch::{Dispatcher, NotificationKey};
+use crate::client::handle::ClientHandle;
+use crate::client::notification::{NotificationResult, QueueSender};
+use crate::client::read::{check_link, AdsReader, ClientResult, TcpFrameView};
+use crate::client::transport::TransportWrite;
+use crate::error::{AdsError, ClientError};
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::request::{
+    AddDeviceNotificationRequest, DeleteDeviceNotificationRequest, Request,
+};
+use crate::proto::response::{AddDeviceNotificationResponse, Response};
+
+///Dispatcher of every local port which uses the connection. Key is the port.
+type Ports = Arc<Mutex<HashMap<u16, Dispatcher>>>;
+
+///TCP connection to a remote router. Shared by all local ports which send to a target behind it.
+///Received frames are dispatched to the port they are addressed to.
+#[derive(Debug)]
+pub(crate) struct RouterConnection {
+    link: SharedLink,
+    stream: Option<TcpStream>,
+    state: ConnectionStateTracker,
+    ports: Ports,
+    ///Handles of the ports of AdsPort. Clones share the invoke ids of the port.
+    handles: Mutex<HashMap<u16, ClientHandle>>,
+    tx_thread_cancel: Option<Sender<bool>>,
+    read_thread: Option<JoinHandle<()>>,
+}
+
+impl RouterConnection {
+    ///Connect to the remote router and start the reader thread.
+    ///source is the address of the local router (port 0). Requests are sent from the local ports.
+    pub(crate) fn open(addr: SocketAddr, source: AmsAddress) -> ClientResult<Self> {
+        let config = ConnectionConfig {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            ..ConnectionConfig::default()
+        };
+        let stream = config.open().map_err(check_link)?;
+        let reader = AdsReader::new(stream.try_clone()?);
+
+        let link: SharedLink = Arc::new(Mutex::new(Some(Link::new(
+            Box::new(stream.try_clone()?),
+            source,
+        ))));
+        let state = ConnectionStateTracker::new(ConnectionState::Connecting);
+        let ports: Ports = Arc::new(Mutex::new(HashMap::new()));
+
+        let (tx, rx) = channel::<bool>();
+        let mut connection = RouterConnection {
+            link,
+            stream: Some(stream),
+            state: state.clone(),
+            ports: Arc::clone(&ports),
+            handles: Mutex::new(HashMap::new()),
+            tx_thread_cancel: Some(tx),
+            read_thread: None,
+        };
+        state.set(ConnectionState::Connected);
+        connection.read_thread = Some(thread::spawn(move || {
+            RouterConnection::run(ports, state, reader, rx)
+        }));
+        Ok(connection)
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.state.get() == ConnectionState::Connected
+    }
+
+    ///Handle which sends from source to target over this connection
+    pub(crate) fn handle(&self, source: AmsAddress, target: AmsAddress) -> ClientHandle {
+        let mut handles = match self.handles.lock() {
+            Ok(c) => c,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let handle = handles.entry(source.port).or_insert_with(|| {
+            let dispatcher = self.dispatcher(&source);
+            ClientHandle::new(
+                target.clone(),
+                Arc::clone(&self.link),
+                Arc::clone(&dispatcher.responses),
+                Arc::clone(&dispatcher.pending_requests),
+                self.state.clone(),
+            )
+            .with_source(source.clone())
+        });
+        handle.with_target(target)
+    }
+
+    ///Dispatcher of a local port. Created on first use.
+    fn dispatcher(&self, source: &AmsAddress) -> Dispatcher {
+        match self.ports.lock() {
+            Ok(mut c) => c
+                .entry(source.port)
+                .or_insert_with(|| Dispatcher::new(source.clone(), self.state.clone()))
+                .clone(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Dispatch the frames sent to the port of the dispatcher to it.
+    ///Used by a Connection opened on a local port.
+    ///Fails with RouterErrPortAlreadyInUse if the port already receives frames.
+    pub(crate) fn attach(&self, dispatcher: Dispatcher) -> ClientResult<()> {
+        if !self.is_connected() {
+            return Err(AdsError::ErrPortNotConnected.into());
+        }
+        match self.ports.lock() {
+            Ok(mut c) if c.contains_key(&dispatcher.source.port) => {
+                Err(AdsError::RouterErrPortAlreadyInUse.into())
+            }
+            Ok(mut c) => {
+                c.insert(dispatcher.source.port, dispatcher);
+                Ok(())
+            }
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Stop dispatching frames to the port
+    pub(crate) fn detach(&self, port: u16) {
+        match self.handles.lock() {
+            Ok(mut c) => c.remove(&port),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        match self.ports.lock() {
+            Ok(mut c) => c.remove(&port),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+
+    ///Write a complete frame. Frames of different ports are not interleaved.
+    pub(crate) fn write(&self, buffer: &[u8]) -> io::Result<()> {
+        let result = match self.link.lock() {
+            Ok(mut l) => match l.as_mut() {
+                Some(link) => link.write(buffer),
+                None => Err(AdsError::ErrPortNotConnected.into()),
+            },
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        result.map_err(|e| match e {
+            ClientError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::NotConnected, e.to_string()),
+        })
+    }
+
+    ///Register a device notification for a local port.
+    ///The queue is attached before the confirmation is dispatched so no sample gets lost.
+    pub(crate) fn add_notification(
+        &self,
+        handle: &ClientHandle,
+        source: &AmsAddress,
+        request: AddDeviceNotificationRequest,
+        sender: QueueSender<NotificationResult>,
+    ) -> ClientResult<u32> {
+        let dispatcher = self.dispatcher(source);
+        let invoke_id = handle.next_invoke_id();
+        match dispatcher.pending_notifications.lock() {
+            Ok(mut c) => c.insert(invoke_id, sender),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        let response = handle
+            .request_with_id(Request::AddDeviceNotification(request), invoke_id)
+            .and_then(|r| -> ClientResult<AddDeviceNotificationResponse> { Ok(r.try_into()?) });
+        //Still pending if the request failed
+        let pending = match dispatcher.pending_notifications.lock() {
+            Ok(mut c) => c.remove(&invoke_id),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+
+        let result = response.and_then(|r| {
+            if r.result != AdsError::ErrNoError {
+                return Err(r.result.into());
+            }
+            Ok(r.notification_handle)
+        });
+        if let (Err(ClientError::Ads(e)), Some(sender)) = (&result, pending) {
+            sender.send(Err(e.clone()));
+        }
+        result
+    }
+
+    ///Delete a device notification of a local port on the remote device
+    pub(crate) fn delete_notification(
+        &self,
+        handle: &ClientHandle,
+        source: &AmsAddress,
+        notification_handle: u32,
+    ) -> ClientResult<()> {
+        match self.dispatcher(source).notifications.lock() {
+            Ok(mut c) => c.remove(&(handle.target().clone(), notification_handle)),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let request = Request::DeleteDeviceNotification(DeleteDeviceNotificationRequest::new(
+            notification_handle,
+        ));
+        match handle.request(request)? {
+            Response::DeleteDeviceNotification(r) if r.result != AdsError::ErrNoError => {
+                Err(r.result.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///Delete all device notifications of a closed local port and stop dispatching to it
+    pub(crate) fn release_port(&self, source: &AmsAddress) {
+        let dispatcher = match self.ports.lock() {
+            Ok(c) => c.get(&source.port).cloned(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        let keys: Vec<NotificationKey> = match dispatcher {
+            Some(dispatcher) if self.is_connected() => match dispatcher.notifications.lock() {
+                Ok(c) => c.keys().cloned().collect(),
+                Err(_) => panic!("Failed to get lock!"),
+            },
+            _ => Vec::new(),
+        };
+        for (target, notification_handle) in keys {
+            let handle = self.handle(source.clone(), target);
+            if let Err(e) = self.delete_notification(&handle, source, notification_handle) {
+                log::warn!(
+                    "Failed to delete notification {} of port {}. {}",
+                    notification_handle,
+                    source.port,
+                    e
+                );
+            }
+        }
+        self.detach(source.port);
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.state.set(ConnectionState::Closed);
+        if let Some(tx) = self.tx_thread_cancel.take() {
+            tx.send(true);
+        }
+        match self.link.lock() {
+            Ok(mut l) => *l = None,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        //Unblocks the reader thread which is waiting for data
+        if let Some(s) = self.stream.take() {
+            TransportWrite::shutdown(&s);
+        }
+        if let Some(t) = self.read_thread.take() {
+            t.join();
+        }
+    }
+
+    ///Reader thread. Dispatches each frame to the dispatcher of the local port it is sent to.
+    ///A lost link fails everything pending on all ports.
+    fn run(
+        ports: Ports,
+        state: ConnectionStateTracker,
+        mut reader: AdsReader,
+        rx_thread_cancel: Receiver<bool>,
+    ) {
+        let mut cancel: bool = false;
+        while !cancel {
+            match reader.read_frame_view() {
+                Ok(TcpFrameView::Ams(frame)) => {
+                    let port = frame.target_address().port;
+                    let dispatcher = match ports.lock() {
+                        Ok(c) => c.get(&port).cloned(),
+                        Err(_) => panic!("Failed to get lock!"),
+                    };
+                    match dispatcher {
+                        Some(dispatcher) => dispatcher.dispatch(frame),
+                        None => log::debug!("No local port {} for {:?}", port, frame.command_id()),
+                    }
+                }
+                Ok(TcpFrameView::Router(frame)) => log::debug!("Router frame received {:?}", frame),
+                Err(ClientError::Timeout) => (),
+                Err(ClientError::Disconnected(e)) => {
+                    log::warn!("Router connection lost. {}", e);
+                    break;
+                }
+                Err(e) => log::warn!("Invalid frame received. {}", e),
+            }
+
+            if let Ok(c) = rx_thread_cancel.try_recv() {
+                cancel = c;
+            }
+        }
+
+        if !cancel {
+            state.set(ConnectionState::Disconnected);
+            let dispatchers: Vec<Dispatcher> = match ports.lock() {
+                Ok(c) => c.values().cloned().collect(),
+                Err(_) => panic!("Failed to get lock!"),
+            };
+            for dispatcher in dispatchers {
+                dispatcher.disconnect();
+            }
+        }
+    }
+}
+
+impl Drop for RouterConnection {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+///Write half of a Connection opened on a local port.
+///Writes to the shared router connection. Shutdown detaches the port.
+#[derive(Debug)]
+pub(crate) struct PortWriter {
+    connection: Arc<RouterConnection>,
+    port: u16,
+}
+
+impl PortWriter {
+    pub(crate) fn new(connection: Arc<RouterConnection>, port: u16) -> Self {
+        PortWriter { connection, port }
+    }
+}
+
+impl Write for PortWriter {
+    ///Writes the whole frame at once so it is not interleaved with frames of other ports
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.connection.write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TransportWrite for PortWriter {
+    fn shutdown(&self) -> io::Result<()> {
+        self.connection.detach(self.port);
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+pub mod ams_router;
+pub(crate) mod connection;
+pub mod port;
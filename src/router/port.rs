@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use crate::client::ads_client::Connection;
+use crate::client::builder::ConnectionConfig;
+use crate::client::handle::{ClientHandle, DEFAULT_REQUEST_TIMEOUT};
+use crate::client::notification::{queue, NotificationResult, QueueConfig, Subscription};
+use crate::client::read::ClientResult;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::request::AddDeviceNotificationRequest;
+use crate::router::ams_router::AmsRouter;
+
+///Virtual port of an AmsRouter. The port is closed on drop.
+///Notifications added through the port are deleted when it is closed.
+#[derive(Debug)]
+pub struct AdsPort {
+    router: AmsRouter,
+    port: u16,
+    timeout: Duration,
+}
+
+impl AdsPort {
+    pub(crate) fn new(router: AmsRouter, port: u16) -> Self {
+        AdsPort {
+            router,
+            port,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    ///Source address of requests sent from this port
+    pub fn local_address(&self) -> AmsAddress {
+        AmsAddress::new(self.router.local_net_id(), self.port)
+    }
+
+    ///Time to wait for a response. Applies to handles created afterwards.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    ///Handle which sends from this port to the target.
+    ///The TCP connection to the remote router is opened on first use and shared with other ports.
+    pub fn handle(&self, target: AmsAddress) -> ClientResult<ClientHandle> {
+        Ok(self
+            .router
+            .handle(self.port, target)?
+            .with_timeout(self.timeout))
+    }
+
+    ///Add a device notification. Samples are only delivered to this port.
+    ///Returns the notification handle and the subscription.
+    pub fn add_device_notification(
+        &self,
+        target: AmsAddress,
+        request: AddDeviceNotificationRequest,
+        queue_config: QueueConfig,
+    ) -> ClientResult<(u32, Subscription)> {
+        let handle = self.handle(target.clone())?;
+        let (tx, rx) = queue::<NotificationResult>(queue_config);
+        let connection = self.router.connection(&target.ams_net_id)?;
+        let notification_handle =
+            connection.add_notification(&handle, &self.local_address(), request, tx)?;
+        Ok((notification_handle, rx))
+    }
+
+    pub fn delete_device_notification(
+        &self,
+        target: AmsAddress,
+        notification_handle: u32,
+    ) -> ClientResult<()> {
+        let handle = self.handle(target.clone())?;
+        let connection = self.router.connection(&target.ams_net_id)?;
+        connection.delete_notification(&handle, &self.local_address(), notification_handle)
+    }
+
+    ///Open a Connection to the target which sends from this port.
+    ///The Connection shares the TCP connection to the remote router with all other ports
+    ///and owns the port. Fails with RouterErrPortAlreadyInUse if handles of the port were used.
+    pub fn connect(self, target: AmsAddress) -> ClientResult<Connection> {
+        let config = ConnectionConfig {
+            response_timeout: self.timeout,
+            ..ConnectionConfig::default()
+        };
+        let mut connection = Connection::with_config(target, config);
+        connection.set_router_port(Some(self));
+        connection.connect()?;
+        Ok(connection)
+    }
+
+    pub(crate) fn router(&self) -> &AmsRouter {
+        &self.router
+    }
+}
+
+impl Drop for AdsPort {
+    fn drop(&mut self) {
+        self.router.close_port(self.port);
+    }
+}
@@ -0,0 +1,788 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::client::ads_client::ADS_TCP_SERVER_PORT;
+use crate::client::read::{check_link, AdsReader, ClientResult, TcpFrame};
+use crate::client::transport::Transport;
+use crate::error::{AdsError, ClientError};
+use crate::proto::ads_state::AdsState;
+use crate::proto::ams_address::{AmsAddress, AmsNetId};
+use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+use crate::proto::proto_traits::WriteTo;
+use crate::proto::request::{AddDeviceNotificationRequest, Request};
+use crate::proto::response::*;
+use crate::proto::router_command::RouterFrame;
+use crate::proto::state_flags::StateFlags;
+use crate::server::device::{AdsDevice, DeviceResult};
+use crate::server::notification::{NotificationManager, NotificationSink};
+
+///First port assigned to clients which connect with PortConnect
+pub const DYNAMIC_PORT_BASE: u16 = 32768;
+
+type SharedDevice = Arc<Mutex<Box<dyn AdsDevice>>>;
+type Sessions = Arc<Mutex<HashMap<u64, TcpStream>>>;
+
+///ADS server which serves AdsDevice implementations on the AMS ports of one NetId.
+///Requests to a port without device are answered with ErrTargetPortNotFound.
+///Clones share the devices, so devices can be added while the server is running.
+#[derive(Clone)]
+pub struct AdsServer {
+    net_id: AmsNetId,
+    devices: Arc<Mutex<HashMap<u16, SharedDevice>>>,
+    next_dynamic_port: Arc<AtomicU16>,
+    notifications: NotificationManager,
+}
+
+impl std::fmt::Debug for AdsServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ports: Vec<u16> = match self.devices.lock() {
+            Ok(c) => c.keys().copied().collect(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        f.debug_struct("AdsServer")
+            .field("net_id", &self.net_id)
+            .field("ports", &ports)
+            .finish()
+    }
+}
+
+impl AdsServer {
+    pub fn new(net_id: AmsNetId) -> Self {
+        AdsServer {
+            net_id,
+            devices: Arc::new(Mutex::new(HashMap::new())),
+            next_dynamic_port: Arc::new(AtomicU16::new(DYNAMIC_PORT_BASE)),
+            notifications: NotificationManager::new(),
+        }
+    }
+
+    pub fn net_id(&self) -> &AmsNetId {
+        &self.net_id
+    }
+
+    ///Device notifications of all clients
+    pub fn notifications(&self) -> &NotificationManager {
+        &self.notifications
+    }
+
+    ///Serve the device on the AMS port. A device already registered on the port is replaced.
+    pub fn add_device<D: AdsDevice + 'static>(&self, port: u16, device: D) {
+        match self.devices.lock() {
+            Ok(mut c) => c.insert(port, Arc::new(Mutex::new(Box::new(device)))),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+
+    ///Returns false if no device was registered on the port
+    pub fn remove_device(&self, port: u16) -> bool {
+        match self.devices.lock() {
+            Ok(mut c) => c.remove(&port).is_some(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    fn device(&self, port: u16) -> Option<SharedDevice> {
+        match self.devices.lock() {
+            Ok(c) => c.get(&port).cloned(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Listen on all interfaces on port 48898
+    pub fn start(&self) -> io::Result<ServerHandle> {
+        self.start_on((Ipv4Addr::UNSPECIFIED, ADS_TCP_SERVER_PORT))
+    }
+
+    ///Listen on the address. Every client is served by its own thread.
+    pub fn start_on<A: ToSocketAddrs>(&self, addr: A) -> io::Result<ServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let running = Arc::new(AtomicBool::new(true));
+        let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+
+        let server = self.clone();
+        let accept_running = Arc::clone(&running);
+        let accept_sessions = Arc::clone(&sessions);
+        let accept_thread = thread::spawn(move || {
+            let session_ids = AtomicU64::new(0);
+            for stream in listener.incoming() {
+                if !accept_running.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        log::warn!("Failed to accept client. {}", e);
+                        continue;
+                    }
+                };
+                let id = session_ids.fetch_add(1, Ordering::Relaxed);
+                match stream.try_clone() {
+                    Ok(s) => match accept_sessions.lock() {
+                        Ok(mut c) => c.insert(id, s),
+                        Err(_) => panic!("Failed to get lock!"),
+                    },
+                    Err(e) => {
+                        log::warn!("Failed to accept client. {}", e);
+                        continue;
+                    }
+                };
+
+                let server = server.clone();
+                let sessions = Arc::clone(&accept_sessions);
+                thread::spawn(move || {
+                    if let Err(e) = server.serve(stream) {
+                        log::debug!("Client session {} closed. {}", id, e);
+                    }
+                    match sessions.lock() {
+                        Ok(mut c) => c.remove(&id),
+                        Err(_) => panic!("Failed to get lock!"),
+                    };
+                });
+            }
+        });
+
+        let server = self.clone();
+        let notification_running = Arc::clone(&running);
+        let notification_thread = thread::spawn(move || {
+            server.notifications.run(
+                &notification_running,
+                |client, device, index_group, index_offset, length| {
+                    server.read_value(client, device, index_group, index_offset, length)
+                },
+            )
+        });
+
+        Ok(ServerHandle {
+            local_addr,
+            running,
+            sessions,
+            notifications: self.notifications.clone(),
+            accept_thread: Some(accept_thread),
+            notification_thread: Some(notification_thread),
+        })
+    }
+
+    ///Answer the requests of one client until it disconnects.
+    ///Notifications of the client are deleted when it disconnects.
+    ///Called for every accepted TCP client. Can be used to serve other transports.
+    pub fn serve<T: Transport + 'static>(&self, stream: T) -> ClientResult<()> {
+        let (read, write) = Box::new(stream).split()?;
+        let mut reader = AdsReader::from_read(read);
+        //Shared with the notification thread so frames are not interleaved
+        let writer = Arc::new(Mutex::new(write));
+        let session = self
+            .notifications
+            .open_session(writer.clone() as NotificationSink);
+        let result = self.serve_session(session, &mut reader, &writer);
+        for removed in self.notifications.close_session(session) {
+            if let Some(device) = self.device(removed.device.port) {
+                match device.lock() {
+                    Ok(mut d) => d.delete_notification(&removed.client, removed.handle),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+            }
+        }
+        result
+    }
+
+    fn serve_session(
+        &self,
+        session: u64,
+        reader: &mut AdsReader,
+        writer: &Mutex<impl Write>,
+    ) -> ClientResult<()> {
+        loop {
+            let mut buffer = Vec::new();
+            match reader.read_frame() {
+                Ok(TcpFrame::Ams(frame)) => {
+                    if let Some(response) = self.dispatch_session(Some(session), &frame) {
+                        AmsTcpHeader::from(response).write_to(&mut buffer)?;
+                    }
+                }
+                Ok(TcpFrame::Router(frame)) => {
+                    if let Some(response) = self.dispatch_router_frame(&frame) {
+                        response.write_to(&mut buffer)?;
+                    }
+                }
+                Err(ClientError::Timeout) => continue,
+                Err(ClientError::Disconnected(_)) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            if !buffer.is_empty() {
+                match writer.lock() {
+                    Ok(mut w) => w.write_all(&buffer).map_err(check_link)?,
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+                self.notifications.confirm(session);
+            }
+        }
+    }
+
+    ///Answer a request frame. The response is addressed to the sender of the request.
+    ///Returns None for frames which are not answered (responses and notifications).
+    ///AddDeviceNotification is rejected because there is no connection to send samples to.
+    pub fn dispatch(&self, frame: &AmsTcpHeader) -> Option<AmsHeader> {
+        self.dispatch_session(None, frame)
+    }
+
+    ///Answer a request frame of a client session registered with the notification manager.
+    ///Added notifications are sampled after NotificationManager::confirm.
+    ///Requests with the no return flag are executed but not answered.
+    pub fn dispatch_session(
+        &self,
+        session: Option<u64>,
+        frame: &AmsTcpHeader,
+    ) -> Option<AmsHeader> {
+        if frame.state_flags().is_response() {
+            return None;
+        }
+
+        let no_return = frame.state_flags().is_no_return();
+        let client = frame.source_address().clone();
+        let target = frame.target_address().clone();
+        let error_response = |error: AdsError| {
+            if no_return {
+                return None;
+            }
+            Some(AmsHeader::new_error_response(
+                client.clone(),
+                target.clone(),
+                frame.command_id(),
+                frame.invoke_id(),
+                error,
+            ))
+        };
+        if target.ams_net_id != self.net_id {
+            return error_response(AdsError::ErrTargetMachineNotFound);
+        }
+        let device = match self.device(target.port) {
+            Some(device) => device,
+            None => return error_response(AdsError::ErrTargetPortNotFound),
+        };
+        let request = match frame.request() {
+            Ok(request) => request,
+            Err(_) => return error_response(AdsError::AdsErrDeviceInvalidData),
+        };
+
+        let response = match request {
+            Request::AddDeviceNotification(r) => Response::AddDeviceNotification(
+                match self.add_notification(session, &device, &client, &target, &r) {
+                    Ok(handle) => AddDeviceNotificationResponse::new(AdsError::ErrNoError, handle),
+                    Err(e) => AddDeviceNotificationResponse::new(e, 0),
+                },
+            ),
+            Request::DeleteDeviceNotification(r) => {
+                Response::DeleteDeviceNotification(DeleteDeviceNotificationResponse::new(
+                    match self.delete_notification(&device, &client, r.handle) {
+                        Ok(()) => AdsError::ErrNoError,
+                        Err(e) => e,
+                    },
+                ))
+            }
+            request => match device.lock() {
+                Ok(mut d) => AdsServer::handle_request(d.as_mut(), &client, request)?,
+                Err(_) => panic!("Failed to get lock!"),
+            },
+        };
+        if no_return {
+            return None;
+        }
+        match AmsHeader::new_response(
+            client.clone(),
+            target.clone(),
+            StateFlags::resp_default(),
+            frame.invoke_id(),
+            response,
+        ) {
+            Ok(header) => Some(header),
+            Err(e) => {
+                log::warn!("Failed to encode response. {}", e);
+                Some(AmsHeader::new_error_response(
+                    client,
+                    target,
+                    frame.command_id(),
+                    frame.invoke_id(),
+                    AdsError::AdsErrDeviceError,
+                ))
+            }
+        }
+    }
+
+    ///Call the device. Errors of the device are returned as result code of the response.
+    fn handle_request(
+        device: &mut dyn AdsDevice,
+        client: &AmsAddress,
+        request: Request,
+    ) -> Option<Response> {
+        let ok = AdsError::ErrNoError;
+        let response = match request {
+            Request::ReadDeviceInfo(_) => {
+                Response::ReadDeviceInfo(match device.read_device_info() {
+                    Ok(info) => ReadDeviceInfoResponse::new(
+                        ok,
+                        info.major_version,
+                        info.minor_version,
+                        info.version_build,
+                        info.device_name_bytes(),
+                    ),
+                    Err(e) => ReadDeviceInfoResponse::new(e, 0, 0, 0, [0; 16]),
+                })
+            }
+            Request::ReadState(_) => Response::ReadState(match device.read_state() {
+                Ok((ads_state, device_state)) => {
+                    ReadStateResponse::new(ok, ads_state, device_state)
+                }
+                Err(e) => ReadStateResponse::new(e, AdsState::AdsStateInvalid, 0),
+            }),
+            Request::Read(r) => Response::Read(
+                match device.read(client, r.index_group, r.index_offset, r.length) {
+                    Ok(mut data) => {
+                        data.truncate(r.length as usize);
+                        ReadResponse::new(ok, data)
+                    }
+                    Err(e) => ReadResponse::new(e, Vec::new()),
+                },
+            ),
+            Request::Write(r) => Response::Write(WriteResponse::new(
+                match device.write(client, r.index_group, r.index_offset, &r.data) {
+                    Ok(()) => ok,
+                    Err(e) => e,
+                },
+            )),
+            Request::ReadWrite(r) => Response::ReadWrite(
+                match device.read_write(
+                    client,
+                    r.index_group,
+                    r.index_offset,
+                    r.read_length,
+                    &r.data,
+                ) {
+                    Ok(mut data) => {
+                        data.truncate(r.read_length as usize);
+                        ReadWriteResponse::new(ok, data)
+                    }
+                    Err(e) => ReadWriteResponse::new(e, Vec::new()),
+                },
+            ),
+            Request::WriteControl(r) => Response::WriteControl(WriteControlResponse::new(
+                match device.write_control(client, r.ads_state, r.device_state, &r.data) {
+                    Ok(()) => ok,
+                    Err(e) => e,
+                },
+            )),
+            //Handled by the notification manager
+            Request::AddDeviceNotification(_)
+            | Request::DeleteDeviceNotification(_)
+            | Request::DeviceNotification(_)
+            | Request::Invalid(_) => return None,
+        };
+        Some(response)
+    }
+
+    ///Let the device check the notification and read the value once to validate
+    ///index group, index offset and length before the notification is registered
+    fn add_notification(
+        &self,
+        session: Option<u64>,
+        device: &SharedDevice,
+        client: &AmsAddress,
+        target: &AmsAddress,
+        request: &AddDeviceNotificationRequest,
+    ) -> DeviceResult<u32> {
+        let session = session.ok_or(AdsError::AdsErrDeviceSrvNotSupp)?;
+        match device.lock() {
+            Ok(mut d) => {
+                d.add_notification(client, request)?;
+                d.read(
+                    client,
+                    request.index_group,
+                    request.index_offset,
+                    request.length,
+                )?;
+            }
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        self.notifications
+            .add(session, client.clone(), target.clone(), request)
+    }
+
+    fn delete_notification(
+        &self,
+        device: &SharedDevice,
+        client: &AmsAddress,
+        handle: u32,
+    ) -> DeviceResult<()> {
+        let removed = self.notifications.delete(client, handle)?;
+        match device.lock() {
+            Ok(mut d) => d.delete_notification(&removed.client, removed.handle),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Sample the value of a notification
+    fn read_value(
+        &self,
+        client: &AmsAddress,
+        device: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        length: u32,
+    ) -> DeviceResult<Vec<u8>> {
+        match self.device(device.port) {
+            Some(d) => match d.lock() {
+                Ok(mut d) => d.read(client, index_group, index_offset, length),
+                Err(_) => panic!("Failed to get lock!"),
+            },
+            None => Err(AdsError::ErrTargetPortNotFound),
+        }
+    }
+
+    ///Answer router commands so clients can connect as to a local router
+    fn dispatch_router_frame(&self, frame: &RouterFrame) -> Option<RouterFrame> {
+        match frame {
+            RouterFrame::PortConnectRequest { port } => {
+                let port = match port {
+                    0 => self.next_dynamic_port.fetch_add(1, Ordering::Relaxed),
+                    port => *port,
+                };
+                Some(RouterFrame::PortConnectResponse(AmsAddress::new(
+                    self.net_id.clone(),
+                    port,
+                )))
+            }
+            RouterFrame::GetLocalNetIdRequest => {
+                Some(RouterFrame::GetLocalNetIdResponse(self.net_id.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+///Running AdsServer. The listener and all client connections are closed by shutdown or drop.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    running: Arc<AtomicBool>,
+    sessions: Sessions,
+    notifications: NotificationManager,
+    accept_thread: Option<JoinHandle<()>>,
+    notification_thread: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    ///Address the server listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    ///Number of connected clients
+    pub fn clients(&self) -> usize {
+        match self.sessions.lock() {
+            Ok(c) => c.len(),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        if !self.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        //Wake up the listener which is blocked in accept
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        TcpStream::connect(wake_addr);
+        if let Some(t) = self.accept_thread.take() {
+            t.join();
+        }
+        self.notifications.wake();
+        if let Some(t) = self.notification_thread.take() {
+            t.join();
+        }
+        match self.sessions.lock() {
+            Ok(mut c) => {
+                for (_, stream) in c.drain() {
+                    stream.shutdown(Shutdown::Both);
+                }
+            }
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ads_client::Connection;
+    use crate::client::notification::QueueConfig;
+    use crate::proto::ads_transition_mode::AdsTransMode;
+    use crate::proto::frame_view::NotificationStreamView;
+    use crate::router::ams_router::AmsRouter;
+    use std::convert::TryInto;
+    use std::time::{Duration, Instant};
+
+    ///Device with 16 bytes of memory in index group 1
+    struct Memory {
+        data: Vec<u8>,
+    }
+
+    impl AdsDevice for Memory {
+        fn read(
+            &mut self,
+            _source: &AmsAddress,
+            index_group: u32,
+            index_offset: u32,
+            length: u32,
+        ) -> DeviceResult<Vec<u8>> {
+            if index_group != 1 {
+                return Err(AdsError::AdsErrDeviceInvalidGrp);
+            }
+            let start = index_offset as usize;
+            match self.data.get(start..start + length as usize) {
+                Some(data) => Ok(data.to_vec()),
+                None => Err(AdsError::AdsErrDeviceInvalidOffset),
+            }
+        }
+
+        fn write(
+            &mut self,
+            _source: &AmsAddress,
+            index_group: u32,
+            index_offset: u32,
+            data: &[u8],
+        ) -> DeviceResult<()> {
+            if index_group != 1 {
+                return Err(AdsError::AdsErrDeviceInvalidGrp);
+            }
+            let start = index_offset as usize;
+            match self.data.get_mut(start..start + data.len()) {
+                Some(memory) => {
+                    memory.copy_from_slice(data);
+                    Ok(())
+                }
+                None => Err(AdsError::AdsErrDeviceInvalidOffset),
+            }
+        }
+    }
+
+    fn net_id() -> AmsNetId {
+        AmsNetId::new(127, 0, 0, 1, 1, 1)
+    }
+
+    fn start() -> (AdsServer, ServerHandle) {
+        let server = AdsServer::new(net_id());
+        server.add_device(851, Memory { data: vec![0; 16] });
+        let handle = server.start_on("127.0.0.1:0").unwrap();
+        (server, handle)
+    }
+
+    fn connect(handle: &ServerHandle, port: u16) -> Connection {
+        Connection::builder(AmsAddress::new(net_id(), port))
+            .port(handle.local_addr().port())
+            .connect()
+            .unwrap()
+    }
+
+    #[test]
+    fn read_write_test() {
+        let (_server, handle) = start();
+        let connection = connect(&handle, 851);
+        let client = connection.handle();
+
+        client.write(1, 4, vec![1, 2, 3]).unwrap();
+        assert_eq!(client.read(1, 3, 5).unwrap(), vec![0, 1, 2, 3, 0]);
+        let error = client.read(2, 0, 1).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::AdsErrDeviceInvalidGrp));
+
+        let state = client.read_state().unwrap();
+        assert_eq!(state.ads_state, AdsState::AdsStateRun);
+        let info = client.read_device_info().unwrap();
+        assert_eq!(info.get_device_name().unwrap(), "rust-ads");
+        assert_eq!(handle.clients(), 1);
+    }
+
+    #[test]
+    fn not_supported_test() {
+        let (_server, handle) = start();
+        let connection = connect(&handle, 851);
+        let error = connection
+            .handle()
+            .write_control(AdsState::AdsStateStop, 0)
+            .unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::AdsErrDeviceSrvNotSupp));
+    }
+
+    #[test]
+    fn unknown_port_test() {
+        let (server, handle) = start();
+        let connection = connect(&handle, 852);
+        let error = connection.handle().read_state().unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrTargetPortNotFound));
+
+        server.add_device(852, Memory { data: vec![0; 1] });
+        assert!(connection.handle().read_state().is_ok());
+    }
+
+    #[test]
+    fn port_connect_test() {
+        let (_server, handle) = start();
+        let mut connection = Connection::builder(AmsAddress::new(net_id(), 851))
+            .port(handle.local_addr().port())
+            .local_router(true)
+            .connect()
+            .unwrap();
+        assert!(connection.handle().read_state().is_ok());
+        assert_eq!(connection.get_local_net_id().unwrap(), net_id());
+    }
+
+    #[test]
+    fn port_connect_with_source_test() {
+        let (_server, handle) = start();
+        let error = Connection::builder(AmsAddress::new(net_id(), 851))
+            .port(handle.local_addr().port())
+            .source(AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000))
+            .local_router(true)
+            .connect()
+            .unwrap_err();
+        assert!(matches!(error, ClientError::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+
+        let mut connection = Connection::builder(AmsAddress::new(net_id(), 851))
+            .port(handle.local_addr().port())
+            .source(AmsAddress::new(AmsNetId::new(10, 0, 0, 1, 1, 1), 30000))
+            .connect()
+            .unwrap();
+        assert!(connection.get_local_net_id().is_err());
+    }
+
+    fn notification_request(trans_mode: AdsTransMode) -> AddDeviceNotificationRequest {
+        //Cycle time 10ms, max delay 0
+        AddDeviceNotificationRequest::new(1, 0, 2, trans_mode, 0, 100_000)
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() && start.elapsed() < Duration::from_secs(2) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn notification_test() {
+        let (server, handle) = start();
+        let router = AmsRouter::new(AmsNetId::new(10, 0, 0, 1, 1, 1));
+        router
+            .add_route_addr(net_id(), handle.local_addr())
+            .unwrap();
+        let port = router.open_port().unwrap();
+        let target = AmsAddress::new(net_id(), 851);
+
+        let (cyclic, cyclic_rx) = port
+            .add_device_notification(
+                target.clone(),
+                notification_request(AdsTransMode::Cyclic),
+                QueueConfig::default(),
+            )
+            .unwrap();
+        let (on_change, on_change_rx) = port
+            .add_device_notification(
+                target.clone(),
+                notification_request(AdsTransMode::OnChange),
+                QueueConfig::default(),
+            )
+            .unwrap();
+        assert_eq!(server.notifications().count(), 2);
+
+        let samples = |stream: NotificationStreamView, handle: u32| -> Vec<Vec<u8>> {
+            stream
+                .for_handle(handle)
+                .samples()
+                .map(|(_, s)| s.data.to_vec())
+                .collect()
+        };
+        let timeout = Duration::from_secs(1);
+        for _ in 0..3 {
+            let stream = cyclic_rx.recv_timeout(timeout).unwrap().unwrap();
+            assert_eq!(samples(stream, cyclic), vec![vec![0, 0]]);
+        }
+
+        //The current value is sent first, then only changes
+        let stream = on_change_rx.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(samples(stream, on_change), vec![vec![0, 0]]);
+        port.handle(target.clone())
+            .unwrap()
+            .write(1, 0, vec![7, 0])
+            .unwrap();
+        let changed = loop {
+            let stream = on_change_rx.recv_timeout(timeout).unwrap().unwrap();
+            let changed = samples(stream, on_change);
+            if !changed.is_empty() {
+                break changed;
+            }
+        };
+        assert_eq!(changed, vec![vec![7, 0]]);
+
+        port.delete_device_notification(target.clone(), cyclic)
+            .unwrap();
+        assert_eq!(server.notifications().count(), 1);
+        let error = port.delete_device_notification(target, cyclic).unwrap_err();
+        assert_eq!(
+            error.ads_error(),
+            Some(&AdsError::AdsErrDeviceNotifyHndInvalid)
+        );
+    }
+
+    #[test]
+    fn notification_cleanup_test() {
+        let (server, handle) = start();
+        let router = AmsRouter::new(AmsNetId::new(10, 0, 0, 1, 1, 1));
+        router
+            .add_route_addr(net_id(), handle.local_addr())
+            .unwrap();
+        let port = router.open_port().unwrap();
+        let target = AmsAddress::new(net_id(), 851);
+
+        let error = port
+            .add_device_notification(
+                AmsAddress::new(net_id(), 851),
+                AddDeviceNotificationRequest::new(2, 0, 2, AdsTransMode::Cyclic, 0, 100_000),
+                QueueConfig::default(),
+            )
+            .unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::AdsErrDeviceInvalidGrp));
+
+        port.add_device_notification(
+            target,
+            notification_request(AdsTransMode::Cyclic),
+            QueueConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(server.notifications().count(), 1);
+
+        //Closing the connection removes the notifications of the client
+        router.delete_route(&net_id()).unwrap();
+        drop(port);
+        wait_for(|| server.notifications().count() == 0);
+        assert_eq!(server.notifications().count(), 0);
+    }
+
+    #[test]
+    fn shutdown_test() {
+        let (_server, mut handle) = start();
+        let connection = connect(&handle, 851);
+        handle.shutdown();
+        assert!(connection.handle().read_state().is_err());
+    }
+}
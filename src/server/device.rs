@@ -0,0 +1,126 @@
+use crate::error::AdsError;
+use crate::proto::ads_state::AdsState;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::request::AddDeviceNotificationRequest;
+
+pub type DeviceResult<T> = Result<T, AdsError>;
+
+///Name and version reported by ReadDeviceInfo
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub version_build: u16,
+    ///Max. 16 bytes. Longer names are cut.
+    pub device_name: String,
+}
+
+impl DeviceInfo {
+    pub fn new(
+        major_version: u8,
+        minor_version: u8,
+        version_build: u16,
+        device_name: &str,
+    ) -> Self {
+        DeviceInfo {
+            major_version,
+            minor_version,
+            version_build,
+            device_name: device_name.to_string(),
+        }
+    }
+
+    ///Device name as null padded bytes
+    pub fn device_name_bytes(&self) -> [u8; 16] {
+        let mut name = [0; 16];
+        let bytes = self.device_name.as_bytes();
+        let len = bytes.len().min(name.len());
+        name[..len].copy_from_slice(&bytes[..len]);
+        name
+    }
+}
+
+///ADS device served by an AdsServer on one AMS port.
+///Every service answers AdsErrDeviceSrvNotSupp unless it is implemented.
+///An error is sent back as result code of the response.
+///source is the AMS address of the client which sent the request.
+pub trait AdsDevice: Send {
+    fn read_device_info(&mut self) -> DeviceResult<DeviceInfo> {
+        Ok(DeviceInfo::new(0, 1, 0, "rust-ads"))
+    }
+
+    fn read(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        length: u32,
+    ) -> DeviceResult<Vec<u8>> {
+        Err(AdsError::AdsErrDeviceSrvNotSupp)
+    }
+
+    fn write(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        data: &[u8],
+    ) -> DeviceResult<()> {
+        Err(AdsError::AdsErrDeviceSrvNotSupp)
+    }
+
+    fn read_write(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        read_length: u32,
+        data: &[u8],
+    ) -> DeviceResult<Vec<u8>> {
+        Err(AdsError::AdsErrDeviceSrvNotSupp)
+    }
+
+    ///ADS state and device state
+    fn read_state(&mut self) -> DeviceResult<(AdsState, u16)> {
+        Ok((AdsState::AdsStateRun, 0))
+    }
+
+    fn write_control(
+        &mut self,
+        source: &AmsAddress,
+        ads_state: AdsState,
+        device_state: u16,
+        data: &[u8],
+    ) -> DeviceResult<()> {
+        Err(AdsError::AdsErrDeviceSrvNotSupp)
+    }
+
+    ///Called before the server registers a notification. The server samples the value
+    ///with read. Return an error to reject the notification.
+    fn add_notification(
+        &mut self,
+        source: &AmsAddress,
+        request: &AddDeviceNotificationRequest,
+    ) -> DeviceResult<()> {
+        Ok(())
+    }
+
+    ///Called after a notification was deleted by the client or because the client disconnected
+    fn delete_notification(&mut self, source: &AmsAddress, handle: u32) -> DeviceResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_name_bytes_test() {
+        let info = DeviceInfo::new(3, 1, 4024, "Plc30 App");
+        assert_eq!(&info.device_name_bytes()[..10], b"Plc30 App\0");
+
+        let info = DeviceInfo::new(3, 1, 4024, "a very long device name");
+        assert_eq!(&info.device_name_bytes(), b"a very long devi");
+    }
+}
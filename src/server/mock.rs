@@ -0,0 +1,868 @@
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::client::read::{check_link, AdsReader, ClientResult, TcpFrame};
+use crate::error::{AdsError, ClientError};
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+use crate::proto::command_id::CommandID;
+use crate::proto::proto_traits::WriteTo;
+use crate::proto::request::Request;
+use crate::proto::response::{AdsNotificationStream, Response};
+use crate::proto::state_flags::StateFlags;
+
+///What the mock does with a matched request
+#[derive(Debug, Clone)]
+enum Reply {
+    Response(Response),
+    Error(AdsError),
+    None,
+    Disconnect,
+}
+
+///Expected request and the reply to it. Without a reply the request is not answered.
+///```ignore
+///peer.expect(
+///    Expectation::new(CommandID::Read)
+///        .index(0x4020, 0)
+///        .respond(Response::Read(ReadResponse::new(AdsError::ErrNoError, vec![1, 2])))
+///        .delay(Duration::from_millis(100)),
+///);
+///```
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    command_id: CommandID,
+    index: Option<(u32, u32)>,
+    payload: Option<Vec<u8>>,
+    reply: Reply,
+    delay: Duration,
+    invoke_id: Option<u32>,
+    times: usize,
+}
+
+impl Expectation {
+    ///Expect one request with the command id
+    pub fn new(command_id: CommandID) -> Self {
+        Expectation {
+            command_id,
+            index: None,
+            payload: None,
+            reply: Reply::None,
+            delay: Duration::from_secs(0),
+            invoke_id: None,
+            times: 1,
+        }
+    }
+
+    ///Only match requests with index group and index offset
+    ///(Read, Write, ReadWrite and AddDeviceNotification)
+    pub fn index(mut self, index_group: u32, index_offset: u32) -> Self {
+        self.index = Some((index_group, index_offset));
+        self
+    }
+
+    ///Only match requests with these data bytes (Write, ReadWrite and WriteControl)
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+
+    pub fn respond(mut self, response: Response) -> Self {
+        self.reply = Reply::Response(response);
+        self
+    }
+
+    ///Answer with an error in the AMS header and no data
+    pub fn respond_error(mut self, error: AdsError) -> Self {
+        self.reply = Reply::Error(error);
+        self
+    }
+
+    ///Do not answer the request
+    pub fn no_reply(mut self) -> Self {
+        self.reply = Reply::None;
+        self
+    }
+
+    ///Close the connection instead of answering
+    pub fn disconnect(mut self) -> Self {
+        self.reply = Reply::Disconnect;
+        self
+    }
+
+    ///Wait before the reply is sent. Later requests are answered in the meantime.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    ///Answer with this invoke id instead of the one of the request
+    pub fn invoke_id(mut self, invoke_id: u32) -> Self {
+        self.invoke_id = Some(invoke_id);
+        self
+    }
+
+    ///Number of requests which are expected. Default is 1.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        if request.command_id() != self.command_id {
+            return false;
+        }
+        let (index, payload) = match request {
+            Request::Read(r) => (Some((r.index_group, r.index_offset)), None),
+            Request::Write(r) => (Some((r.index_group, r.index_offset)), Some(&r.data)),
+            Request::ReadWrite(r) => (Some((r.index_group, r.index_offset)), Some(&r.data)),
+            Request::AddDeviceNotification(r) => (Some((r.index_group, r.index_offset)), None),
+            Request::WriteControl(r) => (None, Some(&r.data)),
+            _ => (None, None),
+        };
+        if self.index.is_some() && self.index != index {
+            return false;
+        }
+        match &self.payload {
+            Some(expected) => payload == Some(expected),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    ///Expectations with the number of matched requests
+    expectations: Vec<(Expectation, usize)>,
+    received: Vec<Request>,
+    unexpected: Vec<Request>,
+    ///Client and device address of the last request
+    addresses: Option<(AmsAddress, AmsAddress)>,
+}
+
+impl MockState {
+    ///First expectation which matches and is not used up
+    fn find(&mut self, request: &Request) -> Option<Expectation> {
+        let (expectation, hits) = self
+            .expectations
+            .iter_mut()
+            .find(|(e, hits)| *hits < e.times && e.matches(request))?;
+        *hits += 1;
+        Some(expectation.clone())
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+type Writer = Arc<Mutex<TcpStream>>;
+
+fn lock(state: &SharedState) -> MutexGuard<'_, MockState> {
+    match state.lock() {
+        Ok(s) => s,
+        Err(_) => panic!("Failed to get lock!"),
+    }
+}
+
+///Mock ADS device for tests. Answers the requests declared with expect
+///and checks afterwards that all expected requests were received.
+///Requests without expectation are answered with AdsErrDeviceSrvNotSupp and fail verify.
+#[derive(Debug)]
+pub struct MockPeer {
+    local_addr: SocketAddr,
+    state: SharedState,
+    running: Arc<AtomicBool>,
+    ///Writers of the client connections
+    sessions: Arc<Mutex<Vec<Writer>>>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+///Expectation registered at a MockPeer
+#[derive(Debug)]
+pub struct Mock {
+    id: usize,
+    state: SharedState,
+}
+
+impl Mock {
+    ///Number of requests which matched the expectation
+    pub fn hits(&self) -> usize {
+        lock(&self.state).expectations[self.id].1
+    }
+
+    ///Panics if the expected number of requests was not received
+    pub fn assert(&self) {
+        let state = lock(&self.state);
+        let (expectation, hits) = &state.expectations[self.id];
+        assert_eq!(
+            *hits, expectation.times,
+            "Expected {} request(s) for {:?}, got {}",
+            expectation.times, expectation, hits
+        );
+    }
+}
+
+impl MockPeer {
+    ///Listen on an ephemeral port of the loopback interface
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let local_addr = listener.local_addr()?;
+        let state: SharedState = Arc::new(Mutex::new(MockState::default()));
+        let running = Arc::new(AtomicBool::new(true));
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+
+        let accept_state = Arc::clone(&state);
+        let accept_running = Arc::clone(&running);
+        let accept_sessions = Arc::clone(&sessions);
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !accept_running.load(Ordering::SeqCst) {
+                    break;
+                }
+                let (stream, writer) = match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok((session, stream)) => {
+                        let writer: Writer = Arc::new(Mutex::new(session));
+                        match accept_sessions.lock() {
+                            Ok(mut c) => c.push(Arc::clone(&writer)),
+                            Err(_) => panic!("Failed to get lock!"),
+                        };
+                        (stream, writer)
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to accept client. {}", e);
+                        continue;
+                    }
+                };
+                let state = Arc::clone(&accept_state);
+                thread::spawn(move || {
+                    if let Err(e) = MockPeer::serve(state, stream, writer) {
+                        log::debug!("Mock session closed. {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(MockPeer {
+            local_addr,
+            state,
+            running,
+            sessions,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn expect(&self, expectation: Expectation) -> Mock {
+        let mut state = lock(&self.state);
+        state.expectations.push((expectation, 0));
+        Mock {
+            id: state.expectations.len() - 1,
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    ///All received requests in order
+    pub fn requests(&self) -> Vec<Request> {
+        lock(&self.state).received.clone()
+    }
+
+    ///Send a device notification to all clients.
+    ///It is addressed from the device to the client of the last request.
+    pub fn notify(&self, stream: AdsNotificationStream) -> ClientResult<()> {
+        let (client, device) = match &lock(&self.state).addresses {
+            Some(addresses) => addresses.clone(),
+            None => {
+                return Err(
+                    io::Error::new(io::ErrorKind::NotConnected, "No request received yet").into(),
+                )
+            }
+        };
+        let header = AmsHeader::new_response(
+            client,
+            device,
+            StateFlags::req_default(),
+            0,
+            Response::DeviceNotification(stream),
+        )?;
+        let mut buffer = Vec::new();
+        AmsTcpHeader::from(header).write_to(&mut buffer)?;
+        let sessions: Vec<Writer> = match self.sessions.lock() {
+            Ok(c) => c.clone(),
+            Err(_) => panic!("Failed to get lock!"),
+        };
+        for writer in sessions {
+            MockPeer::send(&writer, &buffer)?;
+        }
+        Ok(())
+    }
+
+    ///Panics if an expectation was not met or an unexpected request was received
+    pub fn verify(&self) {
+        let state = lock(&self.state);
+        let mut failures: Vec<String> = state
+            .expectations
+            .iter()
+            .filter(|(e, hits)| *hits != e.times)
+            .map(|(e, hits)| format!("Expected {} request(s) for {:?}, got {}", e.times, e, hits))
+            .collect();
+        failures.extend(
+            state
+                .unexpected
+                .iter()
+                .map(|r| format!("Unexpected request {:?}", r)),
+        );
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    ///Close the listener and all connections
+    pub fn shutdown(&mut self) {
+        if !self.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        //Wake up the listener which is blocked in accept
+        TcpStream::connect(self.local_addr);
+        if let Some(t) = self.accept_thread.take() {
+            t.join();
+        }
+        match self.sessions.lock() {
+            Ok(mut c) => {
+                for writer in c.drain(..) {
+                    match writer.lock() {
+                        Ok(w) => w.shutdown(Shutdown::Both),
+                        Err(_) => panic!("Failed to get lock!"),
+                    };
+                }
+            }
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+
+    fn serve(state: SharedState, stream: TcpStream, writer: Writer) -> ClientResult<()> {
+        let mut reader = AdsReader::new(stream);
+        loop {
+            let frame = match reader.read_frame() {
+                Ok(TcpFrame::Ams(frame)) => frame,
+                Ok(TcpFrame::Router(frame)) => continue,
+                Err(ClientError::Timeout) => continue,
+                Err(ClientError::Disconnected(_)) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if frame.state_flags().is_response() {
+                continue;
+            }
+            let request = frame.request()?;
+
+            let expectation = {
+                let mut state = lock(&state);
+                state.received.push(request.clone());
+                state.addresses = Some((
+                    frame.source_address().clone(),
+                    frame.target_address().clone(),
+                ));
+                let expectation = state.find(&request);
+                if expectation.is_none() {
+                    state.unexpected.push(request);
+                }
+                expectation
+            };
+            let (reply, delay, invoke_id) = match expectation {
+                Some(e) => (
+                    e.reply,
+                    e.delay,
+                    e.invoke_id.unwrap_or_else(|| frame.invoke_id()),
+                ),
+                None => (
+                    Reply::Error(AdsError::AdsErrDeviceSrvNotSupp),
+                    Duration::from_secs(0),
+                    frame.invoke_id(),
+                ),
+            };
+
+            let header = match reply {
+                Reply::Response(response) => match AmsHeader::new_response(
+                    frame.source_address().clone(),
+                    frame.target_address().clone(),
+                    StateFlags::resp_default(),
+                    invoke_id,
+                    response,
+                ) {
+                    Ok(header) => header,
+                    Err(e) => {
+                        log::warn!("Failed to encode response. {}", e);
+                        continue;
+                    }
+                },
+                Reply::Error(error) => AmsHeader::new_error_response(
+                    frame.source_address().clone(),
+                    frame.target_address().clone(),
+                    frame.command_id(),
+                    invoke_id,
+                    error,
+                ),
+                Reply::None => continue,
+                Reply::Disconnect => {
+                    match writer.lock() {
+                        Ok(w) => w.shutdown(Shutdown::Both),
+                        Err(_) => panic!("Failed to get lock!"),
+                    };
+                    return Ok(());
+                }
+            };
+            let mut buffer = Vec::new();
+            AmsTcpHeader::from(header).write_to(&mut buffer)?;
+
+            if delay == Duration::from_secs(0) {
+                MockPeer::send(&writer, &buffer)?;
+            } else {
+                let writer = Arc::clone(&writer);
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    MockPeer::send(&writer, &buffer)
+                });
+            }
+        }
+    }
+
+    fn send(writer: &Mutex<TcpStream>, buffer: &[u8]) -> ClientResult<()> {
+        match writer.lock() {
+            Ok(mut w) => w.write_all(buffer).map_err(check_link),
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+}
+
+impl Drop for MockPeer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ads_services::system_services::{
+        ADSIGRP_SYM_VERSION, GET_SYMHANDLE_BY_NAME, READ_WRITE_SYMVAL_BY_HANDLE,
+    };
+    use crate::client::ads_client::Connection;
+    use crate::client::connection_state::ConnectionState;
+    use crate::client::plc_types::{PlcTypes, Var};
+    use crate::error::ProtocolError;
+    use crate::proto::ads_state::AdsState;
+    use crate::proto::ads_transition_mode::AdsTransMode;
+    use crate::proto::ams_address::{AmsAddress, AmsNetId};
+    use crate::proto::response::{
+        AddDeviceNotificationResponse, AdsNotificationSample, AdsStampHeader, ReadResponse,
+        ReadStateResponse, ReadWriteResponse, WriteResponse,
+    };
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    fn connect(peer: &MockPeer) -> Connection {
+        Connection::builder(AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851))
+            .port(peer.local_addr().port())
+            .connect()
+            .unwrap()
+    }
+
+    fn connect_with_timeout(peer: &MockPeer, timeout: Duration) -> Connection {
+        Connection::builder(AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851))
+            .port(peer.local_addr().port())
+            .response_timeout(timeout)
+            .connect()
+            .unwrap()
+    }
+
+    fn read_response(data: Vec<u8>) -> Response {
+        Response::Read(ReadResponse::new(AdsError::ErrNoError, data))
+    }
+
+    fn handle_response(handle: u32) -> Response {
+        Response::ReadWrite(ReadWriteResponse::new(
+            AdsError::ErrNoError,
+            handle.to_le_bytes().to_vec(),
+        ))
+    }
+
+    fn notification(handle: u32, data: Vec<u8>) -> AdsNotificationStream {
+        AdsNotificationStream::from_stamp_headers(vec![AdsStampHeader::new(
+            1,
+            1,
+            vec![AdsNotificationSample::new(handle, data)],
+        )])
+    }
+
+    fn read_state_response(ads_state: AdsState) -> Response {
+        Response::ReadState(ReadStateResponse::new(AdsError::ErrNoError, ads_state, 0))
+    }
+
+    #[test]
+    fn read_write_test() {
+        let peer = MockPeer::start().unwrap();
+        let read = peer.expect(
+            Expectation::new(CommandID::Read)
+                .index(1, 2)
+                .respond(read_response(vec![3, 4]))
+                .times(2),
+        );
+        let write = peer.expect(
+            Expectation::new(CommandID::Write)
+                .payload(vec![5])
+                .respond(Response::Write(WriteResponse::new(AdsError::ErrNoError))),
+        );
+        let connection = connect(&peer);
+        let client = connection.handle();
+
+        assert_eq!(client.read(1, 2, 2).unwrap(), vec![3, 4]);
+        assert_eq!(client.read(1, 2, 2).unwrap(), vec![3, 4]);
+        client.write(1, 2, vec![5]).unwrap();
+        read.assert();
+        assert_eq!(write.hits(), 1);
+        assert_eq!(peer.requests().len(), 3);
+        peer.verify();
+    }
+
+    #[test]
+    fn out_of_order_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(
+            Expectation::new(CommandID::Read)
+                .index(1, 0)
+                .respond(read_response(vec![1]))
+                .delay(Duration::from_millis(300)),
+        );
+        peer.expect(
+            Expectation::new(CommandID::Read)
+                .index(1, 1)
+                .respond(read_response(vec![2])),
+        );
+        let connection = connect(&peer);
+
+        let (tx, rx) = channel();
+        let mut threads = Vec::new();
+        for index_offset in 0..2 {
+            let client = connection.handle();
+            let tx = tx.clone();
+            threads.push(thread::spawn(move || {
+                let value = client.read(1, index_offset, 1).unwrap();
+                tx.send(value).unwrap();
+            }));
+            thread::sleep(Duration::from_millis(50));
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        //The delayed response arrives last and is matched by its invoke id
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![vec![2], vec![1]]);
+        peer.verify();
+    }
+
+    #[test]
+    fn missing_reply_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(Expectation::new(CommandID::ReadState).no_reply());
+        peer.expect(
+            Expectation::new(CommandID::Read).respond_error(AdsError::ErrTargetPortNotFound),
+        );
+        let connection = connect(&peer);
+        let client = connection.handle().with_timeout(Duration::from_millis(200));
+
+        assert!(matches!(client.read_state(), Err(ClientError::Timeout)));
+        let error = client.read(1, 0, 1).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrTargetPortNotFound));
+        peer.verify();
+    }
+
+    #[test]
+    fn undecodable_response_test() {
+        let peer = MockPeer::start().unwrap();
+        //Header without error and without data
+        peer.expect(Expectation::new(CommandID::ReadState).respond_error(AdsError::ErrNoError));
+        peer.expect(Expectation::new(CommandID::Read).respond(read_response(vec![1])));
+        let connection = connect(&peer);
+        let client = connection.handle();
+
+        assert!(matches!(client.read_state(), Err(ClientError::Decode(_))));
+        //The reader thread keeps running
+        assert_eq!(client.read(1, 0, 1).unwrap(), vec![1]);
+        assert!(connection.is_connected());
+        peer.verify();
+    }
+
+    #[test]
+    fn disconnect_fails_pending_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(Expectation::new(CommandID::ReadState).disconnect());
+        let connection = connect(&peer);
+
+        let error = connection.handle().read_state().unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrPortNotConnected));
+        assert_eq!(connection.state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn strict_response_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(
+            Expectation::new(CommandID::ReadState)
+                .respond(read_response(vec![1]))
+                .times(2),
+        );
+        let mut connection = connect_with_timeout(&peer, Duration::from_millis(300));
+        let protocol_errors = connection.protocol_errors();
+        connection.set_strict_responses(true);
+
+        //Dropped. The request keeps waiting for its real response.
+        let error = connection.read_state(1).unwrap_err();
+        let expected = ProtocolError::CommandMismatch {
+            invoke_id: 1,
+            expected: CommandID::ReadState,
+            received: CommandID::Read,
+        };
+        assert!(matches!(error, ClientError::Timeout));
+        assert_eq!(protocol_errors.try_recv(), Ok(expected));
+
+        //Accepted without strict mode. The read state response can't be decoded from it.
+        connection.set_strict_responses(false);
+        assert!(matches!(
+            connection.read_state(2),
+            Err(ClientError::Decode(_))
+        ));
+        assert!(protocol_errors.try_recv().is_err());
+        peer.verify();
+    }
+
+    #[test]
+    fn connection_timeout_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(Expectation::new(CommandID::ReadState).no_reply());
+        peer.expect(
+            Expectation::new(CommandID::ReadState).respond(Response::ReadState(
+                ReadStateResponse::new(AdsError::ErrNoError, AdsState::AdsStateRun, 0),
+            )),
+        );
+        let mut connection =
+            Connection::builder(AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 851))
+                .port(peer.local_addr().port())
+                .response_timeout(Duration::from_millis(200))
+                .connect()
+                .unwrap();
+
+        assert!(matches!(
+            connection.read_state(1),
+            Err(ClientError::Timeout)
+        ));
+        let response = connection.read_state(2).unwrap();
+        assert_eq!(response.ads_state, AdsState::AdsStateRun);
+        peer.verify();
+    }
+
+    #[test]
+    fn connection_read_by_name_test() {
+        let peer = MockPeer::start().unwrap();
+        //Devices without symbol version notifications are supported
+        peer.expect(
+            Expectation::new(CommandID::AddDeviceNotification)
+                .index(
+                    ADSIGRP_SYM_VERSION.index_group,
+                    ADSIGRP_SYM_VERSION.index_offset_start,
+                )
+                .respond_error(AdsError::AdsErrDeviceSrvNotSupp),
+        );
+        peer.expect(
+            Expectation::new(CommandID::ReadWrite)
+                .index(
+                    GET_SYMHANDLE_BY_NAME.index_group,
+                    GET_SYMHANDLE_BY_NAME.index_offset_start,
+                )
+                .payload(b"MAIN.counter".to_vec())
+                .respond(Response::ReadWrite(ReadWriteResponse::new(
+                    AdsError::ErrNoError,
+                    7u32.to_le_bytes().to_vec(),
+                ))),
+        );
+        let read = peer.expect(
+            Expectation::new(CommandID::Read)
+                .index(READ_WRITE_SYMVAL_BY_HANDLE.index_group, 7)
+                .respond(read_response(vec![3, 0]))
+                .times(2),
+        );
+        let mut connection = connect(&peer);
+        let var = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+
+        assert_eq!(connection.get_symhandle(&var, 1).unwrap(), 7);
+        //The handle is cached
+        assert_eq!(connection.get_symhandle(&var, 2).unwrap(), 7);
+        assert_eq!(connection.read_by_name(&var, 3).unwrap(), vec![3, 0]);
+        assert_eq!(connection.read_by_name(&var, 4).unwrap(), vec![3, 0]);
+        read.assert();
+        peer.verify();
+    }
+
+    #[test]
+    fn connection_error_response_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(
+            Expectation::new(CommandID::ReadDeviceInfo)
+                .respond_error(AdsError::ErrTargetPortNotFound),
+        );
+        peer.expect(
+            Expectation::new(CommandID::ReadState)
+                .respond(read_state_response(AdsState::AdsStateStop)),
+        );
+        let mut connection = connect(&peer);
+
+        let error = connection.read_device_info(1).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrTargetPortNotFound));
+        //An error response doesn't affect the connection
+        assert!(connection.is_connected());
+        assert_eq!(
+            connection.read_state(2).unwrap().ads_state,
+            AdsState::AdsStateStop
+        );
+        peer.verify();
+    }
+
+    #[test]
+    fn connection_late_response_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(
+            Expectation::new(CommandID::ReadState)
+                .respond(read_state_response(AdsState::AdsStateRun))
+                .delay(Duration::from_millis(300)),
+        );
+        peer.expect(
+            Expectation::new(CommandID::ReadState)
+                .respond(read_state_response(AdsState::AdsStateStop))
+                .times(2),
+        );
+        let mut connection = connect_with_timeout(&peer, Duration::from_millis(100));
+
+        let start = Instant::now();
+        assert!(matches!(
+            connection.read_state(1),
+            Err(ClientError::Timeout)
+        ));
+        assert!(start.elapsed() < Duration::from_millis(300));
+        assert_eq!(
+            connection.read_state(2).unwrap().ads_state,
+            AdsState::AdsStateStop
+        );
+
+        //The late response of the timed out request is dropped
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(
+            connection.read_state(3).unwrap().ads_state,
+            AdsState::AdsStateStop
+        );
+        assert!(connection.is_connected());
+        peer.verify();
+    }
+
+    #[test]
+    fn connection_disconnect_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(Expectation::new(CommandID::ReadState).disconnect());
+        let mut connection = connect_with_timeout(&peer, Duration::from_secs(5));
+
+        //Failed by the reader thread, not by the timeout
+        let start = Instant::now();
+        let error = connection.read_state(1).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrPortNotConnected));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(connection.state(), ConnectionState::Disconnected);
+
+        let error = connection.read_state(2).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::ErrPortNotConnected));
+        peer.verify();
+    }
+
+    #[test]
+    fn notify_test() {
+        let peer = MockPeer::start().unwrap();
+        assert!(peer.notify(notification(5, vec![1, 0])).is_err());
+        peer.expect(
+            Expectation::new(CommandID::AddDeviceNotification)
+                .index(
+                    ADSIGRP_SYM_VERSION.index_group,
+                    ADSIGRP_SYM_VERSION.index_offset_start,
+                )
+                .respond_error(AdsError::AdsErrDeviceSrvNotSupp),
+        );
+        peer.expect(Expectation::new(CommandID::ReadWrite).respond(handle_response(7)));
+        peer.expect(
+            Expectation::new(CommandID::AddDeviceNotification)
+                .index(READ_WRITE_SYMVAL_BY_HANDLE.index_group, 7)
+                .respond(Response::AddDeviceNotification(
+                    AddDeviceNotificationResponse::new(AdsError::ErrNoError, 5),
+                )),
+        );
+        let mut connection = connect(&peer);
+        let var = Var::new("MAIN.counter".to_string(), PlcTypes::Int, None);
+        let subscription = connection
+            .add_device_notification(&var, AdsTransMode::OnChange, 0, 100_000, 1)
+            .unwrap();
+
+        peer.notify(notification(5, vec![1, 0])).unwrap();
+        let stream = subscription
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stream.stamps[0].samples[0].notification_handle, 5);
+        assert_eq!(&stream.stamps[0].samples[0].data[..], &[1, 0]);
+        peer.verify();
+    }
+
+    #[test]
+    fn sumup_handle_count_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(
+            Expectation::new(CommandID::AddDeviceNotification)
+                .index(
+                    ADSIGRP_SYM_VERSION.index_group,
+                    ADSIGRP_SYM_VERSION.index_offset_start,
+                )
+                .respond_error(AdsError::AdsErrDeviceSrvNotSupp),
+        );
+        //One handle for two variables
+        peer.expect(
+            Expectation::new(CommandID::ReadWrite).respond(Response::ReadWrite(
+                ReadWriteResponse::new(
+                    AdsError::ErrNoError,
+                    vec![0, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0],
+                ),
+            )),
+        );
+        let mut connection = connect(&peer);
+        let vars = vec![
+            Var::new("MAIN.a".to_string(), PlcTypes::Int, None),
+            Var::new("MAIN.b".to_string(), PlcTypes::Int, None),
+        ];
+
+        let error = connection.sumup_get_symhandle(&vars, 1).unwrap_err();
+        assert!(matches!(error, ClientError::Decode(_)));
+        peer.verify();
+    }
+
+    #[test]
+    fn unexpected_request_test() {
+        let peer = MockPeer::start().unwrap();
+        peer.expect(Expectation::new(CommandID::Write).payload(vec![1]));
+        let connection = connect(&peer);
+        let error = connection.handle().write(1, 0, vec![2]).unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::AdsErrDeviceSrvNotSupp));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| peer.verify()));
+        let message = match result {
+            Err(e) => e.downcast::<String>().unwrap(),
+            Ok(()) => panic!("verify passed"),
+        };
+        assert!(message.contains("Expected 1 request(s)"));
+        assert!(message.contains("Unexpected request"));
+    }
+}
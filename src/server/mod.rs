@@ -0,0 +1,5 @@
+pub mod ads_server;
+pub mod device;
+pub mod mock;
+pub mod notification;
+pub mod simulator;
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::AdsError;
+use crate::proto::ads_transition_mode::AdsTransMode;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::ams_header::{AmsHeader, AmsTcpHeader};
+use crate::proto::proto_traits::WriteTo;
+use crate::proto::request::AddDeviceNotificationRequest;
+use crate::proto::response::{
+    AdsNotificationSample, AdsNotificationStream, AdsStampHeader, Response,
+};
+use crate::proto::state_flags::StateFlags;
+use crate::server::device::DeviceResult;
+
+///Connection of a client. Notification frames are written to it.
+pub type NotificationSink = Arc<Mutex<dyn Write + Send>>;
+
+///Shortest interval in which a notification is sampled
+const MIN_CYCLE: Duration = Duration::from_millis(1);
+///Longest time the delivery loop sleeps without checking if the server is still running
+const MAX_WAIT: Duration = Duration::from_millis(100);
+///Seconds from 1601-01-01 (Windows FILETIME epoch) to 1970-01-01
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+///Current time in 100ns units since 1601-01-01 as used in AdsStampHeader
+pub fn filetime_now() -> u64 {
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_unix.as_secs() + FILETIME_UNIX_OFFSET) * 10_000_000
+        + u64::from(since_unix.subsec_nanos() / 100)
+}
+
+///Convert a time in 100ns units (cycle time, max delay) to a Duration
+fn from_100ns(value: u32) -> Duration {
+    Duration::from_nanos(u64::from(value) * 100)
+}
+
+///Notification removed from the manager
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemovedNotification {
+    pub handle: u32,
+    pub client: AmsAddress,
+    pub device: AmsAddress,
+}
+
+#[derive(Debug)]
+struct Registration {
+    session: u64,
+    client: AmsAddress,
+    device: AmsAddress,
+    index_group: u32,
+    index_offset: u32,
+    length: u32,
+    trans_mode: AdsTransMode,
+    cycle_time: Duration,
+    max_delay: Duration,
+    ///Not sampled before the client received the handle
+    confirmed: bool,
+    last_value: Option<Vec<u8>>,
+    next_check: Instant,
+    ///Samples waiting for delivery with their time stamp
+    samples: Vec<(u64, Vec<u8>)>,
+    ///Samples have to be sent until this instant
+    deadline: Option<Instant>,
+}
+
+impl Registration {
+    fn on_change(&self) -> bool {
+        matches!(
+            self.trans_mode,
+            AdsTransMode::OnChange | AdsTransMode::ClientOnChange | AdsTransMode::OnChangeInContext
+        )
+    }
+
+    ///Queue the value. On change notifications only queue changed values.
+    fn sample(&mut self, time_stamp: u64, value: Vec<u8>, now: Instant) {
+        if self.on_change() && self.last_value.as_ref() == Some(&value) {
+            return;
+        }
+        self.last_value = Some(value.clone());
+        self.samples.push((time_stamp, value));
+        if self.deadline.is_none() {
+            self.deadline = Some(now + self.max_delay);
+        }
+    }
+
+    fn group(&self) -> (u64, AmsAddress, AmsAddress) {
+        (self.session, self.client.clone(), self.device.clone())
+    }
+}
+
+///Value to sample: handle, client, device, index group, index offset and length
+type Check = (u32, AmsAddress, AmsAddress, u32, u32, u32);
+
+#[derive(Default)]
+struct State {
+    next_handle: u32,
+    next_session: u64,
+    registrations: HashMap<u32, Registration>,
+    sessions: HashMap<u64, NotificationSink>,
+    woken: bool,
+}
+
+///Device notifications of the clients of an AdsServer.
+///Values are sampled with the cycle time of the notification and delivered
+///in batches after max delay. Samples for the same client and device are sent in one frame.
+#[derive(Clone, Default)]
+pub struct NotificationManager {
+    inner: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl std::fmt::Debug for NotificationManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NotificationManager")
+            .field("notifications", &self.count())
+            .finish()
+    }
+}
+
+impl NotificationManager {
+    pub fn new() -> Self {
+        NotificationManager::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        match self.inner.0.lock() {
+            Ok(s) => s,
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Register a client connection. Returns the session id.
+    pub fn open_session(&self, sink: NotificationSink) -> u64 {
+        let mut state = self.state();
+        let session = state.next_session;
+        state.next_session += 1;
+        state.sessions.insert(session, sink);
+        session
+    }
+
+    ///Remove the connection and all notifications which were added over it
+    pub fn close_session(&self, session: u64) -> Vec<RemovedNotification> {
+        let mut state = self.state();
+        state.sessions.remove(&session);
+        let handles: Vec<u32> = state
+            .registrations
+            .iter()
+            .filter(|(_, r)| r.session == session)
+            .map(|(handle, _)| *handle)
+            .collect();
+        let mut removed: Vec<RemovedNotification> = handles
+            .into_iter()
+            .filter_map(|handle| {
+                state
+                    .registrations
+                    .remove(&handle)
+                    .map(|r| RemovedNotification {
+                        handle,
+                        client: r.client,
+                        device: r.device,
+                    })
+            })
+            .collect();
+        removed.sort_by_key(|r| r.handle);
+        removed
+    }
+
+    ///Add a notification of client for a value of device. Returns the notification handle.
+    pub fn add(
+        &self,
+        session: u64,
+        client: AmsAddress,
+        device: AmsAddress,
+        request: &AddDeviceNotificationRequest,
+    ) -> DeviceResult<u32> {
+        let mut state = self.state();
+        if !state.sessions.contains_key(&session) {
+            return Err(AdsError::ErrPortNotConnected);
+        }
+        state.next_handle = state.next_handle.wrapping_add(1).max(1);
+        let handle = state.next_handle;
+        state.registrations.insert(
+            handle,
+            Registration {
+                session,
+                client,
+                device,
+                index_group: request.index_group,
+                index_offset: request.index_offset,
+                length: request.length,
+                trans_mode: request.transmission_mode,
+                cycle_time: from_100ns(request.cycle_time).max(MIN_CYCLE),
+                max_delay: from_100ns(request.max_delay),
+                confirmed: false,
+                last_value: None,
+                next_check: Instant::now(),
+                samples: Vec::new(),
+                deadline: None,
+            },
+        );
+        Ok(handle)
+    }
+
+    ///Start sampling the notifications added over the session.
+    ///Call it after the AddDeviceNotification responses were sent,
+    ///so no sample reaches the client before its handle.
+    pub fn confirm(&self, session: u64) {
+        let mut state = self.state();
+        let mut confirmed = false;
+        for r in state.registrations.values_mut() {
+            if r.session == session && !r.confirmed {
+                r.confirmed = true;
+                r.next_check = Instant::now();
+                confirmed = true;
+            }
+        }
+        if confirmed {
+            state.woken = true;
+            self.inner.1.notify_all();
+        }
+    }
+
+    ///Delete a notification. Fails with AdsErrDeviceNotifyHndInvalid
+    ///if the handle does not exist or belongs to another client.
+    pub fn delete(&self, client: &AmsAddress, handle: u32) -> DeviceResult<RemovedNotification> {
+        let mut state = self.state();
+        match state.registrations.get(&handle) {
+            Some(r) if &r.client == client => (),
+            _ => return Err(AdsError::AdsErrDeviceNotifyHndInvalid),
+        }
+        match state.registrations.remove(&handle) {
+            Some(r) => Ok(RemovedNotification {
+                handle,
+                client: r.client,
+                device: r.device,
+            }),
+            None => Err(AdsError::AdsErrDeviceNotifyHndInvalid),
+        }
+    }
+
+    ///Number of registered notifications
+    pub fn count(&self) -> usize {
+        self.state().registrations.len()
+    }
+
+    ///Wake up the delivery loop (e.g. on shutdown)
+    pub fn wake(&self) {
+        self.state().woken = true;
+        self.inner.1.notify_all();
+    }
+
+    ///Sample and deliver notifications while running is true.
+    ///read gets the client and device address, index group, index offset and length of a value.
+    pub fn run<F>(&self, running: &AtomicBool, read: F)
+    where
+        F: Fn(&AmsAddress, &AmsAddress, u32, u32, u32) -> DeviceResult<Vec<u8>>,
+    {
+        while running.load(Ordering::SeqCst) {
+            //The device is read without holding the lock
+            for (handle, client, device, index_group, index_offset, length) in self.due_checks() {
+                match read(&client, &device, index_group, index_offset, length) {
+                    Ok(mut value) => {
+                        value.truncate(length as usize);
+                        let time_stamp = filetime_now();
+                        if let Some(r) = self.state().registrations.get_mut(&handle) {
+                            r.sample(time_stamp, value, Instant::now());
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to sample notification {}. {}", handle, e),
+                }
+            }
+
+            for (sink, frame) in self.due_frames() {
+                let mut buffer: Vec<u8> = Vec::new();
+                if AmsTcpHeader::from(frame).write_to(&mut buffer).is_err() {
+                    continue;
+                }
+                //A lost connection is cleaned up when its session is closed
+                match sink.lock() {
+                    Ok(mut s) => s.write_all(&buffer),
+                    Err(_) => panic!("Failed to get lock!"),
+                };
+            }
+            self.wait();
+        }
+    }
+
+    ///Notifications which have to be sampled now. The next check is scheduled.
+    fn due_checks(&self) -> Vec<Check> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let mut checks = Vec::new();
+        for (handle, r) in state.registrations.iter_mut() {
+            if !r.confirmed || r.trans_mode == AdsTransMode::None || r.next_check > now {
+                continue;
+            }
+            r.next_check += r.cycle_time;
+            //Skip missed cycles instead of catching up
+            if r.next_check <= now {
+                r.next_check = now + r.cycle_time;
+            }
+            checks.push((
+                *handle,
+                r.client.clone(),
+                r.device.clone(),
+                r.index_group,
+                r.index_offset,
+                r.length,
+            ));
+        }
+        checks
+    }
+
+    ///Build a notification frame for every client and device with samples past their deadline.
+    ///All queued samples of the client and device are sent in the frame.
+    fn due_frames(&self) -> Vec<(NotificationSink, AmsHeader)> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let mut groups: Vec<(u64, AmsAddress, AmsAddress)> = Vec::new();
+        for r in state.registrations.values() {
+            if r.deadline.is_some_and(|d| d <= now) && !groups.contains(&r.group()) {
+                groups.push(r.group());
+            }
+        }
+
+        let mut frames = Vec::new();
+        for (session, client, device) in groups {
+            let mut samples: Vec<(u64, u32, Vec<u8>)> = Vec::new();
+            for (handle, r) in state.registrations.iter_mut() {
+                if r.group() != (session, client.clone(), device.clone()) {
+                    continue;
+                }
+                r.deadline = None;
+                for (time_stamp, data) in r.samples.drain(..) {
+                    samples.push((time_stamp, *handle, data));
+                }
+            }
+            let sink = match state.sessions.get(&session) {
+                Some(sink) => Arc::clone(sink),
+                None => continue,
+            };
+            let stream = NotificationManager::stream(samples);
+            match AmsHeader::new_response(
+                client,
+                device,
+                StateFlags::req_default(),
+                0,
+                Response::DeviceNotification(stream),
+            ) {
+                Ok(header) => frames.push((sink, header)),
+                Err(e) => log::warn!("Failed to encode notification. {}", e),
+            }
+        }
+        frames
+    }
+
+    ///Samples with the same time stamp share one stamp header
+    fn stream(mut samples: Vec<(u64, u32, Vec<u8>)>) -> AdsNotificationStream {
+        samples.sort_by_key(|(time_stamp, handle, _)| (*time_stamp, *handle));
+        let mut stamp_headers: Vec<AdsStampHeader> = Vec::new();
+        for (time_stamp, handle, data) in samples {
+            let sample = AdsNotificationSample::new(handle, data);
+            match stamp_headers.last_mut() {
+                Some(header) if header.time_stamp == time_stamp => {
+                    header.notification_samples.push(sample);
+                    header.samples += 1;
+                }
+                _ => stamp_headers.push(AdsStampHeader::new(time_stamp, 1, vec![sample])),
+            }
+        }
+        AdsNotificationStream::from_stamp_headers(stamp_headers)
+    }
+
+    ///Sleep until the next check or deadline
+    fn wait(&self) {
+        let now = Instant::now();
+        let mut state = self.state();
+        if state.woken {
+            state.woken = false;
+            return;
+        }
+        let mut timeout = MAX_WAIT;
+        for r in state.registrations.values() {
+            if r.confirmed && r.trans_mode != AdsTransMode::None {
+                timeout = timeout.min(r.next_check.saturating_duration_since(now));
+            }
+            if let Some(deadline) = r.deadline {
+                timeout = timeout.min(deadline.saturating_duration_since(now));
+            }
+        }
+        if timeout == Duration::from_secs(0) {
+            return;
+        }
+        match self.inner.1.wait_timeout(state, timeout) {
+            Ok((mut s, _)) => s.woken = false,
+            Err(_) => panic!("Failed to get lock!"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ams_address::AmsNetId;
+    use crate::proto::proto_traits::ReadFrom;
+    use std::convert::TryInto;
+    use std::thread;
+
+    #[derive(Default)]
+    struct Buffer(Vec<u8>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn address(port: u16) -> AmsAddress {
+        AmsAddress::new(AmsNetId::new(127, 0, 0, 1, 1, 1), port)
+    }
+
+    fn request(index_offset: u32, trans_mode: AdsTransMode) -> AddDeviceNotificationRequest {
+        //10ms cycle time and 40ms max delay in 100ns units
+        AddDeviceNotificationRequest::new(1, index_offset, 1, trans_mode, 400_000, 100_000)
+    }
+
+    fn streams(buffer: &Mutex<Buffer>) -> Vec<AdsNotificationStream> {
+        let data = buffer.lock().unwrap().0.clone();
+        let mut data = data.as_slice();
+        let mut streams = Vec::new();
+        while !data.is_empty() {
+            let mut frame = AmsTcpHeader::read_from(&mut data).unwrap();
+            streams.push(frame.response().unwrap().try_into().unwrap());
+        }
+        streams
+    }
+
+    #[test]
+    fn stream_test() {
+        let stream =
+            NotificationManager::stream(vec![(2, 1, vec![3]), (1, 2, vec![2]), (1, 1, vec![1])]);
+        assert_eq!(stream.stamps, 2);
+        assert_eq!(stream.ads_stamp_headers[0].time_stamp, 1);
+        assert_eq!(stream.ads_stamp_headers[0].samples, 2);
+        assert_eq!(
+            stream.ads_stamp_headers[0].notification_samples[0].data,
+            vec![1]
+        );
+        assert_eq!(stream.ads_stamp_headers[1].samples, 1);
+    }
+
+    #[test]
+    fn delivery_test() {
+        let manager = NotificationManager::new();
+        let buffer = Arc::new(Mutex::new(Buffer::default()));
+        let session = manager.open_session(buffer.clone());
+        let cyclic = manager
+            .add(
+                session,
+                address(30000),
+                address(851),
+                &request(0, AdsTransMode::Cyclic),
+            )
+            .unwrap();
+        let on_change = manager
+            .add(
+                session,
+                address(30000),
+                address(851),
+                &request(1, AdsTransMode::OnChange),
+            )
+            .unwrap();
+        manager.confirm(session);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_manager = manager.clone();
+        let thread_running = Arc::clone(&running);
+        let thread = thread::spawn(move || {
+            thread_manager.run(&thread_running, |_, _, _, index_offset, _| {
+                Ok(vec![index_offset as u8])
+            })
+        });
+        thread::sleep(Duration::from_millis(200));
+        running.store(false, Ordering::SeqCst);
+        manager.wake();
+        thread.join().unwrap();
+
+        let streams = streams(&buffer);
+        assert!(!streams.is_empty());
+        let samples: Vec<&AdsNotificationSample> = streams
+            .iter()
+            .flat_map(|s| s.ads_stamp_headers.iter())
+            .flat_map(|h| h.notification_samples.iter())
+            .collect();
+        let count = |handle| {
+            samples
+                .iter()
+                .filter(|s| s.notification_handle == handle)
+                .count()
+        };
+        //Samples are batched by max delay
+        assert!(streams.len() < count(cyclic));
+        assert!(count(cyclic) > 5);
+        //The value never changes
+        assert_eq!(count(on_change), 1);
+    }
+
+    #[test]
+    fn delete_test() {
+        let manager = NotificationManager::new();
+        let session = manager.open_session(Arc::new(Mutex::new(Buffer::default())));
+        let request = request(0, AdsTransMode::Cyclic);
+        let handle = manager
+            .add(session, address(30000), address(851), &request)
+            .unwrap();
+        manager
+            .add(session, address(30001), address(851), &request)
+            .unwrap();
+
+        assert_eq!(
+            manager.delete(&address(30001), handle),
+            Err(AdsError::AdsErrDeviceNotifyHndInvalid)
+        );
+        assert!(manager.delete(&address(30000), handle).is_ok());
+        assert_eq!(manager.count(), 1);
+
+        let removed = manager.close_session(session);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].client, address(30001));
+        assert_eq!(manager.count(), 0);
+        assert_eq!(
+            manager.add(session, address(30000), address(851), &request),
+            Err(AdsError::ErrPortNotConnected)
+        );
+    }
+}
@@ -0,0 +1,1012 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::ads_services::system_services::{
+    ADSIGRP_DEVICE_DATA, ADSIGRP_SUMUP_READEX, ADSIGRP_SUMUP_READWRITE, ADSIGRP_SUMUP_WRITE,
+    ADSIGRP_SYM_VERSION, ADSIOFFS_DEVDATA_ADSSTATE, ADSIOFFS_DEVDATA_DEVSTATE,
+    GET_SYMHANDLE_BY_NAME, READ_WRITE_SYMVAL_BY_HANDLE, RELEASE_SYMHANDLE,
+};
+use crate::client::plc_types::PlcTypes;
+use crate::error::AdsError;
+use crate::proto::ads_state::AdsState;
+use crate::proto::ams_address::AmsAddress;
+use crate::proto::proto_traits::{ReadFrom, WriteTo};
+use crate::proto::request::ReadRequest;
+use crate::proto::response::{ReadResponse, WriteResponse};
+use crate::proto::sumup::sumup_response::{SumupReadResponse, SumupWriteResponse};
+use crate::server::device::{AdsDevice, DeviceInfo, DeviceResult};
+
+///Index group of the PLC memory. Index offset is the offset of a symbol.
+pub const PLC_MEMORY: u32 = 0x4040;
+///Length of STRING without explicit length
+const DEFAULT_STRING_LENGTH: usize = 80;
+
+///Data type of a symbol or struct member
+#[derive(Debug, Clone)]
+pub enum DataType {
+    Basic(PlcTypes),
+    ///STRING(n). n characters plus null terminator.
+    String(usize),
+    ///Name of a struct declared with TYPE
+    Struct(String),
+}
+
+#[derive(Debug, Clone)]
+struct TypeDef {
+    members: Vec<(String, DataType)>,
+    ///Initial values of all members
+    default: Vec<u8>,
+}
+
+///Symbol in the PLC memory. Members of structs are symbols as well (e.g. MAIN.motor.speed).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+///Symbols and types of a VirtualPlc. Parsed from IEC 61131-3 like declarations:
+///```text
+///TYPE ST_Motor :
+///STRUCT
+///    speed : LREAL := 1.5;
+///    running : BOOL;
+///END_STRUCT
+///END_TYPE
+///
+///MAIN.counter : INT := 5;
+///MAIN.motor : ST_Motor;
+///MAIN.text : STRING(20) := 'hello';
+///```
+///Values are packed in declaration order. Names are not case sensitive.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    types: HashMap<String, TypeDef>,
+    symbols: Vec<Symbol>,
+    index: HashMap<String, usize>,
+    initial: Vec<u8>,
+}
+
+impl SymbolTable {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        SymbolTable::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut table = SymbolTable::default();
+        let mut current: Option<(String, TypeDef)> = None;
+        for (n, line) in text.lines().enumerate() {
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {}: {}", n + 1, message),
+                )
+            };
+            let line = match line.find("//") {
+                Some(i) => &line[..i],
+                None => line,
+            }
+            .trim();
+            let keyword = line.trim_end_matches(';').to_uppercase();
+            if line.is_empty() || keyword == "STRUCT" || keyword == "END_STRUCT" {
+                continue;
+            }
+
+            if keyword.starts_with("TYPE ") {
+                let name = line[5..].trim().trim_end_matches(':').trim();
+                let def = TypeDef {
+                    members: Vec::new(),
+                    default: Vec::new(),
+                };
+                current = Some((name.to_uppercase(), def));
+            } else if keyword == "END_TYPE" {
+                match current.take() {
+                    Some((name, def)) => table.types.insert(name, def),
+                    None => return Err(invalid("END_TYPE without TYPE".to_string())),
+                };
+            } else {
+                let (name, data_type, value) = table.declaration(line).map_err(invalid)?;
+                let value = match value {
+                    Some(value) => {
+                        encode(&data_type, value, table.size(&data_type)).map_err(invalid)?
+                    }
+                    None => table.initial_value(&data_type),
+                };
+                match &mut current {
+                    Some((_, def)) => {
+                        def.members.push((name, data_type));
+                        def.default.extend(value);
+                    }
+                    None => table
+                        .add_symbol(&name, &data_type, value)
+                        .map_err(invalid)?,
+                }
+            }
+        }
+        match current {
+            Some((name, _)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Missing END_TYPE of {}", name),
+            )),
+            None => Ok(table),
+        }
+    }
+
+    ///name : type [:= value];
+    fn declaration<'a>(
+        &self,
+        line: &'a str,
+    ) -> Result<(String, DataType, Option<&'a str>), String> {
+        let line = line.trim_end_matches(';');
+        let (name, rest) = match line.split_once(':') {
+            Some(declaration) => declaration,
+            None => return Err(format!("Expected name : type, got {}", line)),
+        };
+        let (type_name, value) = match rest.split_once(":=") {
+            Some((type_name, value)) => (type_name, Some(value.trim())),
+            None => (rest, None),
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Missing name".to_string());
+        }
+        Ok((name.to_string(), self.data_type(type_name.trim())?, value))
+    }
+
+    fn data_type(&self, name: &str) -> Result<DataType, String> {
+        let name = name.to_uppercase();
+        if name == "STRING" {
+            return Ok(DataType::String(DEFAULT_STRING_LENGTH));
+        }
+        if let Some(length) = name
+            .strip_prefix("STRING(")
+            .and_then(|l| l.strip_suffix(')'))
+        {
+            return match length.trim().parse() {
+                Ok(length) => Ok(DataType::String(length)),
+                Err(_) => Err(format!("Invalid string length {}", length)),
+            };
+        }
+        if let Some(plc_type) = plc_type(&name) {
+            return Ok(DataType::Basic(plc_type));
+        }
+        if self.types.contains_key(&name) {
+            return Ok(DataType::Struct(name));
+        }
+        Err(format!("Unknown type {}", name))
+    }
+
+    fn size(&self, data_type: &DataType) -> usize {
+        match data_type {
+            DataType::Basic(plc_type) => plc_type.size(),
+            DataType::String(length) => length + 1,
+            DataType::Struct(name) => self.types[name].default.len(),
+        }
+    }
+
+    fn initial_value(&self, data_type: &DataType) -> Vec<u8> {
+        match data_type {
+            DataType::Struct(name) => self.types[name].default.clone(),
+            data_type => vec![0; self.size(data_type)],
+        }
+    }
+
+    fn add_symbol(
+        &mut self,
+        name: &str,
+        data_type: &DataType,
+        value: Vec<u8>,
+    ) -> Result<(), String> {
+        let offset = self.initial.len();
+        self.add_entry(name, data_type, offset)?;
+        self.initial.extend(value);
+        Ok(())
+    }
+
+    ///Add the symbol and the members of structs
+    fn add_entry(&mut self, name: &str, data_type: &DataType, offset: usize) -> Result<(), String> {
+        let key = name.to_uppercase();
+        if self.index.contains_key(&key) {
+            return Err(format!("Duplicate symbol {}", name));
+        }
+        self.index.insert(key, self.symbols.len());
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            offset: offset as u32,
+            size: self.size(data_type) as u32,
+        });
+
+        if let DataType::Struct(type_name) = data_type {
+            let mut offset = offset;
+            for (member, member_type) in self.types[type_name].members.clone() {
+                self.add_entry(&format!("{}.{}", name, member), &member_type, offset)?;
+                offset += self.size(&member_type);
+            }
+        }
+        Ok(())
+    }
+
+    ///All symbols in declaration order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.index
+            .get(&name.to_uppercase())
+            .map(|i| &self.symbols[*i])
+    }
+
+    ///Size of the PLC memory
+    pub fn memory_size(&self) -> usize {
+        self.initial.len()
+    }
+}
+
+fn plc_type(name: &str) -> Option<PlcTypes> {
+    let plc_type = match name {
+        "BOOL" => PlcTypes::Bool,
+        "BYTE" => PlcTypes::Byte,
+        "WORD" => PlcTypes::Word,
+        "DWORD" => PlcTypes::DWord,
+        "LWORD" => PlcTypes::LWord,
+        "SINT" => PlcTypes::SInt,
+        "USINT" => PlcTypes::USInt,
+        "INT" => PlcTypes::Int,
+        "UINT" => PlcTypes::UInt,
+        "DINT" => PlcTypes::DInt,
+        "UDINT" => PlcTypes::UDInt,
+        "LINT" => PlcTypes::LInt,
+        "ULINT" => PlcTypes::ULInt,
+        "REAL" => PlcTypes::Real,
+        "LREAL" => PlcTypes::LReal,
+        "TIME" => PlcTypes::Time,
+        "TOD" | "TIME_OF_DAY" => PlcTypes::TimeOfDay,
+        "DATE" => PlcTypes::Date,
+        "DT" | "DATE_AND_TIME" => PlcTypes::DateAndTime,
+        _ => return None,
+    };
+    Some(plc_type)
+}
+
+///Encode an initial value
+fn encode(data_type: &DataType, value: &str, size: usize) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Invalid value {} for {:?}", value, data_type);
+    let bytes = match data_type {
+        DataType::Basic(PlcTypes::Bool) => match value.to_uppercase().as_str() {
+            "TRUE" | "1" => vec![1],
+            "FALSE" | "0" => vec![0],
+            _ => return Err(invalid()),
+        },
+        DataType::Basic(PlcTypes::Real) => {
+            let value: f32 = value.parse().map_err(|_| invalid())?;
+            value.to_le_bytes().to_vec()
+        }
+        DataType::Basic(PlcTypes::LReal) => {
+            let value: f64 = value.parse().map_err(|_| invalid())?;
+            value.to_le_bytes().to_vec()
+        }
+        DataType::Basic(_) => {
+            let value: i128 = value.parse().map_err(|_| invalid())?;
+            value.to_le_bytes()[..size].to_vec()
+        }
+        DataType::String(_) => {
+            let text = value
+                .strip_prefix('\'')
+                .and_then(|v| v.strip_suffix('\''))
+                .ok_or_else(invalid)?;
+            if text.len() >= size {
+                return Err(invalid());
+            }
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.resize(size, 0);
+            bytes
+        }
+        DataType::Struct(_) => return Err(format!("Initial value of struct {}", value)),
+    };
+    Ok(bytes)
+}
+
+///Handle requested with GET_SYMHANDLE_BY_NAME
+#[derive(Debug)]
+struct SymbolHandle {
+    symbol: Symbol,
+    ///Symbol version the handle was created with
+    version: u8,
+}
+
+#[derive(Debug)]
+struct PlcState {
+    info: DeviceInfo,
+    table: SymbolTable,
+    memory: Vec<u8>,
+    handles: HashMap<u32, SymbolHandle>,
+    next_handle: u32,
+    symbol_version: u8,
+    ads_state: AdsState,
+    device_state: u16,
+    ///Errors returned for a symbol. Key is the upper case name.
+    faults: HashMap<String, AdsError>,
+}
+
+///Simulated PLC which serves the symbols of a SymbolTable like a TwinCAT PLC runtime.
+///Supports symbol handles, sum commands, ReadState/WriteControl, the symbol version
+///and the ADS state for device notifications.
+///Clones share the PLC, so a clone can change values and inject errors
+///while the PLC is served by an AdsServer.
+#[derive(Debug, Clone)]
+pub struct VirtualPlc {
+    inner: Arc<Mutex<PlcState>>,
+}
+
+impl VirtualPlc {
+    pub fn new(table: SymbolTable) -> Self {
+        VirtualPlc {
+            inner: Arc::new(Mutex::new(PlcState {
+                info: DeviceInfo::new(3, 1, 4024, "Plc30 App"),
+                memory: table.initial.clone(),
+                table,
+                handles: HashMap::new(),
+                next_handle: 1,
+                symbol_version: 1,
+                ads_state: AdsState::AdsStateRun,
+                device_state: 0,
+                faults: HashMap::new(),
+            })),
+        }
+    }
+
+    ///Load the symbols from a declaration file (see SymbolTable)
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(VirtualPlc::new(SymbolTable::load(path)?))
+    }
+
+    fn state(&self) -> MutexGuard<'_, PlcState> {
+        match self.inner.lock() {
+            Ok(s) => s,
+            Err(_) => panic!("Failed to get lock!"),
+        }
+    }
+
+    ///Current value of a symbol
+    pub fn value(&self, name: &str) -> Option<Vec<u8>> {
+        let state = self.state();
+        let symbol = state.table.symbol(name)?;
+        state
+            .memory(symbol.offset, symbol.size)
+            .ok()
+            .map(|v| v.to_vec())
+    }
+
+    ///Change the value of a symbol like the PLC program would
+    pub fn set_value(&self, name: &str, data: &[u8]) -> DeviceResult<()> {
+        let mut state = self.state();
+        let symbol = match state.table.symbol(name) {
+            Some(symbol) => symbol.clone(),
+            None => return Err(AdsError::AdsErrDeviceSymbolNotFound),
+        };
+        state.write_symbol(&symbol, data)
+    }
+
+    pub fn ads_state(&self) -> AdsState {
+        self.state().ads_state
+    }
+
+    pub fn symbol_version(&self) -> u8 {
+        self.state().symbol_version
+    }
+
+    ///Simulate an online change. The symbol version is incremented and
+    ///all handles fail with AdsErrDeviceSymbolVersionInvalid. Values are kept.
+    pub fn online_change(&self) {
+        let mut state = self.state();
+        state.symbol_version = state.symbol_version.wrapping_add(1);
+    }
+
+    ///Fail every access to the symbol (handle request, read and write) with error
+    pub fn fail_symbol(&self, name: &str, error: AdsError) {
+        self.state().faults.insert(name.to_uppercase(), error);
+    }
+
+    pub fn clear_faults(&self) {
+        self.state().faults.clear();
+    }
+
+    ///Number of symbol handles requested by clients and not released
+    pub fn handle_count(&self) -> usize {
+        self.state().handles.len()
+    }
+}
+
+impl PlcState {
+    fn memory(&self, offset: u32, length: u32) -> DeviceResult<&[u8]> {
+        let start = offset as usize;
+        self.memory
+            .get(start..start + length as usize)
+            .ok_or(AdsError::AdsErrDeviceInvalidOffset)
+    }
+
+    fn check_fault(&self, name: &str) -> DeviceResult<()> {
+        match self.faults.get(&name.to_uppercase()) {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    fn handle(&self, handle: u32) -> DeviceResult<Symbol> {
+        let handle = match self.handles.get(&handle) {
+            Some(handle) => handle,
+            None => return Err(AdsError::AdsErrDeviceSymbolNotFound),
+        };
+        if handle.version != self.symbol_version {
+            return Err(AdsError::AdsErrDeviceSymbolVersionInvalid);
+        }
+        self.check_fault(&handle.symbol.name)?;
+        Ok(handle.symbol.clone())
+    }
+
+    fn write_symbol(&mut self, symbol: &Symbol, data: &[u8]) -> DeviceResult<()> {
+        if data.len() > symbol.size as usize {
+            return Err(AdsError::AdsErrDeviceInvalidSize);
+        }
+        let start = symbol.offset as usize;
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&mut self, index_group: u32, index_offset: u32, length: u32) -> DeviceResult<Vec<u8>> {
+        match index_group {
+            PLC_MEMORY => Ok(self.memory(index_offset, length)?.to_vec()),
+            ig if ig == READ_WRITE_SYMVAL_BY_HANDLE.index_group => {
+                let symbol = self.handle(index_offset)?;
+                if length > symbol.size {
+                    return Err(AdsError::AdsErrDeviceInvalidSize);
+                }
+                Ok(self.memory(symbol.offset, length)?.to_vec())
+            }
+            ig if ig == ADSIGRP_SYM_VERSION.index_group => Ok(vec![self.symbol_version]),
+            ig if ig == ADSIGRP_DEVICE_DATA.index_group => {
+                let mut data = Vec::new();
+                match index_offset {
+                    ADSIOFFS_DEVDATA_ADSSTATE => {
+                        data.write_u16::<LittleEndian>(self.ads_state as u16);
+                        data.write_u16::<LittleEndian>(self.device_state);
+                    }
+                    ADSIOFFS_DEVDATA_DEVSTATE => {
+                        data.write_u16::<LittleEndian>(self.device_state);
+                    }
+                    _ => return Err(AdsError::AdsErrDeviceInvalidOffset),
+                }
+                Ok(data)
+            }
+            _ => Err(AdsError::AdsErrDeviceInvalidGrp),
+        }
+    }
+
+    fn write(&mut self, index_group: u32, index_offset: u32, data: &[u8]) -> DeviceResult<()> {
+        match index_group {
+            PLC_MEMORY => {
+                let start = index_offset as usize;
+                match self.memory.get_mut(start..start + data.len()) {
+                    Some(memory) => memory.copy_from_slice(data),
+                    None => return Err(AdsError::AdsErrDeviceInvalidOffset),
+                }
+                Ok(())
+            }
+            ig if ig == READ_WRITE_SYMVAL_BY_HANDLE.index_group => {
+                let symbol = self.handle(index_offset)?;
+                self.write_symbol(&symbol, data)
+            }
+            ig if ig == RELEASE_SYMHANDLE.index_group => {
+                let handle = (&mut &data[..])
+                    .read_u32::<LittleEndian>()
+                    .map_err(|_| AdsError::AdsErrDeviceInvalidSize)?;
+                match self.handles.remove(&handle) {
+                    Some(_) => Ok(()),
+                    None => Err(AdsError::AdsErrDeviceSymbolNotFound),
+                }
+            }
+            _ => Err(AdsError::AdsErrDeviceInvalidGrp),
+        }
+    }
+
+    fn read_write(
+        &mut self,
+        index_group: u32,
+        index_offset: u32,
+        read_length: u32,
+        data: &[u8],
+    ) -> DeviceResult<Vec<u8>> {
+        match index_group {
+            ig if ig == GET_SYMHANDLE_BY_NAME.index_group => {
+                let name = String::from_utf8_lossy(data);
+                let name = name.trim_end_matches('\0');
+                self.check_fault(name)?;
+                let symbol = match self.table.symbol(name) {
+                    Some(symbol) => symbol.clone(),
+                    None => return Err(AdsError::AdsErrDeviceSymbolNotFound),
+                };
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.handles.insert(
+                    handle,
+                    SymbolHandle {
+                        symbol,
+                        version: self.symbol_version,
+                    },
+                );
+                Ok(handle.to_le_bytes().to_vec())
+            }
+            ig if ig == ADSIGRP_SUMUP_READEX.index_group => {
+                self.sumup_read(index_offset, data).map_err(invalid_data)
+            }
+            ig if ig == ADSIGRP_SUMUP_WRITE.index_group => self.sumup_write(index_offset, data),
+            ig if ig == ADSIGRP_SUMUP_READWRITE.index_group => {
+                self.sumup_read_write(index_offset, data)
+            }
+            _ => Err(AdsError::AdsErrDeviceInvalidGrp),
+        }
+    }
+
+    ///count read requests. Results and lengths followed by the data.
+    fn sumup_read(&mut self, count: u32, mut data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut responses = Vec::new();
+        for _ in 0..count {
+            let request = ReadRequest::read_from(&mut data)?;
+            responses.push(
+                match self.read(request.index_group, request.index_offset, request.length) {
+                    Ok(data) => ReadResponse::new(AdsError::ErrNoError, data),
+                    Err(e) => ReadResponse::new(e, Vec::new()),
+                },
+            );
+        }
+        let mut buffer = Vec::new();
+        SumupReadResponse::new(responses).write_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    ///count write headers (index group, index offset, length) followed by the data.
+    ///Returns a result for every write.
+    fn sumup_write(&mut self, count: u32, mut data: &[u8]) -> DeviceResult<Vec<u8>> {
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            headers.push((
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+            ));
+        }
+        let mut responses = Vec::new();
+        for (index_group, index_offset, length) in headers {
+            let value = take_value(&mut data, length)?;
+            responses.push(WriteResponse::new(
+                match self.write(index_group, index_offset, &value) {
+                    Ok(()) => AdsError::ErrNoError,
+                    Err(e) => e,
+                },
+            ));
+        }
+        let mut buffer = Vec::new();
+        SumupWriteResponse::new(responses)
+            .write_to(&mut buffer)
+            .map_err(invalid_data)?;
+        Ok(buffer)
+    }
+
+    ///count read write headers (index group, index offset, read length, write length)
+    ///followed by the data. Results and lengths followed by the read data.
+    fn sumup_read_write(&mut self, count: u32, mut data: &[u8]) -> DeviceResult<Vec<u8>> {
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            headers.push((
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+                data.read_u32::<LittleEndian>().map_err(invalid_data)?,
+            ));
+        }
+        let mut responses = Vec::new();
+        for (index_group, index_offset, read_length, write_length) in headers {
+            let value = take_value(&mut data, write_length)?;
+            responses.push(
+                match self.read_write(index_group, index_offset, read_length, &value) {
+                    Ok(mut data) => {
+                        data.truncate(read_length as usize);
+                        ReadResponse::new(AdsError::ErrNoError, data)
+                    }
+                    Err(e) => ReadResponse::new(e, Vec::new()),
+                },
+            );
+        }
+        let mut buffer = Vec::new();
+        SumupReadResponse::new(responses)
+            .write_to(&mut buffer)
+            .map_err(invalid_data)?;
+        Ok(buffer)
+    }
+}
+
+fn invalid_data(_: io::Error) -> AdsError {
+    AdsError::AdsErrDeviceInvalidData
+}
+
+///Take length bytes of a sumup request.
+///The length is checked against the received data before anything is allocated.
+fn take_value(data: &mut &[u8], length: u32) -> DeviceResult<Vec<u8>> {
+    let length = length as usize;
+    if length > data.len() {
+        return Err(AdsError::AdsErrDeviceInvalidSize);
+    }
+    let (value, rest) = data.split_at(length);
+    *data = rest;
+    Ok(value.to_vec())
+}
+
+impl AdsDevice for VirtualPlc {
+    fn read_device_info(&mut self) -> DeviceResult<DeviceInfo> {
+        Ok(self.state().info.clone())
+    }
+
+    fn read(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        length: u32,
+    ) -> DeviceResult<Vec<u8>> {
+        self.state().read(index_group, index_offset, length)
+    }
+
+    fn write(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        data: &[u8],
+    ) -> DeviceResult<()> {
+        self.state().write(index_group, index_offset, data)
+    }
+
+    fn read_write(
+        &mut self,
+        source: &AmsAddress,
+        index_group: u32,
+        index_offset: u32,
+        read_length: u32,
+        data: &[u8],
+    ) -> DeviceResult<Vec<u8>> {
+        self.state()
+            .read_write(index_group, index_offset, read_length, data)
+    }
+
+    fn read_state(&mut self) -> DeviceResult<(AdsState, u16)> {
+        let state = self.state();
+        Ok((state.ads_state, state.device_state))
+    }
+
+    ///Run and Stop change the state. Reset restores the initial values and stops the PLC.
+    fn write_control(
+        &mut self,
+        source: &AmsAddress,
+        ads_state: AdsState,
+        device_state: u16,
+        data: &[u8],
+    ) -> DeviceResult<()> {
+        let mut state = self.state();
+        match ads_state {
+            AdsState::AdsStateRun | AdsState::AdsStateStop => state.ads_state = ads_state,
+            AdsState::AdsStateReset => {
+                state.memory = state.table.initial.clone();
+                state.ads_state = AdsState::AdsStateStop;
+            }
+            _ => return Err(AdsError::AdsErrDeviceInvalidState),
+        }
+        state.device_state = device_state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ads_client::Connection;
+    use crate::client::notification::NotificationResult;
+    use crate::client::plc_types::Var;
+    use crate::proto::ads_transition_mode::AdsTransMode;
+    use crate::proto::ams_address::AmsNetId;
+    use crate::server::ads_server::{AdsServer, ServerHandle};
+    use std::time::Duration;
+
+    const SYMBOLS: &str = "
+        //Test symbols
+        TYPE ST_Motor :
+        STRUCT
+            speed : LREAL := 1.5;
+            running : BOOL;
+        END_STRUCT
+        END_TYPE
+
+        MAIN.counter : INT := -2;
+        MAIN.motor : ST_Motor;
+        MAIN.text : STRING(5) := 'abc';
+        GVL.flag : BOOL := TRUE;
+    ";
+
+    fn net_id() -> AmsNetId {
+        AmsNetId::new(127, 0, 0, 1, 1, 1)
+    }
+
+    fn start() -> (VirtualPlc, ServerHandle, Connection) {
+        let plc = VirtualPlc::new(SymbolTable::parse(SYMBOLS).unwrap());
+        let server = AdsServer::new(net_id());
+        server.add_device(851, plc.clone());
+        let handle = server.start_on("127.0.0.1:0").unwrap();
+        let connection = Connection::builder(AmsAddress::new(net_id(), 851))
+            .port(handle.local_addr().port())
+            .connect()
+            .unwrap();
+        (plc, handle, connection)
+    }
+
+    fn var(name: &str, plc_type: PlcTypes) -> Var {
+        Var::new(name.to_string(), plc_type, None)
+    }
+
+    #[test]
+    fn symbol_table_test() {
+        let table = SymbolTable::parse(SYMBOLS).unwrap();
+        let names: Vec<&str> = table.symbols().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "MAIN.counter",
+                "MAIN.motor",
+                "MAIN.motor.speed",
+                "MAIN.motor.running",
+                "MAIN.text",
+                "GVL.flag"
+            ]
+        );
+        let speed = table.symbol("main.motor.SPEED").unwrap();
+        assert_eq!((speed.offset, speed.size), (2, 8));
+        assert_eq!(table.symbol("MAIN.motor").unwrap().size, 9);
+        assert_eq!(table.memory_size(), 2 + 9 + 6 + 1);
+        assert_eq!(&table.initial[..2], &(-2i16).to_le_bytes());
+        assert_eq!(&table.initial[2..10], &1.5f64.to_le_bytes());
+        assert_eq!(&table.initial[11..17], b"abc\0\0\0");
+        assert_eq!(table.initial[17], 1);
+    }
+
+    #[test]
+    fn symbol_table_error_test() {
+        let error = SymbolTable::parse("a : INT;\nb : FOO;").unwrap_err();
+        assert_eq!(error.to_string(), "Line 2: Unknown type FOO");
+        assert!(SymbolTable::parse("a : INT;\na : INT;").is_err());
+        assert!(SymbolTable::parse("a : STRING(2) := 'abc';").is_err());
+        assert!(SymbolTable::parse("TYPE A :\nSTRUCT\nb : INT;").is_err());
+    }
+
+    #[test]
+    fn read_write_by_name_test() {
+        let (plc, _handle, mut connection) = start();
+        let counter = var("MAIN.counter", PlcTypes::Int);
+        connection.get_symhandle(&counter, 1).unwrap();
+        assert_eq!(
+            connection.read_by_name(&counter, 2).unwrap(),
+            (-2i16).to_le_bytes()
+        );
+        connection
+            .write_by_name(&counter, 3, 7i16.to_le_bytes().to_vec())
+            .unwrap();
+        assert_eq!(plc.value("MAIN.counter").unwrap(), 7i16.to_le_bytes());
+
+        let error = connection
+            .get_symhandle(&var("MAIN.missing", PlcTypes::Int), 4)
+            .unwrap_err();
+        assert_eq!(
+            error.ads_error(),
+            Some(&AdsError::AdsErrDeviceSymbolNotFound)
+        );
+
+        plc.fail_symbol("GVL.flag", AdsError::AdsErrDeviceSymbolNotFound);
+        let error = connection
+            .get_symhandle(&var("GVL.flag", PlcTypes::Bool), 5)
+            .unwrap_err();
+        assert_eq!(
+            error.ads_error(),
+            Some(&AdsError::AdsErrDeviceSymbolNotFound)
+        );
+        plc.clear_faults();
+        assert!(connection
+            .get_symhandle(&var("GVL.flag", PlcTypes::Bool), 6)
+            .is_ok());
+    }
+
+    #[test]
+    fn no_return_test() {
+        let (plc, _handle, mut connection) = start();
+        let protocol_errors = connection.protocol_errors();
+        connection.set_strict_responses(true);
+        let counter = var("MAIN.counter", PlcTypes::Int);
+        connection.get_symhandle(&counter, 1).unwrap();
+        connection
+            .write_by_name_no_return(&counter, 2, 7i16.to_le_bytes().to_vec())
+            .unwrap();
+        //Requests are handled in order, so the write is done when the read is answered
+        assert_eq!(
+            connection.read_by_name(&counter, 3).unwrap(),
+            7i16.to_le_bytes()
+        );
+
+        let handle = connection.handle();
+        handle
+            .write_by_name_no_return(&counter, 8i16.to_le_bytes().to_vec())
+            .unwrap();
+        assert_eq!(handle.read_by_name(&counter).unwrap(), 8i16.to_le_bytes());
+        //Failed writes are not answered either
+        handle.write_no_return(PLC_MEMORY, 0xFFFF, vec![1]).unwrap();
+        assert_eq!(handle.read_by_name(&counter).unwrap(), 8i16.to_le_bytes());
+        assert!(protocol_errors.try_recv().is_err());
+    }
+
+    #[test]
+    fn sumup_test() {
+        let (plc, _handle, mut connection) = start();
+        let vars = vec![
+            var("MAIN.counter", PlcTypes::Int),
+            var("MAIN.motor.speed", PlcTypes::LReal),
+            var("GVL.flag", PlcTypes::Bool),
+        ];
+        connection.sumup_get_symhandle(&vars, 1).unwrap();
+        assert_eq!(plc.handle_count(), 3);
+
+        let values = connection.sumup_read_by_name(&vars, 2).unwrap();
+        assert_eq!(values["MAIN.counter"], (-2i16).to_le_bytes());
+        assert_eq!(values["MAIN.motor.speed"], 1.5f64.to_le_bytes());
+        assert_eq!(values["GVL.flag"], vec![1]);
+        let values = connection.sumup_read_by_name_bytes(&vars, 2).unwrap();
+        assert_eq!(values["MAIN.motor.speed"], 1.5f64.to_le_bytes()[..]);
+
+        let vars = vec![
+            Var::new(
+                "MAIN.counter".to_string(),
+                PlcTypes::Int,
+                Some(9i16.to_le_bytes().to_vec()),
+            ),
+            Var::new("GVL.flag".to_string(), PlcTypes::Bool, Some(vec![0])),
+        ];
+        let results = connection.sumup_write_by_name(&vars, 3).unwrap();
+        assert_eq!(results["MAIN.counter"], AdsError::ErrNoError);
+        assert_eq!(results["GVL.flag"], AdsError::ErrNoError);
+        assert_eq!(plc.value("MAIN.counter").unwrap(), 9i16.to_le_bytes());
+        assert_eq!(plc.value("GVL.flag").unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn sumup_length_exceeds_data_test() {
+        let mut plc = VirtualPlc::new(SymbolTable::parse(SYMBOLS).unwrap());
+        let source = AmsAddress::new(net_id(), 30000);
+        //One write header claiming 4 GB followed by a single byte
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(ADSIGRP_DEVICE_DATA.index_group)
+            .unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        data.write_u32::<LittleEndian>(u32::MAX).unwrap();
+        data.push(1);
+        let result = plc.read_write(&source, ADSIGRP_SUMUP_WRITE.index_group, 1, 4, &data);
+        assert_eq!(result, Err(AdsError::AdsErrDeviceInvalidSize));
+
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(ADSIGRP_DEVICE_DATA.index_group)
+            .unwrap();
+        data.write_u32::<LittleEndian>(0).unwrap();
+        data.write_u32::<LittleEndian>(4).unwrap();
+        data.write_u32::<LittleEndian>(u32::MAX).unwrap();
+        let result = plc.read_write(&source, ADSIGRP_SUMUP_READWRITE.index_group, 1, 8, &data);
+        assert_eq!(result, Err(AdsError::AdsErrDeviceInvalidSize));
+    }
+
+    #[test]
+    fn online_change_test() {
+        let (plc, _handle, mut connection) = start();
+        let counter = var("MAIN.counter", PlcTypes::Int);
+        connection.get_symhandle(&counter, 1).unwrap();
+        plc.online_change();
+        assert_eq!(plc.symbol_version(), 2);
+        //The handle is refreshed and the read retried
+        assert_eq!(
+            connection.read_by_name(&counter, 2).unwrap(),
+            (-2i16).to_le_bytes()
+        );
+        assert_eq!(plc.handle_count(), 2);
+    }
+
+    #[test]
+    fn state_test() {
+        let (plc, _handle, mut connection) = start();
+        assert_eq!(
+            connection.read_state(1).unwrap().ads_state,
+            AdsState::AdsStateRun
+        );
+        plc.set_value("MAIN.counter", &[5, 0]).unwrap();
+        connection
+            .write_control(AdsState::AdsStateReset, 0, 2)
+            .unwrap();
+        assert_eq!(plc.ads_state(), AdsState::AdsStateStop);
+        assert_eq!(plc.value("MAIN.counter").unwrap(), (-2i16).to_le_bytes());
+        let error = connection
+            .write_control(AdsState::AdsStateConfig, 0, 3)
+            .unwrap_err();
+        assert_eq!(error.ads_error(), Some(&AdsError::AdsErrDeviceInvalidState));
+        connection
+            .write_control(AdsState::AdsStateRun, 0, 4)
+            .unwrap();
+        assert_eq!(
+            connection.read_state(5).unwrap().ads_state,
+            AdsState::AdsStateRun
+        );
+    }
+
+    #[test]
+    fn notification_test() {
+        let (plc, _handle, mut connection) = start();
+        let counter = var("MAIN.counter", PlcTypes::Int);
+        let subscription = connection
+            .add_device_notification(&counter, AdsTransMode::OnChange, 0, 100_000, 1)
+            .unwrap();
+        let timeout = Duration::from_secs(1);
+        let value = |stream: NotificationResult| stream.unwrap().stamps[0].samples[0].data.to_vec();
+        assert_eq!(
+            value(subscription.recv_timeout(timeout).unwrap()),
+            (-2i16).to_le_bytes()
+        );
+        plc.set_value("MAIN.counter", &3i16.to_le_bytes()).unwrap();
+        assert_eq!(
+            value(subscription.recv_timeout(timeout).unwrap()),
+            3i16.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn notification_per_handle_test() {
+        let (plc, _handle, mut connection) = start();
+        //The first samples of both notifications are sent in one frame after max delay (200 ms)
+        let counter = connection
+            .add_device_notification(
+                &var("MAIN.counter", PlcTypes::Int),
+                AdsTransMode::OnChange,
+                2_000_000,
+                100_000,
+                1,
+            )
+            .unwrap();
+        let flag = connection
+            .add_device_notification(
+                &var("GVL.flag", PlcTypes::Bool),
+                AdsTransMode::OnChange,
+                2_000_000,
+                100_000,
+                2,
+            )
+            .unwrap();
+        let timeout = Duration::from_secs(1);
+        let samples = |stream: NotificationResult| -> Vec<Vec<u8>> {
+            stream
+                .unwrap()
+                .samples()
+                .map(|(_, s)| s.data.to_vec())
+                .collect()
+        };
+        assert_eq!(
+            samples(counter.recv_timeout(timeout).unwrap()),
+            vec![(-2i16).to_le_bytes().to_vec()]
+        );
+        assert_eq!(samples(flag.recv_timeout(timeout).unwrap()), vec![vec![1]]);
+    }
+}